@@ -9,10 +9,12 @@ pub struct Vertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub color: [f32; 3],
-    pub tex_coord: [f32; 2]
+    pub tex_coord: [f32; 2],
+    // second uv set for lightmaps and detail textures, not affected by SpriteAnimation
+    pub tex_coord2: [f32; 2]
 }
 
-vulkano::impl_vertex!(Vertex, position, normal, color, tex_coord);
+vulkano::impl_vertex!(Vertex, position, normal, color, tex_coord, tex_coord2);
 
 impl PartialEq for Vertex {
     fn eq(&self, other: &Self) -> bool {
@@ -20,6 +22,7 @@ impl PartialEq for Vertex {
             && self.normal == other.normal
             && self.color == other.color
             && self.tex_coord == other.tex_coord
+            && self.tex_coord2 == other.tex_coord2
     }
 }
 
@@ -38,5 +41,7 @@ impl Hash for Vertex {
         self.color[2].to_bits().hash(state);
         self.tex_coord[0].to_bits().hash(state);
         self.tex_coord[1].to_bits().hash(state);
+        self.tex_coord2[0].to_bits().hash(state);
+        self.tex_coord2[1].to_bits().hash(state);
     }
 }
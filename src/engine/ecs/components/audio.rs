@@ -0,0 +1,19 @@
+use specs::{Component, HashMapStorage};
+
+
+/// Requests a sound whenever the entity's collider hits something
+/// with at least min_impact_speed (m/s) along the contact normal
+/// 
+/// The collider needs ActiveEvents::COLLISION_EVENTS to generate events
+#[derive(Component, Clone, Debug)]
+#[storage(HashMapStorage)]
+pub struct CollisionSound {
+    pub sound: String,
+    pub min_impact_speed: f32
+}
+
+impl CollisionSound {
+    pub fn new(sound: &str, min_impact_speed: f32) -> Self {
+        CollisionSound { sound: sound.to_string(), min_impact_speed }
+    }
+}
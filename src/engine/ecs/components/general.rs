@@ -1,29 +1,24 @@
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use log::warn;
-use nalgebra::{Matrix4, Vector3, UnitQuaternion};
-use specs::{Component, VecStorage, HashMapStorage, NullStorage};
+use nalgebra::{Matrix4, Vector3, UnitQuaternion, Unit};
+use specs::{Component, VecStorage, HashMapStorage, NullStorage, FlaggedStorage, Entity};
 use serde::{Serialize, Deserialize};
 use vulkano::{buffer::CpuAccessibleBuffer, descriptor_set::PersistentDescriptorSet};
 
-use crate::data_structures::graphics::Vertex;
+use crate::{data_structures::graphics::Vertex, graphics::{streaming::MipChain, minimap, vulkan::{MinimapTarget, OffscreenTarget, UploadHandle}}, ecs::resources::RandomSource};
 
 
-#[derive(Component, Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 #[storage(VecStorage)]
 pub struct Transform {
     /*
     Coordinate system is right handed, -z forward, y up, x right
     */
 
-    // TODO: possibility to optimize
-    // lazy generating transformation matrix
-    // for example: if the object doesnt move,
-    // we still recreate the transformation matrix anew
-    // on every render
-
-    pub pos: Vector3<f32>,
-    pub rot: UnitQuaternion<f32>,
-    pub scale: Vector3<f32>,
+    // only changed through the setters, which clear the cached matrix
+    pos: Vector3<f32>,
+    rot: UnitQuaternion<f32>,
+    scale: Vector3<f32>,
 
     #[serde(skip)]
     pub mov: Vector3<f32>,
@@ -33,16 +28,75 @@ pub struct Transform {
     pub accel: Vector3<f32>,
 
     #[serde(skip)]
-    pub need_physics_update: bool
+    pub need_physics_update: bool,
+
+    // built by the first transformation_matrix call after a change, shared by the readers of the storage
+    #[serde(skip)]
+    matrix: OnceLock<Matrix4<f32>>
 }
 
 impl Transform {
+    pub fn from_position(pos: Vector3<f32>) -> Self {
+        Self { pos, ..Default::default() }
+    }
+
+    pub fn from_position_rotation(pos: Vector3<f32>, rot: UnitQuaternion<f32>) -> Self {
+        Self { pos, rot, ..Default::default() }
+    }
+
+    pub fn with_position(mut self, pos: Vector3<f32>) -> Self {
+        self.set_pos(pos);
+        self
+    }
+
+    pub fn with_rotation(mut self, rot: UnitQuaternion<f32>) -> Self {
+        self.set_rot(rot);
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vector3<f32>) -> Self {
+        self.set_scale(scale);
+        self
+    }
+
+    pub fn pos(&self) -> Vector3<f32> {
+        self.pos
+    }
+
+    pub fn rot(&self) -> UnitQuaternion<f32> {
+        self.rot
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    pub fn set_pos(&mut self, pos: Vector3<f32>) {
+        self.pos = pos;
+        self.matrix.take();
+    }
+
+    pub fn set_rot(&mut self, rot: UnitQuaternion<f32>) {
+        self.rot = rot;
+        self.matrix.take();
+    }
+
+    pub fn set_scale(&mut self, scale: Vector3<f32>) {
+        self.scale = scale;
+        self.matrix.take();
+    }
+
+    /*
+    Cached, only rebuilt after the position, rotation or scale changed
+    */
     pub fn transformation_matrix(&self) -> Matrix4<f32> {
-        let translate = Matrix4::new_translation(&self.pos);
-        let rotation = &self.rot.to_homogeneous();
-        let scale = Matrix4::new_nonuniform_scaling(&self.scale);
-        
-        translate * rotation * scale
+        *self.matrix.get_or_init(|| {
+            let translate = Matrix4::new_translation(&self.pos);
+            let rotation = &self.rot.to_homogeneous();
+            let scale = Matrix4::new_nonuniform_scaling(&self.scale);
+
+            translate * rotation * scale
+        })
     }
 
     pub fn forward(&self) -> Vector3<f32> {
@@ -78,28 +132,442 @@ impl Default for Transform {
         let default_vec = Vector3::default();
         let default_quat = UnitQuaternion::identity();
         let default_scale = Vector3::new(1.0, 1.0, 1.0);
-        Transform { pos: default_vec, mov: default_vec, vel: default_vec, accel: default_vec, rot: default_quat, scale: default_scale, need_physics_update: true }
+        Transform { pos: default_vec, mov: default_vec, vel: default_vec, accel: default_vec, rot: default_quat, scale: default_scale, need_physics_update: true, matrix: OnceLock::new() }
     }
 }
 
-#[derive(Component)]
+// Cloning only clones the Arcs, so clones share the same gpu buffers and descriptor sets
+#[derive(Component, Clone)]
 #[storage(VecStorage)]
 pub struct Renderable {
     // TODO: maybe switch to dense vec storage if we have a lot of 
     // non-rendered entities
     pub vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>, 
     pub index_buffer: Arc<CpuAccessibleBuffer<[u32]>>,
-    pub descriptor_set_texture: Arc<PersistentDescriptorSet>
+    pub descriptor_set_texture: Arc<PersistentDescriptorSet>,
+    // textures of the descriptor set, None if they are already on the gpu
+    pub upload: Option<UploadHandle>
+}
+
+impl Renderable {
+    /*
+    False until the textures have been uploaded, the render system skips the entity until then
+    */
+    pub fn is_ready(&self) -> bool {
+        match &self.upload {
+            Some(v) => v.is_finished(),
+            None => true
+        }
+    }
 }
 
+/// Texture of the Renderable with only some of its mip levels on the gpu, see Vulkan::create_streamed_renderable
+/// The engine uploads the levels the TextureStreaming resource asks for and swaps the texture
+/// of the Renderable once they are on the gpu, until then the current levels are drawn
+#[derive(Component, Clone)]
+#[storage(VecStorage)]
+pub struct StreamedTexture {
+    // every level stays on the cpu, the gpu image is recreated from them
+    mips: Arc<MipChain>,
+    // first level on the gpu
+    resident: u32,
+    // level being uploaded
+    pending: Option<u32>
+}
+
+impl StreamedTexture {
+    pub fn new(mips: Arc<MipChain>, resident: u32) -> Self {
+        StreamedTexture { mips, resident, pending: None }
+    }
+
+    pub fn mips(&self) -> &MipChain {
+        &self.mips
+    }
+
+    pub fn resident_level(&self) -> u32 {
+        self.resident
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    pub(crate) fn request(&mut self, level: u32) {
+        self.pending = Some(level);
+    }
+
+    /*
+    The requested level becomes resident, unless its upload failed
+    */
+    pub(crate) fn finish(&mut self, uploaded: bool) {
+        match (self.pending.take(), uploaded) {
+            (Some(level), true) => self.resident = level,
+            (Some(level), false) => warn!("Failed to upload mip level {} of a streamed texture", level),
+            (None, _) => {}
+        }
+    }
+}
+
+/// Metallic roughness material of a glTF primitive, the Renderable of the entity
+/// is drawn with the pbr pipeline instead of the default one, see Vulkan::create_gltf_renderables
+/// The Renderable keeps the base color texture for every other pipeline
+#[derive(Component, Clone)]
+#[storage(VecStorage)]
+pub struct PbrMaterial {
+    // set 1 of the pbr pipeline, its textures are uploaded along with those of the Renderable
+    pub descriptor_set: Arc<PersistentDescriptorSet>
+}
+
+/// Draws the Renderable of the entity as lines instead of solid,
+/// can be added and removed at runtime
+/// Falls back to solid, or blended with Transparent, if the device doesn't support fill_mode_non_solid
 #[derive(Component, Default)]
 #[storage(NullStorage)]
 pub struct Wireframe;
 
+/// Both sides of the faces are drawn, e.g. for foliage or other single layered geometry
+/// Otherwise the default and pbr pipelines only draw faces wound counter clockwise towards the camera
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct DoubleSided;
+
+/// Drawn after everything else without depth testing,
+/// so it's visible through other geometry.
+/// Ignored for entities with a PbrMaterial
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct NoDepthTest;
+
+/// Pulls the entity towards the camera in the depth test, so coplanar
+/// geometry like decals wins against the surface it lies on
+/// The value is in units of the smallest depth difference, scaled up on slopes
+/// Ignored for entities with a PbrMaterial or Transparent
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(HashMapStorage)]
+pub struct DepthBias(pub f32);
+
+/// Blended over the solid geometry, e.g. glass or water, drawn after it from back to front
+/// by the position of each entity, with the default shaders even if it has a PbrMaterial
+/// Depth is tested without writing it, so everything transparent behind it still shows
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Transparent;
+
+/// Drawn normally with its wireframe on top
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct WireframeOverlay;
+
+/// Large renderable skipped by the solid pass while it is hidden behind other geometry, e.g. a room behind a wall
+/// Its bounds are drawn as a box with an occlusion query after the solid pass, the result
+/// is used a frame late, so it can show up a frame after it comes into view
+/// Only worth it for meshes costing more than the query, min and max are in model space
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(HashMapStorage)]
+pub struct OcclusionCulled {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>
+}
+
+impl OcclusionCulled {
+    /*
+    Bounds of the positions of vertices, None without any
+    */
+    pub fn from_vertices(vertices: &[Vertex]) -> Option<Self> {
+        let first = Vector3::from(vertices.first()?.position);
+        let (min, max) = vertices.iter().fold((first, first), |(min, max), v| {
+            let p = Vector3::from(v.position);
+            (min.inf(&p), max.sup(&p))
+        });
+        Some(OcclusionCulled { min, max })
+    }
+
+    /*
+    Bounds of the vertex buffer of renderable, None if it is empty or being written
+    */
+    pub fn from_renderable(renderable: &Renderable) -> Option<Self> {
+        match renderable.vertex_buffer.read() {
+            Ok(v) => OcclusionCulled::from_vertices(&v),
+            Err(e) => {
+                warn!("Could not read the vertices of a renderable for its bounds: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Layers the entity is drawn in by a Minimap, as a mask, e.g. to leave details out of the map
+/// Entities without it are in RenderLayers::DEFAULT, the window draws every layer
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+#[storage(HashMapStorage)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    pub const DEFAULT: u32 = 1;
+    pub const ALL: u32 = u32::MAX;
+
+    /*
+    Whether an entity with layers is drawn by a minimap drawing mask
+    */
+    pub fn visible(layers: Option<&RenderLayers>, mask: u32) -> bool {
+        layers.map_or(RenderLayers::DEFAULT, |v| v.0) & mask != 0
+    }
+}
+
+/// Top-down map around follow, rendered into its own texture every frame and drawn in rect of the
+/// window on top of everything else, with a dot where follow is, see Vulkan::create_minimap_target
+/// North (-Z) is up and the map doesn't turn with follow, it is unshadowed
+#[derive(Component, Clone)]
+#[storage(HashMapStorage)]
+pub struct Minimap {
+    pub target: MinimapTarget,
+    pub follow: Entity,
+    // x and y of the top left corner, width and height, as fractions of the window
+    pub rect: [f32; 4],
+    // world units shown from top to bottom, the width follows the aspect ratio of the target
+    pub extent: f32,
+    // of the camera above follow, nothing higher up is on the map and as far below is
+    pub height: f32,
+    // mask of RenderLayers
+    pub layers: u32,
+    pub marker_color: [f32; 4]
+}
+
+impl Minimap {
+    /*
+    In the top right corner, 40 units across
+    */
+    pub fn new(target: MinimapTarget, follow: Entity) -> Self {
+        Minimap {
+            target,
+            follow,
+            rect: [0.78, 0.02, 0.2, 0.2],
+            extent: 40.0,
+            height: 60.0,
+            layers: RenderLayers::ALL,
+            marker_color: [1.0, 0.2, 0.2, 1.0]
+        }
+    }
+
+    pub fn with_rect(mut self, rect: [f32; 4]) -> Self {
+        self.rect = rect;
+        self
+    }
+
+    pub fn with_layers(mut self, layers: u32) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /*
+    View of the camera above center
+    */
+    pub fn view_matrix(&self, center: &Vector3<f32>) -> Matrix4<f32> {
+        minimap::top_down_view(center, self.height)
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        let aspect = self.target.size[0] as f32 / self.target.size[1] as f32;
+        minimap::orthographic(self.extent, aspect, 0.1, self.height * 2.0)
+    }
+}
+
+/// Camera rendering into its own texture every frame from the transform of the entity,
+/// with the projection of the window camera and the aspect ratio of the target, lit but unshadowed
+/// Displays are renderables showing the texture, see Vulkan::create_renderable_from_texture,
+/// they are left out of the target since it can't be sampled while it is drawn into
+/// Systems can change size, the engine then resizes the target and gives the displays the new texture
+/// Flagged so the engine can drop the pipelines of the target once the component is removed
+#[derive(Clone)]
+pub struct RenderTarget {
+    pub target: OffscreenTarget,
+    pub size: [u32; 2],
+    pub displays: Vec<Entity>
+}
+
+impl Component for RenderTarget {
+    type Storage = FlaggedStorage<Self, HashMapStorage<Self>>;
+}
+
+impl RenderTarget {
+    pub fn new(target: OffscreenTarget) -> Self {
+        RenderTarget { size: target.size, target, displays: vec![] }
+    }
+
+    pub fn with_display(mut self, display: Entity) -> Self {
+        self.displays.push(display);
+        self
+    }
+}
+
+/// Label floating over the entity, e.g. a nameplate or a damage number
+/// Drawn as a quad facing the camera, so it is readable from every side
+/// and shrinks with distance like the rest of the world, see graphics::text for the font
+#[derive(Component, Clone, Debug)]
+#[storage(HashMapStorage)]
+pub struct WorldText {
+    // lines split at '\n', the last one sits on top of the anchor
+    pub content: String,
+    // from the position of the entity to the anchor, in world space so it doesn't turn with the entity
+    pub offset: Vector3<f32>,
+    // of a line in world units
+    pub height: f32,
+    pub color: [f32; 3]
+}
+
+impl WorldText {
+    pub fn new(content: &str, offset: Vector3<f32>) -> Self {
+        WorldText { content: content.into(), offset, height: 0.25, color: [1.0; 3] }
+    }
+
+    pub fn with_height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 3]) -> Self {
+        self.color = color;
+        self
+    }
+
+    /*
+    Model matrix of the label of an entity at position, turned like the camera so it faces it
+    */
+    pub fn billboard(&self, position: &Vector3<f32>, camera_rotation: &UnitQuaternion<f32>) -> Matrix4<f32> {
+        Matrix4::new_translation(&(position + self.offset))
+            * camera_rotation.to_homogeneous()
+            * Matrix4::new_scaling(self.height)
+    }
+}
+
 #[derive(Component, Debug)]
 #[storage(HashMapStorage)]
 pub struct Camera;
 
+/// Perspective of an AttachedCamera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+    // at the eyes of the target, eye_height above its Transform
+    FirstPerson { eye_height: f32 },
+    // behind the target, distance away and height above it, turning as it looks around
+    ThirdPerson { distance: f32, height: f32 }
+}
+
+/// Moves the entity along with target every frame, so the view of a separate camera
+/// entity follows a character controlled through its Camera and Movement components
+/// Up is the UpVector of the target, +Y without one
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(HashMapStorage)]
+pub struct AttachedCamera {
+    pub target: Entity,
+    pub mode: CameraMode
+}
+
+impl AttachedCamera {
+    /*
+    Transform of the camera for the given transform of the target
+    */
+    pub fn transform_for(&self, target: &Transform, up: &Vector3<f32>) -> Transform {
+        let pos = match self.mode {
+            CameraMode::FirstPerson { eye_height } => target.pos() + up * eye_height,
+            CameraMode::ThirdPerson { distance, height } => target.pos() + up * height - target.forward() * distance
+        };
+        Transform::from_position_rotation(pos, target.rot())
+    }
+}
+
+/// Keeps the entity at target and turns it to face the horizontal direction target moves in,
+/// e.g. the model of a third person character, whose own Transform turns with the camera
+/// offset is relative to target in its UpVector frame, e.g. down to the feet of the capsule
+/// Turns at most turn_speed radians per second and keeps facing the same way while target stands still
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(HashMapStorage)]
+pub struct FaceMovement {
+    pub target: Entity,
+    pub offset: Vector3<f32>,
+    pub turn_speed: f32
+}
+
+impl FaceMovement {
+    /*
+    Rotation facing the movement of the target, None if it doesn't move horizontally
+    */
+    pub fn facing(movement: &Vector3<f32>, alignment: &UnitQuaternion<f32>) -> Option<UnitQuaternion<f32>> {
+        let local = alignment.inverse() * movement;
+        let horizontal = Vector3::new(local.x, 0.0, local.z).try_normalize(f32::EPSILON)?;
+        // rotating -z forward by yaw around y gives (-sin(yaw), 0, -cos(yaw))
+        let yaw = (-horizontal.x).atan2(-horizontal.z);
+        Some(alignment * UnitQuaternion::from_euler_angles(0.0, yaw, 0.0))
+    }
+
+    /*
+    Turns current towards desired by at most max_angle radians
+    */
+    pub fn turn_towards(current: &UnitQuaternion<f32>, desired: &UnitQuaternion<f32>, max_angle: f32) -> UnitQuaternion<f32> {
+        let angle = current.angle_to(desired);
+        if angle <= max_angle {
+            return *desired;
+        }
+        current.try_slerp(desired, max_angle / angle, f32::EPSILON).unwrap_or(*desired)
+    }
+}
+
+// Lights uploaded per frame, has to match the array size in the default and pbr fragment shaders
+pub const MAX_LIGHTS: usize = 16;
+
+/// Light source of the default and pbr shaders, lit surfaces also receive the AmbientLight resource
+/// Point lights are placed at the Transform of the entity and are skipped without one
+/// Only MAX_LIGHTS lights are used per frame, directional lights first and then the point lights
+/// closest to the camera, the rest are ignored
+/// Directional lights are shadowed by the Shadows cascades, which are cast along Shadows::direction
+/// Without any lights the scene is drawn unlit
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(HashMapStorage)]
+pub enum Light {
+    Directional {
+        // direction the light shines in, doesn't have to be normalized
+        direction: Vector3<f32>,
+        color: Vector3<f32>,
+        intensity: f32
+    },
+    Point {
+        color: Vector3<f32>,
+        intensity: f32,
+        // distance at which the light has faded out completely
+        range: f32
+    }
+}
+
+/// Device driving an entity with Movement in PlayerInput, for local co-op
+/// Entities without one are driven by the keyboard and mouse
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[storage(HashMapStorage)]
+pub enum InputSource {
+    #[default]
+    KeyboardMouse,
+    // index of the gamepad, see Gamepads
+    Gamepad(usize)
+}
+
+/// Direction the camera and movement of the entity treat as up, +Y without this component
+/// e.g. the normal of the wall being walked on, or the opposite of a custom gravity direction
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(HashMapStorage)]
+pub struct UpVector(pub Unit<Vector3<f32>>);
+
+impl UpVector {
+    /*
+    Rotation from the default +Y up to this up vector
+    */
+    pub fn alignment(&self) -> UnitQuaternion<f32> {
+        match UnitQuaternion::rotation_between(&Vector3::y(), &self.0) {
+            Some(v) => v,
+            // exactly upside down, any axis in the horizontal plane works
+            None => UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f32::consts::PI)
+        }
+    }
+}
+
 #[derive(Component, Debug, Default)]
 #[storage(HashMapStorage)]
 pub struct Movement {
@@ -108,6 +576,8 @@ pub struct Movement {
     pub slow: f32,
     pub jump: f32,
     pub sensitivity: f32,
+    // moves along the full camera direction instead of the horizontal plane
+    pub fly: bool,
 
     pub yaw: f32,
     pub pitch: f32,
@@ -149,4 +619,274 @@ impl Movement {
 
         self.num_jumps_remaining -= 1;
     }
-}
\ No newline at end of file
+}
+
+/// Animates the texture of a Renderable as a sprite sheet
+/// 
+/// The sheet is the texture of the Renderable on the same entity,
+/// with frames laid out left to right, top to bottom in a grid
+/// of `columns` columns.
+#[derive(Component, Debug)]
+#[storage(HashMapStorage)]
+pub struct SpriteAnimation {
+    pub frames: u32,
+    pub fps: f32,
+    pub columns: u32,
+    // if false, the animation stops on the last frame
+    pub looping: bool,
+
+    pub current_frame: u32,
+    pub elapsed: f32
+}
+
+impl SpriteAnimation {
+    pub fn new(frames: u32, fps: f32, columns: u32, looping: bool) -> Self {
+        SpriteAnimation { frames, fps, columns, looping, current_frame: 0, elapsed: 0.0 }
+    }
+
+    pub fn finished(&self) -> bool {
+        !self.looping && self.current_frame + 1 >= self.frames
+    }
+
+    pub fn advance(&mut self, delta: f32) {
+        if self.frames == 0 || self.fps <= 0.0 || self.finished() {
+            return;
+        }
+
+        self.elapsed += delta;
+        let frame_time = 1.0 / self.fps;
+
+        while self.elapsed >= frame_time {
+            self.elapsed -= frame_time;
+
+            if self.current_frame + 1 < self.frames {
+                self.current_frame += 1;
+            }
+            else if self.looping {
+                self.current_frame = 0;
+            }
+            else {
+                self.elapsed = 0.0;
+                break;
+            }
+        }
+    }
+
+    /*
+    Returns the uv sub-rect of the current frame as [offset_x, offset_y, scale_x, scale_y]
+    */
+    pub fn uv_transform(&self) -> [f32; 4] {
+        let columns = self.columns.max(1);
+        let rows = ((self.frames + columns - 1) / columns).max(1);
+
+        let column = self.current_frame % columns;
+        let row = self.current_frame / columns;
+
+        let (scale_x, scale_y) = (1.0 / columns as f32, 1.0 / rows as f32);
+        [column as f32 * scale_x, row as f32 * scale_y, scale_x, scale_y]
+    }
+}
+
+/// Replicated from the server to clients by the network HealthSender system while a NetworkData resource exists,
+/// for entities with a NetworkReplicated component
+#[derive(Component, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Health { current: max, max }
+    }
+
+    pub fn damage(&mut self, amount: f32) {
+        self.current = (self.current - amount).max(0.0);
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+        self.current = (self.current + amount).min(self.max);
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+/// Regenerates Health at rate per second once no damage was taken for delay seconds
+/// Damage is detected from Health going down, so there is nothing to call when dealing it
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct HealthRegen {
+    pub rate: f32,
+    // in seconds
+    pub delay: f32,
+    since_damage: f32,
+    last_health: Option<f32>
+}
+
+impl HealthRegen {
+    pub fn new(rate: f32, delay: f32) -> Self {
+        HealthRegen { rate, delay, since_damage: 0.0, last_health: None }
+    }
+
+    pub fn regenerate(&mut self, health: &mut Health, delta: f32) {
+        if self.last_health.map_or(false, |last| health.current < last) {
+            self.since_damage = 0.0;
+        }
+        else {
+            self.since_damage += delta;
+        }
+
+        // the dead stay dead
+        if self.since_damage >= self.delay && !health.is_dead() {
+            health.heal(self.rate * delta);
+        }
+
+        self.last_health = Some(health.current);
+    }
+}
+
+/// The entity is deleted once remaining reaches zero, along with its rigid body
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct Lifetime {
+    // in seconds
+    pub remaining: f32
+}
+
+impl Lifetime {
+    pub fn new(seconds: f32) -> Self {
+        Lifetime { remaining: seconds }
+    }
+}
+
+/// Lets systems run per entity logic at a lower rate than every frame
+/// 
+/// The UpdateScheduler system advances the accumulator and marks the entity due
+/// once per interval, systems then skip entities which are not due.
+/// The scheduler runs at the end of the frame, so the flag is seen the frame after.
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct UpdateEvery {
+    // in seconds
+    pub interval: f32,
+    pub accumulator: f32,
+    due: bool
+}
+
+impl UpdateEvery {
+    pub fn new(interval: f32) -> Self {
+        UpdateEvery { interval, accumulator: 0.0, due: false }
+    }
+
+    /*
+    Starts at a random point of the interval, so entities created
+    at the same time don't all update on the same frame
+    */
+    pub fn staggered(interval: f32, random: &mut RandomSource) -> Self {
+        UpdateEvery { interval, accumulator: random.range_f32(0.0, interval), due: false }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.due
+    }
+
+    pub fn advance(&mut self, delta: f32) {
+        self.accumulator += delta;
+        self.due = self.accumulator >= self.interval;
+
+        if self.due {
+            // keeping the remainder so the rate doesn't drift,
+            // but not catching up on more than one missed update
+            self.accumulator = (self.accumulator - self.interval).min(self.interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::{World, WorldExt, Builder};
+
+    use super::*;
+
+    #[test]
+    fn transformation_matrix_follows_the_setters() {
+        let mut transform = Transform::from_position(Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(transform.transformation_matrix(), Matrix4::new_translation(&Vector3::new(1.0, 2.0, 3.0)));
+
+        transform.set_pos(Vector3::zeros());
+        transform.set_scale(Vector3::new(2.0, 2.0, 2.0));
+        assert_eq!(transform.transformation_matrix(), Matrix4::new_scaling(2.0));
+
+        let rot = UnitQuaternion::from_euler_angles(0.0, 0.5, 0.0);
+        transform.set_rot(rot);
+        assert_eq!(transform.transformation_matrix(), rot.to_homogeneous() * Matrix4::new_scaling(2.0));
+        // movement only moves the entity once physics sets the position
+        transform.apply_movement(&Vector3::x());
+        assert_eq!(transform.transformation_matrix(), rot.to_homogeneous() * Matrix4::new_scaling(2.0));
+    }
+
+    #[test]
+    fn world_text_faces_the_camera() {
+        let label = WorldText::new("P1", Vector3::new(0.0, 2.0, 0.0)).with_height(0.5);
+        let camera = Transform::from_position_rotation(Vector3::new(4.0, 3.0, 6.0), UnitQuaternion::from_euler_angles(-0.3, 0.8, 0.0));
+        let model = label.billboard(&Vector3::new(1.0, 0.0, 1.0), &camera.rot);
+
+        // the front of the quad points back at the camera, its top stays up on screen
+        let normal = model.transform_vector(&Vector3::z()).normalize();
+        assert!((normal + camera.forward()).norm() < 1e-5);
+        assert!((model.transform_vector(&Vector3::y()) - camera.up() * 0.5).norm() < 1e-5);
+        assert_eq!(model.transform_point(&nalgebra::Point3::origin()).coords, Vector3::new(1.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn third_person_camera_stays_behind_the_target() {
+        let target = Transform::from_position_rotation(Vector3::new(1.0, 0.0, 2.0), UnitQuaternion::from_euler_angles(0.0, 1.2, 0.0));
+        let target_entity = World::new().create_entity().build();
+        let attached = AttachedCamera { target: target_entity, mode: CameraMode::ThirdPerson { distance: 4.0, height: 1.5 } };
+        let camera = attached.transform_for(&target, &Vector3::y());
+
+        // looking where the target looks, from above and behind it
+        assert_eq!(camera.rot, target.rot);
+        let offset = camera.pos - target.pos;
+        assert!((offset.dot(&Vector3::y()) - 1.5).abs() < 1e-5);
+        assert!((offset.dot(&target.forward()) + 4.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn facing_ignores_vertical_movement() {
+        let up = UnitQuaternion::identity();
+        assert!(FaceMovement::facing(&Vector3::zeros(), &up).is_none());
+        assert!(FaceMovement::facing(&Vector3::new(0.0, -3.0, 0.0), &up).is_none());
+
+        let facing = FaceMovement::facing(&Vector3::new(2.0, 5.0, 0.0), &up).unwrap();
+        let forward = Transform::from_position_rotation(Vector3::zeros(), facing).forward();
+        assert!((forward - Vector3::x()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn facing_stays_upright_on_walls() {
+        // walking on a wall whose normal is +x, its movement along x is off the ground
+        let alignment = UpVector(Vector3::x_axis()).alignment();
+        let facing = FaceMovement::facing(&Vector3::new(5.0, 0.0, 2.0), &alignment).unwrap();
+        let turned = Transform::from_position_rotation(Vector3::zeros(), facing);
+        assert!((turned.up() - Vector3::x()).norm() < 1e-5);
+        assert!((turned.forward() - Vector3::z()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn turning_is_limited_by_the_turn_speed() {
+        let current = UnitQuaternion::identity();
+        let desired = UnitQuaternion::from_euler_angles(0.0, std::f32::consts::FRAC_PI_2, 0.0);
+        let (turn_speed, delta) = (3.0, 0.01);
+
+        let turned = FaceMovement::turn_towards(&current, &desired, turn_speed * delta);
+        assert!((current.angle_to(&turned) - turn_speed * delta).abs() < 1e-5);
+        assert!((turned.angle_to(&desired) - (std::f32::consts::FRAC_PI_2 - turn_speed * delta)).abs() < 1e-5);
+
+        // the last step doesn't overshoot
+        assert_eq!(FaceMovement::turn_towards(&turned, &desired, 10.0), desired);
+    }
+}
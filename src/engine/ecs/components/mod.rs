@@ -1,3 +1,4 @@
+pub mod audio;
 pub mod general;
 pub mod physics;
 pub mod network;
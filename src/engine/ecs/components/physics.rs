@@ -2,7 +2,7 @@ use std::{sync::Arc, ops::Mul};
 
 use log::{error, warn};
 use nalgebra::{Matrix4, Vector3, Quaternion, Isometry, UnitQuaternion, Point3};
-use rapier3d::{prelude::{RigidBodyHandle, RigidBody, Collider, ColliderHandle, QueryFilter, Real, ShapeType}, control::KinematicCharacterController};
+use rapier3d::{prelude::{RigidBodyHandle, RigidBody, RigidBodyType, Collider, ColliderHandle, QueryFilter, Real, ShapeType, ActiveEvents, ActiveCollisionTypes}, control::KinematicCharacterController};
 use specs::{Component, VecStorage, HashMapStorage};
 use vulkano::buffer::CpuAccessibleBuffer;
 
@@ -14,21 +14,36 @@ use crate::{ecs::resources::physics::PhysicsData, data_structures::graphics::Ver
 pub struct RigidBodyComponent {
     pub handle: RigidBodyHandle,
     pub grounded: bool,
-    ccontrol: Option<KinematicCharacterController>
+    ccontrol: Option<KinematicCharacterController>,
+    // None if not frozen
+    frozen: Option<FrozenState>
+}
+
+// What a rigid body looked like before freezing, fixed bodies lose their velocity
+#[derive(Clone, Copy, Debug)]
+struct FrozenState {
+    body_type: RigidBodyType,
+    linvel: Vector3<Real>,
+    angvel: Vector3<Real>
 }
 
 impl RigidBodyComponent {
-    pub fn new(rigid_body: RigidBody, physics_data: &mut PhysicsData, character_controller: Option<KinematicCharacterController>) -> Self {
+    /*
+    Inserts the rigid body into the physics world with the sleep thresholds of physics_data
+    */
+    pub fn new(mut rigid_body: RigidBody, physics_data: &mut PhysicsData, character_controller: Option<KinematicCharacterController>) -> Self {
         if character_controller.is_some() && !rigid_body.is_kinematic() {
             warn!("KinematicCharacterController is set but rigid body is not set to kinematic, this rigid body will not move!");
         }
 
-        let handle = physics_data.rigid_body_set.insert(rigid_body);
-        RigidBodyComponent { handle, grounded: false, ccontrol: character_controller }
+        physics_data.sleep_thresholds().apply(&mut rigid_body);
+
+        let handle = physics_data.insert_rigid_body(rigid_body);
+        RigidBodyComponent { handle, grounded: false, ccontrol: character_controller, frozen: None }
     }
 
     pub fn transformation_matrix(&self, physics_data: &PhysicsData) -> Matrix4<f32> {
-        let rigid_body = physics_data.rigid_body_set.get(self.handle);
+        let rigid_body = physics_data.rigid_body(self.handle);
 
         match rigid_body {
             Some(v) => {
@@ -45,7 +60,7 @@ impl RigidBodyComponent {
     }
 
     pub fn position(&self, physics_data: &PhysicsData) -> Isometry<f32, nalgebra::Unit<Quaternion<f32>>, 3> {
-        let rigid_body = physics_data.rigid_body_set.get(self.handle);
+        let rigid_body = physics_data.rigid_body(self.handle);
 
         match rigid_body {
             Some(v) => {
@@ -58,6 +73,135 @@ impl RigidBodyComponent {
         }
     }
 
+    /*
+    Moves the rigid body to the given position and zeroes its velocity
+    Kinematic bodies also get their next kinematic position reset, otherwise
+    the next physics step would interpolate them back
+    */
+    pub fn teleport(&self, position: Isometry<f32, nalgebra::Unit<Quaternion<f32>>, 3>, physics_data: &mut PhysicsData) {
+        let rigid_body = match physics_data.rigid_body_mut(self.handle) {
+            Some(v) => v,
+            None => return error!("Could not find entity with handle: {:?}", self.handle)
+        };
+
+        rigid_body.set_position(position, true);
+        if rigid_body.is_kinematic() {
+            rigid_body.set_next_kinematic_position(position);
+        }
+        rigid_body.set_linvel(Vector3::zeros(), true);
+        rigid_body.set_angvel(Vector3::zeros(), true);
+    }
+
+    /*
+    Changes the velocity instantly, e.g. for launching projectiles or knockback
+    Only dynamic bodies can be pushed around, others are left untouched with a warning
+    */
+    pub fn apply_impulse(&self, impulse: Vector3<f32>, physics_data: &mut PhysicsData) {
+        if let Some(v) = self.dynamic_body_mut(physics_data, "apply an impulse to") {
+            v.apply_impulse(impulse, true);
+        }
+    }
+
+    /*
+    Adds a force which rapier keeps applying every step until reset_forces is called,
+    for one-off pushes use apply_impulse instead
+    */
+    pub fn apply_force(&self, force: Vector3<f32>, physics_data: &mut PhysicsData) {
+        if let Some(v) = self.dynamic_body_mut(physics_data, "apply a force to") {
+            v.add_force(force, true);
+        }
+    }
+
+    pub fn reset_forces(&self, physics_data: &mut PhysicsData) {
+        if let Some(v) = self.dynamic_body_mut(physics_data, "reset the forces of") {
+            v.reset_forces(true);
+        }
+    }
+
+    pub fn set_linvel(&self, linvel: Vector3<f32>, physics_data: &mut PhysicsData) {
+        if let Some(v) = self.dynamic_body_mut(physics_data, "set the velocity of") {
+            v.set_linvel(linvel, true);
+        }
+    }
+
+    // The rigid body if it is dynamic, warns with action otherwise
+    fn dynamic_body_mut<'a>(&self, physics_data: &'a mut PhysicsData, action: &str) -> Option<&'a mut RigidBody> {
+        let rigid_body = match physics_data.rigid_body_mut(self.handle) {
+            Some(v) => v,
+            None => {
+                error!("Could not find entity with handle: {:?}", self.handle);
+                return None;
+            }
+        };
+
+        if !rigid_body.is_dynamic() {
+            warn!("Tried to {action} {:?} rigid body {:?}, only dynamic bodies can be moved this way", rigid_body.body_type(), self.handle);
+            return None;
+        }
+
+        Some(rigid_body)
+    }
+
+    /*
+    Puts the rigid body to sleep or wakes it up
+    A sleeping body wakes up again by itself when something touches it,
+    use freeze to keep it in place regardless
+    */
+    pub fn set_sleeping(&self, sleeping: bool, physics_data: &mut PhysicsData) {
+        let rigid_body = match physics_data.rigid_body_mut(self.handle) {
+            Some(v) => v,
+            None => return error!("Could not find entity with handle: {:?}", self.handle)
+        };
+
+        match sleeping {
+            true => rigid_body.sleep(),
+            false => rigid_body.wake_up(true)
+        }
+    }
+
+    /*
+    Turns the rigid body fixed until unfreeze is called, which restores its original type
+    The velocity is kept, so the body continues moving as before when unfrozen
+    */
+    pub fn freeze(&mut self, physics_data: &mut PhysicsData) {
+        if self.frozen.is_some() {
+            return;
+        }
+
+        let rigid_body = match physics_data.rigid_body_mut(self.handle) {
+            Some(v) => v,
+            None => return error!("Could not find entity with handle: {:?}", self.handle)
+        };
+
+        self.frozen = Some(FrozenState { 
+            body_type: rigid_body.body_type(), 
+            linvel: *rigid_body.linvel(), 
+            angvel: *rigid_body.angvel() 
+        });
+        rigid_body.set_body_type(RigidBodyType::Fixed, false);
+    }
+
+    pub fn unfreeze(&mut self, physics_data: &mut PhysicsData) {
+        let frozen = match self.frozen {
+            Some(v) => v,
+            None => return
+        };
+
+        let rigid_body = match physics_data.rigid_body_mut(self.handle) {
+            Some(v) => v,
+            None => return error!("Could not find entity with handle: {:?}", self.handle)
+        };
+
+        rigid_body.set_body_type(frozen.body_type, true);
+        rigid_body.set_linvel(frozen.linvel, true);
+        rigid_body.set_angvel(frozen.angvel, true);
+        self.frozen = None;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
     pub fn has_character_controller(&self) -> bool {
         self.ccontrol.is_some()
     }
@@ -66,7 +210,7 @@ impl RigidBodyComponent {
     Applies movement if this component has a KinematicCharacterController
     */
     pub fn apply_movement(&self, movement: &Vector3<f32>, velocity: &Vector3<f32>, acceleration: &Vector3<f32>, rotation: Option<&UnitQuaternion<Real>>, dt: f32, collider: &ColliderComponent, physics_data: &mut PhysicsData) -> Option<bool> {
-        let cc = match self.ccontrol {
+        let mut cc = match self.ccontrol {
             Some(v) => v,
             None => {
                 error!("Tried to apply movement to a RigidBodyComponent which has no KinematicCharacterController");
@@ -74,7 +218,7 @@ impl RigidBodyComponent {
             }
         };
 
-        let collider = match physics_data.collider_set.get(collider.handle) {
+        let collider = match physics_data.collider(collider.handle) {
             Some(v) => v,
             None => {
                 error!("Could not find collider with handle {:?}", collider.handle);
@@ -82,20 +226,23 @@ impl RigidBodyComponent {
             }
         };
 
+        // grounded means standing on something against the current gravity, whichever way it points
+        if let Some(up) = physics_data.up() {
+            cc.up = up;
+        }
+
         let mut position = self.position(physics_data);
         let accel_gravity = acceleration + physics_data.gravity * 5.0;
         let desired_translation = movement + velocity * dt + 0.5 * accel_gravity * dt * dt;
 
-        let corrected_movement = cc.move_shape(
+        let corrected_movement = physics_data.move_character(
+            &cc,
             dt, 
-            &physics_data.rigid_body_set, 
-            &physics_data.collider_set,
-            &physics_data.query_pipeline, 
             collider.shape(), 
             &position, 
             desired_translation, 
-            QueryFilter::default().exclude_rigid_body(self.handle), 
-            |_| {}
+            // sensors don't block, the character has to move into them to trigger them
+            QueryFilter::default().exclude_sensors().exclude_rigid_body(self.handle)
         );
         
         position.append_translation_mut(&corrected_movement.translation.into());
@@ -105,7 +252,7 @@ impl RigidBodyComponent {
             None => ()
         }
         
-        match physics_data.rigid_body_set.get_mut(self.handle) {
+        match physics_data.rigid_body_mut(self.handle) {
             Some(v) => v.set_next_kinematic_position(position),
             None => error!("Was unable to get rigid body with handle {:?}", self.handle)
         }
@@ -115,6 +262,9 @@ impl RigidBodyComponent {
 }
 
 
+// Number of subdivisions used for the debug meshes of round colliders
+pub const DEFAULT_SUBDIVISIONS: u32 = 8;
+
 #[derive(Component, Default, Debug)]
 #[storage(VecStorage)]
 pub struct ColliderComponent {
@@ -123,15 +273,45 @@ pub struct ColliderComponent {
 
 impl ColliderComponent {
     pub fn new(collider: Collider, parent_handle: Option<&RigidBodyHandle>, physics_data: &mut PhysicsData) -> Self {
-        let handle = match parent_handle {
-            Some(v) => physics_data.collider_set.insert_with_parent(collider, *v, &mut physics_data.rigid_body_set),
-            None => physics_data.collider_set.insert(collider)
-        };
+        let handle = physics_data.insert_collider(collider, parent_handle.copied());
         ColliderComponent { handle }
     }
 
+    /*
+    Same as new, but turns the collider into a sensor if sensor is true
+    Sensors don't push anything away, they only report when other colliders start and stop
+    overlapping them through the CollisionEvents resource, e.g. for trigger zones or pickups
+
+    Besides the sensor flag this enables collision events and every collision type on the collider,
+    rapier skips pairs of kinematic and fixed colliders by default, which would keep
+    kinematic characters from triggering sensors without a rigid body
+    */
+    pub fn with_sensor(mut collider: Collider, sensor: bool, parent_handle: Option<&RigidBodyHandle>, physics_data: &mut PhysicsData) -> Self {
+        if sensor {
+            collider.set_sensor(true);
+            collider.set_active_events(collider.active_events() | ActiveEvents::COLLISION_EVENTS);
+            collider.set_active_collision_types(ActiveCollisionTypes::all());
+        }
+        Self::new(collider, parent_handle, physics_data)
+    }
+
+    pub fn handle(&self) -> ColliderHandle {
+        self.handle
+    }
+
+    pub fn is_sensor(&self, physics_data: &PhysicsData) -> bool {
+        physics_data.collider(self.handle).map_or(false, |c| c.is_sensor())
+    }
+
     pub fn get_vertices(&self, physics_data: &PhysicsData) -> (Vec<Point3<Real>>, Vec<u32>) {
-        let collider = match physics_data.collider_set.get(self.handle) {
+        self.get_vertices_subdivided(physics_data, DEFAULT_SUBDIVISIONS)
+    }
+
+    /*
+    Same as get_vertices, but with a configurable number of subdivisions for round shapes
+    */
+    pub fn get_vertices_subdivided(&self, physics_data: &PhysicsData, subdivisions: u32) -> (Vec<Point3<Real>>, Vec<u32>) {
+        let collider = match physics_data.collider(self.handle) {
             Some(v) => v,
             None => {
                 error!("Failed to get collider with handle {:?}", self.handle);
@@ -139,10 +319,10 @@ impl ColliderComponent {
             }
         };
 
-        const SUBDIV: u32 = 8;
+        let subdiv = subdivisions.max(3);
         let (points, indices) = match collider.shape().shape_type() {
-            ShapeType::Ball => collider.shape().as_ball().unwrap().to_trimesh(SUBDIV, SUBDIV),
-            ShapeType::Capsule => collider.shape().as_capsule().unwrap().to_trimesh(SUBDIV, SUBDIV),
+            ShapeType::Ball => collider.shape().as_ball().unwrap().to_trimesh(subdiv, subdiv),
+            ShapeType::Capsule => collider.shape().as_capsule().unwrap().to_trimesh(subdiv, subdiv),
             ShapeType::Cuboid => collider.shape().as_cuboid().unwrap().to_trimesh(),
             ShapeType::HeightField => collider.shape().as_heightfield().unwrap().to_trimesh(),
             s => {
@@ -162,18 +342,155 @@ pub struct ColliderRenderable {
     pub index_buffer: Arc<CpuAccessibleBuffer<[u32]>>
 }
 
+// Debug mesh colors by collider type
+pub const COLLIDER_COLOR_SENSOR: [f32; 3] = [0.0, 1.0, 0.0];
+pub const COLLIDER_COLOR_FIXED: [f32; 3] = [0.5, 0.5, 0.5];
+pub const COLLIDER_COLOR_KINEMATIC: [f32; 3] = [0.0, 0.5, 1.0];
+pub const COLLIDER_COLOR_DYNAMIC: [f32; 3] = [1.0, 0.0, 0.0];
+
 impl ColliderRenderable {
     pub fn convert_to_vertex(vertices: Vec<Point3<Real>>) -> Vec<Vertex> {
+        ColliderRenderable::convert_to_vertex_colored(vertices, COLLIDER_COLOR_DYNAMIC)
+    }
+
+    pub fn convert_to_vertex_colored(vertices: Vec<Point3<Real>>, color: [f32; 3]) -> Vec<Vertex> {
         vertices
             .iter()
             .map(|v| {
                 Vertex {
                     position: v.coords.into(),
                     normal: [0.0, 0.0, 0.0],
-                    color: [1.0, 0.0, 0.0],
-                    tex_coord: [0.0, 0.0]
+                    color,
+                    tex_coord: [0.0, 0.0],
+                    tex_coord2: [0.0, 0.0]
                 }
             })
             .collect()
     }
+
+    /*
+    Picks a color based on the type of the collider, sensors are always COLLIDER_COLOR_SENSOR
+    and other colliders take the color of the type of their rigid body
+    */
+    pub fn debug_color(collider: &ColliderComponent, physics_data: &PhysicsData) -> [f32; 3] {
+        let collider = match physics_data.collider(collider.handle) {
+            Some(v) => v,
+            None => {
+                error!("Could not find collider with handle {:?}", collider.handle);
+                return COLLIDER_COLOR_DYNAMIC;
+            }
+        };
+
+        if collider.is_sensor() {
+            return COLLIDER_COLOR_SENSOR;
+        }
+
+        match collider.parent().and_then(|p| physics_data.rigid_body(p)).map(|r| r.body_type()) {
+            Some(RigidBodyType::Dynamic) => COLLIDER_COLOR_DYNAMIC,
+            Some(RigidBodyType::KinematicPositionBased) | Some(RigidBodyType::KinematicVelocityBased) => COLLIDER_COLOR_KINEMATIC,
+            // colliders without a rigid body can't move either
+            Some(RigidBodyType::Fixed) | None => COLLIDER_COLOR_FIXED
+        }
+    }
+}
+
+/// Pulls dynamic rigid bodies within radius of the entity's Transform towards it, e.g. a planet or a magnet
+/// strength is the force at a distance of 1 for inverse square falloff and at the center for linear falloff
+/// A negative strength pushes bodies away instead
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(HashMapStorage)]
+pub struct Attractor {
+    pub strength: f32,
+    pub radius: f32,
+    pub falloff: AttractorFalloff
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum AttractorFalloff {
+    // like gravity, cut off at the radius
+    #[default]
+    InverseSquare,
+    // fades out to nothing at the radius
+    Linear
+}
+
+// Inverse square pull is capped at this distance, so bodies at the center aren't flung away
+const MIN_ATTRACTOR_DISTANCE: f32 = 0.1;
+
+impl Attractor {
+    /*
+    Force towards the attractor at the given distance
+    */
+    pub fn force_at(&self, distance: f32) -> f32 {
+        if distance > self.radius {
+            return 0.0;
+        }
+
+        match self.falloff {
+            AttractorFalloff::InverseSquare => self.strength / distance.max(MIN_ATTRACTOR_DISTANCE).powi(2),
+            AttractorFalloff::Linear => self.strength * (1.0 - distance / self.radius.max(f32::EPSILON))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ecs::utils::{collision::CollisionEventCollector, objects::{create_character, create_trigger_zone, CapsuleSize}};
+
+    use super::*;
+
+    #[test]
+    fn characters_walk_into_sensors() {
+        let mut physics_data = PhysicsData::default();
+        physics_data.set_gravity(Vector3::zeros());
+        let size = CapsuleSize { half_height: 0.5, radius: 0.5 };
+        let (_, rigid_body, collider) = create_character(&mut physics_data, size, Vector3::zeros(), KinematicCharacterController::default());
+        let (_, zone) = create_trigger_zone(&mut physics_data, Vector3::new(1.0, 2.0, 1.0), Vector3::new(3.0, 1.0, 0.0));
+
+        let collector = CollisionEventCollector::default();
+        let mut events = Vec::new();
+        for _ in 0..20 {
+            physics_data.step(0.1, &collector);
+            rigid_body.apply_movement(&Vector3::new(0.25, 0.0, 0.0), &Vector3::zeros(), &Vector3::zeros(), None, 0.1, &collider, &mut physics_data);
+            events.extend(collector.drain());
+        }
+
+        // past the zone's far side, so it wasn't stopped at the near one
+        assert!(rigid_body.position(&physics_data).translation.x > 4.0);
+        let entered = events.iter()
+            .find(|e| e.started() && (e.collider1() == zone.handle() || e.collider2() == zone.handle()))
+            .expect("the character should have entered the zone");
+        assert!(entered.sensor());
+    }
+
+    #[test]
+    fn inverse_square_falloff_is_cut_off_at_the_radius() {
+        let attractor = Attractor { strength: 8.0, radius: 10.0, falloff: AttractorFalloff::InverseSquare };
+        assert_eq!(attractor.force_at(1.0), 8.0);
+        assert_eq!(attractor.force_at(2.0), 2.0);
+        assert_eq!(attractor.force_at(10.0), 0.08);
+        assert_eq!(attractor.force_at(10.5), 0.0);
+    }
+
+    #[test]
+    fn inverse_square_falloff_is_capped_near_the_center() {
+        let attractor = Attractor { strength: 1.0, radius: 10.0, falloff: AttractorFalloff::InverseSquare };
+        let capped = attractor.force_at(MIN_ATTRACTOR_DISTANCE);
+        assert!((capped - 100.0).abs() < 1e-3);
+        assert_eq!(attractor.force_at(0.0), capped);
+        assert_eq!(attractor.force_at(MIN_ATTRACTOR_DISTANCE / 2.0), capped);
+    }
+
+    #[test]
+    fn linear_falloff_fades_out_at_the_radius() {
+        let attractor = Attractor { strength: 4.0, radius: 2.0, falloff: AttractorFalloff::Linear };
+        assert_eq!(attractor.force_at(0.0), 4.0);
+        assert_eq!(attractor.force_at(1.0), 2.0);
+        assert_eq!(attractor.force_at(2.0), 0.0);
+        assert_eq!(attractor.force_at(3.0), 0.0);
+
+        // pushing away fades out the same way
+        let repeller = Attractor { strength: -4.0, ..attractor };
+        assert_eq!(repeller.force_at(1.0), -2.0);
+    }
 }
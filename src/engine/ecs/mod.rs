@@ -1,8 +1,8 @@
 use specs::{World, WorldExt};
 
-use crate::ecs::components::general::{Transform, Renderable};
+use crate::ecs::components::general::{Transform, Renderable, PbrMaterial, StreamedTexture};
 
-use self::components::{general::{Camera, Movement, Wireframe}, physics::{RigidBodyComponent, ColliderComponent, ColliderRenderable}};
+use self::components::{audio::CollisionSound, general::{Camera, AttachedCamera, FaceMovement, Light, Movement, Wireframe, DoubleSided, SpriteAnimation, NoDepthTest, DepthBias, Transparent, Health, HealthRegen, Lifetime, InputSource, WireframeOverlay, OcclusionCulled, RenderLayers, Minimap, RenderTarget, WorldText, UpdateEvery, UpVector}, physics::{RigidBodyComponent, ColliderComponent, ColliderRenderable, Attractor}, network::NetworkReplicated};
 
 pub mod components;
 pub mod resources;
@@ -23,11 +23,36 @@ impl ECS {
     fn register_components(world: &mut World) {
         world.register::<Transform>();
         world.register::<Renderable>();
+        world.register::<PbrMaterial>();
+        world.register::<StreamedTexture>();
         world.register::<Camera>();
+        world.register::<AttachedCamera>();
+        world.register::<FaceMovement>();
+        world.register::<Light>();
         world.register::<Movement>();
+        world.register::<InputSource>();
+        world.register::<UpVector>();
         world.register::<RigidBodyComponent>();
         world.register::<ColliderComponent>();
+        world.register::<Attractor>();
         world.register::<Wireframe>();
+        world.register::<DoubleSided>();
+        world.register::<NoDepthTest>();
+        world.register::<DepthBias>();
+        world.register::<Transparent>();
+        world.register::<WireframeOverlay>();
+        world.register::<OcclusionCulled>();
+        world.register::<RenderLayers>();
+        world.register::<Minimap>();
+        world.register::<RenderTarget>();
+        world.register::<WorldText>();
         world.register::<ColliderRenderable>();
+        world.register::<SpriteAnimation>();
+        world.register::<Health>();
+        world.register::<HealthRegen>();
+        world.register::<Lifetime>();
+        world.register::<UpdateEvery>();
+        world.register::<CollisionSound>();
+        world.register::<NetworkReplicated>();
     }
 }
@@ -0,0 +1,47 @@
+use nalgebra::Point3;
+
+
+#[derive(Clone, Debug)]
+pub struct SoundRequest {
+    pub sound: String,
+    // in world space
+    pub position: Point3<f32>,
+    // 0.0 - 1.0
+    pub volume: f32
+}
+
+/// Sounds requested during the current frame
+/// 
+/// Drained by the engine every frame after collision sounds are queued,
+/// each request is handed to the SoundPlayer
+#[derive(Default)]
+pub struct SoundQueue(pub Vec<SoundRequest>);
+
+impl SoundQueue {
+    pub fn play(&mut self, sound: &str, position: Point3<f32>, volume: f32) {
+        self.0.push(SoundRequest { sound: sound.to_string(), position, volume: volume.clamp(0.0, 1.0) })
+    }
+
+    pub fn drain(&mut self) -> Vec<SoundRequest> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// Plays the sounds drained from SoundQueue
+/// 
+/// The engine has no audio output of its own, the game registers whatever plays the sounds.
+/// Requests are dropped when nothing is registered
+#[derive(Default)]
+pub struct SoundPlayer(Vec<Box<dyn Fn(&SoundRequest) + Send + Sync>>);
+
+impl SoundPlayer {
+    pub fn register(&mut self, player: impl Fn(&SoundRequest) + Send + Sync + 'static) {
+        self.0.push(Box::new(player));
+    }
+
+    pub fn play(&self, request: &SoundRequest) {
+        for player in self.0.iter() {
+            player(request);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::marker::PhantomData;
+
+use log::warn;
+use specs::World;
+
+/// Queue of gameplay events of type T, e.g. a PlayerDied event written by a damage system
+/// and read by any number of other systems without them knowing about each other
+///
+/// Double buffered: update, called by the engine once per frame for every type registered
+/// with HawkEngine::add_event, drops the events of the previous frame and keeps the current ones.
+/// Every event can therefore be read during the frame it was sent in and the one after,
+/// so readers see it no matter if they run before or after the writer.
+/// Each reading system keeps its own EventReader, which remembers what it has already seen.
+pub struct Events<T> {
+    previous: Vec<T>,
+    current: Vec<T>,
+    // id of the first event in previous, ids keep counting up across updates
+    start: usize
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self { previous: Vec::new(), current: Vec::new(), start: 0 }
+    }
+}
+
+impl<T> Events<T> {
+    pub fn send(&mut self, event: T) {
+        self.current.push(event);
+    }
+
+    /*
+    Drops the events of the previous frame, the current ones become the previous
+    Events which no reader has seen by then are lost
+    */
+    pub fn update(&mut self) {
+        self.start += self.previous.len();
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /*
+    A reader which only sees events sent from now on
+    EventReader::default sees every event still buffered instead
+    */
+    pub fn reader(&self) -> EventReader<T> {
+        EventReader { next: self.end(), phantom: PhantomData }
+    }
+
+    /*
+    Every buffered event, oldest first, regardless of any reader
+    */
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.previous.iter().chain(self.current.iter())
+    }
+
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.update();
+        self.update();
+    }
+
+    // id the next sent event will get
+    fn end(&self) -> usize {
+        self.start + self.previous.len() + self.current.len()
+    }
+}
+
+impl<T: Send + Sync + 'static> Events<T> {
+    /*
+    Updates the Events<T> resource of world, does nothing if it doesn't exist
+    Registered by HawkEngine::add_event to run every frame
+    */
+    pub fn update_in(world: &World) {
+        if let Some(mut events) = world.try_fetch_mut::<Events<T>>() {
+            events.update();
+        }
+    }
+}
+
+/// Position of a single system in an Events<T> queue, kept in the system's struct
+pub struct EventReader<T> {
+    // id of the first event not read yet
+    next: usize,
+    phantom: PhantomData<fn() -> T>
+}
+
+impl<T> Default for EventReader<T> {
+    fn default() -> Self {
+        Self { next: 0, phantom: PhantomData }
+    }
+}
+
+impl<T> EventReader<T> {
+    /*
+    Returns every event which this reader hasn't seen yet, oldest first
+    */
+    pub fn read<'a>(&mut self, events: &'a Events<T>) -> impl Iterator<Item = &'a T> {
+        // a default reader starting out only sees what is still buffered, that's expected
+        if self.next != 0 && self.next < events.start {
+            warn!("Event reader missed {} events of {}, it has to read at least once per frame", events.start - self.next, std::any::type_name::<T>());
+        }
+
+        let skip = self.next.saturating_sub(events.start);
+        self.next = events.end();
+        events.iter().skip(skip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(reader: &mut EventReader<u32>, events: &Events<u32>) -> Vec<u32> {
+        reader.read(events).copied().collect()
+    }
+
+    #[test]
+    fn readers_in_the_same_frame_see_the_same_events() {
+        let mut events = Events::default();
+        let mut first = events.reader();
+        let mut second = events.reader();
+
+        events.send(1);
+        events.send(2);
+        assert_eq!(read_all(&mut first, &events), vec![1, 2]);
+        assert_eq!(read_all(&mut second, &events), vec![1, 2]);
+
+        // nothing is read twice, even while the events stay buffered
+        assert!(read_all(&mut first, &events).is_empty());
+        events.update();
+        assert!(read_all(&mut second, &events).is_empty());
+    }
+
+    #[test]
+    fn readers_running_before_the_writer_see_events_the_next_frame() {
+        let mut events = Events::default();
+        let mut reader = events.reader();
+
+        assert!(read_all(&mut reader, &events).is_empty());
+        events.send(1);
+        events.update();
+
+        events.send(2);
+        assert_eq!(read_all(&mut reader, &events), vec![1, 2]);
+    }
+
+    #[test]
+    fn lagging_readers_only_see_what_is_still_buffered() {
+        let mut events = Events::default();
+        let mut reader = events.reader();
+
+        events.send(1);
+        events.update();
+        events.send(2);
+        events.update();
+        events.send(3);
+        events.update();
+        events.send(4);
+
+        // 1 was dropped two updates ago, 2 with the last one
+        assert_eq!(read_all(&mut reader, &events), vec![3, 4]);
+        assert!(read_all(&mut reader, &events).is_empty());
+
+        events.send(5);
+        assert_eq!(read_all(&mut reader, &events), vec![5]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+// Stick deflection below this is treated as centered, worn sticks rarely rest at exactly 0
+pub const STICK_DEAD_ZONE: f32 = 0.15;
+
+/// State of every connected gamepad, updated before each frame
+/// Gamepads are identified by the index they were given when connected, see InputSource::Gamepad
+#[derive(Default)]
+pub struct Gamepads {
+    pads: HashMap<usize, GamepadState>
+}
+
+/// Buttons and axes of a single gamepad
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    held: HashSet<Button>,
+    // since the last frame
+    pressed: HashSet<Button>,
+    axes: HashMap<Axis, f32>
+}
+
+impl Gamepads {
+    pub fn get(&self, index: usize) -> Option<&GamepadState> {
+        self.pads.get(&index)
+    }
+
+    pub fn connected(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pads.keys().copied()
+    }
+
+    /*
+    Gamepads which were connected before gilrs was created don't send a connected event
+    */
+    pub(crate) fn from_connected(gilrs: &Gilrs) -> Gamepads {
+        let mut gamepads = Gamepads::default();
+        for (id, _) in gilrs.gamepads() {
+            gamepads.connect(id.into());
+        }
+        gamepads
+    }
+
+    /*
+    Replaces the presses of the last frame with the events since then
+    */
+    pub(crate) fn record(&mut self, gilrs: &mut Gilrs) {
+        self.begin_frame();
+        while let Some(event) = gilrs.next_event() {
+            let index = event.id.into();
+            match event.event {
+                EventType::Connected => self.connect(index),
+                EventType::Disconnected => self.disconnect(index),
+                EventType::ButtonPressed(button, _) => self.press(index, button),
+                EventType::ButtonReleased(button, _) => self.release(index, button),
+                EventType::AxisChanged(axis, value, _) => self.set_axis(index, axis, value),
+                _ => ()
+            }
+        }
+    }
+
+    /*
+    Forgets the presses of the previous frame, called before the new events are recorded
+    */
+    pub(crate) fn begin_frame(&mut self) {
+        for pad in self.pads.values_mut() {
+            pad.pressed.clear();
+        }
+    }
+
+    pub(crate) fn connect(&mut self, index: usize) {
+        self.pads.entry(index).or_default();
+    }
+
+    pub(crate) fn disconnect(&mut self, index: usize) {
+        self.pads.remove(&index);
+    }
+
+    pub(crate) fn press(&mut self, index: usize, button: Button) {
+        let pad = self.pads.entry(index).or_default();
+        // some drivers repeat presses without a release in between
+        if pad.held.insert(button) {
+            pad.pressed.insert(button);
+        }
+    }
+
+    pub(crate) fn release(&mut self, index: usize, button: Button) {
+        if let Some(pad) = self.pads.get_mut(&index) {
+            pad.held.remove(&button);
+        }
+    }
+
+    pub(crate) fn set_axis(&mut self, index: usize, axis: Axis, value: f32) {
+        self.pads.entry(index).or_default().axes.insert(axis, value);
+    }
+}
+
+impl GamepadState {
+    pub fn held(&self, button: Button) -> bool {
+        self.held.contains(&button)
+    }
+
+    pub fn pressed(&self, button: Button) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    pub fn axis(&self, axis: Axis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    /*
+    x and y of a stick with STICK_DEAD_ZONE applied, up and right are positive
+    */
+    pub fn stick(&self, x: Axis, y: Axis) -> [f32; 2] {
+        apply_dead_zone([self.axis(x), self.axis(y)], STICK_DEAD_ZONE)
+    }
+}
+
+/*
+Zero inside dead_zone, rescaled so the deflection still goes from 0 at its edge to 1
+Radial, so diagonals aren't snapped to the axes
+*/
+pub fn apply_dead_zone(stick: [f32; 2], dead_zone: f32) -> [f32; 2] {
+    let length = (stick[0] * stick[0] + stick[1] * stick[1]).sqrt();
+    if length <= dead_zone || length.is_nan() {
+        return [0.0, 0.0];
+    }
+
+    let scaled = ((length - dead_zone) / (1.0 - dead_zone).max(f32::EPSILON)).min(1.0);
+    [stick[0] / length * scaled, stick[1] / length * scaled]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_last_a_single_frame() {
+        let mut gamepads = Gamepads::default();
+        gamepads.press(1, Button::South);
+        gamepads.press(1, Button::South);
+
+        let pad = gamepads.get(1).unwrap();
+        assert!(pad.pressed(Button::South) && pad.held(Button::South));
+        assert!(gamepads.get(0).is_none());
+
+        gamepads.begin_frame();
+        let pad = gamepads.get(1).unwrap();
+        assert!(!pad.pressed(Button::South) && pad.held(Button::South));
+
+        gamepads.release(1, Button::South);
+        assert!(!gamepads.get(1).unwrap().held(Button::South));
+
+        gamepads.disconnect(1);
+        assert_eq!(gamepads.connected().count(), 0);
+    }
+
+    #[test]
+    fn dead_zone_is_radial() {
+        assert_eq!(apply_dead_zone([0.1, -0.1], 0.15), [0.0, 0.0]);
+        assert_eq!(apply_dead_zone([1.0, 0.0], 0.15), [1.0, 0.0]);
+
+        let [x, y] = apply_dead_zone([0.5, 0.5], 0.15);
+        assert!((x - y).abs() < 1e-6);
+        let length = (x * x + y * y).sqrt();
+        assert!((length - (0.5f32.sqrt() - 0.15) / 0.85).abs() < 1e-5);
+    }
+}
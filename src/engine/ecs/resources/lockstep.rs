@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Serialize, Deserialize};
+
+// Local state hashes older than this many hash intervals are no longer compared
+const KEPT_HASH_INTERVALS: u64 = 16;
+
+/// Deterministic lockstep, every peer simulates the same world from the inputs of all players instead of replicating state
+/// Inputs are scheduled input_delay ticks ahead, which hides the latency while they travel, and the simulation
+/// only advances by a fixed tick once the inputs of every player for it arrived, see advance
+/// Insert it before start_engine, along with a RandomSource of a seed shared by every peer
+pub struct Lockstep {
+    player: u32,
+    players: u32,
+    tick_length: f32,
+    input_delay: u64,
+    hash_interval: u64,
+    // next tick to simulate
+    tick: u64,
+    // next tick the local input is scheduled for
+    local_tick: u64,
+    inputs: BTreeMap<u64, BTreeMap<u32, Vec<u8>>>,
+    // tick simulated this frame and the inputs of every player for it
+    current: Option<(u64, Vec<(u32, Vec<u8>)>)>,
+    hashes: BTreeMap<u64, u64>,
+    remote_hashes: BTreeMap<(u64, u32), u64>,
+    desynced: Option<u64>,
+    outgoing: Vec<LockstepMessage>
+}
+
+/// Sent between the peers of a Lockstep game, data is the serialized input of the game
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LockstepMessage {
+    Input { player: u32, tick: u64, data: Vec<u8> },
+    StateHash { player: u32, tick: u64, hash: u64 }
+}
+
+impl Lockstep {
+    /*
+    player is the index of this peer out of players, each tick advances the simulation by tick_length seconds
+    The ticks before the input delay has passed have no input
+    */
+    pub fn new(player: u32, players: u32, tick_length: f32) -> Self {
+        let mut lockstep = Self {
+            player,
+            players: players.max(1),
+            tick_length,
+            input_delay: 0,
+            hash_interval: 30,
+            tick: 0,
+            local_tick: 0,
+            inputs: BTreeMap::new(),
+            current: None,
+            hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            desynced: None,
+            outgoing: Vec::new()
+        };
+        lockstep.set_input_delay(3);
+        lockstep
+    }
+
+    /*
+    Only before the first input was submitted, changing it later would skip or repeat ticks
+    */
+    pub fn with_input_delay(mut self, ticks: u64) -> Self {
+        self.set_input_delay(ticks);
+        self
+    }
+
+    /*
+    Ticks between state hashes, which are compared between the peers to detect a desync
+    */
+    pub fn with_hash_interval(mut self, ticks: u64) -> Self {
+        self.hash_interval = ticks.max(1);
+        self
+    }
+
+    fn set_input_delay(&mut self, ticks: u64) {
+        self.input_delay = ticks;
+        self.local_tick = self.tick + ticks;
+        self.inputs.clear();
+        for tick in self.tick..self.local_tick {
+            self.inputs.insert(tick, (0..self.players).map(|p| (p, Vec::new())).collect());
+        }
+    }
+
+    pub fn player(&self) -> u32 {
+        self.player
+    }
+
+    pub fn tick_length(&self) -> f32 {
+        self.tick_length
+    }
+
+    /*
+    Schedules the input of this peer for the next tick without one, input_delay ticks ahead of the simulation
+    Returns false while the input is already scheduled that far ahead, e.g. while waiting for other peers,
+    the game should keep it and submit it again the next frame
+    */
+    pub fn submit(&mut self, data: Vec<u8>) -> bool {
+        if self.local_tick > self.tick + self.input_delay {
+            return false;
+        }
+
+        self.inputs.entry(self.local_tick).or_default().insert(self.player, data.clone());
+        self.outgoing.push(LockstepMessage::Input { player: self.player, tick: self.local_tick, data });
+        self.local_tick += 1;
+        true
+    }
+
+    /*
+    Message of another peer, inputs of ticks which were simulated already are ignored
+    */
+    pub fn receive(&mut self, message: LockstepMessage) {
+        match message {
+            LockstepMessage::Input { player, .. } | LockstepMessage::StateHash { player, .. } if player >= self.players || player == self.player => {
+                warn!("Ignoring a lockstep message of player {player}, this is player {} of {}", self.player, self.players);
+            },
+            LockstepMessage::Input { player, tick, data } => {
+                if tick >= self.tick {
+                    // the first one counts, a repeated message can't change an input
+                    self.inputs.entry(tick).or_default().entry(player).or_insert(data);
+                }
+            },
+            LockstepMessage::StateHash { player, tick, hash } => {
+                self.remote_hashes.insert((tick, player), hash);
+                self.compare_hashes();
+            }
+        }
+    }
+
+    /*
+    Called once per frame before the systems run, simulates the next tick if the inputs of every player for it are there
+    Returns the time step of the tick, None if the simulation has to wait this frame
+    */
+    pub fn advance(&mut self) -> Option<f32> {
+        self.current = None;
+        let complete = self.inputs.get(&self.tick).map_or(false, |v| v.len() as u32 == self.players);
+        if !complete {
+            return None;
+        }
+
+        let inputs = self.inputs.remove(&self.tick).unwrap_or_default();
+        self.current = Some((self.tick, inputs.into_iter().collect()));
+        self.tick += 1;
+        Some(self.tick_length)
+    }
+
+    /*
+    Tick simulated this frame, None while waiting for inputs
+    */
+    pub fn simulated(&self) -> Option<u64> {
+        self.current.as_ref().map(|(tick, _)| *tick)
+    }
+
+    /*
+    Inputs of every player for the tick simulated this frame, in the order of the players
+    */
+    pub fn inputs(&self) -> &[(u32, Vec<u8>)] {
+        match &self.current {
+            Some((_, inputs)) => inputs,
+            None => &[]
+        }
+    }
+
+    /*
+    Tick whose state should be hashed after it was simulated this frame
+    */
+    pub fn hash_due(&self) -> Option<u64> {
+        self.simulated().filter(|tick| tick % self.hash_interval == 0)
+    }
+
+    /*
+    Hash of the local state after tick, sent to the other peers and compared against theirs
+    */
+    pub fn record_hash(&mut self, tick: u64, hash: u64) {
+        self.hashes.insert(tick, hash);
+        self.outgoing.push(LockstepMessage::StateHash { player: self.player, tick, hash });
+
+        let oldest = tick.saturating_sub(self.hash_interval * KEPT_HASH_INTERVALS);
+        self.hashes.retain(|t, _| *t >= oldest);
+        self.remote_hashes.retain(|(t, _), _| *t >= oldest);
+        self.compare_hashes();
+    }
+
+    fn compare_hashes(&mut self) {
+        let compared: Vec<(u64, u32)> = self.remote_hashes.keys()
+            .filter(|(tick, _)| self.hashes.contains_key(tick))
+            .copied()
+            .collect();
+
+        for key in compared {
+            let remote = self.remote_hashes.remove(&key);
+            if remote == self.hashes.get(&key.0).copied() {
+                continue;
+            }
+
+            warn!("Lockstep desync at tick {}, player {} simulated a different state", key.0, key.1);
+            if self.desynced.map_or(true, |t| key.0 < t) {
+                self.desynced = Some(key.0);
+            }
+        }
+    }
+
+    /*
+    First tick another peer hashed a different state for, the simulations have diverged since
+    */
+    pub fn desynced(&self) -> Option<u64> {
+        self.desynced
+    }
+
+    /*
+    Messages for the other peers, sent by the LockstepSync system
+    */
+    pub fn take_outgoing(&mut self) -> Vec<LockstepMessage> {
+        std::mem::take(&mut self.outgoing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(from: &mut Lockstep, to: &mut Lockstep) {
+        for message in from.take_outgoing() {
+            to.receive(message);
+        }
+    }
+
+    #[test]
+    fn waits_for_every_input() {
+        let mut a = Lockstep::new(0, 2, 0.02).with_input_delay(2);
+        let mut b = Lockstep::new(1, 2, 0.02).with_input_delay(2);
+
+        // the ticks within the input delay have no input
+        for _ in 0..2 {
+            assert!(a.submit(vec![1]));
+            assert!(b.submit(vec![2]));
+            assert_eq!(a.advance(), Some(0.02));
+            assert_eq!(b.advance(), Some(0.02));
+            assert!(a.inputs().iter().all(|(_, v)| v.is_empty()));
+        }
+
+        // b's input of tick 2 hasn't arrived yet
+        assert!(a.submit(vec![1]));
+        assert_eq!(a.advance(), None);
+        assert!(!a.submit(vec![1]));
+        assert_eq!(a.inputs(), &[]);
+
+        deliver(&mut b, &mut a);
+        assert_eq!(a.advance(), Some(0.02));
+        assert_eq!(a.simulated(), Some(2));
+        assert_eq!(a.inputs(), &[(0, vec![1]), (1, vec![2])]);
+
+        // a repeated message doesn't change the input
+        a.receive(LockstepMessage::Input { player: 1, tick: 3, data: vec![9] });
+        assert_eq!(a.advance(), Some(0.02));
+        assert_eq!(a.inputs(), &[(0, vec![1]), (1, vec![2])]);
+    }
+
+    #[test]
+    fn detects_a_desync_from_state_hashes() {
+        let mut a = Lockstep::new(0, 2, 0.02).with_hash_interval(10);
+        let mut b = Lockstep::new(1, 2, 0.02).with_hash_interval(10);
+
+        a.record_hash(10, 42);
+        b.record_hash(10, 42);
+        deliver(&mut a, &mut b);
+        deliver(&mut b, &mut a);
+        assert_eq!(a.desynced(), None);
+        assert_eq!(b.desynced(), None);
+
+        // the hash of b arrives before a got to tick 20
+        b.record_hash(20, 7);
+        deliver(&mut b, &mut a);
+        assert_eq!(a.desynced(), None);
+        a.record_hash(20, 8);
+        assert_eq!(a.desynced(), Some(20));
+    }
+}
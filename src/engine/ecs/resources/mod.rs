@@ -1,25 +1,266 @@
-use std::sync::Arc;
+use std::{sync::Arc, collections::{HashSet, VecDeque}, time::{SystemTime, UNIX_EPOCH, Instant, Duration}, thread};
 
-use nalgebra::Matrix4;
+use nalgebra::{Matrix4, Perspective3, Orthographic3, Vector3};
 use specs::Entity;
-use vulkano::{command_buffer::{PrimaryAutoCommandBuffer, allocator::StandardCommandBufferAllocator}, pipeline::GraphicsPipeline, render_pass::Framebuffer, buffer::CpuBufferPool, descriptor_set::allocator::StandardDescriptorSetAllocator};
+use winit::event::{WindowEvent, ElementState, MouseButton, VirtualKeyCode, KeyboardInput};
+use vulkano::{command_buffer::{PrimaryAutoCommandBuffer, allocator::StandardCommandBufferAllocator}, pipeline::{GraphicsPipeline, graphics::viewport::Viewport}, render_pass::Framebuffer, buffer::{CpuBufferPool, CpuAccessibleBuffer}, descriptor_set::{allocator::StandardDescriptorSetAllocator, PersistentDescriptorSet}, sampler::Sampler, memory::allocator::StandardMemoryAllocator, swapchain::PresentMode, image::SampleCount};
 
-use crate::shaders::default::vs::ty::VPUniformBufferObject;
+use crate::{graphics::vulkan::{ShadowMap, UploadHandle}, data_structures::graphics::Vertex, shaders::default::{vs::ty::VPUniformBufferObject, fs::ty::{ShadowUniformBufferObject, LightsUniformBufferObject}}, ecs::components::general::Transform};
 
+pub mod audio;
+pub mod events;
+pub mod input;
+pub mod lockstep;
 pub mod network;
 pub mod physics;
 
 pub struct RenderData {
     pub pipeline: Arc<GraphicsPipeline>,
-    pub pipeline_wireframe: Arc<GraphicsPipeline>,
+    // None if the device doesn't support non solid fill modes
+    pub pipeline_wireframe: Option<Arc<GraphicsPipeline>>,
+    // for entities with a PbrMaterial, its set 1 isn't compatible with the one of the pipelines above
+    pub pipeline_pbr: Arc<GraphicsPipeline>,
+    // default and pbr without back face culling, see the DoubleSided component
+    pub pipeline_double_sided: Arc<GraphicsPipeline>,
+    pub pipeline_pbr_double_sided: Arc<GraphicsPipeline>,
+    pub pipeline_no_depth: Arc<GraphicsPipeline>,
+    // depth bias is set per entity, see the DepthBias component
+    pub pipeline_depth_bias: Arc<GraphicsPipeline>,
+    // None if the device doesn't support non solid fill modes
+    pub pipeline_wireframe_overlay: Option<Arc<GraphicsPipeline>>,
+    // Transparent entities after the solid pass
+    pub pipeline_transparent: Arc<GraphicsPipeline>,
+    // draws every renderable instead of the pipelines above, see HawkEngine::set_pipeline_override
+    pub pipeline_override: Option<Arc<GraphicsPipeline>>,
+    // bounding boxes of OcclusionCulled entities, writes neither color nor depth
+    pub pipeline_occlusion: Arc<GraphicsPipeline>,
+    // unit cube the bounds are drawn with, see graphics::occlusion::proxy_box
+    pub occlusion_proxy: (Arc<CpuAccessibleBuffer<[Vertex]>>, Arc<CpuAccessibleBuffer<[u32]>>),
+    // texture of a Minimap over the window
+    pub pipeline_minimap: Arc<GraphicsPipeline>,
+    // the Skybox resource, behind everything else
+    pub pipeline_skybox: Arc<GraphicsPipeline>,
+    // WorldText labels, and the font they sample, None if it couldn't be created
+    pub pipeline_text: Arc<GraphicsPipeline>,
+    pub text_font: Option<TextFont>,
+    // line list, for PhysicsDebugRender
+    pub pipeline_debug_lines: Arc<GraphicsPipeline>,
+    // for textures sampled by the render system itself, e.g. the minimap
+    pub sampler: Arc<Sampler>,
+    // vertices built every frame, e.g. the glyph quads of WorldText labels and debug lines
+    pub vertex_pool: Arc<CpuBufferPool<Vertex>>,
     pub ubo_pool: Arc<CpuBufferPool<VPUniformBufferObject>>,
+    pub shadow_ubo_pool: Arc<CpuBufferPool<ShadowUniformBufferObject>>,
+    pub lights_ubo_pool: Arc<CpuBufferPool<LightsUniformBufferObject>>,
     pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
     pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    // for buffers kept across frames, see the solid pass of the Render system
+    pub memory_allocator: Arc<StandardMemoryAllocator>,
     pub queue_family_index: u32
 }
 
+/// Cubemap drawn behind everything else, see Vulkan::create_skybox
+/// Games without this resource only see the clear color
+#[derive(Clone)]
+pub struct Skybox {
+    pub descriptor_set: Arc<PersistentDescriptorSet>,
+    // the cubemap, None if it is already on the gpu
+    pub upload: Option<UploadHandle>
+}
+
+impl Skybox {
+    /*
+    False until the cubemap has been uploaded, it isn't drawn until then
+    */
+    pub fn is_ready(&self) -> bool {
+        match &self.upload {
+            Some(v) => v.is_finished(),
+            None => true
+        }
+    }
+}
+
+/// Atlas of the built in font of the WorldText labels, see Vulkan::create_text_font
+#[derive(Clone)]
+pub struct TextFont {
+    pub descriptor_set: Arc<PersistentDescriptorSet>,
+    pub upload: Option<UploadHandle>
+}
+
+impl TextFont {
+    /*
+    False until the atlas has been uploaded, no labels are drawn until then
+    */
+    pub fn is_ready(&self) -> bool {
+        match &self.upload {
+            Some(v) => v.is_finished(),
+            None => true
+        }
+    }
+}
+
 pub struct RenderDataFrameBuffer(pub Arc<Framebuffer>);
 
+/// The FXAA or upscaling pass of the current frame, None while FXAA is off and the RenderScale is 1
+/// Render draws the scene into RenderDataFrameBuffer and then this pass onto the swapchain image
+#[derive(Default)]
+pub struct RenderDataPostProcess(pub Option<PostProcessFrame>);
+
+pub struct PostProcessFrame {
+    pub framebuffer: Arc<Framebuffer>,
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub descriptor_set: Arc<PersistentDescriptorSet>
+}
+
+/// Depth maps of the Shadows cascades, rendered by the Render system before anything else
+pub struct RenderDataShadowMap(pub ShadowMap);
+
+/// Cascaded shadow maps cast by the sun, changes are applied before the next frame
+/// The view frustum of the active camera is split into cascade_count depth ranges up to distance,
+/// each with its own resolution x resolution shadow map, 0 cascades turns shadows off
+/// split_lambda blends between uniform (0.0) and logarithmic (1.0) splits,
+/// the latter gives the cascades near the camera more detail
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shadows {
+    pub cascade_count: u32,
+    pub split_lambda: f32,
+    pub resolution: u32,
+    // nothing further away from the camera is shadowed, clamped to the far plane
+    pub distance: f32,
+    // world space direction the sun shines in
+    pub direction: Vector3<f32>
+}
+
+pub const MAX_SHADOW_CASCADES: u32 = 4;
+
+impl Default for Shadows {
+    fn default() -> Self {
+        Shadows { cascade_count: 0, split_lambda: 0.75, resolution: 2048, distance: 150.0, direction: Vector3::new(-0.4, -1.0, -0.3) }
+    }
+}
+
+impl Shadows {
+    /*
+    At most MAX_SHADOW_CASCADES, split_lambda in [0, 1] and a resolution of at least 1
+    */
+    pub fn clamped(&self) -> Shadows {
+        let split_lambda = if self.split_lambda.is_nan() { Shadows::default().split_lambda } else { self.split_lambda.clamp(0.0, 1.0) };
+        let distance = if self.distance.is_nan() { Shadows::default().distance } else { self.distance.max(f32::EPSILON) };
+        Shadows {
+            cascade_count: self.cascade_count.min(MAX_SHADOW_CASCADES),
+            split_lambda,
+            resolution: self.resolution.max(1),
+            distance,
+            direction: self.direction
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.cascade_count > 0
+    }
+}
+
+/// How many mip levels of streamed textures stay on the gpu, see Vulkan::create_streamed_renderable
+/// Textures within full_detail_distance of the active camera get every level, and lose the largest one
+/// each time the distance doubles after that, textures off screen keep only their smallest levels
+/// Past budget_bytes the textures farthest away lose levels first
+/// At most max_uploads_per_frame textures are uploaded each frame, so detail fills in over a few frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureStreaming {
+    pub full_detail_distance: f32,
+    pub budget_bytes: u64,
+    pub max_uploads_per_frame: u32
+}
+
+impl Default for TextureStreaming {
+    fn default() -> Self {
+        TextureStreaming { full_detail_distance: 10.0, budget_bytes: 256 * 1024 * 1024, max_uploads_per_frame: 2 }
+    }
+}
+
+/// Look of the loading screen, shown instead of the game until every job started with HawkEngine::preload
+/// finished, only read when the engine starts
+/// background is the name of a texture in resources stretched over the window, multiplied with background_color
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadingScreen {
+    pub background: Option<String>,
+    pub background_color: [f32; 4],
+    pub bar_color: [f32; 4]
+}
+
+impl Default for LoadingScreen {
+    fn default() -> Self {
+        LoadingScreen { background: None, background_color: [0.02, 0.02, 0.02, 1.0], bar_color: [0.9, 0.9, 0.9, 1.0] }
+    }
+}
+
+/// Present mode of the swapchain, changes are applied before the next frame
+/// Modes the surface doesn't support are reset to the current one, see HawkEngine::supported_present_modes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapchainPresentMode(pub PresentMode);
+
+/// Anti aliasing method, changes are applied before the next frame, see HawkEngine::set_anti_aliasing
+/// Unsupported MSAA sample counts are lowered, the resource is updated to what was actually applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum AntiAliasing {
+    #[default]
+    Off,
+    Msaa(SampleCount),
+    // post process pass on the rendered image, cheaper than MSAA but blurs some detail
+    Fxaa
+}
+
+/// Resolution the scene is rendered at relative to the window, changes are applied before the next frame
+/// Below 1.0 the scene is upscaled to the window afterwards, e.g. 0.5 renders a quarter of the pixels
+/// scale is clamped to MIN_RENDER_SCALE..=1.0, the resource is updated to what was actually applied
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderScale {
+    pub scale: f32,
+    pub filter: UpscaleFilter
+}
+
+pub const MIN_RENDER_SCALE: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum UpscaleFilter {
+    // blocky pixels, e.g. for a retro look
+    Nearest,
+    #[default]
+    Linear
+}
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        RenderScale { scale: 1.0, filter: UpscaleFilter::default() }
+    }
+}
+
+impl RenderScale {
+    pub fn clamped(&self) -> RenderScale {
+        RenderScale { scale: self.scale.clamp(MIN_RENDER_SCALE, 1.0), filter: self.filter }
+    }
+
+    /*
+    Size of the scene for a window of the given size, at least 1x1
+    */
+    pub fn dimensions(&self, window: [u32; 2]) -> [u32; 2] {
+        let scale = self.clamped().scale;
+        [
+            ((window[0] as f32 * scale).round() as u32).max(1),
+            ((window[1] as f32 * scale).round() as u32).max(1)
+        ]
+    }
+
+    pub fn is_scaled(&self) -> bool {
+        self.clamped().scale < 1.0
+    }
+}
+
+/// Name of the pipeline every renderable is drawn with instead of its own, changes are applied before the next frame
+/// See HawkEngine::set_pipeline_override for the names, unknown names are reset to the current override
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct PipelineOverride(pub Option<String>);
+
 #[derive(Default)]
 pub struct CommandBuffer {
     pub command_buffer: Option<Arc<PrimaryAutoCommandBuffer>>
@@ -30,8 +271,632 @@ pub struct ProjectionMatrix(pub Matrix4<f32>);
 
 pub struct ActiveCamera(pub Entity);
 
+/// Region of the window a camera of Cameras renders into, in fractions of the window size
+/// from the top left corner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32
+}
+
+impl ViewportRect {
+    pub const FULL: ViewportRect = ViewportRect { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+    /*
+    The rect in pixels of a framebuffer of extent, at least one pixel large
+    */
+    pub fn viewport(&self, extent: [u32; 2]) -> Viewport {
+        let [width, height] = [extent[0] as f32, extent[1] as f32];
+        Viewport {
+            origin: [self.x * width, self.y * height],
+            dimensions: [(self.width * width).max(1.0), (self.height * height).max(1.0)],
+            depth_range: 0.0..1.0
+        }
+    }
+}
+
+impl Default for ViewportRect {
+    fn default() -> Self {
+        ViewportRect::FULL
+    }
+}
+
+/// Cameras rendered into their own region of the window in the same frame, e.g. for split screen
+/// Each uses ProjectionKind with the aspect ratio of its region
+/// Empty renders only ActiveCamera, over the whole window
+/// Shadow cascades are fitted to the first camera and occlusion queries only test against it,
+/// the others draw everything and are shadowed only where the cascades reach
+#[derive(Default)]
+pub struct Cameras(pub Vec<(Entity, ViewportRect)>);
+
+/// Drives ProjectionMatrix, changes are applied before the next frame is rendered
+/// near has to be greater than 0 and far greater than near, invalid values are clamped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProjectionKind {
+    Perspective {
+        // vertical
+        fov_degrees: f32,
+        near: f32,
+        far: f32
+    },
+    Orthographic {
+        // visible height in world units, the width follows from the aspect ratio
+        height: f32,
+        near: f32,
+        far: f32
+    }
+}
+
+impl Default for ProjectionKind {
+    fn default() -> Self {
+        ProjectionKind::Perspective { fov_degrees: 45.0, near: 0.1, far: 1000.0 }
+    }
+}
+
+impl ProjectionKind {
+    /*
+    Projection matrix in Vulkan clip space for the given aspect ratio (width / height)
+    */
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        let mut proj = match *self {
+            ProjectionKind::Perspective { fov_degrees, near, far } => {
+                let near = near.max(f32::EPSILON);
+                let far = far.max(near * 2.0);
+                let fov = fov_degrees.clamp(1.0, 179.0);
+                Perspective3::new(aspect, fov.to_radians(), near, far).to_homogeneous()
+            },
+            ProjectionKind::Orthographic { height, near, far } => {
+                let near = near.max(f32::EPSILON);
+                let far = far.max(near * 2.0);
+                let half_height = height.max(f32::EPSILON) / 2.0;
+                let half_width = half_height * aspect;
+                let mut ortho = Orthographic3::new(-half_width, half_width, -half_height, half_height, near, far).to_homogeneous();
+                // Vulkan depth goes from 0 to 1 instead of -1 to 1
+                ortho[(2, 2)] *= 0.5;
+                ortho[(2, 3)] = 0.5 * ortho[(2, 3)] + 0.5;
+                ortho
+            }
+        };
+        // convert from OpenGL to Vulkan coordinates
+        proj[(1, 1)] *= -1.0;
+        proj
+    }
+}
+
+/// Perspective only projection, replaced by ProjectionKind
+/// No longer read by the engine, insert the ProjectionKind it converts into instead
+#[deprecated(note = "use ProjectionKind::Perspective")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraProjection {
+    // vertical
+    pub fov_degrees: f32,
+    pub near: f32,
+    pub far: f32
+}
+
+#[allow(deprecated)]
+impl Default for CameraProjection {
+    fn default() -> Self {
+        CameraProjection { fov_degrees: 45.0, near: 0.1, far: 1000.0 }
+    }
+}
+
+#[allow(deprecated)]
+impl CameraProjection {
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        ProjectionKind::from(*self).matrix(aspect)
+    }
+}
+
+#[allow(deprecated)]
+impl From<CameraProjection> for ProjectionKind {
+    fn from(projection: CameraProjection) -> Self {
+        ProjectionKind::Perspective { fov_degrees: projection.fov_degrees, near: projection.near, far: projection.far }
+    }
+}
+
 #[derive(Default)]
 pub struct CursorGrab(pub bool);
+/// Name of the dispatcher profile to run, None or a name without a profile runs all dispatchers
+/// Name of the dispatcher profile to run, None runs all dispatchers
+/// See HawkEngine::define_profile
+#[derive(Default)]
+pub struct ActiveProfile(pub Option<String>);
+
+/// Color the frame is cleared to before rendering
+pub struct ClearColor(pub [f32; 4]);
+
+impl Default for ClearColor {
+    fn default() -> Self {
+        ClearColor([0.0, 0.0, 0.0, 1.0])
+    }
+}
+
+impl ClearColor {
+    /*
+    Components limited to [0, 1], NaN is treated as 0
+    */
+    pub fn clamped(&self) -> [f32; 4] {
+        self.0.map(|c| if c.is_nan() { 0.0 } else { c.clamp(0.0, 1.0) })
+    }
+}
+
+/// Light every surface receives while there are Light entities, as rgb
+pub struct AmbientLight(pub [f32; 3]);
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        AmbientLight([0.1, 0.1, 0.1])
+    }
+}
+
+/// Value the depth buffer is cleared to before rendering
+/// 1.0 is the far plane with the default depth test,
+/// reversed-Z would need 0.0 along with a greater than depth compare
+pub struct ClearDepth(pub f32);
+
+impl Default for ClearDepth {
+    fn default() -> Self {
+        ClearDepth(1.0)
+    }
+}
+
+impl ClearDepth {
+    /*
+    Vulkan only accepts depth clear values in [0, 1], NaN falls back to the default
+    */
+    pub fn clamped(&self) -> f32 {
+        if self.0.is_nan() { 1.0 } else { self.0.clamp(0.0, 1.0) }
+    }
+}
 
 #[derive(Default)]
 pub struct DeltaTime(pub f32);
+
+/// Seconds since the last frame, clamped to MaxDeltaTime but unaffected by TimeScale and lockstep
+/// For what has to keep its speed in slow motion or while paused, e.g. looking around
+#[derive(Default)]
+pub struct UnscaledDeltaTime(pub f32);
+
+/// Upper limit for DeltaTime in seconds
+/// A single long frame (window dragged, breakpoint hit) would otherwise
+/// advance the simulation so far that objects tunnel through each other
+pub struct MaxDeltaTime(pub f32);
+
+impl Default for MaxDeltaTime {
+    fn default() -> Self {
+        MaxDeltaTime(0.1)
+    }
+}
+
+/// Multiplier for DeltaTime, 0.5 runs the simulation at half speed
+/// and 0.0 freezes it, the frame rate is unaffected
+pub struct TimeScale(pub f32);
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        TimeScale(1.0)
+    }
+}
+
+/// Evens out frame delivery, off by default
+/// Short frames are delayed to the typical frame time of the last frames,
+/// and DeltaTime is averaged over the last smoothing_frames frames so a
+/// single slow frame doesn't make everything jump ahead
+#[derive(Clone, Copy, Debug)]
+pub struct FramePacing {
+    pub enabled: bool,
+    pub smoothing_frames: usize
+}
+
+impl Default for FramePacing {
+    fn default() -> Self {
+        FramePacing { enabled: false, smoothing_frames: 8 }
+    }
+}
+
+/// Time between the last frames in seconds, before DeltaTime is clamped or scaled,
+/// and the draw calls of the last rendered frame
+#[derive(Default)]
+pub struct FrameStats {
+    frame_times: VecDeque<f32>,
+    draw_calls: u32,
+    recorded_passes: u64
+}
+
+impl FrameStats {
+    const FRAMES: usize = 120;
+
+    pub(crate) fn record(&mut self, frame_time: f32) {
+        if self.frame_times.len() == FrameStats::FRAMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    pub fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    /*
+    Variance of the frame times, lower means more evenly delivered frames
+    */
+    pub fn frame_time_variance(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+
+        let average = self.average_frame_time();
+        self.frame_times.iter().map(|t| (t - average).powi(2)).sum::<f32>() / self.frame_times.len() as f32
+    }
+
+    pub fn fps(&self) -> f32 {
+        match self.average_frame_time() {
+            t if t > 0.0 => 1.0 / t,
+            _ => 0.0
+        }
+    }
+
+    pub fn draw_calls(&self) -> u32 {
+        self.draw_calls
+    }
+
+    pub(crate) fn set_draw_calls(&mut self, draw_calls: u32) {
+        self.draw_calls = draw_calls;
+    }
+
+    /*
+    How often the solid geometry was recorded since the start, stays the same while nothing drawn by it changes
+    */
+    pub fn recorded_passes(&self) -> u64 {
+        self.recorded_passes
+    }
+
+    pub(crate) fn set_recorded_passes(&mut self, recorded_passes: u64) {
+        self.recorded_passes = recorded_passes;
+    }
+}
+
+/// Draw calls per frame above which the render system logs a warning
+/// Meant to catch scenes which spawn more geometry than intended
+pub struct DrawCallBudget(pub u32);
+
+impl Default for DrawCallBudget {
+    fn default() -> Self {
+        DrawCallBudget(2000)
+    }
+}
+
+/// Delays frames and smooths DeltaTime as configured by FramePacing
+#[derive(Default)]
+pub(crate) struct FramePacer {
+    // frame times without the pacing delay, so delaying doesn't raise the target
+    work_times: VecDeque<f32>,
+    delta_times: VecDeque<f32>
+}
+
+impl FramePacer {
+    // frames the pacing target is taken from
+    const TARGET_FRAMES: usize = 30;
+
+    /*
+    Sleeps until the median frame time of the last frames has passed since last_frame
+    The median ignores the occasional slow frame, which the delay can't make up for anyway
+    */
+    pub fn pace(&mut self, last_frame: Instant) {
+        let work_time = last_frame.elapsed().as_secs_f32();
+
+        let mut sorted: Vec<f32> = self.work_times.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        if self.work_times.len() == FramePacer::TARGET_FRAMES {
+            self.work_times.pop_front();
+        }
+        self.work_times.push_back(work_time);
+
+        if let Some(target) = sorted.get(sorted.len() / 2) {
+            if work_time < *target {
+                thread::sleep(Duration::from_secs_f32(target - work_time));
+            }
+        }
+    }
+
+    /*
+    Average of the given and the previous delta times
+    */
+    pub fn smooth(&mut self, delta_time: f32, frames: usize) -> f32 {
+        self.delta_times.push_back(delta_time);
+        while self.delta_times.len() > frames.max(1) {
+            self.delta_times.pop_front();
+        }
+
+        self.delta_times.iter().sum::<f32>() / self.delta_times.len() as f32
+    }
+}
+
+/// Points where entities are placed when respawning
+/// Spawn points are handed out in order, wrapping around at the end
+#[derive(Default)]
+pub struct SpawnPoints {
+    pub points: Vec<Transform>,
+    next: usize
+}
+
+impl SpawnPoints {
+    pub fn new(points: Vec<Transform>) -> Self {
+        Self { points, next: 0 }
+    }
+
+    pub fn random(&self, rng: &mut RandomSource) -> Option<Transform> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        Some(self.points[rng.range_u32(0, self.points.len() as u32) as usize].clone())
+    }
+
+    pub fn next(&mut self) -> Option<Transform> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let point = self.points[self.next % self.points.len()].clone();
+        self.next = (self.next + 1) % self.points.len();
+        Some(point)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    KeyPressed(VirtualKeyCode),
+    KeyReleased(VirtualKeyCode),
+    MousePressed(MouseButton),
+    MouseReleased(MouseButton)
+}
+
+/// Every discrete key and mouse button event since the last dispatched frame, in order
+/// 
+/// Unlike the WinitInputHelper snapshot, repeated taps of the same key
+/// between two frames are all kept, and events are not lost on frames
+/// which get skipped (e.g. while the swapchain is being recreated).
+/// OS key repeat is filtered out.
+#[derive(Default)]
+pub struct InputEvents(pub Vec<InputEvent>);
+
+impl InputEvents {
+    pub fn key_presses(&self, key: VirtualKeyCode) -> usize {
+        self.0.iter().filter(|e| **e == InputEvent::KeyPressed(key)).count()
+    }
+
+    pub fn mouse_presses(&self, button: MouseButton) -> usize {
+        self.0.iter().filter(|e| **e == InputEvent::MousePressed(button)).count()
+    }
+}
+
+/// Collects InputEvents from winit events until the next frame drains them
+#[derive(Default)]
+pub(crate) struct InputEventQueue {
+    pending: Vec<InputEvent>,
+    held_keys: HashSet<VirtualKeyCode>
+}
+
+impl InputEventQueue {
+    pub fn record(&mut self, event: &WindowEvent<'_>) {
+        match event {
+            WindowEvent::KeyboardInput { input: KeyboardInput { virtual_keycode: Some(key), state, .. }, .. } => {
+                match state {
+                    // insert returns false for key repeat
+                    ElementState::Pressed => if self.held_keys.insert(*key) {
+                        self.pending.push(InputEvent::KeyPressed(*key));
+                    },
+                    ElementState::Released => {
+                        self.held_keys.remove(key);
+                        self.pending.push(InputEvent::KeyReleased(*key));
+                    }
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => {
+                match state {
+                    ElementState::Pressed => self.pending.push(InputEvent::MousePressed(*button)),
+                    ElementState::Released => self.pending.push(InputEvent::MouseReleased(*button))
+                }
+            },
+            // keys released while unfocused never send a release event
+            WindowEvent::Focused(false) => self.held_keys.clear(),
+            _ => ()
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<InputEvent> {
+        self.pending.drain(..).collect()
+    }
+}
+
+/// Seeded random number generator for procedural content
+/// 
+/// Uses PCG32 (XSH RR), so the same seed always produces the same sequence
+/// on every platform. Engine side procedural systems should draw from this
+/// resource instead of their own generators, which keeps worlds reproducible
+/// and lets networked clients generate the same content from a shared seed.
+/// 
+/// Insert this with a fixed seed before calling start_engine for a
+/// deterministic run, otherwise a seed based on the current time is used.
+#[derive(Clone, Debug)]
+pub struct RandomSource {
+    seed: u64,
+    state: u64,
+    inc: u64
+}
+
+impl RandomSource {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = RandomSource { seed, state: 0, inc: (seed << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn from_time() -> Self {
+        let seed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(v) => v.as_nanos() as u64,
+            Err(_) => 0
+        };
+        RandomSource::new(seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(RandomSource::MULTIPLIER).wrapping_add(self.inc);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /*
+    Uniformly distributed float in [0, 1)
+    */
+    pub fn next_f32(&mut self) -> f32 {
+        // 24 bits is all the precision an f32 mantissa can hold
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /*
+    Uniformly distributed float in [min, max)
+    */
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /*
+    Uniformly distributed integer in [min, max), returns min if the range is empty
+    */
+    pub fn range_u32(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+
+        // rejection sampling to avoid modulo bias
+        let range = max - min;
+        let threshold = range.wrapping_neg() % range;
+        loop {
+            let v = self.next_u32();
+            if v >= threshold {
+                return min + v % range;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orthographic_depth_goes_from_zero_at_near_to_one_at_far() {
+        let proj = ProjectionKind::Orthographic { height: 10.0, near: 1.0, far: 101.0 }.matrix(16.0 / 9.0);
+        // the camera looks down -z
+        let depth = |z: f32| {
+            let clip = proj * nalgebra::Vector4::new(0.0, 0.0, z, 1.0);
+            clip.z / clip.w
+        };
+        assert!(depth(-1.0).abs() < 1e-6);
+        assert!((depth(-101.0) - 1.0).abs() < 1e-6);
+        assert!((depth(-51.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn frame_stats_keep_the_last_frames() {
+        let mut stats = FrameStats::default();
+        assert_eq!(stats.fps(), 0.0);
+        assert_eq!(stats.frame_time_variance(), 0.0);
+
+        stats.record(0.01);
+        stats.record(0.03);
+        assert!((stats.average_frame_time() - 0.02).abs() < 1e-6);
+        assert!((stats.frame_time_variance() - 0.0001).abs() < 1e-6);
+        assert!((stats.fps() - 50.0).abs() < 1e-3);
+
+        // the oldest frames drop out, leaving only even ones
+        for _ in 0..FrameStats::FRAMES {
+            stats.record(0.02);
+        }
+        assert!((stats.average_frame_time() - 0.02).abs() < 1e-6);
+        assert!(stats.frame_time_variance() < 1e-9);
+    }
+
+    #[test]
+    fn render_scale_is_clamped_and_at_least_one_pixel() {
+        let half = RenderScale { scale: 0.5, ..Default::default() };
+        assert!(half.is_scaled());
+        assert_eq!(half.dimensions([1280, 720]), [640, 360]);
+
+        assert!(!RenderScale { scale: 2.0, ..Default::default() }.is_scaled());
+        assert_eq!(RenderScale { scale: 2.0, ..Default::default() }.dimensions([1280, 720]), [1280, 720]);
+        assert_eq!(RenderScale { scale: 0.0, ..Default::default() }.clamped().scale, MIN_RENDER_SCALE);
+        assert_eq!(RenderScale { scale: 0.0, ..Default::default() }.dimensions([4, 4]), [1, 1]);
+    }
+
+    #[test]
+    fn viewport_rects_scale_to_the_extent() {
+        let viewport = ViewportRect::FULL.viewport([1280, 720]);
+        assert_eq!(viewport.origin, [0.0, 0.0]);
+        assert_eq!(viewport.dimensions, [1280.0, 720.0]);
+
+        let right = ViewportRect { x: 0.5, y: 0.0, width: 0.5, height: 1.0 }.viewport([1280, 720]);
+        assert_eq!(right.origin, [640.0, 0.0]);
+        assert_eq!(right.dimensions, [640.0, 720.0]);
+
+        // an empty rect still covers a pixel
+        let empty = ViewportRect { x: 0.25, y: 0.5, width: 0.0, height: 0.0 }.viewport([1280, 720]);
+        assert_eq!(empty.origin, [320.0, 360.0]);
+        assert_eq!(empty.dimensions, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn frame_pacer_smooths_over_the_window() {
+        let mut pacer = FramePacer::default();
+        assert_eq!(pacer.smooth(0.01, 2), 0.01);
+        assert!((pacer.smooth(0.03, 2) - 0.02).abs() < 1e-6);
+        // the first frame is out of the window now
+        assert!((pacer.smooth(0.05, 2) - 0.04).abs() < 1e-6);
+        // no smoothing with 0 frames
+        assert_eq!(pacer.smooth(0.07, 0), 0.07);
+    }
+
+    #[test]
+    fn frame_pacer_waits_for_the_median_frame_time() {
+        let mut pacer = FramePacer::default();
+        let frame = Duration::from_millis(20);
+
+        // without any history there is nothing to wait for
+        let start = Instant::now();
+        pacer.pace(start);
+        assert!(start.elapsed() < frame);
+
+        for _ in 0..3 {
+            pacer.pace(Instant::now() - frame);
+        }
+        // a single slow frame doesn't raise the target
+        pacer.pace(Instant::now() - Duration::from_millis(500));
+
+        let start = Instant::now();
+        pacer.pace(start);
+        let waited = start.elapsed();
+        assert!(waited >= Duration::from_millis(15));
+        assert!(waited < Duration::from_millis(250));
+    }
+}
@@ -1,31 +1,409 @@
-use std::{net::SocketAddr, collections::HashMap};
+use std::{net::SocketAddr, collections::{HashMap, VecDeque}, time::{SystemTime, UNIX_EPOCH, Duration}};
+#[cfg(feature = "net-debug")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use nalgebra::Isometry3;
+use rapier3d::prelude::ColliderHandle;
 use serde::{Serialize, Deserialize};
-use specs::Entity;
-use tokio::sync::mpsc::{Sender, Receiver};
+use specs::{Entity, ReadStorage};
+use tokio::sync::{mpsc::{Sender, Receiver}, watch};
 use uuid::Uuid;
 
+use crate::ecs::{components::physics::RigidBodyComponent, resources::physics::PhysicsData};
+
 
 pub struct NetworkMessageData {
     pub addr: SocketAddr,
-    pub packet: NetworkPacket
+    pub packet: NetworkPacket,
+    pub priority: MessagePriority
 }
 
-#[derive(Serialize, Deserialize)]
+impl NetworkMessageData {
+    /*
+    Uses the default priority of the message type, see MessageType::priority
+    */
+    pub fn new(addr: SocketAddr, packet: NetworkPacket) -> Self {
+        let priority = packet.message_type.priority();
+        Self { addr, packet, priority }
+    }
+
+    pub fn with_priority(mut self, priority: MessagePriority) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+/// Order in which the network thread sends queued messages, highest first
+/// Bulk messages may be dropped when the send queue is congested
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessagePriority {
+    // state which is replaced by newer messages, e.g. transforms
+    Bulk,
+    Normal,
+    // messages the connection depends on
+    Critical
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub enum MessageType {
     ComponentTransform,
-    ComponentCustom(String)
+    ComponentCustom(String),
+    // handled by the network thread itself, see KeepAlive
+    KeepAlive { sequence: u32, ack: bool }
+}
+
+impl MessageType {
+    pub fn priority(&self) -> MessagePriority {
+        match self {
+            MessageType::ComponentTransform => MessagePriority::Bulk,
+            MessageType::ComponentCustom(_) => MessagePriority::Normal,
+            MessageType::KeepAlive { .. } => MessagePriority::Critical
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct NetworkPacket {
     pub net_id: Uuid,
     pub message_type: MessageType,
-    pub data: Vec<u8>
+    pub data: Vec<u8>,
+
+    // Only present with the net-debug feature, both ends need to
+    // be built with the same features or deserialization will fail
+    #[cfg(feature = "net-debug")]
+    pub debug: PacketDebugInfo
+}
+
+impl NetworkPacket {
+    pub fn new(net_id: Uuid, message_type: MessageType, data: Vec<u8>) -> Self {
+        Self {
+            net_id,
+            message_type,
+            data,
+            #[cfg(feature = "net-debug")]
+            debug: PacketDebugInfo::next()
+        }
+    }
+
+    #[cfg(feature = "net-debug")]
+    pub fn log(&self, direction: &str, addr: &SocketAddr) {
+        let now = timestamp_micros();
+        log::debug!(
+            "[net-debug] {} packet #{} {:?} for {} ({} bytes) {:?}, sent at {}us, {}us ago",
+            direction,
+            self.debug.tag,
+            self.message_type,
+            self.net_id,
+            self.data.len(),
+            addr,
+            self.debug.sent_at,
+            now.saturating_sub(self.debug.sent_at)
+        );
+    }
+}
+
+/// Metadata for tracing a single packet through the whole pipeline
+/// 
+/// The tag is unique per process, so to follow a message from one
+/// machine to another look for the same tag in the logs of both.
+/// The age is computed from wall clock time and thus only meaningful
+/// if the clocks of both machines are in sync.
+#[cfg(feature = "net-debug")]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PacketDebugInfo {
+    pub tag: u64,
+    // microseconds since unix epoch
+    pub sent_at: u64
+}
+
+#[cfg(feature = "net-debug")]
+impl PacketDebugInfo {
+    fn next() -> Self {
+        static NEXT_TAG: AtomicU64 = AtomicU64::new(0);
+        Self { tag: NEXT_TAG.fetch_add(1, Ordering::Relaxed), sent_at: timestamp_micros() }
+    }
+}
+
+/*
+Microseconds since unix epoch, the clock StateHistory and PacketDebugInfo use
+Only comparable between machines if their clocks are in sync
+*/
+pub fn timestamp_micros() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(v) => v.as_micros() as u64,
+        Err(_) => 0
+    }
+}
+
+/// State of the connection, updated from the network thread by the ConnectionMonitor system
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Connecting,
+    // for servers, listening for clients
+    Connected,
+    // the connection was established but got lost
+    Disconnected,
+    // the connection could not be established at all
+    Failed
+}
+
+/// Called with the new state whenever ConnectionState changes
+#[derive(Default)]
+pub struct ConnectionCallbacks(Vec<Box<dyn Fn(ConnectionState) + Send + Sync>>);
+
+impl ConnectionCallbacks {
+    pub fn register(&mut self, callback: impl Fn(ConnectionState) + Send + Sync + 'static) {
+        self.0.push(Box::new(callback));
+    }
+
+    pub fn notify(&self, state: ConnectionState) {
+        for callback in self.0.iter() {
+            callback(state);
+        }
+    }
+}
+
+/// Keep-alives a client sends over its UDP socket, which keep the NAT mapping open and
+/// measure the round trip time of the same path the game messages take
+/// Datagrams can get lost, so only max_missed unanswered keep-alives in a row count as a lost connection
+#[derive(Clone, Copy, Debug)]
+pub struct KeepAlive {
+    // None disables keep-alives
+    pub interval: Option<Duration>,
+    // not answered after this long counts as missed
+    pub timeout: Duration,
+    pub max_missed: u32
 }
 
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self { interval: Some(Duration::from_secs(1)), timeout: Duration::from_secs(2), max_missed: 5 }
+    }
+}
+
+/// Measured by the keep-alives of the network thread, copied over by the ConnectionMonitor system
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeepAliveStats {
+    // smoothed over the last answers, None until the first one arrived
+    pub round_trip: Option<Duration>,
+    // unanswered in a row
+    pub missed: u32
+}
+
+pub const DEFAULT_MAX_REPLICATED: usize = 4096;
+
 pub struct NetworkData {
     pub sender: Sender<NetworkMessageData>,
     pub receiver: Receiver<NetworkMessageData>,
     pub target_addr: SocketAddr,
-    pub net_id_ent: HashMap<Uuid, Entity>
+    pub connection_state: watch::Receiver<ConnectionState>,
+    pub keep_alive: watch::Receiver<KeepAliveStats>,
+    pub net_id_ent: HashMap<Uuid, Entity>,
+    // upper limit for registered net ids, protects against
+    // a peer flooding us with new entities
+    pub max_replicated: usize
+}
+
+impl NetworkData {
+    /*
+    Maps net_id to entity, so received messages can be applied without searching
+    Replaces any entity previously registered with the same net_id
+    Returns false if the net_id is new and max_replicated has been reached
+    */
+    pub fn register(&mut self, net_id: Uuid, entity: Entity) -> bool {
+        if !self.net_id_ent.contains_key(&net_id) && self.net_id_ent.len() >= self.max_replicated {
+            log::warn!("Reached the maximum of {} replicated entities, rejecting net_id {net_id}", self.max_replicated);
+            return false;
+        }
+
+        if let Some(old) = self.net_id_ent.insert(net_id, entity) {
+            if old != entity {
+                log::warn!("net_id {net_id} was registered to {:?}, replacing it with {:?}", old, entity);
+            }
+        }
+
+        true
+    }
+
+    pub fn unregister(&mut self, net_id: &Uuid) -> Option<Entity> {
+        self.net_id_ent.remove(net_id)
+    }
+
+    pub fn entity(&self, net_id: &Uuid) -> Option<Entity> {
+        self.net_id_ent.get(net_id).copied()
+    }
+}
+
+/// Server side, positions of replicated entities over the last length, recorded by StateHistoryRecorder
+/// Used for lag compensation, e.g. testing a shot against where targets were when the shooter saw them
+/// Times are microseconds since unix epoch, see timestamp_micros
+pub struct StateHistory {
+    length: Duration,
+    frames: VecDeque<HistoryFrame>
+}
+
+/// Positions of the replicated entities at time, of the rigid body if the entity has one
+pub struct HistoryFrame {
+    pub time: u64,
+    pub positions: HashMap<Entity, Isometry3<f32>>
+}
+
+impl Default for StateHistory {
+    fn default() -> Self {
+        StateHistory::new(Duration::from_millis(500))
+    }
+}
+
+impl StateHistory {
+    pub fn new(length: Duration) -> Self {
+        Self { length, frames: VecDeque::new() }
+    }
+
+    pub fn length(&self) -> Duration {
+        self.length
+    }
+
+    /*
+    Adds the positions at time, dropping frames older than length before it
+    Frames have to be recorded in order, one older than the latest is ignored
+    */
+    pub fn record(&mut self, time: u64, positions: HashMap<Entity, Isometry3<f32>>) {
+        if self.frames.back().map_or(false, |f| f.time > time) {
+            return log::warn!("Tried to record a state history frame older than the latest one, ignoring it");
+        }
+
+        self.frames.push_back(HistoryFrame { time, positions });
+
+        let oldest = time.saturating_sub(self.length.as_micros() as u64);
+        while self.frames.front().map_or(false, |f| f.time < oldest) {
+            self.frames.pop_front();
+        }
+    }
+
+    pub fn frames(&self) -> &VecDeque<HistoryFrame> {
+        &self.frames
+    }
+
+    /*
+    Position of entity at time, interpolated between the recorded frames around it
+    Times outside of the history use the oldest or latest frame
+    None if the entity wasn't recorded in the frames around time
+    */
+    pub fn position_at(&self, entity: Entity, time: u64) -> Option<Isometry3<f32>> {
+        let next = self.frames.iter().position(|f| f.time >= time);
+        let (before, after) = match next {
+            Some(0) => (None, self.frames.front()),
+            Some(i) => (self.frames.get(i - 1), self.frames.get(i)),
+            None => (self.frames.back(), None)
+        };
+
+        match (before.and_then(|f| f.positions.get(&entity).map(|p| (f.time, p))), after.and_then(|f| f.positions.get(&entity).map(|p| (f.time, p)))) {
+            (Some((t0, p0)), Some((t1, p1))) => {
+                let t = (time - t0) as f32 / (t1 - t0).max(1) as f32;
+                Some(p0.try_lerp_slerp(p1, t, f32::EPSILON).unwrap_or(*p1))
+            },
+            (Some((_, p)), None) | (None, Some((_, p))) => Some(*p),
+            (None, None) => None
+        }
+    }
+
+    /*
+    Moves the colliders of every recorded entity with a rigid body to where it was at time,
+    for queries like PhysicsData::cast_ray, and returns what restores them
+    Only the colliders and the query pipeline are changed, the simulation doesn't see the rewind,
+    but it has to be restored before the next physics step
+    */
+    pub fn rewind_to(&self, time: u64, physics_data: &mut PhysicsData, rigid_bodies: &ReadStorage<'_, RigidBodyComponent>) -> Rewind {
+        let entities = match self.frames.back() {
+            Some(v) => v.positions.keys().copied().collect::<Vec<_>>(),
+            None => return Rewind { colliders: Vec::new() }
+        };
+
+        let mut moved = Vec::new();
+        for entity in entities {
+            let (rigid_body, position) = match (rigid_bodies.get(entity), self.position_at(entity, time)) {
+                (Some(r), Some(p)) => (r, p),
+                _ => continue
+            };
+
+            let colliders = match physics_data.rigid_body(rigid_body.handle) {
+                Some(v) => v.colliders().to_vec(),
+                None => continue
+            };
+
+            for handle in colliders {
+                if let Some(collider) = physics_data.collider_mut(handle) {
+                    let relative = collider.position_wrt_parent().copied().unwrap_or_else(Isometry3::identity);
+                    moved.push((handle, *collider.position()));
+                    collider.set_position(position * relative);
+                }
+            }
+        }
+
+        physics_data.update_query_pipeline();
+        Rewind { colliders: moved }
+    }
+}
+
+/// Colliders moved by StateHistory::rewind_to, with their positions from before
+#[must_use = "the colliders stay rewound until restored"]
+pub struct Rewind {
+    colliders: Vec<(ColliderHandle, Isometry3<f32>)>
+}
+
+impl Rewind {
+    /*
+    Moves the colliders back to their current positions
+    */
+    pub fn restore(self, physics_data: &mut PhysicsData) {
+        for (handle, position) in self.colliders {
+            if let Some(collider) = physics_data.collider_mut(handle) {
+                collider.set_position(position);
+            }
+        }
+
+        physics_data.update_query_pipeline();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Translation3, Vector3};
+    use specs::{World, WorldExt, Builder};
+
+    use super::*;
+
+    fn at(x: f32) -> Isometry3<f32> {
+        Isometry3::from_parts(Translation3::new(x, 0.0, 0.0), Default::default())
+    }
+
+    #[test]
+    fn history_drops_frames_older_than_its_length() {
+        let entity = World::new().create_entity().build();
+        let mut history = StateHistory::new(Duration::from_millis(100));
+        history.record(1_000_000, HashMap::from([(entity, at(0.0))]));
+        history.record(1_050_000, HashMap::from([(entity, at(1.0))]));
+        history.record(1_120_000, HashMap::from([(entity, at(2.0))]));
+        assert_eq!(history.frames().iter().map(|f| f.time).collect::<Vec<_>>(), vec![1_050_000, 1_120_000]);
+
+        // out of order frames are ignored
+        history.record(1_100_000, HashMap::from([(entity, at(3.0))]));
+        assert_eq!(history.frames().len(), 2);
+    }
+
+    #[test]
+    fn position_at_interpolates_between_frames() {
+        let mut world = World::new();
+        let (entity, unrecorded) = (world.create_entity().build(), world.create_entity().build());
+        let mut history = StateHistory::default();
+        history.record(1_000_000, HashMap::from([(entity, at(0.0))]));
+        history.record(1_100_000, HashMap::from([(entity, at(4.0))]));
+
+        let halfway = history.position_at(entity, 1_025_000).unwrap();
+        assert!((halfway.translation.vector - Vector3::new(1.0, 0.0, 0.0)).norm() < 1e-5);
+        // clamped to the oldest and latest frame
+        assert_eq!(history.position_at(entity, 0), Some(at(0.0)));
+        assert_eq!(history.position_at(entity, 2_000_000), Some(at(4.0)));
+        assert_eq!(history.position_at(unrecorded, 1_050_000), None);
+    }
 }
@@ -1,21 +1,183 @@
-use nalgebra::Vector3;
-use rapier3d::prelude::{RigidBodySet, ColliderSet, IntegrationParameters, PhysicsPipeline, IslandManager, BroadPhase, NarrowPhase, ImpulseJointSet, MultibodyJointSet, CCDSolver, QueryPipeline};
+use std::collections::HashMap;
 
+use log::warn;
+use nalgebra::{Vector3, Point3, UnitVector3};
+use rapier3d::control::{KinematicCharacterController, EffectiveCharacterMovement};
+use rapier3d::pipeline::{DebugRenderPipeline, DebugRenderBackend};
+use rapier3d::prelude::{CollisionEvent, ColliderHandle, EventHandler, RigidBodySet, RigidBodyHandle, Collider, Shape, ColliderSet, IntegrationParameters, PhysicsPipeline, IslandManager, BroadPhase, NarrowPhase, ImpulseJointSet, MultibodyJointSet, CCDSolver, QueryPipeline, SharedShape, Isometry, QueryFilter, Ray, RigidBody, RigidBodyActivation, DebugRenderMode};
+use specs::Entity;
 
+
+/// Entities with a rigid body falling below this height are respawned
+pub struct KillPlane(pub f32);
+
+/// Draws the debug view of rapier's DebugRenderPipeline as lines instead of the ColliderRenderable meshes,
+/// which also shows joints, contacts and rigid body axes depending on mode
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicsDebugRender {
+    pub enabled: bool,
+    pub mode: DebugRenderMode
+}
+
+impl Default for PhysicsDebugRender {
+    fn default() -> Self {
+        PhysicsDebugRender { enabled: false, mode: DebugRenderMode::default() | DebugRenderMode::CONTACTS }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ContactPoint {
+    // in world space
+    pub point: Point3<f32>,
+    // in world space, pointing from collider1 towards collider2
+    pub normal: Vector3<f32>,
+    // negative if the colliders are penetrating
+    pub distance: f32
+}
+
+#[derive(Clone, Debug)]
+pub struct CollisionEventData {
+    pub event: CollisionEvent,
+    // empty for sensors and for stopped events
+    // for sensors, started means a collider entered it and stopped that it left
+    pub contacts: Vec<ContactPoint>,
+    // largest relative speed of the two bodies along the contact normal
+    pub impact_speed: f32,
+    // entities of collider1 and collider2, None for colliders without a ColliderComponent
+    pub entity1: Option<Entity>,
+    pub entity2: Option<Entity>
+}
+
+impl CollisionEventData {
+    pub fn collider1(&self) -> ColliderHandle {
+        self.event.collider1()
+    }
+
+    pub fn collider2(&self) -> ColliderHandle {
+        self.event.collider2()
+    }
+
+    pub fn started(&self) -> bool {
+        self.event.started()
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.event.stopped()
+    }
+
+    // true if either collider is a sensor
+    pub fn sensor(&self) -> bool {
+        self.event.sensor()
+    }
+
+    pub fn involves(&self, entity: Entity) -> bool {
+        self.entity1 == Some(entity) || self.entity2 == Some(entity)
+    }
+
+    /*
+    The entity entity collided with, None if entity isn't part of the event
+    or the other collider doesn't belong to an entity
+    */
+    pub fn other(&self, entity: Entity) -> Option<Entity> {
+        if self.entity1 == Some(entity) {
+            self.entity2
+        }
+        else if self.entity2 == Some(entity) {
+            self.entity1
+        }
+        else {
+            None
+        }
+    }
+
+    /*
+    The contact point penetrating the deepest, if any
+    */
+    pub fn deepest_contact(&self) -> Option<&ContactPoint> {
+        self.contacts
+            .iter()
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+/// Collision events from the latest physics step, replaced by the Physics system every step
+/// Systems reacting to them, e.g. for damage or pickups, should run after physics
+#[derive(Default)]
+pub struct CollisionEvents(pub Vec<CollisionEventData>);
+
+/// When idle rigid bodies are put to sleep, a body sleeps once both its linear
+/// and angular velocity stayed below the thresholds for time_until_sleep seconds
+/// Sleeping bodies are skipped by the solver until something touches them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SleepThresholds {
+    pub linear: f32,
+    pub angular: f32,
+    // in seconds
+    pub time_until_sleep: f32
+}
+
+impl Default for SleepThresholds {
+    fn default() -> Self {
+        SleepThresholds {
+            linear: RigidBodyActivation::default_linear_threshold(),
+            angular: RigidBodyActivation::default_angular_threshold(),
+            time_until_sleep: RigidBodyActivation::default_time_until_sleep()
+        }
+    }
+}
+
+impl SleepThresholds {
+    /*
+    Applies the thresholds to the rigid body, unless it was built with can_sleep(false)
+    */
+    pub fn apply(&self, rigid_body: &mut RigidBody) {
+        // rapier marks bodies which can't sleep with negative thresholds
+        if rigid_body.activation().linear_threshold < 0.0 {
+            return;
+        }
+
+        let activation = rigid_body.activation_mut();
+        activation.linear_threshold = self.linear;
+        activation.angular_threshold = self.angular;
+        activation.time_until_sleep = self.time_until_sleep;
+    }
+}
+
+/// The rapier world, inserted as a resource by the game
+///
+/// Access goes through specs, which never runs a system writing this resource
+/// alongside any other system fetching it, so there is only ever a single writer.
+/// The three phases map onto that as follows:
+/// - step: only the Physics system advances the simulation, through PhysicsData::step,
+///   the pipeline, broad phase and CCD solver are private so nothing else can step it
+/// - query: methods taking &self (rigid_body, collider, intersect_shape, cast_ray, move_character, ...),
+///   systems which only query take Read<PhysicsData> and may run in parallel with each other
+/// - mutate: adding, removing or moving bodies and colliders goes through the &mut self methods
+///   (insert_rigid_body, rigid_body_mut, remove_rigid_body, ...), so it needs Write<PhysicsData>
+/// The rapier sets are private, so all access has to go through one of these methods
+///
+/// Helpers called from systems take &PhysicsData or &mut PhysicsData as an argument
+/// and must not fetch it from the World again, as that panics while the system holds it.
+/// Outside of the dispatcher, e.g. while setting up the game, the same rules apply
+/// to World::fetch and World::fetch_mut.
 pub struct PhysicsData {
-    pub rigid_body_set: RigidBodySet,
-    pub collider_set: ColliderSet,
+    rigid_body_set: RigidBodySet,
+    collider_set: ColliderSet,
 
     pub gravity: Vector3<f32>,
     pub integration_parameters: IntegrationParameters,
-    pub physics_pipeline: PhysicsPipeline,
-    pub island_manager: IslandManager,
-    pub broad_phase: BroadPhase,
-    pub narrow_phase: NarrowPhase,
-    pub impulse_joint_set: ImpulseJointSet,
-    pub multibody_joint_set: MultibodyJointSet,
-    pub ccd_solver: CCDSolver,
-    pub query_pipeline: QueryPipeline
+    physics_pipeline: PhysicsPipeline,
+    island_manager: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    impulse_joint_set: ImpulseJointSet,
+    multibody_joint_set: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    query_pipeline: QueryPipeline,
+    // applied to every rigid body added with RigidBodyComponent::new
+    sleep_thresholds: SleepThresholds,
+    // entity of each ColliderComponent, see attach_entity
+    collider_entities: HashMap<ColliderHandle, Entity>
 }
 
 impl Default for PhysicsData {
@@ -32,39 +194,315 @@ impl Default for PhysicsData {
             impulse_joint_set: Default::default(), 
             multibody_joint_set: Default::default(), 
             ccd_solver: Default::default(), 
-            query_pipeline: Default::default() 
+            query_pipeline: Default::default(),
+            sleep_thresholds: Default::default(),
+            collider_entities: Default::default()
         }
     }
 }
 
 impl PhysicsData {
-    pub fn split_borrow(&mut self) -> (
-        &Vector3<f32>,
-        &IntegrationParameters,
-        &mut PhysicsPipeline,
-        &mut IslandManager,
-        &mut BroadPhase,
-        &mut NarrowPhase,
-        &mut RigidBodySet,
-        &mut ColliderSet,
-        &mut ImpulseJointSet,
-        &mut MultibodyJointSet,
-        &mut CCDSolver,
-        &mut QueryPipeline
-    ) {
-        (
-            &self.gravity, 
-            &self.integration_parameters,
-            &mut self.physics_pipeline,
+    /*
+    Sets the contact skin, the distance at which contacts between two colliders
+    start being generated before they actually touch (rapier's prediction distance)
+
+    A larger skin lets the solver react before thin or fast objects interpenetrate,
+    at the cost of more contacts being tracked and objects possibly appearing to
+    rest slightly above each other. Values of a couple of centimeters work well
+    for objects around a meter in size.
+
+    Rapier 0.17 only supports this globally, not per collider.
+    Kinematic characters are unaffected, their equivalent is the offset
+    of the KinematicCharacterController.
+    */
+    pub fn set_contact_skin(&mut self, skin: f32) {
+        if skin < 0.0 {
+            return warn!("Tried to set a negative contact skin {skin}, ignoring");
+        }
+
+        self.integration_parameters.prediction_distance = skin;
+    }
+
+    pub fn contact_skin(&self) -> f32 {
+        self.integration_parameters.prediction_distance
+    }
+
+    /*
+    Sets when idle rigid bodies fall asleep, for existing bodies as well as ones added later
+    Higher thresholds let settled objects sleep sooner, saving cpu time,
+    but slow moving bodies may then stop before they come to rest
+    Bodies built with can_sleep(false) keep never sleeping
+    */
+    pub fn set_sleep_thresholds(&mut self, thresholds: SleepThresholds) {
+        if thresholds.linear < 0.0 || thresholds.angular < 0.0 || thresholds.time_until_sleep < 0.0 {
+            return warn!("Tried to set negative sleep thresholds {:?}, ignoring", thresholds);
+        }
+
+        self.sleep_thresholds = thresholds;
+        for (_, rigid_body) in self.rigid_body_set.iter_mut() {
+            thresholds.apply(rigid_body);
+        }
+    }
+
+    pub fn sleep_thresholds(&self) -> SleepThresholds {
+        self.sleep_thresholds
+    }
+
+    /*
+    Changes gravity from the next step on, e.g. for zero-g sections or flipped gravity
+    Wakes every dynamic body, sleeping ones would otherwise keep hanging in place until touched
+    */
+    pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
+        self.gravity = gravity;
+        for (_, rigid_body) in self.rigid_body_set.iter_mut() {
+            if rigid_body.is_dynamic() {
+                rigid_body.wake_up(true);
+            }
+        }
+    }
+
+    /*
+    Opposite of the gravity direction, None without gravity
+    Character controllers use it as their up direction for ground detection
+    */
+    pub fn up(&self) -> Option<UnitVector3<f32>> {
+        UnitVector3::try_new(-self.gravity, f32::EPSILON)
+    }
+
+    /*
+    Returns the handles of all colliders overlapping the given shape placed at position
+
+    The query pipeline is updated during the physics step,
+    so colliders added after the latest step are not found
+    */
+    pub fn intersect_shape(&self, shape: &SharedShape, position: &Isometry<f32>, filter: QueryFilter<'_>) -> Vec<ColliderHandle> {
+        let mut handles = Vec::new();
+        self.query_pipeline.intersections_with_shape(
+            &self.rigid_body_set,
+            &self.collider_set,
+            position,
+            shape.as_ref(),
+            filter,
+            |handle| {
+                handles.push(handle);
+                true
+            }
+        );
+        handles
+    }
+
+    /*
+    Returns the entity of the first collider hit by the ray and the distance along it,
+    direction doesn't have to be normalized but the distance is in multiples of it
+    None if nothing was hit or the collider doesn't belong to an entity, see attach_entity
+    */
+    pub fn cast_ray(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32, filter: QueryFilter<'_>) -> Option<(Entity, f32)> {
+        let (handle, distance) = self.cast_ray_collider(origin, direction, max_distance, filter)?;
+        self.collider_entity(handle).map(|e| (e, distance))
+    }
+
+    /*
+    Same as cast_ray, but returns the collider which was hit
+    Same as intersect_shape, colliders added after the latest step are not found
+    */
+    pub fn cast_ray_collider(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32, filter: QueryFilter<'_>) -> Option<(ColliderHandle, f32)> {
+        self.query_pipeline.cast_ray(
+            &self.rigid_body_set,
+            &self.collider_set,
+            &Ray::new(origin, direction),
+            max_distance,
+            true,
+            filter
+        )
+    }
+
+    /*
+    Records entity as the owner of the collider, the Physics system does this for every
+    ColliderComponent before stepping, so new colliders are found by cast_ray from the next step on
+    The collider's user_data is set to the entity id in the low and its generation in the high 32 bits,
+    for use with rapier's own queries
+    */
+    pub fn attach_entity(&mut self, handle: ColliderHandle, entity: Entity) {
+        let collider = match self.collider_set.get_mut(handle) {
+            Some(v) => v,
+            None => return warn!("Tried to attach {:?} to a collider which doesn't exist", entity)
+        };
+        collider.user_data = (entity.gen().id() as u32 as u128) << 32 | entity.id() as u128;
+        self.collider_entities.insert(handle, entity);
+
+        // handles of removed colliders are never reused, so they would only pile up
+        if self.collider_entities.len() > self.collider_set.len() {
+            let collider_set = &self.collider_set;
+            self.collider_entities.retain(|h, _| collider_set.contains(*h));
+        }
+    }
+
+    pub fn collider_entity(&self, handle: ColliderHandle) -> Option<Entity> {
+        self.collider_entities.get(&handle).copied()
+    }
+
+    pub fn rigid_body(&self, handle: RigidBodyHandle) -> Option<&RigidBody> {
+        self.rigid_body_set.get(handle)
+    }
+
+    pub fn collider(&self, handle: ColliderHandle) -> Option<&Collider> {
+        self.collider_set.get(handle)
+    }
+
+    /*
+    Returns how far a character with the given shape can actually move along desired_translation,
+    see KinematicCharacterController::move_shape
+    Same as intersect_shape, colliders added after the latest step are not found
+    */
+    pub fn move_character(
+        &self,
+        controller: &KinematicCharacterController,
+        dt: f32,
+        shape: &dyn Shape,
+        position: &Isometry<f32>,
+        desired_translation: Vector3<f32>,
+        filter: QueryFilter<'_>
+    ) -> EffectiveCharacterMovement {
+        controller.move_shape(
+            dt,
+            &self.rigid_body_set,
+            &self.collider_set,
+            &self.query_pipeline,
+            shape,
+            position,
+            desired_translation,
+            filter,
+            |_| {}
+        )
+    }
+
+    /*
+    Draws the bodies, colliders, joints and contacts selected by the mode of pipeline into backend
+    */
+    pub fn debug_render(&self, pipeline: &mut DebugRenderPipeline, backend: &mut impl DebugRenderBackend) {
+        pipeline.render(
+            backend,
+            &self.rigid_body_set,
+            &self.collider_set,
+            &self.impulse_joint_set,
+            &self.multibody_joint_set,
+            &self.narrow_phase
+        );
+    }
+
+    pub fn insert_rigid_body(&mut self, rigid_body: RigidBody) -> RigidBodyHandle {
+        self.rigid_body_set.insert(rigid_body)
+    }
+
+    /*
+    Inserts the collider, attached to the rigid body of parent if there is one
+    */
+    pub fn insert_collider(&mut self, collider: Collider, parent: Option<RigidBodyHandle>) -> ColliderHandle {
+        match parent {
+            Some(v) => self.collider_set.insert_with_parent(collider, v, &mut self.rigid_body_set),
+            None => self.collider_set.insert(collider)
+        }
+    }
+
+    pub fn rigid_body_mut(&mut self, handle: RigidBodyHandle) -> Option<&mut RigidBody> {
+        self.rigid_body_set.get_mut(handle)
+    }
+
+    pub fn collider_mut(&mut self, handle: ColliderHandle) -> Option<&mut Collider> {
+        self.collider_set.get_mut(handle)
+    }
+
+    /*
+    Removes the rigid body along with its colliders and joints
+    */
+    pub fn remove_rigid_body(&mut self, handle: RigidBodyHandle) -> Option<RigidBody> {
+        self.rigid_body_set.remove(
+            handle,
+            &mut self.island_manager,
+            &mut self.collider_set,
+            &mut self.impulse_joint_set,
+            &mut self.multibody_joint_set,
+            true
+        )
+    }
+
+    /*
+    Makes queries see colliders added or moved since the latest step right away
+    */
+    pub fn update_query_pipeline(&mut self) {
+        self.query_pipeline.update(&self.rigid_body_set, &self.collider_set);
+    }
+
+    /*
+    Removes every rigid body, collider and joint, keeping gravity, integration parameters and sleep thresholds
+    Any RigidBodyComponent and ColliderComponent still around is left dangling,
+    use reset_physics to clean up the world as well
+    */
+    pub fn clear(&mut self) {
+        *self = PhysicsData {
+            gravity: self.gravity,
+            integration_parameters: self.integration_parameters,
+            sleep_thresholds: self.sleep_thresholds,
+            ..Default::default()
+        };
+    }
+
+    /*
+    Advances the simulation by dt seconds, ignoring the dt of integration_parameters
+    Collision and contact force events are passed to event_handler
+    Updates the query pipeline, so queries afterwards see the new positions
+    */
+    pub fn step(&mut self, dt: f32, event_handler: &dyn EventHandler) {
+        let integration_parameters = IntegrationParameters {
+            dt,
+            ..self.integration_parameters
+        };
+
+        self.physics_pipeline.step(
+            &self.gravity,
+            &integration_parameters,
             &mut self.island_manager,
             &mut self.broad_phase,
             &mut self.narrow_phase,
             &mut self.rigid_body_set,
-            &mut self.collider_set, 
+            &mut self.collider_set,
             &mut self.impulse_joint_set,
             &mut self.multibody_joint_set,
             &mut self.ccd_solver,
-            &mut self.query_pipeline
-        )
+            Some(&mut self.query_pipeline),
+            &(),
+            event_handler
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::DMatrix;
+    use rapier3d::prelude::ColliderBuilder;
+    use specs::{World, WorldExt, Builder};
+
+    use super::*;
+
+    #[test]
+    fn cast_ray_hits_terrain_entity() {
+        let mut world = World::new();
+        let terrain = world.create_entity().build();
+
+        let mut physics_data = PhysicsData::default();
+        // 10x10 flat terrain centered at the origin, 2 units high
+        let collider = ColliderBuilder::heightfield(DMatrix::from_element(4, 4, 2.0), Vector3::new(10.0, 1.0, 10.0)).build();
+        let handle = physics_data.insert_collider(collider, None);
+        physics_data.attach_entity(handle, terrain);
+        physics_data.update_query_pipeline();
+
+        let hit = physics_data.cast_ray(Point3::new(1.0, 5.0, 1.0), -Vector3::y(), 100.0, QueryFilter::default());
+        let (entity, distance) = hit.expect("ray should hit the terrain");
+        assert_eq!(entity, terrain);
+        assert!((distance - 3.0).abs() < 1e-4, "distance was {}", distance);
+
+        // pointing away and passing beside it
+        assert!(physics_data.cast_ray(Point3::new(1.0, 5.0, 1.0), Vector3::y(), 100.0, QueryFilter::default()).is_none());
+        assert!(physics_data.cast_ray(Point3::new(20.0, 5.0, 0.0), -Vector3::y(), 100.0, QueryFilter::default()).is_none());
     }
 }
@@ -0,0 +1,60 @@
+use log::trace;
+use specs::{System, Read, Write, ReadStorage};
+
+use crate::ecs::{components::{audio::CollisionSound, physics::ColliderComponent}, resources::{audio::{SoundQueue, SoundPlayer}, physics::CollisionEvents}};
+
+// Impact speed relative to the threshold at which a sound reaches full volume
+const FULL_VOLUME_IMPACT_MULTIPLIER: f32 = 4.0;
+
+/// Turns collision events of entities with a CollisionSound into SoundRequests
+pub struct CollisionSounds;
+
+impl<'a> System<'a> for CollisionSounds {
+    type SystemData = (
+        Read<'a, CollisionEvents>,
+        Write<'a, SoundQueue>,
+
+        ReadStorage<'a, ColliderComponent>,
+        ReadStorage<'a, CollisionSound>
+    );
+
+    fn run(&mut self, (collision_events, mut sound_queue, collider, collision_sound): Self::SystemData) {
+        use specs::Join;
+
+        for event in collision_events.0.iter().filter(|e| e.started()) {
+            let contact = match event.deepest_contact() {
+                Some(c) => c,
+                None => continue
+            };
+
+            for (c, s) in (&collider, &collision_sound).join() {
+                if c.handle() != event.collider1() && c.handle() != event.collider2() {
+                    continue;
+                }
+                if event.impact_speed < s.min_impact_speed {
+                    continue;
+                }
+
+                let full_volume_speed = s.min_impact_speed.max(f32::EPSILON) * FULL_VOLUME_IMPACT_MULTIPLIER;
+                sound_queue.play(&s.sound, contact.point, event.impact_speed / full_volume_speed);
+            }
+        }
+    }
+}
+
+/// Empties SoundQueue every frame, handing the requests to SoundPlayer
+pub struct PlaySounds;
+
+impl<'a> System<'a> for PlaySounds {
+    type SystemData = (
+        Write<'a, SoundQueue>,
+        Read<'a, SoundPlayer>
+    );
+
+    fn run(&mut self, (mut sound_queue, sound_player): Self::SystemData) {
+        for request in sound_queue.drain() {
+            trace!("Playing sound {} at {:?}", request.sound, request.position);
+            sound_player.play(&request);
+        }
+    }
+}
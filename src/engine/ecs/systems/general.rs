@@ -1,30 +1,38 @@
 use std::sync::Arc;
 
+use gilrs::{Axis, Button};
 use log::{error, debug, warn};
 use nalgebra::{clamp, UnitQuaternion, Vector3};
 use rapier3d::prelude::RigidBody;
-use specs::{System, Read, ReadStorage, WriteStorage, Write};
+use specs::{System, Read, ReadStorage, WriteStorage, Write, Entities, Entity};
 use vulkano::swapchain::Surface;
 use winit::{event::VirtualKeyCode, window::{CursorGrabMode}, dpi::PhysicalPosition};
 use winit_input_helper::WinitInputHelper;
 
-use crate::{ecs::{components::{general::{Camera, Transform, Movement}, physics::{RigidBodyComponent, ColliderComponent}}, resources::{CursorGrab, physics::PhysicsData, DeltaTime}}, graphics::utils::get_window_from_surface};
+use crate::{ecs::{components::{general::{Camera, AttachedCamera, FaceMovement, InputSource, Transform, Movement, UpVector, SpriteAnimation, Lifetime, UpdateEvery, Health, HealthRegen}, physics::{RigidBodyComponent, ColliderComponent}}, resources::{CursorGrab, physics::PhysicsData, DeltaTime, UnscaledDeltaTime, input::{Gamepads, GamepadState}}}, graphics::utils::get_window_from_surface};
 
 pub struct PlayerInput;
 
+// Degrees per second at full deflection of the right stick
+const GAMEPAD_LOOK_SPEED: f32 = 180.0;
+
 impl<'a> System<'a> for PlayerInput {
     type SystemData = (
         Read<'a, DeltaTime>,
+        Read<'a, UnscaledDeltaTime>,
         Option<Read<'a, Arc<WinitInputHelper>>>,
         Option<Read<'a, Arc<Surface>>>,
+        Read<'a, Gamepads>,
         Write<'a, CursorGrab>,
         ReadStorage<'a, Camera>,
         ReadStorage<'a, RigidBodyComponent>,
+        ReadStorage<'a, InputSource>,
+        ReadStorage<'a, UpVector>,
         WriteStorage<'a, Movement>,
         WriteStorage<'a, Transform>,
     );
 
-    fn run(&mut self, (delta, input, surface, mut cursor_grabbed, camera, rigid_body, mut movement, mut transform): Self::SystemData) {
+    fn run(&mut self, (delta, unscaled_delta, input, surface, gamepads, mut cursor_grabbed, camera, rigid_body, input_source, up_vector, mut movement, mut transform): Self::SystemData) {
         use specs::Join;
         // Verify we have all dependencies
         // Abort if not
@@ -49,8 +57,11 @@ impl<'a> System<'a> for PlayerInput {
             None => return error!("Could not get window in PlayerInput")
         };
 
-        let last_x: Option<f32>;
-        let last_y: Option<f32>;
+        let mut last_x: Option<f32> = None;
+        let mut last_y: Option<f32> = None;
+        // the cursor is only centered after grabbing, so the mouse delta
+        // of this frame is relative to wherever it was clicked
+        let just_grabbed = input.mouse_pressed(0) && !cursor_grabbed.0;
         if input.mouse_pressed(0) {
             let result = window.set_cursor_grab(CursorGrabMode::Confined)
                 .or_else(|_e| window.set_cursor_grab(CursorGrabMode::Locked));
@@ -88,9 +99,6 @@ impl<'a> System<'a> for PlayerInput {
                 Err(e) => debug!("Failed to set cursor position, not available on some platforms: {:?}", e)
             };
         }
-        else {
-            return
-        }
 
         // the mouse should never be outside but taking it into account still
         let (x, y) = match input.mouse() {
@@ -98,24 +106,92 @@ impl<'a> System<'a> for PlayerInput {
             None => (0.0, 0.0)
         };
 
-        for (_, r, m, t) in (&camera, &rigid_body, &mut movement, &mut transform).join() {
+        for (_, r, source, up, m, t) in (&camera, &rigid_body, input_source.maybe(), up_vector.maybe(), &mut movement, &mut transform).join() {
+            let pad = match source.copied().unwrap_or_default() {
+                // the keyboard and mouse only control the game while the cursor is grabbed
+                InputSource::KeyboardMouse if !cursor_grabbed.0 => continue,
+                InputSource::KeyboardMouse => None,
+                InputSource::Gamepad(index) => match gamepads.get(index) {
+                    Some(v) => Some(v),
+                    // stands still until the gamepad is connected
+                    None => continue
+                }
+            };
+
             if !r.has_character_controller() {
                 error!("Entity has movement but rigid body component does not have a character controller. Movement will not be applied!");
                 continue;
             }
 
-            t.rot = match self.calculate_rotation(x, y, last_x, last_y, m) {
-                Some(v) => v,
-                None => t.rot
+            let alignment = match up {
+                Some(v) => v.alignment(),
+                None => UnitQuaternion::identity()
+            };
+
+            let rotation = match pad {
+                None if just_grabbed => None,
+                None => self.calculate_rotation(x, y, last_x, last_y, m),
+                Some(pad) => {
+                    let [look_x, look_y] = pad.stick(Axis::RightStickX, Axis::RightStickY);
+                    // the camera turns at the same speed in slow motion, like it does with the mouse
+                    let speed = GAMEPAD_LOOK_SPEED * unscaled_delta.0;
+                    self.turn(-look_x * speed, look_y * speed, m)
+                }
             };
+            if let Some(v) = rotation {
+                t.set_rot(v);
+            }
+
+            // the up vector can change without the mouse moving, so the rotation is rebuilt every frame
+            if up.is_some() {
+                t.set_rot(alignment * UnitQuaternion::from_euler_angles(m.pitch.to_radians(), m.yaw.to_radians(), 0.0));
+            }
 
-            if m.can_jump(r.grounded) && input.key_pressed(VirtualKeyCode::Space) {
-                let jump_accel = Vector3::y() * m.jump;
+            let jump = match pad {
+                None => input.key_pressed(VirtualKeyCode::Space),
+                Some(pad) => pad.pressed(Button::South)
+            };
+            if m.can_jump(r.grounded) && jump {
+                let jump_accel = alignment * Vector3::y() * m.jump;
                 t.apply_acceleration(&jump_accel);
                 m.consume_jump(r.grounded)
             }
 
-            t.apply_movement(&self.calculate_movement(&input, &t.rot, m, delta.0));
+            let controls = match pad {
+                None => MoveControls::keyboard(&input),
+                Some(pad) => MoveControls::gamepad(pad)
+            };
+            t.apply_movement(&self.calculate_movement(&controls, &t.rot(), &alignment, m, delta.0));
+        }
+    }
+}
+
+/// Movement asked for by one input device, forward and right are in [-1, 1]
+struct MoveControls {
+    forward: f32,
+    right: f32,
+    boost: bool,
+    slow: bool
+}
+
+impl MoveControls {
+    fn keyboard(input: &WinitInputHelper) -> MoveControls {
+        let axis = |positive, negative| (input.key_held(positive) as i32 - input.key_held(negative) as i32) as f32;
+        MoveControls {
+            forward: axis(VirtualKeyCode::W, VirtualKeyCode::S),
+            right: axis(VirtualKeyCode::D, VirtualKeyCode::A),
+            boost: input.held_shift(),
+            slow: input.held_control()
+        }
+    }
+
+    fn gamepad(pad: &GamepadState) -> MoveControls {
+        let [right, forward] = pad.stick(Axis::LeftStickX, Axis::LeftStickY);
+        MoveControls {
+            forward,
+            right,
+            boost: pad.held(Button::RightTrigger2),
+            slow: pad.held(Button::LeftTrigger2)
         }
     }
 }
@@ -135,54 +211,227 @@ impl PlayerInput {
             m.last_x = x;
             m.last_y = y;
 
-            m.yaw += dx * m.sensitivity;
-            m.pitch = clamp(m.pitch + dy * m.sensitivity, -89.0, 89.0);
-
-            if m.yaw > 360.0 {
-                m.yaw -= 360.0;
-            }
-            else if m.yaw < 0.0 {
-                m.yaw += 360.0;
-            }
-
-            // roll, pitch, yaw is actually x,y,z
-            Some(UnitQuaternion::from_euler_angles(
-                m.pitch.to_radians(), 
-                m.yaw.to_radians(),
-                0.0
-            ))
+            self.turn(dx * m.sensitivity, dy * m.sensitivity, m)
         }
         else {
             None
         }
     }
 
-    fn calculate_movement(&self, input: &Arc<WinitInputHelper>, rot: &UnitQuaternion<f32>, m: &Movement, delta: f32) -> Vector3<f32> {
+    /*
+    Adds to the yaw and pitch in degrees, pitch stays within 89 degrees of the horizon
+    */
+    fn turn(&self, yaw: f32, pitch: f32, m: &mut Movement) -> Option<UnitQuaternion<f32>> {
+        if yaw == 0.0 && pitch == 0.0 {
+            return None;
+        }
+
+        m.yaw += yaw;
+        m.pitch = clamp(m.pitch + pitch, -89.0, 89.0);
+
+        if m.yaw > 360.0 {
+            m.yaw -= 360.0;
+        }
+        else if m.yaw < 0.0 {
+            m.yaw += 360.0;
+        }
+
+        // roll, pitch, yaw is actually x,y,z
+        Some(UnitQuaternion::from_euler_angles(
+            m.pitch.to_radians(), 
+            m.yaw.to_radians(),
+            0.0
+        ))
+    }
+
+    fn calculate_movement(&self, controls: &MoveControls, rot: &UnitQuaternion<f32>, alignment: &UnitQuaternion<f32>, m: &Movement, delta: f32) -> Vector3<f32> {
+        // walking ignores pitch so looking down doesn't move us into the ground
+        let rot = match m.fly {
+            true => *rot,
+            false => alignment * UnitQuaternion::from_euler_angles(0.0, m.yaw.to_radians(), 0.0)
+        };
         let forward = rot * Vector3::new(0.0, 0.0, -1.0);
         let right = rot * Vector3::new(1.0, 0.0, 0.0);
 
         let mut speed = m.speed;
-        if input.held_shift() {
+        if controls.boost {
             speed += m.boost;
         }
-        else if input.held_control() {
+        else if controls.slow {
             speed -= m.slow;
         }
 
-        let mut cum_move = Vector3::new(0.0, 0.0, 0.0);
-        if input.key_held(VirtualKeyCode::W) {
-            cum_move += forward * speed;
+        let cum_move = (forward * controls.forward + right * controls.right) * speed;
+
+        return cum_move * delta;
+    }
+}
+
+/// Moves every AttachedCamera to its target, after PlayerInput has moved and turned the target
+pub struct FollowCamera;
+
+impl<'a> System<'a> for FollowCamera {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, AttachedCamera>,
+        ReadStorage<'a, UpVector>,
+        WriteStorage<'a, Transform>
+    );
+
+    fn run(&mut self, (entities, attached_camera, up_vector, mut transform): Self::SystemData) {
+        use specs::Join;
+
+        let updates: Vec<(Entity, Transform)> = (&entities, &attached_camera).join()
+            .filter_map(|(e, a)| {
+                // despawned target, the camera stays where it was
+                let target = transform.get(a.target)?;
+                let up = match up_vector.get(a.target) {
+                    Some(v) => v.0.into_inner(),
+                    None => Vector3::y()
+                };
+                Some((e, a.transform_for(target, &up)))
+            })
+            .collect();
+
+        for (e, t) in updates {
+            if let Some(v) = transform.get_mut(e) {
+                v.set_pos(t.pos());
+                v.set_rot(t.rot());
+            }
         }
-        if input.key_held(VirtualKeyCode::S) {
-            cum_move -= forward * speed;
+    }
+}
+
+/// Places every FaceMovement entity at its target and turns it towards the direction the target moves in
+/// Runs after PlayerInput, before Physics uses up the movement on the next frame
+pub struct FaceMovementDirection;
+
+impl<'a> System<'a> for FaceMovementDirection {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, DeltaTime>,
+        ReadStorage<'a, FaceMovement>,
+        ReadStorage<'a, UpVector>,
+        WriteStorage<'a, Transform>
+    );
+
+    fn run(&mut self, (entities, delta, face_movement, up_vector, mut transform): Self::SystemData) {
+        use specs::Join;
+
+        let updates: Vec<(Entity, Vector3<f32>, Option<UnitQuaternion<f32>>, f32)> = (&entities, &face_movement).join()
+            .filter_map(|(e, f)| {
+                // despawned target, the entity stays where it was
+                let target = transform.get(f.target)?;
+                let alignment = match up_vector.get(f.target) {
+                    Some(v) => v.alignment(),
+                    None => UnitQuaternion::identity()
+                };
+                Some((e, target.pos() + alignment * f.offset, FaceMovement::facing(&target.mov, &alignment), f.turn_speed))
+            })
+            .collect();
+
+        for (e, pos, facing, turn_speed) in updates {
+            if let Some(t) = transform.get_mut(e) {
+                t.set_pos(pos);
+                if let Some(desired) = facing {
+                    t.set_rot(FaceMovement::turn_towards(&t.rot(), &desired, turn_speed * delta.0));
+                }
+            }
         }
-        if input.key_held(VirtualKeyCode::A) {
-            cum_move -= right * speed;
+    }
+}
+
+/// Advances all sprite sheet animations by DeltaTime
+pub struct SpriteAnimator;
+
+impl<'a> System<'a> for SpriteAnimator {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        WriteStorage<'a, SpriteAnimation>
+    );
+
+    fn run(&mut self, (delta, mut animation): Self::SystemData) {
+        use specs::Join;
+
+        for a in (&mut animation).join() {
+            a.advance(delta.0);
         }
-        if input.key_held(VirtualKeyCode::D) {
-            cum_move += right * speed;
+    }
+}
+
+/// Deletes entities whose Lifetime has run out
+/// Their rigid bodies and colliders are removed from the physics world as well
+pub struct Lifetimes;
+
+impl<'a> System<'a> for Lifetimes {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, DeltaTime>,
+        Option<Write<'a, PhysicsData>>,
+        WriteStorage<'a, Lifetime>,
+        WriteStorage<'a, RigidBodyComponent>
+    );
+
+    fn run(&mut self, (entities, delta, mut physics_data, mut lifetime, mut rigid_body): Self::SystemData) {
+        use specs::Join;
+
+        let mut expired = Vec::new();
+        for (e, l) in (&entities, &mut lifetime).join() {
+            l.remaining -= delta.0;
+            if l.remaining <= 0.0 {
+                expired.push(e);
+            }
         }
 
-        return cum_move * delta;
+        for e in expired {
+            // removing the component right away so systems running later
+            // this frame don't look up the removed rigid body
+            if let (Some(r), Some(physics_data)) = (rigid_body.remove(e), physics_data.as_deref_mut()) {
+                // also removes the colliders attached to the rigid body
+                physics_data.remove_rigid_body(r.handle);
+            }
+
+            if let Err(err) = entities.delete(e) {
+                error!("Failed to delete entity {:?} after its lifetime ended: {err}", e);
+            }
+        }
+    }
+}
+
+/// Advances every UpdateEvery by DeltaTime, marking the ones whose interval has passed
+pub struct UpdateScheduler;
+
+impl<'a> System<'a> for UpdateScheduler {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        WriteStorage<'a, UpdateEvery>
+    );
+
+    fn run(&mut self, (delta, mut update_every): Self::SystemData) {
+        use specs::Join;
+
+        for u in (&mut update_every).join() {
+            u.advance(delta.0);
+        }
+    }
+}
+
+/// Regenerates the Health of entities with HealthRegen
+/// Only runs on the server, clients get the result through replication
+pub struct HealthRegeneration;
+
+impl<'a> System<'a> for HealthRegeneration {
+    type SystemData = (
+        Read<'a, DeltaTime>,
+        WriteStorage<'a, HealthRegen>,
+        WriteStorage<'a, Health>
+    );
+
+    fn run(&mut self, (delta, mut health_regen, mut health): Self::SystemData) {
+        use specs::Join;
+
+        for (r, h) in (&mut health_regen, &mut health).join() {
+            r.regenerate(h, delta.0);
+        }
     }
 }
@@ -1,4 +1,6 @@
+pub mod audio;
 pub mod general;
 pub mod network;
 pub mod physics;
 pub mod render;
+pub mod role;
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use log::{warn, error};
+use specs::{System, ReadStorage, WriteStorage, Read, Join};
+use uuid::Uuid;
+
+use crate::ecs::{components::{general::Transform, network::NetworkReplicated}, resources::network::{MessageType, NetworkData, NetworkMessageData, NetworkPacket}, utils::network::transform_state_hash};
+
+// Name of the ComponentCustom message carrying state hashes
+pub const STATE_HASH_MESSAGE: &str = "state_hash";
+
+// Keeps a single message below the udp buffer size
+const HASHES_PER_MESSAGE: usize = 32;
+
+// Mismatches in a row before an entity is reported, a single one
+// is usually just a transform update which is still in flight
+const DESYNC_CONFIRMATIONS: u32 = 2;
+
+/// Server side, sends a hash of the Transform of every replicated entity every interval frames, added by the engine
+/// Clients compare them in ReplicationReceiver, see DesyncDetector
+pub struct StateHashSender {
+    pub interval: u32,
+    frame: u32
+}
+
+impl StateHashSender {
+    pub fn new(interval: u32) -> Self {
+        Self { interval: interval.max(1), frame: 0 }
+    }
+}
+
+impl Default for StateHashSender {
+    fn default() -> Self {
+        StateHashSender::new(30)
+    }
+}
+
+impl<'a> System<'a> for StateHashSender {
+    type SystemData = (
+        ReadStorage<'a, NetworkReplicated>,
+        ReadStorage<'a, Transform>,
+        Option<Read<'a, NetworkData>>
+    );
+
+    fn run(&mut self, (network_replicated, transform, network_data): Self::SystemData) {
+        self.frame += 1;
+        if self.frame < self.interval {
+            return;
+        }
+        self.frame = 0;
+
+        // networking is optional, nothing to compare against
+        let net_data = match network_data {
+            Some(v) => v,
+            None => return
+        };
+
+        let hashes: Vec<(Uuid, u64)> = (&network_replicated, &transform).join()
+            .filter(|(net_rep, _)| !net_rep.net_id.is_nil())
+            .map(|(net_rep, t)| (net_rep.net_id, transform_state_hash(t)))
+            .collect();
+
+        for chunk in hashes.chunks(HASHES_PER_MESSAGE) {
+            match rmp_serde::to_vec(chunk) {
+                Ok(v) => {
+                    // not about a single entity, so no net_id
+                    let message = NetworkMessageData::new(
+                        net_data.target_addr,
+                        NetworkPacket::new(Uuid::nil(), MessageType::ComponentCustom(STATE_HASH_MESSAGE.into()), v)
+                    );
+
+                    if let Err(e) = net_data.sender.try_send(message) {
+                        error!("Failed to queue state hashes: {e}");
+                    }
+                },
+                Err(e) => error!("Could not serialize state hashes: {e}")
+            };
+        }
+    }
+}
+
+/// Client side, compares state hashes received from the server against the local state
+/// and logs entities which stay different over consecutive checks
+#[derive(Default)]
+pub struct DesyncDetector {
+    mismatches: HashMap<Uuid, u32>
+}
+
+impl DesyncDetector {
+    /*
+    Checks a received state hash message against the local transforms
+    Returns false if the packet was not a state hash message
+    */
+    pub fn check_packet(&mut self, packet: &NetworkPacket, net_data: &NetworkData, transform: &WriteStorage<'_, Transform>) -> bool {
+        match &packet.message_type {
+            MessageType::ComponentCustom(name) if name == STATE_HASH_MESSAGE => {},
+            _ => return false
+        }
+
+        let hashes = match rmp_serde::from_slice::<Vec<(Uuid, u64)>>(&packet.data) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Could not deserialize state hashes: {e}");
+                return true;
+            }
+        };
+
+        let mut desynced = Vec::new();
+        for (net_id, hash) in hashes {
+            let local = net_data.entity(&net_id).and_then(|e| transform.get(e).map(|t| (e, transform_state_hash(t))));
+
+            match local {
+                Some((_, local_hash)) if local_hash == hash => {
+                    self.mismatches.remove(&net_id);
+                },
+                _ => {
+                    let count = self.mismatches.entry(net_id).or_insert(0);
+                    *count += 1;
+                    // only reported once, when it gets confirmed
+                    if *count == DESYNC_CONFIRMATIONS {
+                        desynced.push((net_id, local.map(|(e, _)| e)));
+                    }
+                }
+            }
+        }
+
+        if !desynced.is_empty() {
+            warn!("Desync detected for {} entities: {:?}", desynced.len(), desynced);
+        }
+
+        true
+    }
+
+    /*
+    True while the entity's hash has differed from the server's in enough checks in a row
+    */
+    pub fn is_desynced(&self, net_id: &Uuid) -> bool {
+        self.mismatches.get(net_id).map_or(false, |count| *count >= DESYNC_CONFIRMATIONS)
+    }
+}
@@ -0,0 +1,33 @@
+use specs::{System, Read, Write};
+
+use crate::ecs::resources::network::{NetworkData, ConnectionState, ConnectionCallbacks, KeepAliveStats};
+
+/// Copies the connection state reported by the network thread into
+/// the ConnectionState resource, notifying ConnectionCallbacks on changes
+/// KeepAliveStats are copied as well
+pub struct ConnectionMonitor;
+
+impl<'a> System<'a> for ConnectionMonitor {
+    type SystemData = (
+        Option<Write<'a, NetworkData>>,
+        Write<'a, ConnectionState>,
+        Write<'a, KeepAliveStats>,
+        Read<'a, ConnectionCallbacks>
+    );
+
+    fn run(&mut self, (network_data, mut connection_state, mut keep_alive_stats, callbacks): Self::SystemData) {
+        // networking is optional, nothing to monitor
+        let mut net_data = match network_data {
+            Some(v) => v,
+            None => return
+        };
+
+        let state = *net_data.connection_state.borrow_and_update();
+        if state != *connection_state {
+            *connection_state = state;
+            callbacks.notify(state);
+        }
+
+        *keep_alive_stats = *net_data.keep_alive.borrow_and_update();
+    }
+}
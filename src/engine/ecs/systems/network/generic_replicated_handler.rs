@@ -25,22 +25,18 @@ impl<'a> System<'a> for GenericHandler {
             }
         };
 
-        for (net_rep, &t) in (&network_replicated, &transform).join() {
+        for (net_rep, t) in (&network_replicated, &transform).join() {
             if net_rep.net_id.is_nil() {
                 error!("Tried to update a network replicated entity with respect to transform, which did not have a valid net_id. Ignoring");
                 continue;
             }
 
-            match rmp_serde::to_vec(&t) {
+            match rmp_serde::to_vec(t) {
                 Ok(v) => {
-                    let message = NetworkMessageData {
-                        addr: net_data.target_addr,
-                        packet: NetworkPacket {
-                            net_id: net_rep.net_id,
-                            message_type: MessageType::ComponentTransform,
-                            data: v 
-                        }
-                    };
+                    let message = NetworkMessageData::new(
+                        net_data.target_addr,
+                        NetworkPacket::new(net_rep.net_id, MessageType::ComponentTransform, v)
+                    );
 
                     // its fine to not await here for now
                     net_data.sender.send(message);
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use log::{warn, error};
+use specs::{System, ReadStorage, WriteStorage, Read, Join};
+use uuid::Uuid;
+
+use crate::ecs::{components::{general::Health, network::NetworkReplicated}, resources::network::{MessageType, NetworkData, NetworkMessageData, NetworkPacket}};
+
+// Name of the ComponentCustom message carrying Health
+pub const HEALTH_MESSAGE: &str = "health";
+
+/// Server side, sends the Health of replicated entities whenever it changes, added by the engine
+/// Clients apply it in ReplicationReceiver
+#[derive(Default)]
+pub struct HealthSender {
+    last_sent: HashMap<Uuid, Health>
+}
+
+impl<'a> System<'a> for HealthSender {
+    type SystemData = (
+        ReadStorage<'a, NetworkReplicated>,
+        ReadStorage<'a, Health>,
+        Option<Read<'a, NetworkData>>
+    );
+
+    fn run(&mut self, (network_replicated, health, network_data): Self::SystemData) {
+        // networking is optional, nothing to replicate to
+        let net_data = match network_data {
+            Some(v) => v,
+            None => return
+        };
+
+        for (net_rep, &h) in (&network_replicated, &health).join() {
+            if net_rep.net_id.is_nil() {
+                error!("Tried to replicate health of an entity which did not have a valid net_id. Ignoring");
+                continue;
+            }
+
+            if self.last_sent.get(&net_rep.net_id) == Some(&h) {
+                continue;
+            }
+
+            match rmp_serde::to_vec(&h) {
+                Ok(v) => {
+                    let message = NetworkMessageData::new(
+                        net_data.target_addr,
+                        NetworkPacket::new(net_rep.net_id, MessageType::ComponentCustom(HEALTH_MESSAGE.into()), v)
+                    );
+
+                    match net_data.sender.try_send(message) {
+                        Ok(_) => { self.last_sent.insert(net_rep.net_id, h); },
+                        // not marking it as sent, so it's retried next frame
+                        Err(e) => error!("Failed to queue a health update: {e}")
+                    }
+                },
+                Err(e) => error!("Could not serialize health: {e}")
+            };
+        }
+    }
+}
+
+/*
+Client side, applies a received Health message to the entity it belongs to
+Returns false if the packet was not a Health message
+*/
+pub fn apply_health_packet(packet: &NetworkPacket, net_data: &NetworkData, health: &mut WriteStorage<'_, Health>) -> bool {
+    match &packet.message_type {
+        MessageType::ComponentCustom(name) if name == HEALTH_MESSAGE => {},
+        _ => return false
+    }
+
+    let entity = match net_data.entity(&packet.net_id) {
+        Some(v) => v,
+        None => {
+            warn!("Received health for unknown net_id {}", packet.net_id);
+            return true;
+        }
+    };
+
+    match rmp_serde::from_slice::<Health>(&packet.data) {
+        Ok(v) => {
+            if let Err(e) = health.insert(entity, v) {
+                error!("Failed to update health of {:?}: {e}", entity);
+            }
+        },
+        Err(e) => error!("Could not deserialize health: {e}")
+    }
+
+    true
+}
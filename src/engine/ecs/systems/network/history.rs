@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+
+use nalgebra::Isometry3;
+use specs::{System, ReadStorage, Read, Write, Entities, Join};
+
+use crate::ecs::{components::{general::Transform, network::NetworkReplicated, physics::RigidBodyComponent}, resources::{network::{StateHistory, timestamp_micros}, physics::PhysicsData}};
+
+/// Server side, records the positions of replicated entities into StateHistory every frame
+/// Added by the engine on servers, physics has stepped already so the history matches what the clients are sent
+pub struct StateHistoryRecorder;
+
+impl<'a> System<'a> for StateHistoryRecorder {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, StateHistory>,
+        Option<Read<'a, PhysicsData>>,
+        ReadStorage<'a, NetworkReplicated>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, RigidBodyComponent>
+    );
+
+    fn run(&mut self, (entities, mut history, physics_data, network_replicated, transform, rigid_body): Self::SystemData) {
+        let positions: HashMap<_, _> = (&entities, &network_replicated, &transform, rigid_body.maybe()).join()
+            .map(|(e, _, t, r)| {
+                // the rotation of a character Transform is where it looks, its collider doesn't turn
+                let position = match (r, physics_data.as_deref()) {
+                    (Some(r), Some(physics_data)) => r.position(physics_data),
+                    _ => Isometry3::from_parts(t.pos().into(), t.rot())
+                };
+                (e, position)
+            })
+            .collect();
+
+        history.record(timestamp_micros(), positions);
+    }
+}
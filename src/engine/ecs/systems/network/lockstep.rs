@@ -0,0 +1,178 @@
+use log::{warn, error};
+use specs::{System, ReadStorage, Read, Write, Entities, Join};
+use uuid::Uuid;
+
+use crate::ecs::{components::general::Transform, resources::{lockstep::{Lockstep, LockstepMessage}, network::{MessageType, MessagePriority, NetworkData, NetworkMessageData, NetworkPacket}}, utils::network::world_state_hash};
+
+// Name of the ComponentCustom message carrying a LockstepMessage
+pub const LOCKSTEP_MESSAGE: &str = "lockstep";
+
+/// Hashes the state after the ticks Lockstep asks for and sends the queued inputs and hashes to the other peers
+/// Added by the engine, after physics so the hash covers the whole tick
+pub struct LockstepSync;
+
+impl<'a> System<'a> for LockstepSync {
+    type SystemData = (
+        Entities<'a>,
+        Option<Write<'a, Lockstep>>,
+        Option<Read<'a, NetworkData>>,
+        ReadStorage<'a, Transform>
+    );
+
+    fn run(&mut self, (entities, lockstep, network_data, transform): Self::SystemData) {
+        // not running in lockstep
+        let mut lockstep = match lockstep {
+            Some(v) => v,
+            None => return
+        };
+
+        if let Some(tick) = lockstep.hash_due() {
+            let hash = world_state_hash((&entities, &transform).join().map(|(e, t)| (e.id(), t)));
+            lockstep.record_hash(tick, hash);
+        }
+
+        let outgoing = lockstep.take_outgoing();
+        if outgoing.is_empty() {
+            return;
+        }
+
+        let net_data = match network_data {
+            Some(v) => v,
+            None => return warn!("No network data, cannot send lockstep inputs.")
+        };
+
+        for message in outgoing {
+            let data = match rmp_serde::to_vec(&message) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Could not serialize a lockstep message: {e}");
+                    continue;
+                }
+            };
+
+            // not about a single entity, so no net_id
+            // every peer waits for these inputs before simulating the tick
+            let message = NetworkMessageData::new(
+                net_data.target_addr,
+                NetworkPacket::new(Uuid::nil(), MessageType::ComponentCustom(LOCKSTEP_MESSAGE.into()), data)
+            ).with_priority(MessagePriority::Critical);
+
+            if let Err(e) = net_data.sender.try_send(message) {
+                error!("Failed to queue a lockstep message: {e}");
+            }
+        }
+    }
+}
+
+/*
+Passes a received lockstep message on to lockstep
+Returns false if the packet was not a lockstep message
+*/
+pub fn apply_lockstep_packet(packet: &NetworkPacket, lockstep: Option<&mut Lockstep>) -> bool {
+    match &packet.message_type {
+        MessageType::ComponentCustom(name) if name == LOCKSTEP_MESSAGE => {},
+        _ => return false
+    }
+
+    let lockstep = match lockstep {
+        Some(v) => v,
+        None => {
+            warn!("Received a lockstep message without running in lockstep, ignoring");
+            return true;
+        }
+    };
+
+    match rmp_serde::from_slice::<LockstepMessage>(&packet.data) {
+        Ok(v) => lockstep.receive(v),
+        Err(e) => error!("Could not deserialize a lockstep message: {e}")
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+    use specs::{World, WorldExt, Builder};
+
+    /*
+    Stands in for the systems of a game, moves the unit of each player by its input
+    */
+    fn simulate(world: &mut World, lockstep: &Lockstep, units: &[specs::Entity], delta: f32) {
+        let mut transform = world.write_storage::<Transform>();
+        for (player, input) in lockstep.inputs() {
+            let direction = match input.as_slice() {
+                [x, z] => Vector3::new(*x as i8 as f32, 0.0, *z as i8 as f32),
+                _ => Vector3::zeros()
+            };
+            if let Some(t) = transform.get_mut(units[*player as usize]) {
+                t.set_pos(t.pos() + direction * 5.0 * delta);
+            }
+        }
+    }
+
+    fn peer(player: u32) -> (World, Vec<specs::Entity>, Lockstep) {
+        let mut world = World::new();
+        world.register::<Transform>();
+        let units = (0..2)
+            .map(|i| world.create_entity().with(Transform::from_position(Vector3::new(i as f32 * 3.0, 0.0, 0.0))).build())
+            .collect();
+        (world, units, Lockstep::new(player, 2, 1.0 / 30.0).with_hash_interval(10))
+    }
+
+    fn state_hash(world: &World) -> u64 {
+        let entities = world.entities();
+        let transform = world.read_storage::<Transform>();
+        world_state_hash((&entities, &transform).join().map(|(e, t)| (e.id(), t)))
+    }
+
+    #[test]
+    fn peers_stay_in_sync() {
+        let mut peers = [peer(0), peer(1)];
+        // state after every simulated tick
+        let mut states = [Vec::new(), Vec::new()];
+
+        for frame in 0..120u32 {
+            for ((world, units, lockstep), states) in peers.iter_mut().zip(states.iter_mut()) {
+                let input = vec![(frame % 3) as u8, (lockstep.player() as u8).wrapping_sub(1)];
+                lockstep.submit(input);
+                if let Some(delta) = lockstep.advance() {
+                    simulate(world, lockstep, units, delta);
+                    states.push(state_hash(world));
+                }
+                if let Some(tick) = lockstep.hash_due() {
+                    let hash = state_hash(world);
+                    lockstep.record_hash(tick, hash);
+                }
+            }
+
+            // delivered a frame late in one direction
+            let (a, b) = peers.split_at_mut(1);
+            for message in b[0].2.take_outgoing() {
+                a[0].2.receive(message);
+            }
+            if frame % 2 == 0 {
+                for message in a[0].2.take_outgoing() {
+                    b[0].2.receive(message);
+                }
+            }
+        }
+
+        // the peer receiving late waits for inputs more often, the ticks both simulated match
+        let simulated = states[0].len().min(states[1].len());
+        assert!(simulated > 50, "only {simulated} ticks simulated");
+        assert_eq!(states[0][..simulated], states[1][..simulated]);
+        assert!(peers.iter().all(|(_, _, lockstep)| lockstep.desynced().is_none()));
+
+        // a peer whose unit was pushed aside no longer matches
+        let (world, units, lockstep) = &mut peers[1];
+        world.write_storage::<Transform>().get_mut(units[0]).unwrap().set_pos(Vector3::new(100.0, 0.0, 0.0));
+        lockstep.record_hash(1000, state_hash(world));
+        let hash = state_hash(&peers[0].0);
+        peers[0].2.record_hash(1000, hash);
+        for message in peers[1].2.take_outgoing() {
+            peers[0].2.receive(message);
+        }
+        assert_eq!(peers[0].2.desynced(), Some(1000));
+    }
+}
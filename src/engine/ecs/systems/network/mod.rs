@@ -1,2 +1,8 @@
-
+pub mod checksum;
+pub mod connection;
 mod generic_replicated_handler;
+pub mod health;
+pub mod history;
+pub mod lockstep;
+pub mod receiver;
+pub mod snapshot;
@@ -0,0 +1,178 @@
+use log::{warn, error};
+use specs::{System, Write, WriteStorage, Entities};
+
+use crate::ecs::{components::{general::{Transform, Health}, network::NetworkReplicated}, resources::{network::{MessageType, NetworkData, NetworkPacket}, lockstep::Lockstep}};
+
+use super::{health::apply_health_packet, checksum::DesyncDetector, snapshot::{apply_snapshot_packet, SnapshotAssembler}, lockstep::apply_lockstep_packet};
+
+/// Applies every message received since the last frame, server state on clients and
+/// the inputs of the other peers in lockstep, added by the engine
+#[derive(Default)]
+pub struct ReplicationReceiver {
+    snapshot_assembler: SnapshotAssembler,
+    desync_detector: DesyncDetector
+}
+
+impl ReplicationReceiver {
+    pub fn desync_detector(&self) -> &DesyncDetector {
+        &self.desync_detector
+    }
+}
+
+impl<'a> System<'a> for ReplicationReceiver {
+    type SystemData = (
+        Entities<'a>,
+        Option<Write<'a, NetworkData>>,
+        Option<Write<'a, Lockstep>>,
+        WriteStorage<'a, NetworkReplicated>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Health>
+    );
+
+    fn run(&mut self, (entities, network_data, mut lockstep, mut network_replicated, mut transform, mut health): Self::SystemData) {
+        // networking is optional, nothing to receive
+        let mut net_data = match network_data {
+            Some(v) => v,
+            None => return
+        };
+
+        while let Ok(message) = net_data.receiver.try_recv() {
+            let packet = message.packet;
+
+            if apply_health_packet(&packet, &net_data, &mut health) {
+                continue;
+            }
+
+            if apply_lockstep_packet(&packet, lockstep.as_deref_mut()) {
+                continue;
+            }
+
+            if self.desync_detector.check_packet(&packet, &net_data, &transform) {
+                continue;
+            }
+
+            if apply_snapshot_packet(&packet, &mut self.snapshot_assembler, &entities, &mut net_data, &mut network_replicated, &mut transform, &mut health) {
+                continue;
+            }
+
+            match &packet.message_type {
+                MessageType::ComponentTransform => apply_transform_packet(&packet, &net_data, &mut transform),
+                MessageType::ComponentCustom(name) => warn!("No handler for custom message {name}, ignoring"),
+                // answered by the network thread, never forwarded
+                MessageType::KeepAlive { .. } => {}
+            }
+        }
+    }
+}
+
+fn apply_transform_packet(packet: &NetworkPacket, net_data: &NetworkData, transform: &mut WriteStorage<'_, Transform>) {
+    let entity = match net_data.entity(&packet.net_id) {
+        Some(v) => v,
+        None => return warn!("Received transform for unknown net_id {}", packet.net_id)
+    };
+
+    let received = match rmp_serde::from_slice::<Transform>(&packet.data) {
+        Ok(v) => v,
+        Err(e) => return error!("Could not deserialize transform: {e}")
+    };
+
+    match transform.get_mut(entity) {
+        // only the serialized fields are replicated
+        Some(t) => {
+            t.set_pos(received.pos());
+            t.set_rot(received.rot());
+            t.set_scale(received.scale());
+        },
+        None => {
+            if let Err(e) = transform.insert(entity, received) {
+                error!("Failed to insert transform for {:?}: {e}", entity);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use nalgebra::Vector3;
+    use specs::{Builder, Join, RunNow, World, WorldExt};
+    use tokio::sync::{mpsc, watch};
+    use uuid::Uuid;
+
+    use crate::ecs::{ECS, resources::network::{ConnectionState, KeepAliveStats, NetworkMessageData, DEFAULT_MAX_REPLICATED}, utils::network::transform_state_hash};
+    use super::super::{checksum::STATE_HASH_MESSAGE, snapshot::{WorldSnapshot, EntitySnapshot, SNAPSHOT_MESSAGE}};
+    use super::*;
+
+    // network data without a network thread, returns the sender the thread would pass received packets to
+    fn network_data() -> (NetworkData, mpsc::Sender<NetworkMessageData>) {
+        let (received_sender, received) = mpsc::channel(16);
+        let (sender, _) = mpsc::channel(16);
+        let (_, connection_state) = watch::channel(ConnectionState::Connected);
+        let (_, keep_alive) = watch::channel(KeepAliveStats::default());
+        let net_data = NetworkData {
+            sender,
+            receiver: received,
+            target_addr: ([127, 0, 0, 1], 0).into(),
+            connection_state,
+            keep_alive,
+            net_id_ent: HashMap::new(),
+            max_replicated: DEFAULT_MAX_REPLICATED
+        };
+        (net_data, received_sender)
+    }
+
+    fn receive_hash(world: &World, receiver: &mut ReplicationReceiver, sender: &mpsc::Sender<NetworkMessageData>, net_id: Uuid, hash: u64) {
+        let packet = NetworkPacket::new(Uuid::nil(), MessageType::ComponentCustom(STATE_HASH_MESSAGE.into()), rmp_serde::to_vec(&vec![(net_id, hash)]).unwrap());
+        sender.try_send(NetworkMessageData::new(([127, 0, 0, 1], 0).into(), packet)).unwrap();
+        receiver.run_now(world);
+    }
+
+    #[test]
+    fn mismatching_state_hashes_are_detected() {
+        let mut world = ECS::new().world;
+        let net_id = Uuid::from_u128(1);
+        let transform = Transform::from_position(Vector3::new(1.0, 2.0, 3.0));
+        let local_hash = transform_state_hash(&transform);
+        let entity = world.create_entity().with(transform).with(NetworkReplicated { net_id }).build();
+
+        let (mut net_data, sender) = network_data();
+        net_data.register(net_id, entity);
+        world.insert(net_data);
+
+        let mut receiver = ReplicationReceiver::default();
+        receive_hash(&world, &mut receiver, &sender, net_id, local_hash.wrapping_add(1));
+        // a single mismatch may just be a transform update still in flight
+        assert!(!receiver.desync_detector().is_desynced(&net_id));
+
+        receive_hash(&world, &mut receiver, &sender, net_id, local_hash.wrapping_add(1));
+        assert!(receiver.desync_detector().is_desynced(&net_id));
+
+        receive_hash(&world, &mut receiver, &sender, net_id, local_hash);
+        assert!(!receiver.desync_detector().is_desynced(&net_id));
+    }
+
+    #[test]
+    fn snapshot_spawns_stop_at_the_replicated_limit() {
+        let mut world = ECS::new().world;
+        let (mut net_data, sender) = network_data();
+        net_data.max_replicated = 3;
+        world.insert(net_data);
+
+        let entities = (0..10)
+            .map(|i| EntitySnapshot { net_id: Uuid::from_u128(i + 1), transform: Some(Transform::default()), health: None })
+            .collect();
+        for chunk in (WorldSnapshot { entities }).to_chunks(0).unwrap() {
+            let packet = NetworkPacket::new(Uuid::nil(), MessageType::ComponentCustom(SNAPSHOT_MESSAGE.into()), rmp_serde::to_vec(&chunk).unwrap());
+            sender.try_send(NetworkMessageData::new(([127, 0, 0, 1], 0).into(), packet)).unwrap();
+        }
+
+        ReplicationReceiver::default().run_now(&world);
+        world.maintain();
+
+        assert_eq!(world.read_resource::<NetworkData>().net_id_ent.len(), 3);
+        // the rejected ones are deleted again
+        assert_eq!((&world.entities(), &world.read_storage::<Transform>()).join().count(), 3);
+        assert_eq!(world.read_storage::<NetworkReplicated>().join().count(), 3);
+    }
+}
@@ -0,0 +1,309 @@
+use std::net::SocketAddr;
+
+use log::{warn, error, info};
+use serde::{Serialize, Deserialize};
+use specs::{System, ReadStorage, WriteStorage, Read, Write, Entities, Join};
+use uuid::Uuid;
+
+use crate::ecs::{components::{general::{Transform, Health}, network::NetworkReplicated}, resources::network::{MessageType, NetworkData, NetworkMessageData, NetworkPacket}, utils::network::make_replicated};
+
+// Name of the ComponentCustom message carrying a part of a WorldSnapshot
+pub const SNAPSHOT_MESSAGE: &str = "snapshot";
+
+// Compressed bytes per message, keeps a single message below the udp buffer size
+const SNAPSHOT_CHUNK_SIZE: usize = 1024;
+
+// Larger snapshots are rejected before decompressing, protects against a peer claiming a huge size
+const MAX_SNAPSHOT_SIZE: usize = 64 * 1024 * 1024;
+
+/// Replicated state of a single entity in a WorldSnapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EntitySnapshot {
+    pub net_id: Uuid,
+    pub transform: Option<Transform>,
+    pub health: Option<Health>
+}
+
+/// Replicated state of every entity, sent to clients joining late instead of waiting for each update
+/// Serialized and compressed with lz4, then split over as many messages as needed, see SnapshotSender
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub entities: Vec<EntitySnapshot>
+}
+
+/// Part of a compressed WorldSnapshot, snapshot_id tells the parts of consecutive snapshots apart
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    pub snapshot_id: u32,
+    pub index: u32,
+    pub count: u32,
+    pub data: Vec<u8>
+}
+
+impl WorldSnapshot {
+    pub fn capture(network_replicated: &ReadStorage<'_, NetworkReplicated>, transform: &ReadStorage<'_, Transform>, health: &ReadStorage<'_, Health>) -> WorldSnapshot {
+        let entities = (network_replicated, transform.maybe(), health.maybe()).join()
+            .filter(|(net_rep, _, _)| !net_rep.net_id.is_nil())
+            .map(|(net_rep, t, h)| EntitySnapshot { net_id: net_rep.net_id, transform: t.cloned(), health: h.copied() })
+            .collect();
+
+        WorldSnapshot { entities }
+    }
+
+    pub fn compress(&self) -> Result<Vec<u8>, String> {
+        let serialized = rmp_serde::to_vec(self).map_err(|e| format!("Could not serialize the world snapshot: {e}"))?;
+        Ok(lz4_flex::compress_prepend_size(&serialized))
+    }
+
+    pub fn decompress(data: &[u8]) -> Result<WorldSnapshot, String> {
+        let size = match data.get(..4) {
+            Some(v) => u32::from_le_bytes([v[0], v[1], v[2], v[3]]) as usize,
+            None => return Err("World snapshot is missing its size".into())
+        };
+        if size > MAX_SNAPSHOT_SIZE {
+            return Err(format!("World snapshot claims {size} bytes, more than the maximum of {MAX_SNAPSHOT_SIZE}"));
+        }
+
+        let serialized = lz4_flex::decompress_size_prepended(data).map_err(|e| format!("Could not decompress the world snapshot: {e}"))?;
+        rmp_serde::from_slice(&serialized).map_err(|e| format!("Could not deserialize the world snapshot: {e}"))
+    }
+
+    /*
+    Compressed and split into chunks small enough for a single message each
+    */
+    pub fn to_chunks(&self, snapshot_id: u32) -> Result<Vec<SnapshotChunk>, String> {
+        let compressed = self.compress()?;
+        let count = compressed.chunks(SNAPSHOT_CHUNK_SIZE).len() as u32;
+        Ok(compressed
+            .chunks(SNAPSHOT_CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, data)| SnapshotChunk { snapshot_id, index: i as u32, count, data: data.to_vec() })
+            .collect())
+    }
+}
+
+/// Server side, sends a WorldSnapshot to every address in SnapshotRequests, e.g. clients which just joined
+/// Added by the engine, clients apply the snapshot in ReplicationReceiver
+#[derive(Default)]
+pub struct SnapshotSender {
+    next_id: u32
+}
+
+/// Addresses the next WorldSnapshot is sent to, emptied by SnapshotSender
+#[derive(Default)]
+pub struct SnapshotRequests(pub Vec<SocketAddr>);
+
+impl<'a> System<'a> for SnapshotSender {
+    type SystemData = (
+        Write<'a, SnapshotRequests>,
+        ReadStorage<'a, NetworkReplicated>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Health>,
+        Option<Read<'a, NetworkData>>
+    );
+
+    fn run(&mut self, (mut requests, network_replicated, transform, health, network_data): Self::SystemData) {
+        if requests.0.is_empty() {
+            return;
+        }
+
+        let net_data = match network_data {
+            Some(v) => v,
+            None => return warn!("No network data, cannot send world snapshots.")
+        };
+
+        let snapshot = WorldSnapshot::capture(&network_replicated, &transform, &health);
+        let chunks = match snapshot.to_chunks(self.next_id) {
+            Ok(v) => v,
+            Err(e) => return error!("{e}")
+        };
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let serialized: Vec<Vec<u8>> = match chunks.iter().map(rmp_serde::to_vec).collect() {
+            Ok(v) => v,
+            Err(e) => return error!("Could not serialize a world snapshot chunk: {e}")
+        };
+
+        for addr in requests.0.drain(..) {
+            info!("Sending a world snapshot of {} entities in {} messages to {addr}", snapshot.entities.len(), serialized.len());
+            for data in serialized.iter() {
+                // not about a single entity, so no net_id
+                let message = NetworkMessageData::new(
+                    addr,
+                    NetworkPacket::new(Uuid::nil(), MessageType::ComponentCustom(SNAPSHOT_MESSAGE.into()), data.clone())
+                );
+
+                if let Err(e) = net_data.sender.try_send(message) {
+                    error!("Failed to queue a world snapshot chunk for {addr}: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Client side, puts the chunks of a WorldSnapshot back together
+/// Chunks of an older snapshot are dropped once one of a newer snapshot arrives
+#[derive(Default)]
+pub struct SnapshotAssembler {
+    snapshot_id: Option<u32>,
+    chunks: Vec<Option<Vec<u8>>>
+}
+
+impl SnapshotAssembler {
+    /*
+    Returns the snapshot once chunk was the last part missing
+    */
+    pub fn add(&mut self, chunk: SnapshotChunk) -> Option<Result<WorldSnapshot, String>> {
+        let max_chunks = MAX_SNAPSHOT_SIZE / SNAPSHOT_CHUNK_SIZE + 1;
+        if chunk.count == 0 || chunk.count as usize > max_chunks || chunk.index >= chunk.count {
+            return Some(Err(format!("Invalid world snapshot chunk {} of {}", chunk.index, chunk.count)));
+        }
+
+        if self.snapshot_id != Some(chunk.snapshot_id) || self.chunks.len() != chunk.count as usize {
+            self.snapshot_id = Some(chunk.snapshot_id);
+            self.chunks = vec![None; chunk.count as usize];
+        }
+        self.chunks[chunk.index as usize] = Some(chunk.data);
+
+        if self.chunks.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let compressed: Vec<u8> = self.chunks.drain(..).flatten().flatten().collect();
+        self.snapshot_id = None;
+        Some(WorldSnapshot::decompress(&compressed))
+    }
+}
+
+/*
+Client side, adds a received chunk to assembler and applies the snapshot once it is complete
+Entities with a net_id which isn't known yet are created
+Returns false if the packet was not a snapshot message
+*/
+pub fn apply_snapshot_packet(
+    packet: &NetworkPacket,
+    assembler: &mut SnapshotAssembler,
+    entities: &Entities<'_>,
+    net_data: &mut NetworkData,
+    network_replicated: &mut WriteStorage<'_, NetworkReplicated>,
+    transform: &mut WriteStorage<'_, Transform>,
+    health: &mut WriteStorage<'_, Health>
+) -> bool {
+    match &packet.message_type {
+        MessageType::ComponentCustom(name) if name == SNAPSHOT_MESSAGE => {},
+        _ => return false
+    }
+
+    let chunk = match rmp_serde::from_slice::<SnapshotChunk>(&packet.data) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Could not deserialize a world snapshot chunk: {e}");
+            return true;
+        }
+    };
+
+    let snapshot = match assembler.add(chunk) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => {
+            error!("{e}");
+            return true;
+        },
+        None => return true
+    };
+
+    for entity_snapshot in snapshot.entities {
+        let entity = match net_data.entity(&entity_snapshot.net_id) {
+            Some(v) => v,
+            None => {
+                let entity = entities.create();
+                // past the limit of replicated entities
+                if !make_replicated(entity, entity_snapshot.net_id, Some(&mut *net_data), network_replicated) {
+                    let _ = entities.delete(entity);
+                    continue;
+                }
+                entity
+            }
+        };
+
+        if let Some(t) = entity_snapshot.transform {
+            if let Err(e) = transform.insert(entity, t) {
+                error!("Failed to insert transform for {:?}: {e}", entity);
+            }
+        }
+        if let Some(h) = entity_snapshot.health {
+            if let Err(e) = health.insert(entity, h) {
+                error!("Failed to update health of {:?}: {e}", entity);
+            }
+        }
+    }
+
+    info!("Applied a world snapshot");
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector3;
+
+    fn large_snapshot() -> WorldSnapshot {
+        // a grid of crates, like a big level would have
+        let entities = (0..5000)
+            .map(|i| EntitySnapshot {
+                net_id: Uuid::from_u128(i + 1),
+                transform: Some(Transform::from_position(Vector3::new((i % 50) as f32, 0.0, (i / 50) as f32))),
+                health: (i % 4 == 0).then(|| Health::new(100.0))
+            })
+            .collect();
+        WorldSnapshot { entities }
+    }
+
+    #[test]
+    fn compresses_and_reconstructs() {
+        let snapshot = large_snapshot();
+        let uncompressed = rmp_serde::to_vec(&snapshot).unwrap();
+
+        let mut chunks = snapshot.to_chunks(7).unwrap();
+        let compressed: usize = chunks.iter().map(|c| c.data.len()).sum();
+        assert!(compressed * 2 < uncompressed.len(), "{} compressed from {}", compressed, uncompressed.len());
+
+        // arriving out of order
+        chunks.reverse();
+        let last = chunks.pop().unwrap();
+        let mut assembler = SnapshotAssembler::default();
+        for chunk in chunks {
+            assert!(assembler.add(chunk).is_none());
+        }
+        let received = assembler.add(last).unwrap().unwrap();
+
+        assert_eq!(rmp_serde::to_vec(&received).unwrap(), uncompressed);
+    }
+
+    #[test]
+    fn newer_snapshot_replaces_older_chunks() {
+        let snapshot = large_snapshot();
+        let old = snapshot.to_chunks(1).unwrap();
+        let new = snapshot.to_chunks(2).unwrap();
+        assert!(old.len() > 1);
+
+        let mut assembler = SnapshotAssembler::default();
+        assert!(assembler.add(old[0].clone()).is_none());
+        let mut result = None;
+        for chunk in new {
+            result = assembler.add(chunk);
+        }
+        assert_eq!(result.unwrap().unwrap().entities.len(), 5000);
+    }
+
+    #[test]
+    fn rejects_hostile_snapshots() {
+        let mut assembler = SnapshotAssembler::default();
+        let chunk = SnapshotChunk { snapshot_id: 0, index: 0, count: u32::MAX, data: Vec::new() };
+        assert!(assembler.add(chunk).unwrap().is_err());
+
+        // claims 4GiB once decompressed
+        assert!(WorldSnapshot::decompress(&[0xff, 0xff, 0xff, 0xff, 0]).is_err());
+        assert!(WorldSnapshot::decompress(&[1]).is_err());
+    }
+}
@@ -1,28 +1,39 @@
+use std::collections::HashSet;
+
 use log::warn;
-use nalgebra::{Vector3, ComplexField};
-use rapier3d::prelude::{IntegrationParameters, EventHandler};
-use specs::{System, Write, Read, ReadStorage, WriteStorage};
+use nalgebra::{Vector3, ComplexField, Isometry3};
+use rapier3d::prelude::{QueryFilter, SharedShape};
+use specs::{System, Write, Read, ReadStorage, WriteStorage, Entities};
 
-use crate::ecs::{resources::{physics::PhysicsData, DeltaTime}, components::{general::Transform, physics::{RigidBodyComponent, ColliderComponent}}, utils::debug::DebugEventHandler};
+use crate::ecs::{resources::{physics::{PhysicsData, KillPlane, CollisionEvents}, DeltaTime, SpawnPoints}, components::{general::Transform, physics::{RigidBodyComponent, ColliderComponent, Attractor}}, utils::{collision::CollisionEventCollector, objects::respawn_at}};
 
 #[derive(Default)]
 pub struct Physics {
-    debug_event_handler: DebugEventHandler
+    event_collector: CollisionEventCollector
 }
 
 impl<'a> System<'a> for Physics {
     type SystemData = (
+        Entities<'a>,
         Write<'a, PhysicsData>,
         Read<'a, DeltaTime>,
+        Write<'a, CollisionEvents>,
 
         WriteStorage<'a, Transform>,
         WriteStorage<'a, RigidBodyComponent>,
         ReadStorage<'a, ColliderComponent>
     );
 
-    fn run(&mut self, (mut physics_data, delta_time, mut transform, mut rigid_body, collider): Self::SystemData) {
+    fn run(&mut self, (entities, mut physics_data, delta_time, mut collision_events, mut transform, mut rigid_body, collider): Self::SystemData) {
         use specs::Join;
 
+        // Colliders created since the last step, so queries can tell which entity was hit
+        for (e, c) in (&entities, &collider).join() {
+            if physics_data.collider_entity(c.handle()) != Some(e) {
+                physics_data.attach_entity(c.handle(), e);
+            }
+        }
+
         // Update entities
         for (t, r, c) in (&mut transform, &mut rigid_body, &collider).join() {
             if t.need_physics_update && r.has_character_controller() {
@@ -41,70 +52,45 @@ impl<'a> System<'a> for Physics {
                     t.accel = Vector3::zeros();
                 }
 
-                if grounded.unwrap_or(false) {
-                    if t.vel.y <= 0.1 {
-                        t.vel.y = 0.0;
+                // stop falling once grounded, along the gravity direction so flipped gravity works too
+                if let (true, Some(up)) = (grounded.unwrap_or(false), physics_data.up()) {
+                    let vel_up = t.vel.dot(&up);
+                    if vel_up <= 0.1 {
+                        t.vel -= up.into_inner() * vel_up;
                     }
-                    if t.accel.y <= 0.1 {
-                        t.accel.y = 0.0;
+                    let accel_up = t.accel.dot(&up);
+                    if accel_up <= 0.1 {
+                        t.accel -= up.into_inner() * accel_up;
                     }
                 }
             }
         }
 
-        // Run physics step
-        let (
-            gravity, 
-            integration_params,
-            physics_pipeline,
-            island_manager,
-            broad_phase,
-            narrow_phase,
-            rigid_body_set,
-            collider_set,
-            impulse_joint_set,
-            multibody_joint_set,
-            ccd_solver,
-            query_pipeline
-        ) = physics_data.split_borrow();
-
-        // Using non-fixed time step
-        let new_integration_params = IntegrationParameters {
-            dt: delta_time.0,
-            ..*integration_params
-        };
+        // Using non-fixed time step, except for the fixed ticks of Lockstep
+        physics_data.step(delta_time.0, &self.event_collector);
 
-        physics_pipeline.step(
-            &gravity,
-            &new_integration_params,
-            island_manager,
-            broad_phase,
-            narrow_phase,
-            rigid_body_set,
-            collider_set,
-            impulse_joint_set,
-            multibody_joint_set,
-            ccd_solver,
-            Some(query_pipeline),
-            &(),
-            &self.debug_event_handler
-        );
+        let mut events = self.event_collector.drain();
+        for e in events.iter_mut() {
+            e.entity1 = physics_data.collider_entity(e.collider1());
+            e.entity2 = physics_data.collider_entity(e.collider2());
+        }
+        *collision_events = CollisionEvents(events);
 
         // Update transform
         // TODO: fix mismatch
         for (t, r) in (&mut transform, &rigid_body).join() {
             let pos = r.position(&physics_data);
             
-            match physics_data.rigid_body_set.get(r.handle) {
+            match physics_data.rigid_body(r.handle) {
                 Some(v) => {
                     if !v.is_translation_locked() {
-                        t.pos = pos.translation.vector;
+                        t.set_pos(pos.translation.vector);
                     }
 
                     // if any degrees of freedom are locked, don't update rotation
                     // todo: update properly
                     if !v.is_rotation_locked().iter().any(|x| *x) {
-                        t.rot = pos.rotation;
+                        t.set_rot(pos.rotation);
                     }
                 },
                 None => {
@@ -114,3 +100,95 @@ impl<'a> System<'a> for Physics {
         }
     }
 } 
+
+/// Respawns entities which fall below the KillPlane
+/// Does nothing unless both KillPlane and SpawnPoints resources exist
+pub struct Respawn;
+
+impl<'a> System<'a> for Respawn {
+    type SystemData = (
+        Entities<'a>,
+        Option<Write<'a, PhysicsData>>,
+        Option<Read<'a, KillPlane>>,
+        Option<Write<'a, SpawnPoints>>,
+
+        WriteStorage<'a, Transform>,
+        ReadStorage<'a, RigidBodyComponent>
+    );
+
+    fn run(&mut self, (entities, physics_data, kill_plane, spawn_points, mut transform, rigid_body): Self::SystemData) {
+        use specs::Join;
+
+        let (mut physics_data, kill_plane, mut spawn_points) = match (physics_data, kill_plane, spawn_points) {
+            (Some(p), Some(k), Some(s)) => (p, k, s),
+            _ => return
+        };
+
+        for (e, t, r) in (&entities, &mut transform, &rigid_body).join() {
+            if t.pos().y >= kill_plane.0 {
+                continue;
+            }
+
+            match spawn_points.next() {
+                Some(spawn) => respawn_at(&spawn, t, r, &mut physics_data),
+                None => warn!("Entity {:?} fell below the kill plane but there are no spawn points", e)
+            }
+        }
+    }
+}
+
+/// Applies the pull of every Attractor to the dynamic rigid bodies around it
+/// Runs after Physics, so the impulses take effect with the next step
+pub struct Attractors;
+
+impl<'a> System<'a> for Attractors {
+    type SystemData = (
+        Write<'a, PhysicsData>,
+        Read<'a, DeltaTime>,
+        ReadStorage<'a, Attractor>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, RigidBodyComponent>
+    );
+
+    fn run(&mut self, (mut physics_data, delta_time, attractor, transform, rigid_body): Self::SystemData) {
+        use specs::Join;
+
+        for (a, t, own_body) in (&attractor, &transform, rigid_body.maybe()).join() {
+            if a.radius <= 0.0 || a.strength == 0.0 {
+                continue;
+            }
+
+            let center = t.pos();
+            let colliders = physics_data.intersect_shape(
+                &SharedShape::ball(a.radius),
+                &Isometry3::translation(center.x, center.y, center.z),
+                QueryFilter::only_dynamic()
+            );
+
+            // a body with several colliders is only pulled once
+            let bodies: HashSet<_> = colliders.iter()
+                .filter_map(|h| physics_data.collider(*h).and_then(|c| c.parent()))
+                .filter(|h| own_body.map_or(true, |r| r.handle != *h))
+                .collect();
+
+            for handle in bodies {
+                let body = match physics_data.rigid_body_mut(handle) {
+                    Some(v) => v,
+                    None => continue
+                };
+
+                let offset = center - body.center_of_mass().coords;
+                let distance = offset.norm();
+                let direction = match offset.try_normalize(f32::EPSILON) {
+                    Some(v) => v,
+                    None => continue
+                };
+
+                let force = a.force_at(distance);
+                if force != 0.0 {
+                    body.apply_impulse(direction * force * delta_time.0, true);
+                }
+            }
+        }
+    }
+}
@@ -1,30 +1,180 @@
-use std::sync::Arc;
+use std::{sync::{Arc, Weak}, time::{Duration, Instant}};
 
-use log::error;
+use bytemuck::Zeroable;
+use log::{error, warn};
+use nalgebra::{Matrix4, Vector3, Point3, UnitQuaternion};
+use rapier3d::prelude::{DebugRenderPipeline, DebugRenderBackend, DebugRenderObject, DebugRenderStyle};
 use specs::{System, ReadStorage, Read, Write, Entities, Entity};
-use vulkano::{command_buffer::{RenderPassBeginInfo, SubpassContents, AutoCommandBufferBuilder, CommandBufferUsage, allocator::{CommandBufferAllocator, StandardCommandBufferAllocator}, PrimaryAutoCommandBuffer}, descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet}, pipeline::{Pipeline, PipelineBindPoint}, buffer::TypedBufferAccess};
+use vulkano::{device::DeviceOwned, query::{QueryPool, QueryPoolCreateInfo, QueryType, QueryControlFlags, QueryResultFlags}};
+use vulkano::swapchain::Surface;
+use vulkano::{command_buffer::{RenderPassBeginInfo, SubpassContents, AutoCommandBufferBuilder, CommandBufferUsage, CommandBufferInheritanceInfo, allocator::{CommandBufferAllocator, StandardCommandBufferAllocator}, PrimaryAutoCommandBuffer, SecondaryAutoCommandBuffer}, descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet}, pipeline::{Pipeline, GraphicsPipeline, PipelineBindPoint, PipelineLayout, graphics::viewport::{Viewport, Scissor}}, buffer::{TypedBufferAccess, CpuAccessibleBuffer, BufferUsage}, render_pass::{Framebuffer, Subpass}};
 
-use crate::{ecs::{components::{general::{Transform, Renderable, Camera, Wireframe}, physics::ColliderRenderable}, resources::{ActiveCamera, RenderData, ProjectionMatrix, CommandBuffer, RenderDataFrameBuffer}}, shaders::default::vs::ty::{VPUniformBufferObject, ModelPushConstants}};
+use crate::{ecs::{components::{general::{Transform, Renderable, PbrMaterial, Camera, Light, MAX_LIGHTS, Wireframe, DoubleSided, SpriteAnimation, NoDepthTest, DepthBias, Transparent, WireframeOverlay, OcclusionCulled, RenderLayers, Minimap, RenderTarget, WorldText}, physics::ColliderRenderable}, resources::{physics::{PhysicsData, PhysicsDebugRender}, ActiveCamera, Cameras, ViewportRect, RenderData, TextFont, ProjectionMatrix, ProjectionKind, CommandBuffer, RenderDataFrameBuffer, RenderDataPostProcess, RenderDataShadowMap, Shadows, Skybox, ClearColor, ClearDepth, AmbientLight, FrameStats, DrawCallBudget}}, graphics::{minimap::marker_uv, pass_cache::{PassCache, PassSignature}, text::text_vertices, occlusion::{OcclusionVisibility, proxy_matrix, bounds_contain, near_plane_reach}, shadows::{depth_range, cascade_splits, frustum_slice_corners, cascade_matrix}, utils::{get_window_from_surface, hsla_to_rgb}, vulkan::ShadowMap}, data_structures::graphics::Vertex, shaders::{default::{vs::ty::{VPUniformBufferObject, ModelPushConstants}, fs::ty::{ShadowUniformBufferObject, LightsUniformBufferObject, LightData}}, shadow::vs::ty::ShadowPushConstants, skybox::vs::ty::SkyboxPushConstants, minimap::fs::ty::MinimapPushConstants}};
 
-pub struct Render;
+// uv offset 0, scale 1
+const IDENTITY_UV_TRANSFORM: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+// Radius of the marker of a Minimap in pixels
+const MINIMAP_MARKER_RADIUS: f32 = 4.0;
+
+// Exceeding the draw call budget is logged at most this often
+const DRAW_CALL_WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct Render {
+    // per framebuffer, the visibility is shared by all of them
+    occlusion_queries: Vec<OcclusionQueries>,
+    occlusion: OcclusionVisibility,
+    // per framebuffer and camera of Cameras
+    solid_passes: PassCache<(usize, usize), CachedSolidPass>,
+    draw_calls: u32,
+    last_budget_warning: Option<Instant>,
+    // created once PhysicsDebugRender is first enabled
+    physics_debug: Option<DebugRenderPipeline>
+}
+
+/// Collects the lines of rapier's DebugRenderPipeline for the line list pipeline
+#[derive(Default)]
+struct DebugLines(Vec<Vertex>);
+
+impl DebugRenderBackend for DebugLines {
+    fn draw_line(&mut self, _object: DebugRenderObject<'_>, a: Point3<f32>, b: Point3<f32>, color: [f32; 4]) {
+        let color = hsla_to_rgb(color);
+        self.0.push(Vertex { position: a.coords.into(), color, ..Default::default() });
+        self.0.push(Vertex { position: b.coords.into(), color, ..Default::default() });
+    }
+}
+
+/// A camera the frame is rendered from, see Cameras
+struct CameraView {
+    view_matrix: Matrix4<f32>,
+    projection: Matrix4<f32>,
+    camera_pos: Vector3<f32>,
+    camera_rot: UnitQuaternion<f32>,
+    // its rect of the window
+    viewport: Viewport
+}
+
+/// Occlusion queries recorded into the last frame drawn to a framebuffer, read before its next frame
+/// resets them, by then that frame has usually finished, a query which hasn't keeps the last visibility
+struct OcclusionQueries {
+    pool: Arc<QueryPool>,
+    framebuffer: Weak<Framebuffer>,
+    // entity of each query
+    queried: Vec<Entity>
+}
+
+/// Solid geometry recorded into a secondary command buffer, executed again every frame until anything it draws changes
+/// Owns its uniform buffers, which are written every frame instead
+struct CachedSolidPass {
+    commands: Arc<SecondaryAutoCommandBuffer>,
+    // dropped once the framebuffer is recreated
+    framebuffer: Weak<Framebuffer>,
+    view_ubo: Arc<CpuAccessibleBuffer<VPUniformBufferObject>>,
+    shadow_ubo: Arc<CpuAccessibleBuffer<ShadowUniformBufferObject>>,
+    lights_ubo: Arc<CpuAccessibleBuffer<LightsUniformBufferObject>>,
+    draw_calls: u32
+}
+
+impl CachedSolidPass {
+    /*
+    False if a uniform buffer is still in use by the gpu, the pass has to be recorded again with new ones then
+    */
+    fn write_uniforms(&self, view: &VPUniformBufferObject, shadows: &ShadowUniformBufferObject, lights: &LightsUniformBufferObject) -> bool {
+        match (self.view_ubo.write(), self.shadow_ubo.write(), self.lights_ubo.write()) {
+            (Ok(mut v), Ok(mut s), Ok(mut l)) => {
+                *v = *view;
+                *s = *shadows;
+                *l = *lights;
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+/// Pipeline a renderable of the solid pass is drawn with
+#[derive(Clone, Copy)]
+enum SolidPipeline<'r> {
+    Default,
+    Pbr(&'r PbrMaterial),
+    // without back face culling, see the DoubleSided component
+    DoubleSided,
+    PbrDoubleSided(&'r PbrMaterial),
+    DepthBias(f32),
+    // RenderData::pipeline_override
+    Override
+}
+
+/// Renderable drawn by the solid pass, in the order it is recorded
+struct SolidDraw<'r> {
+    entity: Entity,
+    pipeline: SolidPipeline<'r>,
+    model: Matrix4<f32>,
+    uv_transform: [f32; 4],
+    renderable: &'r Renderable
+}
+
+// Storages the solid pass draws from
+type SolidStorages<'s, 'a> = (
+    &'s Entities<'a>,
+    &'s ReadStorage<'a, Transform>,
+    &'s ReadStorage<'a, Renderable>,
+    &'s ReadStorage<'a, PbrMaterial>,
+    &'s ReadStorage<'a, SpriteAnimation>,
+    &'s ReadStorage<'a, Wireframe>,
+    &'s ReadStorage<'a, NoDepthTest>,
+    &'s ReadStorage<'a, DepthBias>,
+    &'s ReadStorage<'a, Transparent>,
+    &'s ReadStorage<'a, OcclusionCulled>,
+    &'s ReadStorage<'a, DoubleSided>
+);
+
+// Storages the minimap and render target passes draw from
+type OffscreenStorages<'s, 'a> = (
+    &'s Entities<'a>,
+    &'s ReadStorage<'a, Transform>,
+    &'s ReadStorage<'a, Renderable>,
+    &'s ReadStorage<'a, PbrMaterial>,
+    &'s ReadStorage<'a, SpriteAnimation>,
+    &'s ReadStorage<'a, Wireframe>,
+    &'s ReadStorage<'a, RenderLayers>
+);
 
 impl<'a> System<'a> for Render {
     type SystemData = (
         Entities<'a>,
-        Option<Read<'a, ActiveCamera>>,
+        (Option<Read<'a, ActiveCamera>>, Read<'a, Cameras>),
         Option<Read<'a, RenderData>>,
         Option<Read<'a, RenderDataFrameBuffer>>,
-        Write<'a, CommandBuffer>,
-        Read<'a, ProjectionMatrix>,
-        ReadStorage<'a, Camera>,
+        Option<Read<'a, RenderDataPostProcess>>,
+        Option<Read<'a, Skybox>>,
+        // nested, a tuple of system data holds at most 26
+        (Write<'a, CommandBuffer>, Write<'a, FrameStats>, Read<'a, DrawCallBudget>),
+        (Read<'a, ProjectionMatrix>, Read<'a, ProjectionKind>),
+        Read<'a, Shadows>,
+        Option<Read<'a, RenderDataShadowMap>>,
+        (Read<'a, ClearColor>, Read<'a, ClearDepth>, Read<'a, AmbientLight>),
+        (ReadStorage<'a, Camera>, ReadStorage<'a, Light>),
         ReadStorage<'a, Transform>,
         ReadStorage<'a, Renderable>,
-        ReadStorage<'a, ColliderRenderable>,
-        ReadStorage<'a, Wireframe>
+        ReadStorage<'a, PbrMaterial>,
+        (ReadStorage<'a, ColliderRenderable>, Read<'a, PhysicsDebugRender>, Option<Read<'a, PhysicsData>>),
+        ReadStorage<'a, Wireframe>,
+        ReadStorage<'a, SpriteAnimation>,
+        ReadStorage<'a, NoDepthTest>,
+        ReadStorage<'a, DepthBias>,
+        (ReadStorage<'a, Transparent>, ReadStorage<'a, DoubleSided>),
+        ReadStorage<'a, WireframeOverlay>,
+        ReadStorage<'a, OcclusionCulled>,
+        ReadStorage<'a, RenderLayers>,
+        (ReadStorage<'a, Minimap>, ReadStorage<'a, RenderTarget>),
+        ReadStorage<'a, WorldText>
     );
 
-    fn run(&mut self, (entities, active_cam, render_data, framebuffer, mut command_buffer, proj, _camera, transform, renderable, collider, wireframe): Self::SystemData) {
+    fn run(&mut self, (entities, (active_cam, cameras), render_data, framebuffer, post_process, skybox, (mut command_buffer, mut frame_stats, draw_call_budget), (proj, projection_kind), shadows, shadow_map, (clear_color, clear_depth, ambient_light), (_camera, light), transform, renderable, pbr_material, (collider, physics_debug_render, physics_data), wireframe, sprite_animation, no_depth_test, depth_bias, (transparent, double_sided), wireframe_overlay, occlusion_culled, render_layers, (minimap, render_target), world_text): Self::SystemData) {
         use specs::Join;
+        self.draw_calls = 0;
         // Verify we have all dependencies
         // Abort if not
         let active_camera = match active_cam {
@@ -51,75 +201,370 @@ impl<'a> System<'a> for Render {
             }
         };
 
-        // Get camera view matrix from transform
-        let view_matrix = match transform.get(active_camera.0) {
-            Some(t) => {
-                match t.transformation_matrix().try_inverse() {
-                    Some(v) => v,
-                    None => return error!("Somehow view matrix is not square, aborting rendering")
-                }
+        let shadow_map = match shadow_map {
+            Some(v) => v,
+            None => {
+                error!("Shadow map was none");
+                return
             }
-            // No transform on active camera
-            None => return error!("No Transform on active camera, cannot render!")
         };
 
-        // Create a command buffer
+        // Every camera of Cameras into its rect of the window, or just the active one over all of it
+        let extent = framebuffer.0.extent();
+        let views = match cameras.0.is_empty() {
+            true => vec![(active_camera.0, ViewportRect::FULL)],
+            false => cameras.0.clone()
+        };
+        let mut camera_views = Vec::new();
+        for (camera, rect) in views {
+            // Get camera view matrix from transform
+            let (view_matrix, camera_pos, camera_rot) = match transform.get(camera) {
+                Some(t) => {
+                    match t.transformation_matrix().try_inverse() {
+                        Some(v) => (v, t.pos(), t.rot()),
+                        None => {
+                            error!("Somehow view matrix is not square, skipping the camera");
+                            continue
+                        }
+                    }
+                }
+                // No transform on the camera
+                None => {
+                    error!("No Transform on a camera, cannot render it!");
+                    continue
+                }
+            };
+            let viewport = rect.viewport(extent);
+            // ProjectionMatrix has the aspect ratio of the whole window
+            let projection = match cameras.0.is_empty() {
+                true => proj.0,
+                false => projection_kind.matrix(viewport.dimensions[0] / viewport.dimensions[1])
+            };
+            camera_views.push(CameraView { view_matrix, projection, camera_pos, camera_rot, viewport });
+        }
+        // Shadows and occlusion culling follow the first camera
+        let primary = match camera_views.first() {
+            Some(v) => v,
+            None => return error!("No camera with a Transform, cannot render!")
+        };
+
+        // Create a command buffer, submitted once, the allocator recycles it after it finished executing
         let mut builder = AutoCommandBufferBuilder::primary(
             &render_data.command_buffer_allocator,
             render_data.queue_family_index,
-            CommandBufferUsage::MultipleSubmit
+            CommandBufferUsage::OneTimeSubmit
         ).unwrap();
 
-        // Setup ubo data
-        let ubo_data = VPUniformBufferObject {
-            view: view_matrix.into(),
-            proj: proj.0.into()
-        };
-        let view_ubo = render_data.ubo_pool.from_data(ubo_data).unwrap();
+        // Shadow maps before anything sampling them
+        let cascades = self.render_shadow_maps(&mut builder, &shadow_map.0, &shadows, &primary.view_matrix, &primary.projection, &transform, &renderable);
 
-        // Allocate and write model and view matrix to descriptor set
-        let layout_view = render_data.pipeline.layout().set_layouts().get(0).unwrap();
-        let descriptor_set_view = PersistentDescriptorSet::new(
-            &render_data.descriptor_set_allocator,
-            layout_view.clone(),
-            [WriteDescriptorSet::buffer(0, view_ubo.clone())]
-        ).unwrap();
+        // Bounds around the camera can't be tested, the near plane may clip them away
+        let near_reach = depth_range(&primary.projection).map_or(0.0, |(near, _)| near_plane_reach(&primary.projection, near));
+        let mut proxies = Vec::new();
+        for (e, t, bounds, _, ()) in (&*entities, &transform, &occlusion_culled, &renderable, !&wireframe).join() {
+            // not drawn by the solid pass
+            if no_depth_test.contains(e) && !pbr_material.contains(e) {
+                continue;
+            }
+            let model = t.transformation_matrix();
+            match bounds_contain(&model, &bounds.min, &bounds.max, &primary.camera_pos, near_reach) {
+                true => self.occlusion.reveal(e),
+                false => proxies.push((e, model * proxy_matrix(&bounds.min, &bounds.max)))
+            }
+        }
+        // Minimaps before the window, which draws them on top
+        let mut minimap_markers = Vec::new();
+        for m in (&minimap).join() {
+            let center = match transform.get(m.follow) {
+                Some(v) => v.pos(),
+                None => continue
+            };
+            let (view, projection) = (m.view_matrix(&center), m.projection_matrix());
+            let storages = (&entities, &transform, &renderable, &pbr_material, &sprite_animation, &wireframe, &render_layers);
+            let target = (&m.target.framebuffer, &m.target.pipeline, &m.target.pipeline_pbr);
+            // unlit
+            let lights = LightsUniformBufferObject::zeroed();
+            match self.render_offscreen(&mut builder, target, &view, &projection, lights, m.layers, &[], &clear_color, &render_data, &shadow_map.0, storages) {
+                Ok(_) => minimap_markers.push((m, marker_uv(&(projection * view), &center))),
+                Err(e) => error!("Failed rendering a minimap: {}", e)
+            }
+        }
+        // Render targets too, so renderables showing them are up to date in the same frame
+        for (t, rt) in (&transform, &render_target).join() {
+            let view = match t.transformation_matrix().try_inverse() {
+                Some(v) => v,
+                None => continue
+            };
+            let projection = projection_kind.matrix(rt.target.aspect());
+            let lights = Render::lights_ubo_data(&light, &transform, &view, &t.pos(), &ambient_light);
+            let storages = (&entities, &transform, &renderable, &pbr_material, &sprite_animation, &wireframe, &render_layers);
+            let target = (&rt.target.framebuffer, &rt.target.pipeline, &rt.target.pipeline_pbr);
+            if let Err(e) = self.render_offscreen(&mut builder, target, &view, &projection, lights, RenderLayers::ALL, &rt.displays, &clear_color, &render_data, &shadow_map.0, storages) {
+                error!("Failed rendering a render target: {}", e);
+            }
+        }
+
+        // reset outside of the render pass
+        let occlusion_pool = self.begin_occlusion_queries(&mut builder, &framebuffer.0, proxies.len(), &render_data);
 
+        let mut clear_values = vec![Some(clear_color.clamped().into()), Some(clear_depth.clamped().into())];
+        // with MSAA there is also the resolve target, which is overwritten completely
+        clear_values.resize(framebuffer.0.attachments().len(), None);
+
+        // Everything is drawn by secondary command buffers, the solid geometry by one kept across frames
         builder
             .begin_render_pass(
                 RenderPassBeginInfo {
-                    clear_values: vec![Some([0.0, 0.0, 0.0, 1.0].into()), Some(1f32.into())],
+                    clear_values,
                     ..RenderPassBeginInfo::framebuffer(framebuffer.0.clone())
                 },
-                SubpassContents::Inline,
+                SubpassContents::SecondaryCommandBuffers,
             )
-            .unwrap()
-            .bind_pipeline_graphics(render_data.pipeline.clone())
-            .bind_descriptor_sets(
-                PipelineBindPoint::Graphics, 
-                render_data.pipeline.layout().clone(), 
-                0, 
-                descriptor_set_view.clone()
+            .unwrap();
+
+        let subpass = Subpass::from(framebuffer.0.render_pass().clone(), 0).unwrap();
+
+        // Cameras without occlusion queries draw every renderable
+        let unoccluded = OcclusionVisibility::default();
+        let overridden = render_data.pipeline_override.is_some();
+        // passes of framebuffers which were recreated, e.g. after a resize
+        self.solid_passes.retain(|pass| pass.framebuffer.strong_count() > 0);
+        let mut occlusion_queried = Vec::new();
+        for (index, CameraView { view_matrix, projection, camera_pos, camera_rot, viewport }) in camera_views.iter().enumerate() {
+            // Setup ubo data
+            let ubo_data = VPUniformBufferObject {
+                view: (*view_matrix).into(),
+                proj: (*projection).into()
+            };
+            let view_ubo = render_data.ubo_pool.from_data(ubo_data).unwrap();
+
+            // Allocate and write model and view matrix to descriptor set
+            let layout_view = render_data.pipeline.layout().set_layouts().get(0).unwrap();
+            let descriptor_set_view = PersistentDescriptorSet::new(
+                &render_data.descriptor_set_allocator,
+                layout_view.clone(),
+                [WriteDescriptorSet::buffer(0, view_ubo.clone())]
+            ).unwrap();
+
+            // The cascades are in world space, the shaders shade in view space
+            let mut shadow_data = ShadowUniformBufferObject::zeroed();
+            if let Some(camera) = view_matrix.try_inverse() {
+                for (i, cascade) in cascades.iter().enumerate() {
+                    shadow_data.shadow_matrices[i] = (cascade * camera).into();
+                }
+                shadow_data.shadow_cascades = cascades.len() as u32;
+            }
+            let shadow_ubo = render_data.shadow_ubo_pool.from_data(shadow_data).unwrap();
+            let lights_data = Render::lights_ubo_data(&light, &transform, view_matrix, camera_pos, &ambient_light);
+            let lights_ubo = render_data.lights_ubo_pool.from_data(lights_data).unwrap();
+            // Same layout in the pbr pipeline
+            let layout_shadows = render_data.pipeline.layout().set_layouts().get(2).unwrap();
+            let descriptor_set_shadows = PersistentDescriptorSet::new(
+                &render_data.descriptor_set_allocator,
+                layout_shadows.clone(),
+                [
+                    WriteDescriptorSet::buffer(0, shadow_ubo),
+                    WriteDescriptorSet::image_view_sampler(1, shadow_map.0.view.clone(), shadow_map.0.sampler.clone()),
+                    WriteDescriptorSet::buffer(2, lights_ubo)
+                ]
+            ).unwrap();
+
+            // Skybox first, everything else is drawn over it
+            if let Some(skybox) = skybox.as_deref().filter(|v| v.is_ready()) {
+                if let Err(e) = self.render_skybox(&mut builder, skybox, view_matrix, projection, viewport, &subpass, &render_data) {
+                    error!("Failed rendering the skybox: {}", e);
+                }
+            }
+
+            // Recorded again only when anything it draws changes, otherwise just its uniform buffers are written
+            let storages = (&entities, &transform, &renderable, &pbr_material, &sprite_animation, &wireframe, &no_depth_test, &depth_bias, &transparent, &occlusion_culled, &double_sided);
+            let occlusion = match index {
+                0 => &self.occlusion,
+                _ => &unoccluded
+            };
+            let solid_draws = Render::solid_draws(occlusion, render_data.pipeline_wireframe.is_some(), overridden, storages);
+            let signature = Render::solid_signature(&solid_draws, viewport, &render_data, &shadow_map.0);
+            let solid_pass = self.solid_passes.get_or_record(
+                (Arc::as_ptr(&framebuffer.0) as usize, index),
+                signature,
+                |pass| pass.write_uniforms(&ubo_data, &shadow_data, &lights_data),
+                || match Render::record_solid_pass(&solid_draws, viewport, &ubo_data, &shadow_data, &lights_data, &framebuffer.0, &subpass, &render_data, &shadow_map.0) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        error!("Failed recording the solid pass: {}", e);
+                        None
+                    }
+                }
             );
+            if let Some(pass) = solid_pass {
+                match builder.execute_commands(pass.commands.clone()) {
+                    Ok(_) => self.draw_calls += pass.draw_calls,
+                    Err(e) => error!("Failed executing the solid pass: {:?}", e)
+                }
+            }
+
+            // The rest changes every frame or is rarely used
+            let mut secondary = match Render::secondary_builder(&render_data, &subpass, CommandBufferUsage::OneTimeSubmit) {
+                Ok(v) => v,
+                Err(e) => return error!("Failed creating a secondary command buffer: {}", e)
+            };
+            secondary
+                .set_viewport(0, [viewport.clone()])
+                .set_scissor(0, [Render::scissor(&viewport)]);
+
+            // Tested against the depth of the solid pass, so walls hide the bounds
+            if let (Some(pool), 0) = (&occlusion_pool, index) {
+                self.record_occlusion_queries(&proxies, &(projection * view_matrix), pool, &mut occlusion_queried, &mut secondary, &render_data);
+            }
+
+            // Rapier's own debug view replaces the collider meshes
+            let physics_debug = match physics_debug_render.enabled {
+                true => physics_data.as_deref(),
+                false => None
+            };
+
+            // Render wireframe pipeline, unless the device doesn't support it
+            if let Some(pipeline_wireframe) = &render_data.pipeline_wireframe {
+                secondary
+                    .bind_pipeline_graphics(pipeline_wireframe.clone())
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics, 
+                        render_data.pipeline.layout().clone(), 
+                        0, 
+                        descriptor_set_view.clone()
+                    );
+
+                if physics_debug.is_none() {
+                    for (e, t, r) in (&*entities, &transform, &collider).join() {
+                        if Render::draw_mesh(e, &t.transformation_matrix(), &r.vertex_buffer, &r.index_buffer, None, IDENTITY_UV_TRANSFORM, &mut secondary, render_data.pipeline.layout()) {
+                            self.draw_calls += 1;
+                        }
+                    }
+                }
+
+                // Adding or removing Wireframe moves the mesh between this and the solid pass
+                if !overridden {
+                    for (e, t, r, _, ()) in (&*entities, &transform, &renderable, &wireframe, !&no_depth_test).join() {
+                        self.render_entity(e, t, r, IDENTITY_UV_TRANSFORM, &mut secondary, &render_data, false);
+                    }
+                }
+            }
+
+            if let Some(physics_data) = physics_debug {
+                self.render_physics_debug(physics_data, &physics_debug_render, &descriptor_set_view, &mut secondary, &render_data);
+            }
+
+            // Render the edges of entities with an overlay on top of their already drawn surface
+            if let (Some(pipeline_wireframe_overlay), false) = (&render_data.pipeline_wireframe_overlay, overridden) {
+                secondary
+                    .bind_pipeline_graphics(pipeline_wireframe_overlay.clone())
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics, 
+                        render_data.pipeline.layout().clone(), 
+                        0, 
+                        descriptor_set_view.clone()
+                    );
+
+                for (e, t, r, _, ()) in (&*entities, &transform, &renderable, &wireframe_overlay, !&wireframe).join() {
+                    self.render_entity(e, t, r, IDENTITY_UV_TRANSFORM, &mut secondary, &render_data, false);
+                }
+            }
 
-        for (e, t, r, ()) in (&*entities, &transform, &renderable, !&wireframe).join() {
-            self.render_entity(e, t, r, &mut builder, &render_data, true);
+            // Labels face the camera, before Transparent entities so those blend over them
+            if let Some(font) = &render_data.text_font {
+                let labels: Vec<_> = (&transform, &world_text).join()
+                    .map(|(t, w)| (w.billboard(&t.pos(), camera_rot), w))
+                    .collect();
+                self.render_world_text(&labels, font, &descriptor_set_view, &mut secondary, &render_data);
+            }
+
+            // Blended over everything drawn so far, the farthest first so nearer surfaces end up on top
+            secondary
+                .bind_pipeline_graphics(render_data.pipeline_transparent.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics, 
+                    render_data.pipeline.layout().clone(), 
+                    0, 
+                    descriptor_set_view.clone()
+                )
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics, 
+                    render_data.pipeline.layout().clone(), 
+                    2, 
+                    descriptor_set_shadows.clone()
+                );
+
+            // the override draws them in the solid pass
+            // Wireframe ones are drawn by the wireframe pass, or here if the device can't draw lines
+            let wireframe_supported = render_data.pipeline_wireframe.is_some();
+            let mut transparent_draws: Vec<_> = (&*entities, &transform, &renderable, sprite_animation.maybe(), &transparent, !&no_depth_test).join()
+                .filter(|(e, ..)| !overridden && !(wireframe_supported && wireframe.contains(*e)))
+                .map(|(e, t, r, s, ..)| (t.pos(), (e, t, r, s)))
+                .collect();
+            Render::sort_back_to_front(&mut transparent_draws, camera_pos);
+
+            for (_, (e, t, r, s)) in transparent_draws {
+                let uv_transform = match s {
+                    Some(v) => v.uv_transform(),
+                    None => IDENTITY_UV_TRANSFORM
+                };
+                self.render_entity(e, t, r, uv_transform, &mut secondary, &render_data, true);
+            }
+
+            // Render entities ignoring depth last so they end up on top
+            secondary
+                .bind_pipeline_graphics(render_data.pipeline_no_depth.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics, 
+                    render_data.pipeline.layout().clone(), 
+                    0, 
+                    descriptor_set_view.clone()
+                )
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics, 
+                    render_data.pipeline.layout().clone(), 
+                    2, 
+                    descriptor_set_shadows
+                );
+
+            // PbrMaterials need the pbr pipeline, those are always depth tested
+            for (e, t, r, s, _, ()) in (&*entities, &transform, &renderable, sprite_animation.maybe(), &no_depth_test, !&pbr_material).join().filter(|_| !overridden) {
+                let uv_transform = match s {
+                    Some(v) => v.uv_transform(),
+                    None => IDENTITY_UV_TRANSFORM
+                };
+                self.render_entity(e, t, r, uv_transform, &mut secondary, &render_data, true);
+            }
+
+            match secondary.build() {
+                Ok(v) => if let Err(e) = builder.execute_commands(v) {
+                    error!("Failed executing a secondary command buffer: {:?}", e);
+                },
+                Err(e) => error!("Failed building a secondary command buffer: {:?}", e)
+            };
         }
+        frame_stats.set_recorded_passes(self.solid_passes.recorded());
 
-        // Render wireframe pipeline
-        builder
-            .bind_pipeline_graphics(render_data.pipeline_wireframe.clone())
-            .bind_descriptor_sets(
-                PipelineBindPoint::Graphics, 
-                render_data.pipeline.layout().clone(), 
-                0, 
-                descriptor_set_view.clone()
-            );
+        // Over every camera, with a viewport of their own
+        if !minimap_markers.is_empty() {
+            match Render::secondary_builder(&render_data, &subpass, CommandBufferUsage::OneTimeSubmit) {
+                Ok(mut secondary) => {
+                    for (m, marker) in minimap_markers {
+                        self.render_minimap(&mut secondary, m, marker, extent, &render_data);
+                    }
+                    match secondary.build() {
+                        Ok(v) => if let Err(e) = builder.execute_commands(v) {
+                            error!("Failed executing a secondary command buffer: {:?}", e);
+                        },
+                        Err(e) => error!("Failed building a secondary command buffer: {:?}", e)
+                    };
+                },
+                Err(e) => error!("Failed creating a secondary command buffer: {}", e)
+            }
+        }
 
-        // TODO: this is bad figure out a better way
-        for (e, t, r) in (&*entities, &transform, &collider).join() {
-            // TODO: this is horrible lmao
-            self.render_entity(e, t, &Renderable { vertex_buffer: r.vertex_buffer.clone(), index_buffer: r.index_buffer.clone(), descriptor_set_texture: descriptor_set_view.clone() }, &mut builder, &render_data, false);
+        if let Some(pool) = occlusion_pool {
+            self.occlusion_queries.push(OcclusionQueries { pool, framebuffer: Arc::downgrade(&framebuffer.0), queried: occlusion_queried });
         }
 
         match builder.end_render_pass() {
@@ -127,51 +572,954 @@ impl<'a> System<'a> for Render {
             Err(e) => return error!("Failed ending render pass: {:?}", e)
         };
 
+        // FXAA or upscaling, samples the rendered scene onto the swapchain image
+        if let Some(post) = post_process.as_ref().and_then(|v| v.0.as_ref()) {
+            match builder.begin_render_pass(
+                RenderPassBeginInfo {
+                    // every pixel is overwritten by the fullscreen triangle
+                    clear_values: vec![None],
+                    ..RenderPassBeginInfo::framebuffer(post.framebuffer.clone())
+                },
+                SubpassContents::Inline
+            ) {
+                Ok(v) => v,
+                Err(e) => return error!("Failed beginning post process render pass: {:?}", e)
+            };
+
+            builder
+                .bind_pipeline_graphics(post.pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    post.pipeline.layout().clone(),
+                    0,
+                    post.descriptor_set.clone()
+                );
+
+            match builder.draw(3, 1, 0, 0) {
+                Ok(_) => self.draw_calls += 1,
+                Err(e) => return error!("Failed drawing post process pass: {:?}", e)
+            };
+
+            match builder.end_render_pass() {
+                Ok(v) => v,
+                Err(e) => return error!("Failed ending post process render pass: {:?}", e)
+            };
+        }
+
         let buffer = match builder.build() {
             Ok(v) => Arc::new(v),
             Err(e) => return error!("Failed building command buffer: {:?}", e)
         };
 
+        frame_stats.set_draw_calls(self.draw_calls);
+        if self.draw_calls > draw_call_budget.0 && self.last_budget_warning.map_or(true, |t| t.elapsed() >= DRAW_CALL_WARNING_INTERVAL) {
+            warn!("{} draw calls this frame, over the budget of {}", self.draw_calls, draw_call_budget.0);
+            self.last_budget_warning = Some(Instant::now());
+        }
+
         command_buffer.command_buffer = Some(buffer);
     }
 }
 
 impl Render {
-    fn render_entity(
-        &self,
+    /*
+    Renderables of the solid pass, the ones with the default pipeline first, then with a PbrMaterial, then biased ones
+    Hidden ones according to the occlusion queries and ones whose textures are still uploading are left out
+    Wireframe ones as well, unless the device can't draw lines
+    With overridden, every renderable is drawn by the pass with the override pipeline instead
+    */
+    fn solid_draws<'r>(
+        occlusion: &OcclusionVisibility,
+        wireframe_supported: bool,
+        overridden: bool,
+        (entities, transforms, renderables, pbr_materials, sprite_animations, wireframes, no_depth_tests, depth_biases, transparents, occlusion_culled, double_sided): SolidStorages<'r, '_>
+    ) -> Vec<SolidDraw<'r>> {
+        use specs::Join;
+
+        let uv_transform = |s: Option<&SpriteAnimation>| match s {
+            Some(v) => v.uv_transform(),
+            None => IDENTITY_UV_TRANSFORM
+        };
+        let visible = |e: Entity, r: &Renderable| r.is_ready() && !(occlusion_culled.contains(e) && occlusion.is_occluded(e));
+        let to_draw = |(entity, t, renderable, s, pipeline): (Entity, &Transform, &'r Renderable, Option<&SpriteAnimation>, SolidPipeline<'r>)| SolidDraw {
+            entity,
+            pipeline,
+            model: t.transformation_matrix(),
+            uv_transform: uv_transform(s),
+            renderable
+        };
+
+        if overridden {
+            return (&**entities, transforms, renderables, sprite_animations.maybe()).join()
+                .filter(|(e, _, r, _)| visible(*e, r))
+                .map(|(e, t, r, s)| to_draw((e, t, r, s, SolidPipeline::Override)))
+                .collect();
+        }
+
+        let default = (&**entities, transforms, renderables, sprite_animations.maybe(), double_sided.maybe(), !pbr_materials, !no_depth_tests, !depth_biases, !transparents).join()
+            .map(|(e, t, r, s, d, ..)| (e, t, r, s, match d {
+                Some(_) => SolidPipeline::DoubleSided,
+                None => SolidPipeline::Default
+            }));
+        let pbr = (&**entities, transforms, renderables, pbr_materials, sprite_animations.maybe(), double_sided.maybe(), !transparents).join()
+            .map(|(e, t, r, m, s, d, ..)| (e, t, r, s, match d {
+                Some(_) => SolidPipeline::PbrDoubleSided(m),
+                None => SolidPipeline::Pbr(m)
+            }));
+        let biased = (&**entities, transforms, renderables, sprite_animations.maybe(), depth_biases, !pbr_materials, !no_depth_tests, !transparents).join()
+            .map(|(e, t, r, s, b, ..)| (e, t, r, s, SolidPipeline::DepthBias(b.0)));
+
+        default.chain(pbr).chain(biased)
+            .filter(|(e, _, r, ..)| visible(*e, r) && !(wireframe_supported && wireframes.contains(*e)))
+            .map(to_draw)
+            .collect()
+    }
+
+    /*
+    Everything the commands of the solid pass depend on, its gpu resources by pointer
+    The recorded pass keeps them alive, so another resource can't reuse their address while it is cached
+    */
+    fn solid_signature(draws: &[SolidDraw<'_>], viewport: &Viewport, render_data: &RenderData, shadow_map: &ShadowMap) -> u64 {
+        let mut signature = PassSignature::default();
+        signature.add_f32s(&viewport.origin);
+        signature.add_f32s(&viewport.dimensions);
+        signature.add_ptr(Arc::as_ptr(&render_data.pipeline));
+        signature.add_ptr(Arc::as_ptr(&render_data.pipeline_pbr));
+        signature.add_ptr(Arc::as_ptr(&render_data.pipeline_depth_bias));
+        signature.add_ptr(Arc::as_ptr(&render_data.pipeline_double_sided));
+        signature.add_ptr(Arc::as_ptr(&render_data.pipeline_pbr_double_sided));
+        signature.add_ptr(Arc::as_ptr(&shadow_map.view));
+        if let Some(pipeline_override) = &render_data.pipeline_override {
+            signature.add_ptr(Arc::as_ptr(pipeline_override));
+        }
+
+        for draw in draws {
+            signature.add(draw.entity);
+            match draw.pipeline {
+                SolidPipeline::Default => signature.add(0u8),
+                SolidPipeline::Pbr(m) => {
+                    signature.add(1u8);
+                    signature.add_ptr(Arc::as_ptr(&m.descriptor_set));
+                },
+                SolidPipeline::DepthBias(bias) => {
+                    signature.add(2u8);
+                    signature.add_f32s(&[bias]);
+                },
+                SolidPipeline::Override => signature.add(3u8),
+                SolidPipeline::DoubleSided => signature.add(4u8),
+                SolidPipeline::PbrDoubleSided(m) => {
+                    signature.add(5u8);
+                    signature.add_ptr(Arc::as_ptr(&m.descriptor_set));
+                }
+            }
+            signature.add_ptr(Arc::as_ptr(&draw.renderable.vertex_buffer));
+            signature.add_ptr(Arc::as_ptr(&draw.renderable.index_buffer));
+            signature.add_ptr(Arc::as_ptr(&draw.renderable.descriptor_set_texture));
+            signature.add_matrix(&draw.model);
+            signature.add_f32s(&draw.uv_transform);
+        }
+        signature.finish()
+    }
+
+    /*
+    Records draws into viewport of a secondary command buffer which can be executed again in later frames,
+    with new uniform buffers holding view and shadows
+    */
+    fn record_solid_pass(
+        draws: &[SolidDraw<'_>],
+        viewport: &Viewport,
+        view: &VPUniformBufferObject,
+        shadows: &ShadowUniformBufferObject,
+        lights: &LightsUniformBufferObject,
+        framebuffer: &Arc<Framebuffer>,
+        subpass: &Subpass,
+        render_data: &RenderData,
+        shadow_map: &ShadowMap
+    ) -> Result<CachedSolidPass, String> {
+        let usage = BufferUsage {
+            uniform_buffer: true,
+            ..Default::default()
+        };
+        let view_ubo = CpuAccessibleBuffer::from_data(&render_data.memory_allocator, usage, false, *view).map_err(|e| format!("{:?}", e))?;
+        let shadow_ubo = CpuAccessibleBuffer::from_data(&render_data.memory_allocator, usage, false, *shadows).map_err(|e| format!("{:?}", e))?;
+        let lights_ubo = CpuAccessibleBuffer::from_data(&render_data.memory_allocator, usage, false, *lights).map_err(|e| format!("{:?}", e))?;
+
+        // Same layouts of set 0 and 2 in every pipeline of the pass
+        let layout = render_data.pipeline.layout();
+        let descriptor_set_view = PersistentDescriptorSet::new(
+            &render_data.descriptor_set_allocator,
+            layout.set_layouts().get(0).unwrap().clone(),
+            [WriteDescriptorSet::buffer(0, view_ubo.clone())]
+        ).map_err(|e| format!("{:?}", e))?;
+        let descriptor_set_shadows = PersistentDescriptorSet::new(
+            &render_data.descriptor_set_allocator,
+            layout.set_layouts().get(2).unwrap().clone(),
+            [
+                WriteDescriptorSet::buffer(0, shadow_ubo.clone()),
+                WriteDescriptorSet::image_view_sampler(1, shadow_map.view.clone(), shadow_map.sampler.clone()),
+                WriteDescriptorSet::buffer(2, lights_ubo.clone())
+            ]
+        ).map_err(|e| format!("{:?}", e))?;
+
+        // Executed by the frames of every swapchain image which may still be in flight
+        let mut builder = Render::secondary_builder(render_data, subpass, CommandBufferUsage::SimultaneousUse)?;
+        builder
+            .set_viewport(0, [viewport.clone()])
+            .set_scissor(0, [Render::scissor(viewport)]);
+        let mut bound = None;
+        let mut draw_calls = 0;
+        for draw in draws {
+            let pipeline = match draw.pipeline {
+                SolidPipeline::Default => &render_data.pipeline,
+                SolidPipeline::Pbr(_) => &render_data.pipeline_pbr,
+                SolidPipeline::DoubleSided => &render_data.pipeline_double_sided,
+                SolidPipeline::PbrDoubleSided(_) => &render_data.pipeline_pbr_double_sided,
+                SolidPipeline::DepthBias(_) => &render_data.pipeline_depth_bias,
+                SolidPipeline::Override => render_data.pipeline_override.as_ref().unwrap_or(&render_data.pipeline)
+            };
+            // e.g. the wireframe pipeline as an override samples neither textures nor shadows
+            let sets = pipeline.layout().set_layouts().len();
+            if bound != Some(Arc::as_ptr(pipeline)) {
+                builder
+                    .bind_pipeline_graphics(pipeline.clone())
+                    .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, descriptor_set_view.clone());
+                if sets > 2 {
+                    builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 2, descriptor_set_shadows.clone());
+                }
+                bound = Some(Arc::as_ptr(pipeline));
+            }
+
+            let texture = match draw.pipeline {
+                // the material is bound instead of the texture
+                SolidPipeline::Pbr(m) | SolidPipeline::PbrDoubleSided(m) => {
+                    builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 1, m.descriptor_set.clone());
+                    None
+                },
+                SolidPipeline::DepthBias(bias) => {
+                    // smaller depth is closer, no clamp since that needs the depth_bias_clamp feature
+                    builder.set_depth_bias(-bias, 0.0, -bias);
+                    Some(&draw.renderable.descriptor_set_texture)
+                },
+                SolidPipeline::Default | SolidPipeline::DoubleSided => Some(&draw.renderable.descriptor_set_texture),
+                SolidPipeline::Override => Some(&draw.renderable.descriptor_set_texture).filter(|_| sets > 1)
+            };
+            let r = draw.renderable;
+            if Render::draw_mesh(draw.entity, &draw.model, &r.vertex_buffer, &r.index_buffer, texture, draw.uv_transform, &mut builder, pipeline.layout()) {
+                draw_calls += 1;
+            }
+        }
+
+        let commands = builder.build().map_err(|e| format!("{:?}", e))?;
+        Ok(CachedSolidPass {
+            commands: Arc::new(commands),
+            framebuffer: Arc::downgrade(framebuffer),
+            view_ubo,
+            shadow_ubo,
+            lights_ubo,
+            draw_calls
+        })
+    }
+
+    /*
+    Secondary command buffer executed within subpass of the main render pass
+    */
+    fn secondary_builder(
+        render_data: &RenderData,
+        subpass: &Subpass,
+        usage: CommandBufferUsage
+    ) -> Result<AutoCommandBufferBuilder<SecondaryAutoCommandBuffer, Arc<StandardCommandBufferAllocator>>, String> {
+        AutoCommandBufferBuilder::secondary(
+            &render_data.command_buffer_allocator,
+            render_data.queue_family_index,
+            usage,
+            CommandBufferInheritanceInfo {
+                render_pass: Some(subpass.clone().into()),
+                ..Default::default()
+            }
+        ).map_err(|e| format!("{:?}", e))
+    }
+
+    /*
+    Renders the depth of every renderable into the cascades of the sun,
+    fitted to the slices of the view frustum given by the Shadows settings
+    Returns the matrices from world space to the clip space of each cascade, none without shadows
+    */
+    fn render_shadow_maps(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer, Arc<StandardCommandBufferAllocator>>,
+        shadow_map: &ShadowMap,
+        shadows: &Shadows,
+        view_matrix: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+        transforms: &ReadStorage<'_, Transform>,
+        renderables: &ReadStorage<'_, Renderable>
+    ) -> Vec<Matrix4<f32>> {
+        use specs::Join;
+
+        // resized before the next frame if the settings just changed
+        if !shadows.enabled() || shadow_map.framebuffers.len() < shadows.cascade_count as usize {
+            return Vec::new();
+        }
+
+        let direction = match shadows.direction.try_normalize(f32::EPSILON) {
+            Some(v) => v,
+            None => return Vec::new()
+        };
+
+        let (inverse_view_projection, (near, far)) = match ((projection * view_matrix).try_inverse(), depth_range(projection)) {
+            (Some(inverse), Some(range)) => (inverse, range),
+            _ => {
+                error!("Somehow the view projection is not invertible, skipping shadows");
+                return Vec::new();
+            }
+        };
+
+        let far = far.min(shadows.distance.max(near * 2.0));
+        let mut slice_near = near;
+        let cascades: Vec<Matrix4<f32>> = cascade_splits(near, far, shadows.cascade_count, shadows.split_lambda).into_iter()
+            .map(|slice_far| {
+                let corners = frustum_slice_corners(&inverse_view_projection, projection, slice_near, slice_far);
+                slice_near = slice_far;
+                // casters up to the shadow distance towards the sun still shadow the slice
+                cascade_matrix(&corners, &direction, shadow_map.resolution, shadows.distance)
+            })
+            .collect();
+
+        let casters: Vec<(Matrix4<f32>, &Renderable)> = (transforms, renderables).join()
+            .map(|(t, r)| (t.transformation_matrix(), r))
+            .collect();
+
+        for (cascade, framebuffer) in cascades.iter().zip(shadow_map.framebuffers.iter()) {
+            if let Err(e) = builder.begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some(1.0.into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                },
+                SubpassContents::Inline
+            ) {
+                error!("Failed beginning shadow render pass: {:?}", e);
+                return Vec::new();
+            }
+
+            builder.bind_pipeline_graphics(shadow_map.pipeline.clone());
+
+            for (model, renderable) in casters.iter() {
+                let push_constants = ShadowPushConstants {
+                    light_mvp: (cascade * model).into()
+                };
+                let result = builder
+                    .push_constants(shadow_map.pipeline.layout().clone(), 0, push_constants)
+                    .bind_vertex_buffers(0, renderable.vertex_buffer.clone())
+                    .bind_index_buffer(renderable.index_buffer.clone())
+                    .draw_indexed(renderable.index_buffer.len() as u32, 1, 0, 0, 0);
+
+                match result {
+                    Ok(_) => self.draw_calls += 1,
+                    Err(e) => error!("Failed drawing a shadow caster: {:?}", e)
+                }
+            }
+
+            if let Err(e) = builder.end_render_pass() {
+                error!("Failed ending shadow render pass: {:?}", e);
+                return Vec::new();
+            }
+        }
+        cascades
+    }
+
+    /*
+    Renders the renderables in layers into the framebuffer of a minimap or render target, except the ones in skip
+    The pipelines have to be made for its render pass, nothing is shadowed
+    */
+    fn render_offscreen(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer, Arc<StandardCommandBufferAllocator>>,
+        (framebuffer, pipeline, pipeline_pbr): (&Arc<Framebuffer>, &Arc<GraphicsPipeline>, &Arc<GraphicsPipeline>),
+        view_matrix: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+        lights: LightsUniformBufferObject,
+        layers: u32,
+        skip: &[Entity],
+        clear_color: &ClearColor,
+        render_data: &RenderData,
+        shadow_map: &ShadowMap,
+        (entities, transforms, renderables, pbr_materials, sprite_animations, wireframes, render_layers): OffscreenStorages<'_, '_>
+    ) -> Result<(), String> {
+        use specs::Join;
+
+        let view_ubo = VPUniformBufferObject {
+            view: (*view_matrix).into(),
+            proj: (*projection).into()
+        };
+        let view_ubo = render_data.ubo_pool.from_data(view_ubo).map_err(|e| format!("{:?}", e))?;
+        let descriptor_set_view = PersistentDescriptorSet::new(
+            &render_data.descriptor_set_allocator,
+            pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [WriteDescriptorSet::buffer(0, view_ubo)]
+        ).map_err(|e| format!("{:?}", e))?;
+        let shadow_ubo = render_data.shadow_ubo_pool.from_data(ShadowUniformBufferObject::zeroed()).map_err(|e| format!("{:?}", e))?;
+        let lights_ubo = render_data.lights_ubo_pool.from_data(lights).map_err(|e| format!("{:?}", e))?;
+        let descriptor_set_shadows = PersistentDescriptorSet::new(
+            &render_data.descriptor_set_allocator,
+            pipeline.layout().set_layouts().get(2).unwrap().clone(),
+            [
+                WriteDescriptorSet::buffer(0, shadow_ubo),
+                WriteDescriptorSet::image_view_sampler(1, shadow_map.view.clone(), shadow_map.sampler.clone()),
+                WriteDescriptorSet::buffer(2, lights_ubo)
+            ]
+        ).map_err(|e| format!("{:?}", e))?;
+
+        builder.begin_render_pass(
+            RenderPassBeginInfo {
+                clear_values: vec![Some(clear_color.clamped().into()), Some(1.0.into())],
+                ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+            },
+            SubpassContents::Inline
+        ).map_err(|e| format!("{:?}", e))?;
+        let [width, height] = framebuffer.extent();
+        builder
+            .set_viewport(0, [Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [width as f32, height as f32],
+                depth_range: 0.0..1.0
+            }])
+            .set_scissor(0, [Scissor { origin: [0, 0], dimensions: [width, height] }]);
+
+        // Same sets in both pipelines, the set 1 of the pbr pipeline is its material
+        for (pipeline, pbr) in [(pipeline, false), (pipeline_pbr, true)] {
+            builder
+                .bind_pipeline_graphics(pipeline.clone())
+                .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, descriptor_set_view.clone())
+                .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 2, descriptor_set_shadows.clone());
+
+            for (e, t, r, m, s, l, ()) in (entities, transforms, renderables, pbr_materials.maybe(), sprite_animations.maybe(), render_layers.maybe(), !wireframes).join() {
+                if m.is_some() != pbr || !RenderLayers::visible(l, layers) || skip.contains(&e) {
+                    continue;
+                }
+                let uv_transform = match s {
+                    Some(v) => v.uv_transform(),
+                    None => IDENTITY_UV_TRANSFORM
+                };
+                if let Some(m) = m {
+                    builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 1, m.descriptor_set.clone());
+                }
+                self.render_entity(e, t, r, uv_transform, builder, render_data, !pbr);
+            }
+        }
+
+        builder.end_render_pass().map_err(|e| format!("{:?}", e))?;
+        Ok(())
+    }
+
+    /*
+    Draws the texture of minimap into its rect of the window, with the marker at marker in uv
+    */
+    fn render_minimap<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, Arc<StandardCommandBufferAllocator>>,
+        minimap: &Minimap,
+        marker: Option<[f32; 2]>,
+        extent: [u32; 2],
+        render_data: &RenderData
+    ) {
+        let [x, y, width, height] = minimap.rect;
+        let viewport = Viewport {
+            origin: [x * extent[0] as f32, y * extent[1] as f32],
+            dimensions: [width * extent[0] as f32, height * extent[1] as f32],
+            depth_range: 0.0..1.0
+        };
+        if viewport.dimensions.iter().any(|v| *v < 1.0) {
+            return;
+        }
+
+        let (marker, marker_radius) = match marker {
+            Some(v) => (v, [MINIMAP_MARKER_RADIUS / viewport.dimensions[0], MINIMAP_MARKER_RADIUS / viewport.dimensions[1]]),
+            None => ([0.0; 2], [0.0; 2])
+        };
+        let push_constants = MinimapPushConstants {
+            marker_color: minimap.marker_color,
+            marker,
+            marker_radius
+        };
+
+        let pipeline = &render_data.pipeline_minimap;
+        let descriptor_set = match PersistentDescriptorSet::new(
+            &render_data.descriptor_set_allocator,
+            pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [WriteDescriptorSet::image_view_sampler(0, minimap.target.color.clone(), render_data.sampler.clone())]
+        ) {
+            Ok(v) => v,
+            Err(e) => return error!("Failed creating the descriptor set of a minimap: {:?}", e)
+        };
+
+        let result = builder
+            .set_scissor(0, [Render::scissor(&viewport)])
+            .set_viewport(0, [viewport])
+            .bind_pipeline_graphics(pipeline.clone())
+            .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, descriptor_set)
+            .push_constants(pipeline.layout().clone(), 0, push_constants)
+            .draw(3, 1, 0, 0);
+
+        match result {
+            Ok(_) => self.draw_calls += 1,
+            Err(e) => error!("Failed drawing a minimap: {:?}", e)
+        }
+    }
+
+    /*
+    Reads the results of the last frame drawn to framebuffer and resets its pool for count queries,
+    which is grown if needed, None if there is nothing to query
+    Recorded before the render pass, resetting queries isn't allowed inside one
+    */
+    fn begin_occlusion_queries(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer, Arc<StandardCommandBufferAllocator>>,
+        framebuffer: &Arc<Framebuffer>,
+        count: usize,
+        render_data: &RenderData
+    ) -> Option<Arc<QueryPool>> {
+        // pools of framebuffers which were recreated, e.g. after a resize
+        self.occlusion_queries.retain(|q| q.framebuffer.strong_count() > 0);
+        let previous = self.occlusion_queries.iter()
+            .position(|q| Weak::as_ptr(&q.framebuffer) == Arc::as_ptr(framebuffer))
+            .map(|i| self.occlusion_queries.swap_remove(i));
+
+        let pool = match previous {
+            Some(previous) => {
+                // a sample count and availability per query, not available ones stay 0
+                let mut results = vec![0u64; previous.queried.len() * 2];
+                if !previous.queried.is_empty() {
+                    let flags = QueryResultFlags { with_availability: true, ..QueryResultFlags::empty() };
+                    match previous.pool.queries_range(0..previous.queried.len() as u32).map(|v| v.get_results(&mut results, flags)) {
+                        Some(Ok(_)) => {},
+                        Some(Err(e)) => error!("Failed reading occlusion query results: {:?}", e),
+                        None => error!("Somehow more occlusion queries were recorded than their pool has")
+                    }
+                }
+                self.occlusion.update(&previous.queried, &results);
+                Some(previous.pool)
+            },
+            None => None
+        };
+
+        if count == 0 {
+            return None;
+        }
+
+        let pool = match pool.filter(|v| v.query_count() as usize >= count) {
+            Some(v) => v,
+            None => {
+                let create_info = QueryPoolCreateInfo {
+                    query_count: count.next_power_of_two() as u32,
+                    ..QueryPoolCreateInfo::query_type(QueryType::Occlusion)
+                };
+                match QueryPool::new(render_data.pipeline_occlusion.device().clone(), create_info) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("Failed creating an occlusion query pool: {:?}", e);
+                        return None;
+                    }
+                }
+            }
+        };
+
+        // Only this framebuffer uses the pool, the frame it was last used in finishes before this one starts
+        if let Err(e) = unsafe { builder.reset_query_pool(pool.clone(), 0..count as u32) } {
+            error!("Failed resetting occlusion queries: {:?}", e);
+            return None;
+        }
+        Some(pool)
+    }
+
+    /*
+    Draws the bounds of each proxy with its own query, the index of the query is its position in queried
+    */
+    fn record_occlusion_queries<L>(
+        &mut self,
+        proxies: &[(Entity, Matrix4<f32>)],
+        view_projection: &Matrix4<f32>,
+        pool: &Arc<QueryPool>,
+        queried: &mut Vec<Entity>,
+        builder: &mut AutoCommandBufferBuilder<L, Arc<StandardCommandBufferAllocator>>,
+        render_data: &RenderData
+    ) {
+        let (vertex_buffer, index_buffer) = render_data.occlusion_proxy.clone();
+        let layout = render_data.pipeline_occlusion.layout().clone();
+        builder
+            .bind_pipeline_graphics(render_data.pipeline_occlusion.clone())
+            .bind_vertex_buffers(0, vertex_buffer)
+            .bind_index_buffer(index_buffer.clone());
+
+        for (entity, model) in proxies {
+            let index = queried.len() as u32;
+            let push_constants = ShadowPushConstants {
+                light_mvp: (view_projection * model).into()
+            };
+            builder.push_constants(layout.clone(), 0, push_constants);
+
+            // every query was reset by begin_occlusion_queries and each index is only used once
+            if let Err(e) = unsafe { builder.begin_query(pool.clone(), index, QueryControlFlags::empty()) } {
+                error!("Failed beginning the occlusion query of {:?}: {:?}", entity, e);
+                break;
+            }
+            match builder.draw_indexed(index_buffer.len() as u32, 1, 0, 0, 0) {
+                Ok(_) => self.draw_calls += 1,
+                Err(e) => error!("Failed drawing the bounds of {:?}: {:?}", entity, e)
+            }
+            if let Err(e) = builder.end_query(pool.clone(), index) {
+                error!("Failed ending the occlusion query of {:?}: {:?}", entity, e);
+                break;
+            }
+            queried.push(*entity);
+        }
+    }
+
+    /*
+    Pixels covered by viewport, drawing is clipped to them so nothing drawn for one camera,
+    e.g. an overlay or wide lines, reaches into the rect of another
+    */
+    fn scissor(viewport: &Viewport) -> Scissor {
+        let min = viewport.origin.map(|v| v.max(0.0).floor());
+        let max = [0, 1].map(|i| (viewport.origin[i] + viewport.dimensions[i]).ceil().max(min[i] + 1.0));
+        Scissor {
+            origin: min.map(|v| v as u32),
+            dimensions: [0, 1].map(|i| (max[i] - min[i]) as u32)
+        }
+    }
+
+    /*
+    Farthest from camera_pos first, by the position of each entity rather than per triangle,
+    so intersecting transparent meshes can still blend in the wrong order
+    */
+    fn sort_back_to_front<T>(draws: &mut [(Vector3<f32>, T)], camera_pos: &Vector3<f32>) {
+        draws.sort_by(|a, b| (b.0 - camera_pos).norm_squared().total_cmp(&(a.0 - camera_pos).norm_squared()));
+    }
+
+    /*
+    Records the WorldText labels with their model matrix, the glyph quads are built again every frame
+    */
+    fn render_world_text<L>(
+        &mut self,
+        labels: &[(Matrix4<f32>, &WorldText)],
+        font: &TextFont,
+        descriptor_set_view: &Arc<PersistentDescriptorSet>,
+        builder: &mut AutoCommandBufferBuilder<L, Arc<StandardCommandBufferAllocator>>,
+        render_data: &RenderData
+    ) {
+        if labels.is_empty() || !font.is_ready() {
+            return;
+        }
+
+        let layout = render_data.pipeline_text.layout();
+        builder
+            .bind_pipeline_graphics(render_data.pipeline_text.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                layout.clone(),
+                0,
+                descriptor_set_view.clone()
+            )
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                layout.clone(),
+                1,
+                font.descriptor_set.clone()
+            );
+
+        for (model, label) in labels {
+            let vertices = text_vertices(&label.content, label.color);
+            if vertices.is_empty() {
+                continue;
+            }
+
+            let vertex_count = vertices.len() as u32;
+            let vertex_buffer = match render_data.vertex_pool.from_iter(vertices) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Failed allocating a WorldText label: {:?}", e);
+                    continue;
+                }
+            };
+
+            let push_constants = ModelPushConstants {
+                model: (*model).into(),
+                uv_transform: IDENTITY_UV_TRANSFORM
+            };
+            let result = builder
+                .push_constants(layout.clone(), 0, push_constants)
+                .bind_vertex_buffers(0, vertex_buffer)
+                .draw(vertex_count, 1, 0, 0);
+
+            match result {
+                Ok(_) => self.draw_calls += 1,
+                Err(e) => error!("Failed drawing a WorldText label: {:?}", e)
+            }
+        }
+    }
+
+    /*
+    Records the lines of rapier's debug view, they are in world space
+    */
+    fn render_physics_debug<L>(
+        &mut self,
+        physics_data: &PhysicsData,
+        settings: &PhysicsDebugRender,
+        descriptor_set_view: &Arc<PersistentDescriptorSet>,
+        builder: &mut AutoCommandBufferBuilder<L, Arc<StandardCommandBufferAllocator>>,
+        render_data: &RenderData
+    ) {
+        let pipeline = self.physics_debug.get_or_insert_with(|| DebugRenderPipeline::new(DebugRenderStyle::default(), settings.mode));
+        pipeline.mode = settings.mode;
+
+        let mut lines = DebugLines::default();
+        physics_data.debug_render(pipeline, &mut lines);
+
+        if lines.0.is_empty() {
+            return;
+        }
+
+        let vertex_count = lines.0.len() as u32;
+        let vertex_buffer = match render_data.vertex_pool.from_iter(lines.0) {
+            Ok(v) => v,
+            Err(e) => return error!("Failed allocating physics debug lines: {:?}", e)
+        };
+
+        let push_constants = ModelPushConstants {
+            model: Matrix4::identity().into(),
+            uv_transform: IDENTITY_UV_TRANSFORM
+        };
+
+        let layout = render_data.pipeline_debug_lines.layout();
+        let result = builder
+            .bind_pipeline_graphics(render_data.pipeline_debug_lines.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                layout.clone(),
+                0,
+                descriptor_set_view.clone()
+            )
+            .push_constants(layout.clone(), 0, push_constants)
+            .bind_vertex_buffers(0, vertex_buffer)
+            .draw(vertex_count, 1, 0, 0);
+
+        match result {
+            Ok(_) => self.draw_calls += 1,
+            Err(e) => error!("Failed drawing physics debug lines: {:?}", e)
+        }
+    }
+
+    /*
+    Only the rotation of the view is used, so the skybox stays centered on the camera
+    */
+    fn skybox_push_constants(view_matrix: &Matrix4<f32>, projection: &Matrix4<f32>) -> SkyboxPushConstants {
+        let rotation = view_matrix.fixed_view::<3, 3>(0, 0).into_owned().to_homogeneous();
+        SkyboxPushConstants {
+            view_proj: (projection * rotation).into()
+        }
+    }
+
+    /*
+    Draws the cube of skybox into viewport in a secondary command buffer of its own, ahead of the solid pass
+    */
+    fn render_skybox(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer, Arc<StandardCommandBufferAllocator>>,
+        skybox: &Skybox,
+        view_matrix: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+        viewport: &Viewport,
+        subpass: &Subpass,
+        render_data: &RenderData
+    ) -> Result<(), String> {
+        let pipeline = &render_data.pipeline_skybox;
+        let mut secondary = Render::secondary_builder(render_data, subpass, CommandBufferUsage::OneTimeSubmit)?;
+        secondary
+            .set_viewport(0, [viewport.clone()])
+            .set_scissor(0, [Render::scissor(viewport)])
+            .bind_pipeline_graphics(pipeline.clone())
+            .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, skybox.descriptor_set.clone())
+            .push_constants(pipeline.layout().clone(), 0, Render::skybox_push_constants(view_matrix, projection));
+        secondary.draw(14, 1, 0, 0).map_err(|e| format!("{:?}", e))?;
+
+        let commands = secondary.build().map_err(|e| format!("{:?}", e))?;
+        builder.execute_commands(commands).map_err(|e| format!("{:?}", e))?;
+        self.draw_calls += 1;
+        Ok(())
+    }
+
+    /*
+    Lights of the frame in view space, directional lights first and then the point lights closest to the camera
+    */
+    fn lights_ubo_data(
+        lights: &ReadStorage<'_, Light>,
+        transforms: &ReadStorage<'_, Transform>,
+        view_matrix: &Matrix4<f32>,
+        camera_pos: &Vector3<f32>,
+        ambient: &AmbientLight
+    ) -> LightsUniformBufferObject {
+        use specs::Join;
+
+        let mut collected: Vec<(f32, LightData)> = (lights, transforms.maybe()).join()
+            .filter_map(|(l, t)| match *l {
+                Light::Directional { direction, color, intensity } => {
+                    let direction = view_matrix.transform_vector(&direction).try_normalize(f32::EPSILON)?;
+                    Some((-1.0, LightData {
+                        position_range: [0.0; 4],
+                        direction_kind: [direction.x, direction.y, direction.z, 0.0],
+                        color_intensity: [color.x, color.y, color.z, intensity]
+                    }))
+                },
+                Light::Point { color, intensity, range } => {
+                    let t = t?;
+                    let position = view_matrix.transform_point(&t.pos().into());
+                    Some(((t.pos() - camera_pos).norm_squared(), LightData {
+                        position_range: [position.x, position.y, position.z, range],
+                        direction_kind: [0.0, 0.0, 0.0, 1.0],
+                        color_intensity: [color.x, color.y, color.z, intensity]
+                    }))
+                }
+            })
+            .collect();
+        collected.sort_by(|a, b| a.0.total_cmp(&b.0));
+        collected.truncate(MAX_LIGHTS);
+
+        let mut data = LightsUniformBufferObject::zeroed();
+        for (i, (_, l)) in collected.iter().enumerate() {
+            data.lights[i] = *l;
+        }
+        data.ambient = [ambient.0[0], ambient.0[1], ambient.0[2], 0.0];
+        data.count = collected.len() as u32;
+        data
+    }
+
+    fn render_entity<L>(
+        &mut self,
         entity: Entity, 
         transform: &Transform, 
         renderable: &Renderable, 
-        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer, Arc<StandardCommandBufferAllocator>>, 
+        uv_transform: [f32; 4],
+        builder: &mut AutoCommandBufferBuilder<L, Arc<StandardCommandBufferAllocator>>, 
         render_data: &RenderData,
         has_texture: bool
     ) {
-        // shorthands for convenience
-        let e = entity;
-        let t = transform;
+        // Sampling a texture which is still being uploaded is undefined
+        if !renderable.is_ready() {
+            return;
+        }
+
+        let texture = match has_texture {
+            true => Some(&renderable.descriptor_set_texture),
+            false => None
+        };
         let r = renderable;
+        if Render::draw_mesh(entity, &transform.transformation_matrix(), &r.vertex_buffer, &r.index_buffer, texture, uv_transform, builder, render_data.pipeline.layout()) {
+            self.draw_calls += 1;
+        }
+    }
 
+    /*
+    Records the draw of a mesh with the model matrix in a push constant, texture is bound to set 1 if given
+    False if recording the draw failed
+    */
+    fn draw_mesh<L>(
+        entity: Entity,
+        model: &Matrix4<f32>,
+        vertex_buffer: &Arc<CpuAccessibleBuffer<[Vertex]>>,
+        index_buffer: &Arc<CpuAccessibleBuffer<[u32]>>,
+        texture: Option<&Arc<PersistentDescriptorSet>>,
+        uv_transform: [f32; 4],
+        builder: &mut AutoCommandBufferBuilder<L, Arc<StandardCommandBufferAllocator>>,
+        layout: &Arc<PipelineLayout>
+    ) -> bool {
         // Insert the model matrix into a push constant
         let push_constants = ModelPushConstants {
-            model: t.transformation_matrix().into()
+            model: (*model).into(),
+            uv_transform
         };
         // Bind everything required and render this entity
-        if has_texture {
+        if let Some(texture) = texture {
             builder.bind_descriptor_sets(PipelineBindPoint::Graphics, 
-                render_data.pipeline.layout().clone(), 
+                layout.clone(), 
                 1, 
-                r.descriptor_set_texture.clone()
+                texture.clone()
             );
         }
 
         let result = builder
-            .push_constants(render_data.pipeline.layout().clone(), 0, push_constants)
-            .bind_vertex_buffers(0, r.vertex_buffer.clone())
-            .bind_index_buffer(r.index_buffer.clone())
-            .draw_indexed(r.index_buffer.len() as u32, 1, 0, 0, 0);
+            .push_constants(layout.clone(), 0, push_constants)
+            .bind_vertex_buffers(0, vertex_buffer.clone())
+            .bind_index_buffer(index_buffer.clone())
+            .draw_indexed(index_buffer.len() as u32, 1, 0, 0, 0);
 
-        if result.is_err() {
-            error!("Building a command buffer failed for entity {:?}", e);
+        match result {
+            Ok(_) => true,
+            Err(_) => {
+                error!("Building a command buffer failed for entity {:?}", entity);
+                false
+            }
         }
     }
 }
+
+/// Recomputes ProjectionMatrix when ProjectionKind or the window size changes
+#[derive(Default)]
+pub struct UpdateProjection {
+    applied: Option<(ProjectionKind, [u32; 2])>
+}
+
+impl<'a> System<'a> for UpdateProjection {
+    type SystemData = (
+        Option<Read<'a, Arc<Surface>>>,
+        Read<'a, ProjectionKind>,
+        Write<'a, ProjectionMatrix>
+    );
+
+    fn run(&mut self, (surface, projection_kind, mut projection_matrix): Self::SystemData) {
+        let size = match surface.as_deref().and_then(get_window_from_surface) {
+            Some(v) => v.inner_size(),
+            None => return
+        };
+
+        // minimized, keep the last matrix
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        let current = (*projection_kind, [size.width, size.height]);
+        if self.applied == Some(current) {
+            return;
+        }
+
+        *projection_matrix = ProjectionMatrix(projection_kind.matrix(size.width as f32 / size.height as f32));
+        self.applied = Some(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transparent_draws_back_to_front() {
+        let camera_pos = Vector3::new(0.0, 1.0, 0.0);
+        let mut draws = vec![
+            (Vector3::new(0.0, 1.0, -2.0), "near"),
+            (Vector3::new(0.0, 1.0, 10.0), "far"),
+            (Vector3::new(5.0, 1.0, 0.0), "middle")
+        ];
+        Render::sort_back_to_front(&mut draws, &camera_pos);
+        assert_eq!(draws.iter().map(|(_, name)| *name).collect::<Vec<_>>(), vec!["far", "middle", "near"]);
+    }
+
+    #[test]
+    fn scissor_covers_the_viewport() {
+        // right half of a 1281 pixel wide window
+        let viewport = Viewport { origin: [640.5, 0.0], dimensions: [640.5, 720.0], depth_range: 0.0..1.0 };
+        let scissor = Render::scissor(&viewport);
+        assert_eq!(scissor.origin, [640, 0]);
+        assert_eq!(scissor.dimensions, [641, 720]);
+
+        let tiny = Viewport { origin: [10.0, 10.0], dimensions: [0.0, 0.0], depth_range: 0.0..1.0 };
+        assert_eq!(Render::scissor(&tiny).dimensions, [1, 1]);
+    }
+}
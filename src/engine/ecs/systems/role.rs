@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+
+use log::debug;
+use specs::{DispatcherBuilder, Dispatcher, System, RunNow};
+
+/// Where a system is meant to run in a networked game
+/// 
+/// The same enum is used for the role of the running instance,
+/// in which case Both means a local game acting as client and server at once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+    Both
+}
+
+impl Role {
+    pub fn runs_on(&self, instance: Role) -> bool {
+        *self == Role::Both || instance == Role::Both || *self == instance
+    }
+}
+
+/// DispatcherBuilder which only registers the systems matching the role of this instance
+/// 
+/// Dependencies on systems which were skipped because of their role are dropped,
+/// so a client-only system can depend on a server-only one without special casing
+pub struct RoleDispatcherBuilder<'a, 'b> {
+    builder: DispatcherBuilder<'a, 'b>,
+    role: Role,
+    registered: HashSet<String>
+}
+
+impl<'a, 'b> RoleDispatcherBuilder<'a, 'b> {
+    pub fn new(role: Role) -> Self {
+        Self { builder: DispatcherBuilder::new(), role, registered: HashSet::new() }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn with<T>(mut self, system: T, name: &str, dep: &[&str], role: Role) -> Self 
+    where
+        T: for<'c> System<'c> + Send + 'a
+    {
+        self.add(system, name, dep, role);
+        self
+    }
+
+    pub fn add<T>(&mut self, system: T, name: &str, dep: &[&str], role: Role)
+    where
+        T: for<'c> System<'c> + Send + 'a
+    {
+        if !role.runs_on(self.role) {
+            return debug!("Skipping system {name} with role {:?} on {:?}", role, self.role);
+        }
+
+        let dep: Vec<&str> = dep
+            .iter()
+            .filter(|d| self.registered.contains(**d))
+            .copied()
+            .collect();
+
+        self.builder.add(system, name, &dep);
+        self.registered.insert(name.into());
+    }
+
+    pub fn with_thread_local<T>(mut self, system: T, role: Role) -> Self
+    where
+        T: for<'c> RunNow<'c> + 'b
+    {
+        self.add_thread_local(system, role);
+        self
+    }
+
+    pub fn add_thread_local<T>(&mut self, system: T, role: Role)
+    where
+        T: for<'c> RunNow<'c> + 'b
+    {
+        if role.runs_on(self.role) {
+            self.builder.add_thread_local(system);
+        }
+    }
+
+    pub fn build(self) -> Dispatcher<'a, 'b> {
+        self.builder.build()
+    }
+}
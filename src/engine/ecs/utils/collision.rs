@@ -0,0 +1,85 @@
+use std::sync::Mutex;
+
+use log::error;
+use nalgebra::{Point3, Vector3};
+use rapier3d::prelude::{EventHandler, RigidBodySet, ColliderSet, CollisionEvent, ContactPair, Real, ColliderHandle};
+
+use crate::ecs::resources::physics::{CollisionEventData, ContactPoint};
+
+/// Collects collision events during a physics step
+/// so they can be moved into the CollisionEvents resource afterwards
+/// The entities are filled in by the Physics system, see PhysicsData::collider_entity
+/// 
+/// Only colliders with ActiveEvents::COLLISION_EVENTS set generate events
+#[derive(Default)]
+pub struct CollisionEventCollector {
+    events: Mutex<Vec<CollisionEventData>>
+}
+
+impl CollisionEventCollector {
+    pub fn drain(&self) -> Vec<CollisionEventData> {
+        match self.events.lock() {
+            Ok(mut v) => v.drain(..).collect(),
+            Err(e) => {
+                error!("Collision event collector mutex was poisoned: {e}");
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl EventHandler for CollisionEventCollector {
+    fn handle_collision_event(
+        &self,
+        bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        event: CollisionEvent,
+        contact_pair: Option<&ContactPair>,
+    ) {
+        // sensors and stopped events have no contact pair
+        let contacts: Vec<ContactPoint> = match contact_pair {
+            Some(pair) => pair.manifolds
+                .iter()
+                .flat_map(|m| m.data.solver_contacts.iter().map(|c| ContactPoint { 
+                    point: c.point, 
+                    normal: m.data.normal,
+                    distance: c.dist
+                }))
+                .collect(),
+            None => Vec::new()
+        };
+
+        let impact_speed = contacts
+            .iter()
+            .map(|c| {
+                let v1 = velocity_at_point(bodies, colliders, event.collider1(), &c.point);
+                let v2 = velocity_at_point(bodies, colliders, event.collider2(), &c.point);
+                (v2 - v1).dot(&c.normal).abs()
+            })
+            .fold(0.0, f32::max);
+
+        match self.events.lock() {
+            Ok(mut v) => v.push(CollisionEventData { event, contacts, impact_speed, entity1: None, entity2: None }),
+            Err(e) => error!("Collision event collector mutex was poisoned: {e}")
+        }
+    }
+
+    fn handle_contact_force_event(
+        &self,
+        _dt: Real,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        _contact_pair: &ContactPair,
+        _total_force_magnitude: Real,
+    ) {}
+}
+
+// Velocity of the rigid body the collider is attached to, zero for colliders without one
+fn velocity_at_point(bodies: &RigidBodySet, colliders: &ColliderSet, handle: ColliderHandle, point: &Point3<Real>) -> Vector3<Real> {
+    colliders
+        .get(handle)
+        .and_then(|c| c.parent())
+        .and_then(|p| bodies.get(p))
+        .map(|b| b.velocity_at_point(point))
+        .unwrap_or_else(Vector3::zeros)
+}
@@ -1,32 +1,31 @@
-use rapier3d::prelude::EventHandler;
+use log::debug;
+use rapier3d::prelude::{EventHandler, RigidBodySet, ColliderSet, CollisionEvent, ContactPair, Real};
 
 
+/// Logs physics events at debug level, the Physics system collects them
+/// into the CollisionEvents resource with its own handler
 #[derive(Default)]
 pub struct DebugEventHandler;
 
 impl EventHandler for DebugEventHandler {
     fn handle_collision_event(
         &self,
-        bodies: &rapier3d::prelude::RigidBodySet,
-        colliders: &rapier3d::prelude::ColliderSet,
-        event: rapier3d::prelude::CollisionEvent,
-        contact_pair: Option<&rapier3d::prelude::ContactPair>,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        event: CollisionEvent,
+        _contact_pair: Option<&ContactPair>,
     ) {
-        println!("Collision event:");
-        println!("started?: {:?}, stopped?: {:?}, removed?: {:?}", event.started(), event.stopped(), event.removed());
+        debug!("Collision event between {:?} and {:?}, started?: {:?}, stopped?: {:?}, removed?: {:?}", event.collider1(), event.collider2(), event.started(), event.stopped(), event.removed());
     }
 
     fn handle_contact_force_event(
         &self,
-        dt: rapier3d::prelude::Real,
-        bodies: &rapier3d::prelude::RigidBodySet,
-        colliders: &rapier3d::prelude::ColliderSet,
-        contact_pair: &rapier3d::prelude::ContactPair,
-        total_force_magnitude: rapier3d::prelude::Real,
+        dt: Real,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        contact_pair: &ContactPair,
+        total_force_magnitude: Real,
     ) {
-        println!("Contact force event:");
-        println!("dt: {:?}, total force magnitude: {:?}", dt, total_force_magnitude);
-
-        //bodies.iter().for_each(|(handle, rb)| println!("", ))
+        debug!("Contact force event between {:?} and {:?}, dt: {:?}, total force magnitude: {:?}", contact_pair.collider1, contact_pair.collider2, dt, total_force_magnitude);
     }
 }
@@ -1,2 +1,4 @@
+pub mod collision;
 pub mod debug;
+pub mod network;
 pub mod objects;
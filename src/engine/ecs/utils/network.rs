@@ -0,0 +1,112 @@
+use log::error;
+use specs::{Entity, WriteStorage};
+use uuid::Uuid;
+
+use crate::ecs::{components::{general::Transform, network::NetworkReplicated}, resources::network::NetworkData};
+
+/*
+Marks the entity as replicated with net_id and registers it in NetworkData,
+so incoming messages for net_id are applied to it
+Returns false if the entity is no longer alive or the limit of replicated
+entities was reached, in which case the caller should delete the entity
+
+Outside of systems, pass world.try_fetch_mut::<NetworkData>().as_deref_mut()
+and world.write_storage()
+*/
+pub fn make_replicated(entity: Entity, net_id: Uuid, mut net_data: Option<&mut NetworkData>, network_replicated: &mut WriteStorage<'_, NetworkReplicated>) -> bool {
+    match net_data.as_deref_mut() {
+        Some(v) => {
+            if !v.register(net_id, entity) {
+                return false;
+            }
+        },
+        None => error!("No network data, {:?} will be marked replicated but cannot receive messages", entity)
+    }
+
+    if let Err(e) = network_replicated.insert(entity, NetworkReplicated { net_id }) {
+        error!("Failed to make {:?} replicated: {e}", entity);
+        if let Some(v) = net_data {
+            v.unregister(&net_id);
+        }
+        return false;
+    }
+
+    true
+}
+
+/*
+Hash of the replicated state of a transform, quantized so that
+float noise from serialization doesn't count as a difference
+Uses FNV-1a, which gives the same result on every platform
+*/
+pub fn transform_state_hash(transform: &Transform) -> u64 {
+    fnv_hash(quantized(transform))
+}
+
+/*
+Hash of the transforms of a whole world by entity id, lockstep peers create their entities
+in the same order, so the ids match as long as their simulations do
+*/
+pub fn world_state_hash<'a>(transforms: impl Iterator<Item = (u32, &'a Transform)>) -> u64 {
+    fnv_hash(transforms.flat_map(|(id, t)| std::iter::once(id as i32).chain(quantized(t))))
+}
+
+fn quantized(transform: &Transform) -> impl Iterator<Item = i32> {
+    // q and -q are the same rotation
+    let rot = match transform.rot().w < 0.0 {
+        true => -transform.rot().coords,
+        false => transform.rot().coords
+    };
+
+    // millimeters and 1e-4 for the rotation
+    let quantized: Vec<i32> = transform.pos().iter().chain(transform.scale().iter()).map(|v| (v * 1000.0).round() as i32)
+        .chain(rot.iter().map(|v| (v * 10000.0).round() as i32))
+        .collect();
+    quantized.into_iter()
+}
+
+fn fnv_hash(values: impl Iterator<Item = i32>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for v in values {
+        for byte in v.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{UnitQuaternion, Vector3};
+
+    #[test]
+    fn equal_transforms_hash_the_same() {
+        let rot = UnitQuaternion::from_euler_angles(0.3, 1.2, -0.7);
+        let a = Transform::from_position_rotation(Vector3::new(1.0, 2.0, 3.0), rot);
+        let b = Transform::from_position_rotation(Vector3::new(1.0, 2.0, 3.0), rot);
+        assert_eq!(transform_state_hash(&a), transform_state_hash(&b));
+
+        // moved by a centimeter
+        let c = Transform::from_position_rotation(Vector3::new(1.01, 2.0, 3.0), rot);
+        assert_ne!(transform_state_hash(&a), transform_state_hash(&c));
+    }
+
+    #[test]
+    fn negated_quaternion_hashes_the_same() {
+        let rot = UnitQuaternion::from_euler_angles(0.3, 1.2, -0.7);
+        let negated = UnitQuaternion::new_unchecked(-rot.into_inner());
+        let a = Transform::from_position_rotation(Vector3::zeros(), rot);
+        let b = Transform::from_position_rotation(Vector3::zeros(), negated);
+        assert_eq!(transform_state_hash(&a), transform_state_hash(&b));
+    }
+
+    #[test]
+    fn float_noise_is_ignored() {
+        let rot = UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3);
+        let a = Transform::from_position_rotation(Vector3::new(10.0, -4.0, 0.25), rot);
+        let b = Transform::from_position_rotation(Vector3::new(10.00001, -4.00002, 0.25001), UnitQuaternion::new_unchecked(rot.into_inner() * 1.000001));
+        assert_eq!(transform_state_hash(&a), transform_state_hash(&b));
+    }
+}
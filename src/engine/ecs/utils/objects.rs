@@ -1,7 +1,9 @@
-use nalgebra::{Vector3, DMatrix};
-use rapier3d::prelude::{ColliderBuilder, RigidBodyBuilder, RigidBodyType, RigidBody, Collider};
+use log::{warn, error};
+use nalgebra::{Vector3, DMatrix, Isometry3, Translation3, UnitQuaternion};
+use rapier3d::{prelude::{ColliderBuilder, RigidBodyBuilder, RigidBodyType, RigidBody, Collider, SharedShape}, control::KinematicCharacterController};
+use specs::{World, WorldExt, Entity, Builder, Join};
 
-use crate::{ecs::components::{general::Renderable}, graphics::{models::{create_terrain_vertices, create_height_field}, vulkan::Vulkan}};
+use crate::{ecs::{components::{general::{Renderable, Transform, Lifetime, Camera, Movement, AttachedCamera, CameraMode}, physics::{RigidBodyComponent, ColliderComponent, ColliderRenderable}}, resources::{SpawnPoints, ActiveCamera, physics::{PhysicsData, CollisionEvents}}}, graphics::{models::{create_terrain_vertices, create_height_field, create_plane_vertices}, vulkan::Vulkan}};
 
 
 
@@ -26,3 +28,319 @@ pub fn create_terrain(height_map_name: &str, texture_name: &str, vulkan: &Vulkan
 
     (renderable, rigid_body, collider)
 }
+
+// Thickness of the ground plane collider, thick enough that
+// fast objects don't tunnel through
+const GROUND_PLANE_THICKNESS: f32 = 1.0;
+
+/*
+Creates a flat square ground of size x size centered at the origin with its top at y = 0,
+for scenes without a height map
+*/
+pub fn create_ground_plane(vulkan: &Vulkan, physics_data: &mut PhysicsData, size: f32, texture_name: &str) -> (
+    Result<Renderable, String>,
+    RigidBodyComponent,
+    ColliderComponent
+) {
+    let (vertices, indices) = create_plane_vertices(size);
+
+    let rigid_body = RigidBodyBuilder::new(RigidBodyType::Fixed).build();
+    let collider = ColliderBuilder::cuboid(size / 2.0, GROUND_PLANE_THICKNESS / 2.0, size / 2.0)
+        .translation(Vector3::new(0.0, -GROUND_PLANE_THICKNESS / 2.0, 0.0))
+        .friction(0.7)
+        .build();
+
+    let rigid_body = RigidBodyComponent::new(rigid_body, physics_data, None);
+    let collider = ColliderComponent::new(collider, Some(&rigid_body.handle), physics_data);
+
+    let renderable = vulkan.create_renderable_from_vertices(vertices, indices, texture_name, None);
+
+    (renderable, rigid_body, collider)
+}
+
+/*
+Clears PhysicsData and removes the physics components from all entities
+With despawn, entities which had a rigid body or a collider are deleted instead
+*/
+pub fn reset_physics(world: &mut World, despawn: bool) {
+    if despawn {
+        let entities = world.entities();
+        let rigid_bodies = world.read_storage::<RigidBodyComponent>();
+        let colliders = world.read_storage::<ColliderComponent>();
+
+        for (e, _) in (&entities, rigid_bodies.mask() | colliders.mask()).join() {
+            if let Err(err) = entities.delete(e) {
+                error!("Failed to delete entity {:?} while resetting physics: {err}", e);
+            }
+        }
+    }
+
+    world.write_storage::<RigidBodyComponent>().clear();
+    world.write_storage::<ColliderComponent>().clear();
+    world.write_storage::<ColliderRenderable>().clear();
+
+    match world.try_fetch_mut::<PhysicsData>() {
+        Some(mut v) => v.clear(),
+        None => warn!("Tried to reset physics but there is no PhysicsData")
+    }
+
+    if let Some(mut v) = world.try_fetch_mut::<CollisionEvents>() {
+        v.0.clear();
+    }
+
+    world.maintain();
+}
+
+/*
+Moves the entity to the next spawn point in SpawnPoints, resetting any velocity
+Returns false if the entity could not be respawned
+*/
+pub fn respawn(world: &World, physics_data: &mut PhysicsData, entity: Entity) -> bool {
+    let spawn = match world.try_fetch_mut::<SpawnPoints>() {
+        Some(mut v) => v.next(),
+        None => None
+    };
+
+    let spawn = match spawn {
+        Some(v) => v,
+        None => {
+            warn!("Tried to respawn {:?} but there are no spawn points", entity);
+            return false;
+        }
+    };
+
+    let mut transforms = world.write_storage::<Transform>();
+    let rigid_bodies = world.read_storage::<RigidBodyComponent>();
+
+    match (transforms.get_mut(entity), rigid_bodies.get(entity)) {
+        (Some(t), Some(r)) => {
+            respawn_at(&spawn, t, r, physics_data);
+            true
+        },
+        _ => {
+            warn!("Tried to respawn {:?} which is missing a Transform or a RigidBodyComponent", entity);
+            false
+        }
+    }
+}
+
+pub fn respawn_at(spawn: &Transform, transform: &mut Transform, rigid_body: &RigidBodyComponent, physics_data: &mut PhysicsData) {
+    rigid_body.teleport(Isometry3::from_parts(Translation3::from(spawn.pos()), spawn.rot()), physics_data);
+
+    transform.set_pos(spawn.pos());
+    transform.set_rot(spawn.rot());
+    transform.mov = Vector3::zeros();
+    transform.vel = Vector3::zeros();
+    transform.accel = Vector3::zeros();
+}
+
+/*
+Spawns an entity for each transform, all sharing the same model
+The model and its textures are loaded and uploaded only once, after which
+every entity gets a clone of the Renderable pointing to the same gpu resources
+A model with several materials, see Vulkan::create_renderables, gets an entity
+per material at each transform, all of them are returned
+*/
+pub fn spawn_model_instances(
+    world: &mut World,
+    vulkan: &Vulkan,
+    model_name: &str,
+    pipeline_name: Option<String>,
+    transforms: impl IntoIterator<Item = Transform>
+) -> Result<Vec<Entity>, String> {
+    let renderables = vulkan.create_renderables(model_name, pipeline_name)?;
+
+    let entities = transforms
+        .into_iter()
+        .flat_map(|t| {
+            renderables
+                .iter()
+                .map(|r| {
+                    world
+                        .create_entity()
+                        .with(r.clone())
+                        .with(t.clone())
+                        .build()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    Ok(entities)
+}
+
+/// Dimensions of an upright character capsule
+#[derive(Clone, Copy, Debug)]
+pub struct CapsuleSize {
+    // half of the height of the cylindrical part
+    pub half_height: f32,
+    pub radius: f32
+}
+
+impl CapsuleSize {
+    pub fn height(&self) -> f32 {
+        2.0 * (self.half_height + self.radius)
+    }
+
+    /*
+    Creates a capsule with the total height of height
+    */
+    pub fn from_height(height: f32, radius: f32) -> Self {
+        CapsuleSize { half_height: (height / 2.0 - radius).max(0.0), radius }
+    }
+}
+
+impl Default for CapsuleSize {
+    fn default() -> Self {
+        CapsuleSize { half_height: 0.9, radius: 1.0 }
+    }
+}
+
+/*
+Creates a kinematic character with a capsule collider
+The capsule is placed so that its bottom, not its center, is at feet_position
+The returned Transform is at the center of the capsule
+*/
+pub fn create_character(
+    physics_data: &mut PhysicsData,
+    size: CapsuleSize,
+    feet_position: Vector3<f32>,
+    character_controller: KinematicCharacterController
+) -> (Transform, RigidBodyComponent, ColliderComponent) {
+    let center = feet_position + Vector3::y() * (size.half_height + size.radius);
+
+    // A sleeping character would stop reacting to movement and to bodies touching it,
+    // kinematic bodies only move through the character controller, which doesn't wake them.
+    // This also keeps the sleep thresholds of PhysicsData from applying to it
+    let rigid_body = RigidBodyBuilder::new(RigidBodyType::KinematicPositionBased)
+        .can_sleep(false)
+        .enabled(true)
+        .translation(center)
+        .lock_rotations()
+        .build();
+    let collider = ColliderBuilder::new(SharedShape::capsule_y(size.half_height, size.radius))
+        .enabled(true)
+        .build();
+
+    let rigid_body = RigidBodyComponent::new(rigid_body, physics_data, Some(character_controller));
+    let collider = ColliderComponent::new(collider, Some(&rigid_body.handle), physics_data);
+
+    let transform = Transform::from_position(center);
+
+    (transform, rigid_body, collider)
+}
+
+/*
+Creates a box shaped sensor without a rigid body, centered at position
+It reports every collider entering or leaving it through CollisionEvents,
+use CollisionEventData::involves with the returned entity's ColliderComponent to react to them
+*/
+pub fn create_trigger_zone(
+    physics_data: &mut PhysicsData,
+    half_extents: Vector3<f32>,
+    position: Vector3<f32>
+) -> (Transform, ColliderComponent) {
+    let collider = ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+        .translation(position)
+        .build();
+    let collider = ColliderComponent::with_sensor(collider, true, None, physics_data);
+
+    (Transform::from_position(position), collider)
+}
+
+/*
+Spawns a character controlled with PlayerInput, see create_character, and a separate camera
+entity attached to it in the given mode, which becomes the ActiveCamera
+Returns (character, camera)
+*/
+pub fn create_player(
+    world: &mut World,
+    physics_data: &mut PhysicsData,
+    size: CapsuleSize,
+    feet_position: Vector3<f32>,
+    character_controller: KinematicCharacterController,
+    movement: Movement,
+    mode: CameraMode
+) -> (Entity, Entity) {
+    let (transform, rigid_body, collider) = create_character(physics_data, size, feet_position, character_controller);
+
+    let character = world
+        .create_entity()
+        .with(Camera)
+        .with(movement)
+        .with(transform.clone())
+        .with(rigid_body)
+        .with(collider)
+        .build();
+    let attached = AttachedCamera { target: character, mode };
+    // placed right away, FollowCamera only runs with the next frame
+    let camera_transform = attached.transform_for(&transform, &Vector3::y());
+    let camera = world
+        .create_entity()
+        .with(attached)
+        .with(camera_transform)
+        .build();
+    world.insert(ActiveCamera(camera));
+
+    (character, camera)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProjectileSettings {
+    pub speed: f32,
+    pub radius: f32,
+    // seconds until the projectile is despawned
+    pub lifetime: f32
+}
+
+impl Default for ProjectileSettings {
+    fn default() -> Self {
+        ProjectileSettings { speed: 30.0, radius: 0.1, lifetime: 5.0 }
+    }
+}
+
+/*
+Spawns a dynamic ball flying towards direction, facing the same way
+inherited_velocity is added on top, usually the velocity of whoever fired it
+
+The Renderable should be created once up front and cloned here,
+loading the model on every shot would stall the frame
+*/
+pub fn spawn_projectile(
+    world: &mut World,
+    physics_data: &mut PhysicsData,
+    renderable: &Renderable,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    inherited_velocity: Vector3<f32>,
+    settings: &ProjectileSettings
+) -> Entity {
+    let direction = match direction.try_normalize(f32::EPSILON) {
+        Some(v) => v,
+        None => {
+            warn!("Tried to spawn a projectile without a direction, firing forward");
+            -Vector3::z()
+        }
+    };
+    let rotation = UnitQuaternion::rotation_between(&-Vector3::z(), &direction).unwrap_or_else(UnitQuaternion::identity);
+
+    let rigid_body = RigidBodyBuilder::new(RigidBodyType::Dynamic)
+        .position(Isometry3::from_parts(Translation3::from(origin), rotation))
+        .linvel(direction * settings.speed + inherited_velocity)
+        // fast and small, would tunnel through thin geometry otherwise
+        .ccd_enabled(true)
+        .build();
+    let collider = ColliderBuilder::ball(settings.radius).build();
+
+    let rigid_body = RigidBodyComponent::new(rigid_body, physics_data, None);
+    let collider = ColliderComponent::new(collider, Some(&rigid_body.handle), physics_data);
+
+    world
+        .create_entity()
+        .with(renderable.clone())
+        .with(Transform::from_position_rotation(origin, rotation))
+        .with(rigid_body)
+        .with(collider)
+        .with(Lifetime::new(settings.lifetime))
+        .build()
+}
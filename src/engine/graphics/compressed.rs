@@ -0,0 +1,321 @@
+use vulkano::format::Format;
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+// magic, header and pixel format of a DDS file
+const DDS_HEADER_SIZE: usize = 128;
+// the extended header which follows if the fourcc is DX10
+const DDS_DX10_HEADER_SIZE: usize = 20;
+// fixed part of a KTX2 header, followed by the level index
+const KTX2_HEADER_SIZE: usize = 80;
+
+/// Block compressed texture as stored in a KTX2 or DDS file, uploaded as is by Vulkan::load_image
+pub struct CompressedImage {
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+    // mip levels starting with the full size one, each tightly packed blocks
+    pub levels: Vec<Vec<u8>>
+}
+
+impl CompressedImage {
+    /*
+    Size of the image on the gpu, all mip levels included
+    */
+    pub fn byte_size(&self) -> usize {
+        self.levels.iter().map(|l| l.len()).sum()
+    }
+}
+
+/*
+True if bytes start like a KTX2 or DDS file, see parse_compressed
+*/
+pub fn is_compressed_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(&KTX2_IDENTIFIER) || bytes.starts_with(&DDS_MAGIC)
+}
+
+/*
+Parses a KTX2 or DDS file holding a single 2D BCn texture, with or without mip levels
+Supercompressed KTX2 files, e.g. Basis Universal, aren't supported since they would need transcoding
+*/
+pub fn parse_compressed(bytes: &[u8]) -> Result<CompressedImage, String> {
+    if bytes.starts_with(&KTX2_IDENTIFIER) {
+        parse_ktx2(bytes)
+    }
+    else if bytes.starts_with(&DDS_MAGIC) {
+        parse_dds(bytes)
+    }
+    else {
+        Err("Not a KTX2 or DDS file".into())
+    }
+}
+
+fn parse_ktx2(bytes: &[u8]) -> Result<CompressedImage, String> {
+    if bytes.len() < KTX2_HEADER_SIZE {
+        return Err("KTX2 header is truncated".into());
+    }
+
+    let vk_format = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?;
+    let depth = read_u32(bytes, 28)?;
+    let layers = read_u32(bytes, 32)?;
+    let faces = read_u32(bytes, 36)?;
+    // 0 asks the loader to generate mip levels, which can't be done for compressed data
+    let level_count = read_u32(bytes, 40)?.max(1);
+    let supercompression = read_u32(bytes, 44)?;
+
+    let format = match format_from_vk(vk_format) {
+        Some(v) => v,
+        None => return Err(format!("KTX2 vkFormat {} is not a BCn format", vk_format))
+    };
+    if supercompression != 0 {
+        return Err(format!("KTX2 supercompression scheme {} is not supported", supercompression));
+    }
+    if depth > 1 || layers > 1 || faces != 1 {
+        return Err("Only single 2D KTX2 textures are supported, not arrays, cubemaps or 3D textures".into());
+    }
+    check_dimensions(width, height, level_count)?;
+
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count {
+        let index = KTX2_HEADER_SIZE + level as usize * 24;
+        let offset = usize::try_from(read_u64(bytes, index)?).unwrap_or(usize::MAX);
+        let length = usize::try_from(read_u64(bytes, index + 8)?).unwrap_or(usize::MAX);
+
+        let expected = level_size(format, width, height, level);
+        if length < expected {
+            return Err(format!("KTX2 level {} has {} bytes, expected {}", level, length, expected));
+        }
+        levels.push(slice(bytes, offset, expected)?.to_vec());
+    }
+
+    Ok(CompressedImage { format, width, height, levels })
+}
+
+fn parse_dds(bytes: &[u8]) -> Result<CompressedImage, String> {
+    if bytes.len() < DDS_HEADER_SIZE {
+        return Err("DDS header is truncated".into());
+    }
+
+    let height = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 16)?;
+    let level_count = read_u32(bytes, 28)?.max(1);
+    let fourcc = slice(bytes, 84, 4)?;
+    check_dimensions(width, height, level_count)?;
+
+    let (format, data_offset) = match fourcc {
+        b"DX10" => {
+            let dxgi_format = read_u32(bytes, DDS_HEADER_SIZE)?;
+            let dimension = read_u32(bytes, DDS_HEADER_SIZE + 4)?;
+            let array_size = read_u32(bytes, DDS_HEADER_SIZE + 12)?;
+            // 3 is D3D10_RESOURCE_DIMENSION_TEXTURE2D
+            if dimension != 3 || array_size > 1 {
+                return Err("Only single 2D DDS textures are supported".into());
+            }
+
+            match format_from_dxgi(dxgi_format) {
+                Some(v) => (v, DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE),
+                None => return Err(format!("DDS DXGI format {} is not a BCn format", dxgi_format))
+            }
+        },
+        // textures are treated as srgb color, same as the uncompressed ones
+        b"DXT1" => (Format::BC1_RGBA_SRGB_BLOCK, DDS_HEADER_SIZE),
+        b"DXT3" => (Format::BC2_SRGB_BLOCK, DDS_HEADER_SIZE),
+        b"DXT5" => (Format::BC3_SRGB_BLOCK, DDS_HEADER_SIZE),
+        b"ATI1" | b"BC4U" => (Format::BC4_UNORM_BLOCK, DDS_HEADER_SIZE),
+        b"ATI2" | b"BC5U" => (Format::BC5_UNORM_BLOCK, DDS_HEADER_SIZE),
+        _ => return Err(format!("DDS fourcc {:?} is not a BCn format", String::from_utf8_lossy(fourcc)))
+    };
+
+    // DDS stores the levels one after the other
+    let mut levels = Vec::with_capacity(level_count as usize);
+    let mut offset = data_offset;
+    for level in 0..level_count {
+        let size = level_size(format, width, height, level);
+        levels.push(slice(bytes, offset, size)?.to_vec());
+        offset += size;
+    }
+
+    Ok(CompressedImage { format, width, height, levels })
+}
+
+/*
+Rejects sizes which can't be uploaded and more mip levels than the size allows,
+the level count comes straight from the file and is used to allocate and shift
+*/
+fn check_dimensions(width: u32, height: u32, level_count: u32) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Image has no pixels, it is {}x{}", width, height));
+    }
+
+    let max_levels = 32 - width.max(height).leading_zeros();
+    if level_count > max_levels {
+        return Err(format!("Image has {} mip levels, a {}x{} image has at most {}", level_count, width, height, max_levels));
+    }
+
+    Ok(())
+}
+
+// Bytes taken by a mip level, which is made of whole blocks even if smaller than one
+fn level_size(format: Format, width: u32, height: u32, level: u32) -> usize {
+    let [block_width, block_height, _] = format.block_extent();
+    let block_size = format.block_size().unwrap_or(0) as usize;
+    let (width, height) = ((width >> level).max(1), (height >> level).max(1));
+
+    let blocks = ((width + block_width - 1) / block_width) * ((height + block_height - 1) / block_height);
+    blocks as usize * block_size
+}
+
+fn format_from_vk(vk_format: u32) -> Option<Format> {
+    let format = match vk_format {
+        131 => Format::BC1_RGB_UNORM_BLOCK,
+        132 => Format::BC1_RGB_SRGB_BLOCK,
+        133 => Format::BC1_RGBA_UNORM_BLOCK,
+        134 => Format::BC1_RGBA_SRGB_BLOCK,
+        135 => Format::BC2_UNORM_BLOCK,
+        136 => Format::BC2_SRGB_BLOCK,
+        137 => Format::BC3_UNORM_BLOCK,
+        138 => Format::BC3_SRGB_BLOCK,
+        139 => Format::BC4_UNORM_BLOCK,
+        140 => Format::BC4_SNORM_BLOCK,
+        141 => Format::BC5_UNORM_BLOCK,
+        142 => Format::BC5_SNORM_BLOCK,
+        143 => Format::BC6H_UFLOAT_BLOCK,
+        144 => Format::BC6H_SFLOAT_BLOCK,
+        145 => Format::BC7_UNORM_BLOCK,
+        146 => Format::BC7_SRGB_BLOCK,
+        _ => return None
+    };
+    Some(format)
+}
+
+fn format_from_dxgi(dxgi_format: u32) -> Option<Format> {
+    let format = match dxgi_format {
+        71 => Format::BC1_RGBA_UNORM_BLOCK,
+        72 => Format::BC1_RGBA_SRGB_BLOCK,
+        74 => Format::BC2_UNORM_BLOCK,
+        75 => Format::BC2_SRGB_BLOCK,
+        77 => Format::BC3_UNORM_BLOCK,
+        78 => Format::BC3_SRGB_BLOCK,
+        80 => Format::BC4_UNORM_BLOCK,
+        81 => Format::BC4_SNORM_BLOCK,
+        83 => Format::BC5_UNORM_BLOCK,
+        84 => Format::BC5_SNORM_BLOCK,
+        95 => Format::BC6H_UFLOAT_BLOCK,
+        96 => Format::BC6H_SFLOAT_BLOCK,
+        98 => Format::BC7_UNORM_BLOCK,
+        99 => Format::BC7_SRGB_BLOCK,
+        _ => return None
+    };
+    Some(format)
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], String> {
+    let range = offset.checked_add(len).map(|end| offset..end);
+    match range.and_then(|r| bytes.get(r)) {
+        Some(v) => Ok(v),
+        None => Err(format!("File is truncated, expected {} bytes at offset {}", len, offset))
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    let b = slice(bytes, offset, 4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    let b = slice(bytes, offset, 8)?;
+    Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8x8 with two mip levels, 4 blocks and 1 block of 16 bytes
+    fn bc7_ktx2() -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for v in [146u32, 1, 8, 8, 0, 0, 1, 2, 0] {
+            bytes.extend(v.to_le_bytes());
+        }
+        // dfd, kvd and sgd are unused
+        bytes.resize(KTX2_HEADER_SIZE, 0);
+
+        let data_start = (KTX2_HEADER_SIZE + 2 * 24) as u64;
+        // KTX2 stores the smallest level first, the index still starts with level 0
+        for (offset, length) in [(data_start + 16, 64u64), (data_start, 16)] {
+            bytes.extend(offset.to_le_bytes());
+            bytes.extend(length.to_le_bytes());
+            bytes.extend(length.to_le_bytes());
+        }
+        bytes.extend([1u8; 16]);
+        bytes.extend([0u8; 64]);
+        bytes
+    }
+
+    // 8x8 DXT5 without mip levels
+    fn dxt5_dds() -> Vec<u8> {
+        let mut bytes = vec![0u8; DDS_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&DDS_MAGIC);
+        bytes[12..16].copy_from_slice(&8u32.to_le_bytes());
+        bytes[16..20].copy_from_slice(&8u32.to_le_bytes());
+        bytes[28..32].copy_from_slice(&1u32.to_le_bytes());
+        bytes[84..88].copy_from_slice(b"DXT5");
+        bytes.extend([0u8; 64]);
+        bytes
+    }
+
+    #[test]
+    fn parses_bc7_ktx2() {
+        let image = parse_compressed(&bc7_ktx2()).unwrap();
+
+        assert_eq!(image.format, Format::BC7_SRGB_BLOCK);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.levels, vec![vec![0u8; 64], vec![1u8; 16]]);
+        // a quarter of the 256 bytes the full size level takes as RGBA8
+        assert_eq!(image.levels[0].len() * 4, 8 * 8 * 4);
+    }
+
+    #[test]
+    fn parses_dxt5_dds() {
+        let image = parse_compressed(&dxt5_dds()).unwrap();
+
+        assert_eq!(image.format, Format::BC3_SRGB_BLOCK);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.byte_size(), 64);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let ktx2 = bc7_ktx2();
+        let dds = dxt5_dds();
+
+        assert!(parse_compressed(&ktx2[..40]).is_err());
+        assert!(parse_compressed(&ktx2[..ktx2.len() - 1]).is_err());
+        assert!(parse_compressed(&dds[..100]).is_err());
+        assert!(parse_compressed(&dds[..dds.len() - 1]).is_err());
+        assert!(parse_compressed(b"not an image").is_err());
+    }
+
+    #[test]
+    fn rejects_hostile_headers() {
+        let mut levels = bc7_ktx2();
+        levels[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_compressed(&levels).is_err());
+
+        let mut offset = bc7_ktx2();
+        offset[KTX2_HEADER_SIZE..KTX2_HEADER_SIZE + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(parse_compressed(&offset).is_err());
+
+        let mut empty = bc7_ktx2();
+        empty[20..24].copy_from_slice(&0u32.to_le_bytes());
+        assert!(parse_compressed(&empty).is_err());
+
+        let mut mips = dxt5_dds();
+        mips[28..32].copy_from_slice(&40u32.to_le_bytes());
+        mips.extend([0u8; 512]);
+        assert!(parse_compressed(&mips).is_err());
+    }
+}
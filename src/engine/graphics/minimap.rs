@@ -0,0 +1,80 @@
+use nalgebra::{Matrix4, Orthographic3, Point3, Vector3};
+
+/*
+View matrix of a camera height above center looking straight down, -Z is up on the map
+*/
+pub fn top_down_view(center: &Vector3<f32>, height: f32) -> Matrix4<f32> {
+    let eye = Point3::from(center + Vector3::y() * height);
+    Matrix4::look_at_rh(&eye, &Point3::from(*center), &-Vector3::z())
+}
+
+/*
+Orthographic projection showing extent world units from top to bottom of a target with aspect ratio aspect
+Depth from near to far in front of the camera is mapped to [0, 1] and y is flipped, like Vulkan expects
+*/
+pub fn orthographic(extent: f32, aspect: f32, near: f32, far: f32) -> Matrix4<f32> {
+    let half_height = extent / 2.0;
+    let half_width = half_height * aspect;
+    let proj = Orthographic3::new(-half_width, half_width, -half_height, half_height, near, far).to_homogeneous();
+
+    // from the OpenGL depth range of [-1, 1]
+    let mut depth = Matrix4::identity();
+    depth[(2, 2)] = 0.5;
+    depth[(2, 3)] = 0.5;
+    depth[(1, 1)] = -1.0;
+    depth * proj
+}
+
+/*
+Where position is on a map rendered with view_projection, in uv of its texture
+None if it is outside of the map
+*/
+pub fn marker_uv(view_projection: &Matrix4<f32>, position: &Vector3<f32>) -> Option<[f32; 2]> {
+    let clip = view_projection * position.push(1.0);
+    if clip.w <= f32::EPSILON {
+        return None;
+    }
+
+    // y is flipped already, so it matches the texture
+    let uv = [(clip.x / clip.w + 1.0) / 2.0, (clip.y / clip.w + 1.0) / 2.0];
+    match uv.iter().all(|v| (0.0..=1.0).contains(v)) {
+        true => Some(uv),
+        false => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn center_is_in_the_middle_of_the_map() {
+        let center = Vector3::new(3.0, 1.0, -4.0);
+        let view_projection = orthographic(20.0, 1.0, 0.1, 100.0) * top_down_view(&center, 50.0);
+
+        let [u, v] = marker_uv(&view_projection, &center).unwrap();
+        assert!((u - 0.5).abs() < 1e-5 && (v - 0.5).abs() < 1e-5);
+
+        // north is at the top of the texture, east to the right
+        let [u, v] = marker_uv(&view_projection, &(center + Vector3::new(5.0, 0.0, -5.0))).unwrap();
+        assert!((u - 0.75).abs() < 1e-5 && (v - 0.25).abs() < 1e-5);
+        assert!(marker_uv(&view_projection, &(center + Vector3::new(11.0, 0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn depth_from_near_to_far() {
+        let center = Vector3::zeros();
+        let view_projection = orthographic(20.0, 2.0, 1.0, 101.0) * top_down_view(&center, 50.0);
+
+        let depth = |height: f32| {
+            let clip = view_projection * Vector3::new(0.0, height, 0.0).push(1.0);
+            clip.z / clip.w
+        };
+        assert!(depth(49.0).abs() < 1e-5);
+        assert!((depth(0.0) - 0.49).abs() < 1e-5);
+        assert!((depth(-51.0) - 1.0).abs() < 1e-5);
+
+        // the width follows the aspect ratio
+        assert!(marker_uv(&view_projection, &Vector3::new(19.0, 0.0, 0.0)).is_some());
+    }
+}
@@ -1,3 +1,10 @@
 pub mod vulkan;
 pub mod models;
 pub mod utils;
+pub mod occlusion;
+pub mod minimap;
+pub mod shadows;
+pub mod streaming;
+pub mod text;
+pub mod pass_cache;
+pub mod compressed;
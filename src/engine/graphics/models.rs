@@ -112,7 +112,8 @@ pub fn create_terrain_vertices(height_field: &Vec<Vec<f32>>) -> (Vec<Vertex>, Ve
                 position: [(x as f64 - xcenter) as f32, z, (y as f64 - ycenter) as f32],
                 normal: get_smooth_normal(x, y, h, w, &height_field).into(),
                 color: [1.0, 1.0, 1.0],
-                tex_coord: [x as f32 / w as f32, y as f32 / h as f32]
+                tex_coord: [x as f32 / w as f32, y as f32 / h as f32],
+                tex_coord2: [x as f32 / w as f32, y as f32 / h as f32]
             };
             //println!("{:?}", vert);
             verts.push(vert);
@@ -134,4 +135,27 @@ pub fn create_terrain_vertices(height_field: &Vec<Vec<f32>>) -> (Vec<Vertex>, Ve
 
     println!("number of indices: {}", indices.len());
     return (verts, indices);
-}
\ No newline at end of file
+}
+
+// Creates a flat square on the xz plane centered at the origin, facing up
+// The texture repeats once per unit, the second uv set covers the plane once
+pub fn create_plane_vertices(size: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let half = size / 2.0;
+    let corners = [(-half, -half), (-half, half), (half, half), (half, -half)];
+
+    let verts = corners
+        .iter()
+        .map(|&(x, z)| Vertex {
+            position: [x, 0.0, z],
+            normal: [0.0, 1.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            tex_coord: [x + half, z + half],
+            tex_coord2: [(x + half) / size, (z + half) / size]
+        })
+        .collect();
+
+    // same winding as the terrain
+    let indices = vec![0, 1, 2, 2, 3, 0];
+
+    return (verts, indices);
+}
@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+
+use nalgebra::{Matrix4, Vector3};
+use specs::Entity;
+
+use crate::data_structures::graphics::Vertex;
+
+/// Which OcclusionCulled entities were found fully hidden by the queries of the Render system
+/// Results arrive a frame late, every entity stays visible until a query found it occluded
+#[derive(Default)]
+pub struct OcclusionVisibility {
+    occluded: HashSet<Entity>
+}
+
+impl OcclusionVisibility {
+    pub fn is_occluded(&self, entity: Entity) -> bool {
+        self.occluded.contains(&entity)
+    }
+
+    /*
+    Drawn again right away, e.g. once the camera is inside its bounds and the proxy can't be tested
+    */
+    pub fn reveal(&mut self, entity: Entity) {
+        self.occluded.remove(&entity);
+    }
+
+    /*
+    Replaces the visibility with the results of the queries of a frame, a sample count and
+    availability per query, those which aren't available yet keep their last visibility
+    Entities which weren't queried in that frame are visible
+    */
+    pub fn update(&mut self, queried: &[Entity], results: &[u64]) {
+        let mut occluded = HashSet::new();
+        for (entity, result) in queried.iter().zip(results.chunks(2)) {
+            let hidden = match result {
+                [samples, available] if *available != 0 => *samples == 0,
+                _ => self.occluded.contains(entity)
+            };
+            if hidden {
+                occluded.insert(*entity);
+            }
+        }
+        self.occluded = occluded;
+    }
+}
+
+/*
+Corners of the unit cube the proxy of an OcclusionCulled entity is drawn with, scaled to its bounds by proxy_matrix
+*/
+pub fn proxy_box() -> (Vec<Vertex>, Vec<u32>) {
+    let vertices = (0..8)
+        .map(|i| Vertex {
+            position: [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32],
+            ..Default::default()
+        })
+        .collect();
+
+    // drawn without culling, so the winding doesn't matter
+    let indices = vec![
+        0, 1, 3, 0, 3, 2,
+        4, 5, 7, 4, 7, 6,
+        0, 1, 5, 0, 5, 4,
+        2, 3, 7, 2, 7, 6,
+        0, 2, 6, 0, 6, 4,
+        1, 3, 7, 1, 7, 5
+    ];
+    (vertices, indices)
+}
+
+/*
+From the unit cube of proxy_box to the bounds min to max
+*/
+pub fn proxy_matrix(min: &Vector3<f32>, max: &Vector3<f32>) -> Matrix4<f32> {
+    Matrix4::new_translation(min) * Matrix4::new_nonuniform_scaling(&(max - min))
+}
+
+/*
+Whether point is in the bounds transformed by model, grown by margin
+The world space box around the transformed bounds is used, which is never smaller
+*/
+pub fn bounds_contain(model: &Matrix4<f32>, min: &Vector3<f32>, max: &Vector3<f32>, point: &Vector3<f32>, margin: f32) -> bool {
+    let corners = (0..8).map(|i| Vector3::new(
+        if i & 1 == 0 { min.x } else { max.x },
+        if i & 2 == 0 { min.y } else { max.y },
+        if i & 4 == 0 { min.z } else { max.z }
+    ));
+
+    let mut world_min = Vector3::repeat(f32::INFINITY);
+    let mut world_max = Vector3::repeat(f32::NEG_INFINITY);
+    for corner in corners {
+        let corner = model.transform_point(&corner.into()).coords;
+        world_min = world_min.inf(&corner);
+        world_max = world_max.sup(&corner);
+    }
+
+    (0..3).all(|i| point[i] >= world_min[i] - margin && point[i] <= world_max[i] + margin)
+}
+
+/*
+Distance from the camera to the corners of the near plane of projection,
+a box closer than this can be clipped by the near plane
+*/
+pub fn near_plane_reach(projection: &Matrix4<f32>, near: f32) -> f32 {
+    let tan_x = 1.0 / projection[(0, 0)].abs().max(f32::EPSILON);
+    let tan_y = 1.0 / projection[(1, 1)].abs().max(f32::EPSILON);
+    near.abs() * (1.0 + tan_x * tan_x + tan_y * tan_y).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::{World, WorldExt, Builder};
+
+    #[test]
+    fn occluded_until_samples_pass() {
+        let mut world = World::new();
+        let wall = world.create_entity().build();
+        let room = world.create_entity().build();
+        let queried = [wall, room];
+
+        let mut visibility = OcclusionVisibility::default();
+        // no samples passed for the room
+        visibility.update(&queried, &[120, 1, 0, 1]);
+        assert!(!visibility.is_occluded(wall));
+        assert!(visibility.is_occluded(room));
+
+        // not available yet, stays occluded
+        visibility.update(&queried, &[120, 1, 0, 0]);
+        assert!(visibility.is_occluded(room));
+
+        visibility.update(&queried, &[120, 1, 4, 1]);
+        assert!(queried.iter().all(|e| !visibility.is_occluded(*e)));
+
+        // no longer queried
+        visibility.update(&queried, &[120, 1, 0, 1]);
+        visibility.update(&[wall], &[120, 1]);
+        assert!(!visibility.is_occluded(room));
+
+        visibility.update(&queried, &[120, 1, 0, 1]);
+        visibility.reveal(room);
+        assert!(!visibility.is_occluded(room));
+    }
+
+    #[test]
+    fn camera_inside_bounds() {
+        let min = Vector3::new(-1.0, 0.0, -1.0);
+        let max = Vector3::new(1.0, 2.0, 1.0);
+        let model = Matrix4::new_translation(&Vector3::new(10.0, 0.0, 0.0));
+
+        assert!(bounds_contain(&model, &min, &max, &Vector3::new(10.5, 1.0, 0.0), 0.0));
+        assert!(!bounds_contain(&model, &min, &max, &Vector3::new(0.0, 1.0, 0.0), 0.0));
+        assert!(bounds_contain(&model, &min, &max, &Vector3::new(11.05, 1.0, 0.0), 0.1));
+
+        let proxy = model * proxy_matrix(&min, &max);
+        let (vertices, indices) = proxy_box();
+        assert_eq!(indices.len(), 36);
+        assert!(indices.iter().all(|i| (*i as usize) < vertices.len()));
+        let far_corner = proxy.transform_point(&vertices[7].position.into());
+        assert_eq!(far_corner.coords, Vector3::new(11.0, 2.0, 1.0));
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::hash_map::DefaultHasher, hash::{Hash, Hasher}};
+
+use nalgebra::Matrix4;
+
+/// Hash of everything a recorded pass depends on, the same signature records the same commands
+/// Gpu resources are added by pointer, their contents never change after creation
+#[derive(Default)]
+pub struct PassSignature(DefaultHasher);
+
+impl PassSignature {
+    pub fn add<T: Hash>(&mut self, value: T) {
+        value.hash(&mut self.0);
+    }
+
+    pub fn add_ptr<T: ?Sized>(&mut self, ptr: *const T) {
+        (ptr as *const () as usize).hash(&mut self.0);
+    }
+
+    pub fn add_f32s(&mut self, values: &[f32]) {
+        for v in values {
+            v.to_bits().hash(&mut self.0);
+        }
+    }
+
+    pub fn add_matrix(&mut self, matrix: &Matrix4<f32>) {
+        self.add_f32s(matrix.as_slice());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+}
+
+/// Passes recorded once and executed again every frame, one per key, e.g. a framebuffer and a view
+/// A pass is recorded again when the signature of its key changes
+pub struct PassCache<K, T> {
+    entries: Vec<(K, u64, T)>,
+    recorded: u64
+}
+
+impl<K, T> Default for PassCache<K, T> {
+    fn default() -> Self {
+        Self { entries: Vec::new(), recorded: 0 }
+    }
+}
+
+impl<K: PartialEq, T> PassCache<K, T> {
+    /*
+    The pass of key if it was recorded with the same signature and reuse accepts it,
+    e.g. after updating its uniform buffers, otherwise the one returned by record
+    Returns None if recording fails, the previous pass is dropped either way
+    */
+    pub fn get_or_record(&mut self, key: K, signature: u64, reuse: impl FnOnce(&T) -> bool, record: impl FnOnce() -> Option<T>) -> Option<&T> {
+        let i = match self.entries.iter().position(|(k, _, _)| *k == key) {
+            Some(i) if self.entries[i].1 == signature && reuse(&self.entries[i].2) => i,
+            Some(i) => {
+                self.entries.swap_remove(i);
+                self.record(key, signature, record)?
+            },
+            None => self.record(key, signature, record)?
+        };
+        Some(&self.entries[i].2)
+    }
+
+    fn record(&mut self, key: K, signature: u64, record: impl FnOnce() -> Option<T>) -> Option<usize> {
+        let pass = record()?;
+        self.recorded += 1;
+        self.entries.push((key, signature, pass));
+        Some(self.entries.len() - 1)
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.entries.retain(|(_, _, pass)| f(pass));
+    }
+
+    /*
+    How many passes were recorded so far, reused ones aren't counted
+    */
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+    use specs::{World, WorldExt, Builder, Entity};
+
+    use super::*;
+
+    // stands in for the vertex buffer, index buffer and texture of a renderable
+    struct Mesh([u8; 3]);
+
+    fn signature(scene: &[(Entity, Matrix4<f32>)], mesh: &Mesh) -> u64 {
+        let mut signature = PassSignature::default();
+        for (e, model) in scene {
+            signature.add(e);
+            signature.add_ptr(&mesh.0[0]);
+            signature.add_ptr(&mesh.0[1]);
+            signature.add_ptr(&mesh.0[2]);
+            signature.add_matrix(model);
+        }
+        signature.finish()
+    }
+
+    fn scene(world: &mut World, count: usize) -> Vec<(Entity, Matrix4<f32>)> {
+        (0..count)
+            .map(|i| (world.create_entity().build(), Matrix4::new_translation(&Vector3::new(i as f32, 0.0, 0.0))))
+            .collect()
+    }
+
+    // Each recording allocates a secondary command buffer with its uniform buffers and descriptor sets,
+    // without the cache that happens every frame
+    #[test]
+    fn static_scene_is_recorded_once() {
+        let mut world = World::new();
+        let scene = scene(&mut world, 500);
+        let mesh = Mesh([0; 3]);
+        let mut cache: PassCache<usize, u64> = PassCache::default();
+
+        for _ in 0..100 {
+            let signature = signature(&scene, &mesh);
+            let pass = cache.get_or_record(0, signature, |_| true, || Some(signature));
+            assert_eq!(pass, Some(&signature));
+        }
+        assert_eq!(cache.recorded(), 1);
+    }
+
+    #[test]
+    fn changes_record_again() {
+        let mut world = World::new();
+        let mut scene = scene(&mut world, 500);
+        let mesh = Mesh([0; 3]);
+        let other_mesh = Mesh([0; 3]);
+        let mut cache: PassCache<usize, ()> = PassCache::default();
+        let mut frame = |scene: &[(Entity, Matrix4<f32>)], mesh: &Mesh| {
+            cache.get_or_record(0, signature(scene, mesh), |_| true, || Some(()));
+            cache.recorded()
+        };
+
+        assert_eq!(frame(&scene, &mesh), 1);
+        // moved
+        scene[250].1 = Matrix4::new_translation(&Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(frame(&scene, &mesh), 2);
+        // another mesh
+        assert_eq!(frame(&scene, &other_mesh), 3);
+        // added and removed
+        scene.push((world.create_entity().build(), Matrix4::identity()));
+        assert_eq!(frame(&scene, &other_mesh), 4);
+        scene.pop();
+        assert_eq!(frame(&scene, &other_mesh), 5);
+        assert_eq!(frame(&scene, &other_mesh), 5);
+    }
+
+    #[test]
+    fn rejected_pass_is_recorded_again() {
+        let mut cache: PassCache<usize, u32> = PassCache::default();
+        cache.get_or_record(0, 1, |_| true, || Some(1));
+        // e.g. its uniform buffer is still in use by the gpu
+        let pass = cache.get_or_record(0, 1, |_| false, || Some(2));
+        assert_eq!(pass, Some(&2));
+        assert_eq!(cache.recorded(), 2);
+
+        // failing to record drops the previous pass
+        assert_eq!(cache.get_or_record(0, 2, |_| true, || None), None);
+        assert_eq!(cache.get_or_record(0, 1, |_| true, || Some(3)), Some(&3));
+        assert_eq!(cache.recorded(), 3);
+    }
+
+    #[test]
+    fn keys_are_separate() {
+        let mut cache: PassCache<usize, usize> = PassCache::default();
+        for _ in 0..3 {
+            for key in 0..3 {
+                assert_eq!(cache.get_or_record(key, 0, |_| true, || Some(key)), Some(&key));
+            }
+        }
+        assert_eq!(cache.recorded(), 3);
+
+        cache.retain(|pass| *pass != 1);
+        assert_eq!(cache.get_or_record(0, 0, |_| true, || Some(4)), Some(&0));
+        assert_eq!(cache.get_or_record(1, 0, |_| true, || Some(5)), Some(&5));
+        assert_eq!(cache.recorded(), 4);
+    }
+}
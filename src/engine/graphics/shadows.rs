@@ -0,0 +1,165 @@
+use nalgebra::{Matrix4, Point3, Vector3};
+
+/*
+View space distances of the near and far plane of projection, which has OpenGL depth like Perspective3
+*/
+pub fn depth_range(projection: &Matrix4<f32>) -> Option<(f32, f32)> {
+    let inverse = projection.try_inverse()?;
+    let near = -inverse.transform_point(&Point3::new(0.0, 0.0, -1.0)).z;
+    let far = -inverse.transform_point(&Point3::new(0.0, 0.0, 1.0)).z;
+    Some((near, far))
+}
+
+/*
+View space distances at which the shadow cascades end, the last one ends at far
+lambda blends between uniform (0.0) and logarithmic (1.0) splits, see Shadows
+*/
+pub fn cascade_splits(near: f32, far: f32, count: u32, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let p = i as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(p);
+            let uniform = near + (far - near) * p;
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/*
+World space corners of the part of the view frustum between the view space distances near and far
+projection is needed to find the depth of those distances, the inverse is that of projection * view
+*/
+pub fn frustum_slice_corners(inverse_view_projection: &Matrix4<f32>, projection: &Matrix4<f32>, near: f32, far: f32) -> [Point3<f32>; 8] {
+    let mut corners = [Point3::origin(); 8];
+    for (i, distance) in [near, far].iter().enumerate() {
+        let z = projection.transform_point(&Point3::new(0.0, 0.0, -distance)).z;
+        for (j, (x, y)) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].iter().enumerate() {
+            corners[i * 4 + j] = inverse_view_projection.transform_point(&Point3::new(*x, *y, z));
+        }
+    }
+    corners
+}
+
+/*
+World space to the clip space of a cascade covering the given points, for a light shining in direction
+The cascade is a cube around their bounding sphere, so its size doesn't change while the camera turns,
+and it only moves in whole texels of the shadow map, which keeps the shadow edges from shimmering
+Depth is in [0, 1] like in Vulkan, casters up to caster_depth in front of the cube are included as well
+*/
+pub fn cascade_matrix(corners: &[Point3<f32>], direction: &Vector3<f32>, resolution: u32, caster_depth: f32) -> Matrix4<f32> {
+    let center = corners.iter().fold(Vector3::zeros(), |sum, c| sum + c.coords) / corners.len().max(1) as f32;
+    let radius = corners.iter().map(|c| (c.coords - center).norm()).fold(f32::EPSILON, f32::max);
+    // rounding errors would otherwise change the texel size a tiny bit every frame
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let direction = direction.try_normalize(f32::EPSILON).unwrap_or(-Vector3::y());
+    // look_at needs an up vector which isn't parallel to the direction
+    let up = match direction.x.abs() < 0.01 && direction.z.abs() < 0.01 {
+        true => Vector3::x(),
+        false => Vector3::y()
+    };
+    let rotation = Matrix4::look_at_rh(&Point3::origin(), &Point3::from(direction), &up);
+
+    let c = rotation.transform_point(&Point3::from(center));
+    let texel = 2.0 * radius / resolution.max(1) as f32;
+    let x = (c.x / texel).round() * texel;
+    let y = (c.y / texel).round() * texel;
+    // the light looks down -z, so distances along it are -z
+    let near = -c.z - radius - caster_depth.max(0.0);
+    let far = -c.z + radius;
+
+    let projection = Matrix4::new(
+        1.0 / radius, 0.0, 0.0, -x / radius,
+        0.0, 1.0 / radius, 0.0, -y / radius,
+        0.0, 0.0, -1.0 / (far - near), -near / (far - near),
+        0.0, 0.0, 0.0, 1.0
+    );
+    projection * rotation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Perspective3;
+
+    // same as the projection of the engine
+    fn projection(aspect: f32) -> Matrix4<f32> {
+        let mut proj = Perspective3::new(aspect, 45.0f32.to_radians(), 0.1, 1000.0).to_homogeneous();
+        proj[(1, 1)] *= -1.0;
+        proj
+    }
+
+    #[test]
+    fn depth_range_of_the_projection() {
+        let (near, far) = depth_range(&projection(16.0 / 9.0)).unwrap();
+        assert!((near - 0.1).abs() < 1e-4);
+        assert!((far - 1000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn splits_blend_uniform_and_logarithmic() {
+        assert_eq!(cascade_splits(1.0, 101.0, 4, 0.0), vec![26.0, 51.0, 76.0, 101.0]);
+
+        let logarithmic = cascade_splits(1.0, 100.0, 2, 1.0);
+        assert!((logarithmic[0] - 10.0).abs() < 1e-3);
+        assert!((logarithmic[1] - 100.0).abs() < 1e-3);
+
+        let blended = cascade_splits(0.1, 150.0, 3, 0.75);
+        assert!(blended.windows(2).all(|w| w[0] < w[1]));
+        assert!((blended[2] - 150.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn slice_corners_lie_at_the_distances() {
+        let projection = projection(16.0 / 9.0);
+        let view = Matrix4::new_translation(&Vector3::new(0.0, -5.0, 0.0));
+        let inverse = (projection * view).try_inverse().unwrap();
+
+        let corners = frustum_slice_corners(&inverse, &projection, 2.0, 30.0);
+        for (i, c) in corners.iter().enumerate() {
+            let v = view.transform_point(c);
+            let expected = if i < 4 { -2.0 } else { -30.0 };
+            assert!((v.z - expected).abs() < 1e-2, "corner {} at {}", i, v.z);
+        }
+    }
+
+    #[test]
+    fn cascade_contains_its_slice() {
+        let projection = projection(1.0);
+        let inverse = projection.try_inverse().unwrap();
+        let corners = frustum_slice_corners(&inverse, &projection, 1.0, 20.0);
+        let direction = Vector3::new(-0.4, -1.0, -0.3);
+
+        let m = cascade_matrix(&corners, &direction, 1024, 50.0);
+        for c in corners.iter() {
+            let p = m.transform_point(c);
+            assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0, "{:?} outside the cascade", p);
+            assert!(p.z >= 0.0 && p.z <= 1.0, "{:?} outside the depth range", p);
+        }
+
+        // casters between the light and the slice end up closer to it
+        let caster = corners[0] - direction.normalize() * 40.0;
+        let p = m.transform_point(&caster);
+        assert!(p.z >= 0.0 && p.z < m.transform_point(&corners[0]).z);
+    }
+
+    #[test]
+    fn cascade_moves_in_whole_texels() {
+        let projection = projection(1.0);
+        let direction = Vector3::new(-0.4, -1.0, -0.3);
+        let resolution = 512;
+        let point = Point3::new(3.0, 0.0, -7.0);
+
+        let inverse = projection.try_inverse().unwrap();
+        let corners = frustum_slice_corners(&inverse, &projection, 1.0, 20.0);
+        let before = cascade_matrix(&corners, &direction, resolution, 50.0).transform_point(&point);
+
+        for offset in [0.01, 0.13, 0.4] {
+            let moved: Vec<_> = corners.iter().map(|c| c + Vector3::new(offset, 0.0, offset * 0.5)).collect();
+            let after = cascade_matrix(&moved, &direction, resolution, 50.0).transform_point(&point);
+            for texels in [(after.x - before.x) * resolution as f32 / 2.0, (after.y - before.y) * resolution as f32 / 2.0] {
+                assert!((texels - texels.round()).abs() < 1e-2, "moved by {} texels", texels);
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// Streamed textures start with and fall back to the levels up to this size
+pub const MIN_STREAMED_SIZE: u32 = 64;
+
+/// Every mip level of an RGBA8 sRGB texture on the cpu, the first one is the full resolution
+/// Kept for textures whose levels are streamed, see StreamedTexture
+pub struct MipChain {
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>
+}
+
+impl MipChain {
+    /*
+    Halves the image down to 1x1, each texel averaging up to 2x2 texels of the level above
+    Colors are averaged in linear space, otherwise smaller levels get darker
+    */
+    pub fn generate(pixels: Vec<u8>, width: u32, height: u32) -> MipChain {
+        let to_linear: Vec<f32> = (0..=255u8).map(|v| srgb_to_linear(v as f32 / 255.0)).collect();
+
+        let mut levels = vec![pixels];
+        let [mut w, mut h] = [width.max(1), height.max(1)];
+        while w > 1 || h > 1 {
+            let src = levels.last().unwrap();
+            let [dw, dh] = [(w / 2).max(1), (h / 2).max(1)];
+            let mut dst = Vec::with_capacity((dw * dh * 4) as usize);
+            for y in 0..dh {
+                for x in 0..dw {
+                    let mut sum = [0.0f32; 4];
+                    let mut count = 0.0;
+                    for sy in (y * 2)..(y * 2 + 2).min(h) {
+                        for sx in (x * 2)..(x * 2 + 2).min(w) {
+                            let i = ((sy * w + sx) * 4) as usize;
+                            for c in 0..3 {
+                                sum[c] += to_linear[src[i + c] as usize];
+                            }
+                            sum[3] += src[i + 3] as f32 / 255.0;
+                            count += 1.0;
+                        }
+                    }
+                    for c in 0..3 {
+                        dst.push((linear_to_srgb(sum[c] / count) * 255.0).round() as u8);
+                    }
+                    dst.push((sum[3] / count * 255.0).round() as u8);
+                }
+            }
+            levels.push(dst);
+            [w, h] = [dw, dh];
+        }
+
+        MipChain { width: width.max(1), height: height.max(1), levels }
+    }
+
+    /*
+    Decodes the PNG, JPEG, BMP or TGA image at path as RGBA and generates its mip levels, doesn't need the gpu,
+    so it can run on another thread, e.g. while preloading, see HawkEngine::preload
+    */
+    pub fn load(path: &str) -> Result<MipChain, String> {
+        let reader = match image::io::Reader::open(path) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to open image {}: {}", path, e))
+        };
+
+        // falls back to the file extension if the contents don't match a known format
+        let reader = match reader.with_guessed_format() {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to read image {}: {}", path, e))
+        };
+
+        // the levels are generated and uploaded as 8 bit RGBA
+        let image = match reader.decode() {
+            Ok(v) => v.into_rgba8(),
+            Err(e) => return Err(format!("Failed to decode image {}: {}", path, e))
+        };
+
+        let (width, height) = image.dimensions();
+        Ok(MipChain::generate(image.into_raw(), width, height))
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    pub fn level_size(&self, level: u32) -> [u32; 2] {
+        [(self.width >> level).max(1), (self.height >> level).max(1)]
+    }
+
+    /*
+    Bytes on the gpu while level is the first resident one
+    */
+    pub fn resident_bytes(&self, level: u32) -> u64 {
+        self.levels.iter().skip(level as usize).map(|v| v.len() as u64).sum()
+    }
+
+    /*
+    Coarsest level a streamed texture keeps, the first one no larger than MIN_STREAMED_SIZE
+    */
+    pub fn min_level(&self) -> u32 {
+        (0..self.level_count())
+            .find(|l| self.level_size(*l).iter().all(|s| *s <= MIN_STREAMED_SIZE))
+            .unwrap_or(self.level_count() - 1)
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+/*
+First level a texture needs at distance from the camera, one level coarser every time
+the distance doubles past full_detail_distance, off screen textures only need min_level
+*/
+pub fn desired_level(distance: f32, full_detail_distance: f32, visible: bool, min_level: u32) -> u32 {
+    if !visible {
+        return min_level;
+    }
+
+    let ratio = distance / full_detail_distance.max(f32::EPSILON);
+    if ratio.is_nan() || ratio <= 1.0 {
+        return 0;
+    }
+    (ratio.log2().floor() as u32).min(min_level)
+}
+
+/*
+Coarsens the levels of the farthest textures first until all of them together fit into budget bytes
+requests are the first level, the distance to the camera and the mips of each texture
+Textures don't go past their min_level, so the budget may still be exceeded
+*/
+pub fn fit_to_budget(requests: &mut [(u32, f32, &MipChain)], budget: u64) {
+    let mut total: u64 = requests.iter().map(|(level, _, mips)| mips.resident_bytes(*level)).sum();
+
+    let mut farthest: Vec<usize> = (0..requests.len()).collect();
+    farthest.sort_by(|a, b| requests[*b].1.total_cmp(&requests[*a].1));
+
+    for i in farthest {
+        let (level, _, mips) = &mut requests[i];
+        while total > budget && *level < mips.min_level() {
+            total -= mips.resident_bytes(*level) - mips.resident_bytes(*level + 1);
+            *level += 1;
+        }
+        if total <= budget {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, rgba: [u8; 4]) -> MipChain {
+        MipChain::generate(rgba.repeat((width * height) as usize), width, height)
+    }
+
+    #[test]
+    fn chain_goes_down_to_one_texel() {
+        let mips = solid(300, 75, [10, 20, 30, 255]);
+        assert_eq!(mips.level_count(), 9);
+        assert_eq!(mips.level_size(1), [150, 37]);
+        assert_eq!(mips.level_size(8), [1, 1]);
+        for (level, data) in mips.levels.iter().enumerate() {
+            let [w, h] = mips.level_size(level as u32);
+            assert_eq!(data.len(), (w * h * 4) as usize);
+            assert_eq!(&data[..4], &[10, 20, 30, 255]);
+        }
+    }
+
+    #[test]
+    fn averages_in_linear_space() {
+        // black and white texels, half of the light is mid gray in sRGB, not 128
+        let mips = MipChain::generate(vec![0, 0, 0, 0, 255, 255, 255, 255], 2, 1);
+        assert_eq!(mips.levels[1], vec![188, 188, 188, 128]);
+    }
+
+    #[test]
+    fn levels_follow_the_distance() {
+        assert_eq!(desired_level(5.0, 10.0, true, 4), 0);
+        assert_eq!(desired_level(25.0, 10.0, true, 4), 1);
+        assert_eq!(desired_level(45.0, 10.0, true, 4), 2);
+        assert_eq!(desired_level(1000.0, 10.0, true, 4), 4);
+        assert_eq!(desired_level(5.0, 10.0, false, 4), 4);
+    }
+
+    #[test]
+    fn budget_coarsens_the_farthest_first() {
+        let big = solid(256, 256, [255; 4]);
+        assert_eq!(big.min_level(), 2);
+
+        let mut requests = [(0, 5.0, &big), (0, 50.0, &big)];
+        let budget = big.resident_bytes(0) + big.resident_bytes(1);
+        fit_to_budget(&mut requests, budget);
+        assert_eq!(requests.map(|r| r.0), [0, 1]);
+
+        let mut requests = [(0, 5.0, &big), (0, 50.0, &big)];
+        fit_to_budget(&mut requests, big.resident_bytes(1));
+        assert_eq!(requests.map(|r| r.0), [2, 2]);
+    }
+}
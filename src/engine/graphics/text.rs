@@ -0,0 +1,209 @@
+use crate::data_structures::graphics::Vertex;
+
+// Glyphs are 5x7 texels, each in a cell with a texel of padding around it so filtering doesn't bleed
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const CELL_SIZE: usize = 8;
+const ATLAS_COLUMNS: usize = 8;
+
+// Space after every glyph and between lines, in texels
+const GLYPH_SPACING: f32 = 1.0;
+const LINE_SPACING: f32 = 2.0;
+
+// Built in font of the WorldText labels, rows from the top, the highest of the 5 bits is the left texel
+// Lowercase letters use the uppercase ones, anything else missing is drawn as '?'
+const GLYPHS: &[(char, [u8; GLYPH_HEIGHT])] = &[
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('A', [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    ('!', [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04]),
+    ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('\'', [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('#', [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A]),
+    ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]),
+    ('<', [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02]),
+    ('>', [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08]),
+    ('*', [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00])
+];
+
+/*
+Width and height of the atlas created by font_atlas, in texels
+*/
+pub fn atlas_size() -> (u32, u32) {
+    let rows = (GLYPHS.len() + ATLAS_COLUMNS - 1) / ATLAS_COLUMNS;
+    ((ATLAS_COLUMNS * CELL_SIZE) as u32, (rows * CELL_SIZE) as u32)
+}
+
+/*
+RGBA8 texels of every glyph, white where the glyph is set and transparent elsewhere
+*/
+pub fn font_atlas() -> Vec<u8> {
+    let (width, height) = atlas_size();
+    let mut texels = vec![0u8; (width * height * 4) as usize];
+
+    for (i, (_, rows)) in GLYPHS.iter().enumerate() {
+        let (cell_x, cell_y) = cell_origin(i);
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - x)) == 0 {
+                    continue;
+                }
+                let texel = ((cell_y + 1 + y) * width as usize + cell_x + 1 + x) * 4;
+                texels[texel..texel + 4].copy_from_slice(&[255; 4]);
+            }
+        }
+    }
+    texels
+}
+
+fn cell_origin(index: usize) -> (usize, usize) {
+    ((index % ATLAS_COLUMNS) * CELL_SIZE, (index / ATLAS_COLUMNS) * CELL_SIZE)
+}
+
+/*
+Uv rectangle of the glyph drawn for c, min and max corner
+*/
+fn glyph_uv(c: char) -> [f32; 4] {
+    let c = c.to_ascii_uppercase();
+    let index = GLYPHS.iter().position(|(g, _)| *g == c)
+        .or_else(|| GLYPHS.iter().position(|(g, _)| *g == '?'))
+        .unwrap_or(0);
+
+    let (width, height) = atlas_size();
+    let (cell_x, cell_y) = cell_origin(index);
+    let min = [(cell_x + 1) as f32 / width as f32, (cell_y + 1) as f32 / height as f32];
+    [min[0], min[1], min[0] + GLYPH_WIDTH as f32 / width as f32, min[1] + GLYPH_HEIGHT as f32 / height as f32]
+}
+
+/*
+Two triangles per visible character of content, lines split at '\n'
+A line is 1.0 high, every line is centered on x = 0 and the bottom of the last line is at y = 0,
+so a label sits on top of the point it is placed at
+*/
+pub fn text_vertices(content: &str, color: [f32; 3]) -> Vec<Vertex> {
+    // texels of a glyph to the units of a line
+    let scale = 1.0 / GLYPH_HEIGHT as f32;
+    let lines: Vec<&str> = content.lines().collect();
+
+    let mut vertices = Vec::new();
+    for (line_i, line) in lines.iter().enumerate() {
+        let count = line.chars().count() as f32;
+        let line_width = count * (GLYPH_WIDTH as f32 + GLYPH_SPACING) - GLYPH_SPACING;
+        let bottom = (lines.len() - 1 - line_i) as f32 * (GLYPH_HEIGHT as f32 + LINE_SPACING) * scale;
+
+        for (i, c) in line.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+
+            let left = (i as f32 * (GLYPH_WIDTH as f32 + GLYPH_SPACING) - line_width / 2.0) * scale;
+            let right = left + GLYPH_WIDTH as f32 * scale;
+            let top = bottom + 1.0;
+            let [u0, v0, u1, v1] = glyph_uv(c);
+
+            // counter clockwise seen from +z, where the camera is
+            let corner = |x: f32, y: f32, u: f32, v: f32| Vertex {
+                position: [x, y, 0.0],
+                normal: [0.0, 0.0, 1.0],
+                color,
+                tex_coord: [u, v],
+                ..Default::default()
+            };
+            vertices.extend([
+                corner(left, bottom, u0, v1), corner(right, bottom, u1, v1), corner(right, top, u1, v0),
+                corner(left, bottom, u0, v1), corner(right, top, u1, v0), corner(left, top, u0, v0)
+            ]);
+        }
+    }
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs_fit_their_cells() {
+        let (width, height) = atlas_size();
+        let atlas = font_atlas();
+        assert_eq!(atlas.len(), (width * height * 4) as usize);
+        assert!(GLYPHS.iter().all(|(_, rows)| rows.iter().all(|r| *r < 1 << GLYPH_WIDTH)));
+
+        // the padding of every cell stays transparent
+        for i in 0..GLYPHS.len() {
+            let (x, y) = cell_origin(i);
+            for px in x..x + CELL_SIZE {
+                assert_eq!(atlas[(y * width as usize + px) * 4 + 3], 0);
+            }
+        }
+
+        // lowercase uses the uppercase glyph, unknown characters the '?'
+        assert_eq!(glyph_uv('a'), glyph_uv('A'));
+        assert_eq!(glyph_uv('~'), glyph_uv('?'));
+        assert_ne!(glyph_uv('A'), glyph_uv('B'));
+    }
+
+    #[test]
+    fn lines_are_centered_above_the_origin() {
+        let vertices = text_vertices("Hi you\nP1", [1.0, 0.0, 0.0]);
+        // spaces are skipped
+        assert_eq!(vertices.len(), 7 * 6);
+
+        let min_x = vertices.iter().map(|v| v.position[0]).fold(f32::INFINITY, f32::min);
+        let max_x = vertices.iter().map(|v| v.position[0]).fold(f32::NEG_INFINITY, f32::max);
+        let min_y = vertices.iter().map(|v| v.position[1]).fold(f32::INFINITY, f32::min);
+        let max_y = vertices.iter().map(|v| v.position[1]).fold(f32::NEG_INFINITY, f32::max);
+        assert!((min_x + max_x).abs() < 1e-5);
+        assert_eq!(min_y, 0.0);
+        assert!((max_y - (2.0 + LINE_SPACING / GLYPH_HEIGHT as f32)).abs() < 1e-5);
+
+        // the last line is at the bottom
+        let last_line = &vertices[5 * 6..];
+        assert!(last_line.iter().all(|v| v.position[1] <= 1.0));
+        assert!(text_vertices("", [1.0; 3]).is_empty());
+    }
+}
@@ -1,8 +1,29 @@
 use std::sync::Arc;
 
 use log::error;
+use nalgebra::{Matrix4, Point3, Vector3, Unit};
 use vulkano::swapchain::Surface;
-use winit::window::Window;
+use winit::{window::Window, dpi::PhysicalSize};
+
+/*
+Converts the HSLA colors used by rapier's debug rendering to RGB, hue is in degrees
+*/
+pub fn hsla_to_rgb(hsla: [f32; 4]) -> [f32; 3] {
+    let [h, s, l, _] = hsla;
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x)
+    };
+    let m = l - c / 2.0;
+    [r + m, g + m, b + m]
+}
 
 pub fn get_window_from_surface(surface: &Arc<Surface>) -> Option<&Window> {
     match surface.object() {
@@ -13,3 +34,76 @@ pub fn get_window_from_surface(surface: &Arc<Surface>) -> Option<&Window> {
         }
     }
 }
+
+/*
+Resolutions of the monitor the window is currently on, largest first
+Video modes only differing in refresh rate or bit depth are merged
+*/
+pub fn supported_resolutions(window: &Window) -> Vec<PhysicalSize<u32>> {
+    let monitor = match window.current_monitor() {
+        Some(v) => v,
+        None => {
+            error!("Failed to get the monitor of the window");
+            return vec![]
+        }
+    };
+
+    let mut resolutions: Vec<PhysicalSize<u32>> = monitor.video_modes().map(|m| m.size()).collect();
+    resolutions.sort_by(|a, b| (b.width, b.height).cmp(&(a.width, a.height)));
+    resolutions.dedup();
+    resolutions
+}
+
+/*
+Resizes the window, the swapchain is recreated by the resize event this causes
+Does nothing for fullscreen windows
+*/
+pub fn set_window_resolution(window: &Window, size: PhysicalSize<u32>) {
+    if size.width == 0 || size.height == 0 {
+        return error!("Cannot set the window size to {}x{}", size.width, size.height);
+    }
+
+    window.set_inner_size(size);
+}
+
+/*
+Unprojects a pixel through the inverse view projection into a world space ray
+x and y are in pixels from the top left corner, like winit cursor positions
+Returns the ray origin on the near plane and its direction,
+None if the screen is empty or the matrices can't be inverted
+*/
+pub fn screen_to_world_ray(x: f32, y: f32, screen_size: [u32; 2], view: &Matrix4<f32>, projection: &Matrix4<f32>) -> Option<(Point3<f32>, Unit<Vector3<f32>>)> {
+    if screen_size[0] == 0 || screen_size[1] == 0 {
+        return None;
+    }
+
+    let inverse = (projection * view).try_inverse()?;
+
+    // The projection matrix already flips y, so y grows downwards in ndc as well
+    let ndc_x = 2.0 * x / screen_size[0] as f32 - 1.0;
+    let ndc_y = 2.0 * y / screen_size[1] as f32 - 1.0;
+
+    // Vulkan clips depth to [0, 1], 0 is the closest visible point
+    let near = inverse.transform_point(&Point3::new(ndc_x, ndc_y, 0.0));
+    let far = inverse.transform_point(&Point3::new(ndc_x, ndc_y, 1.0));
+    let direction = Unit::try_new(far - near, f32::EPSILON)?;
+
+    Some((near, direction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hsla_hues_map_to_their_primaries() {
+        let close = |a: [f32; 3], b: [f32; 3]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5);
+
+        assert!(close(hsla_to_rgb([0.0, 1.0, 0.5, 1.0]), [1.0, 0.0, 0.0]));
+        assert!(close(hsla_to_rgb([120.0, 1.0, 0.5, 1.0]), [0.0, 1.0, 0.0]));
+        assert!(close(hsla_to_rgb([240.0, 1.0, 0.5, 1.0]), [0.0, 0.0, 1.0]));
+        // hue wraps around, no saturation is grey
+        assert!(close(hsla_to_rgb([-360.0, 1.0, 0.5, 1.0]), [1.0, 0.0, 0.0]));
+        assert!(close(hsla_to_rgb([90.0, 0.0, 0.25, 1.0]), [0.25, 0.25, 0.25]));
+    }
+}
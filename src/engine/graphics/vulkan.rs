@@ -1,33 +1,49 @@
 use crate::data_structures::graphics::Vertex;
-use crate::ecs::components::general::Renderable;
+use crate::ecs::components::general::{Renderable, PbrMaterial, StreamedTexture};
+use crate::ecs::resources::{TextFont, Skybox, RenderScale, UpscaleFilter};
 use crate::shaders;
+use crate::graphics::streaming::MipChain;
+use crate::graphics::compressed;
+use crate::graphics::text;
 use crate::shaders::default::vs::ty::VPUniformBufferObject;
+use crate::shaders::default::fs::ty::{ShadowUniformBufferObject, LightsUniformBufferObject};
+use crate::shaders::pbr::fs::ty::MaterialUniformBufferObject;
+use crate::shaders::loading::fs::ty::LoadingPushConstants;
 use vulkano::buffer::cpu_pool::CpuBufferPoolSubbuffer;
 use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
-use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet, DescriptorSet};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
-use vulkano::format::Format;
+use vulkano::format::{Format, NumericType};
 use vulkano::instance::debug::ValidationFeatureEnable;
 use vulkano::memory::allocator::{StandardMemoryAllocator, MemoryUsage};
-use vulkano::pipeline::graphics::color_blend::ColorBlendState;
-use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
-use vulkano::pipeline::graphics::rasterization::{RasterizationState, PolygonMode};
-use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
-use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::color_blend::{ColorBlendState, ColorComponents};
+use vulkano::pipeline::graphics::depth_stencil::{DepthStencilState, DepthState, CompareOp};
+use vulkano::pipeline::graphics::rasterization::{RasterizationState, PolygonMode, DepthBias, DepthBiasState};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
 use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
 use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
-use vulkano::sampler::{Sampler, SamplerCreateInfo, Filter, SamplerAddressMode};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::sampler::{Sampler, SamplerCreateInfo, Filter, SamplerAddressMode, SamplerMipmapMode, BorderColor, LOD_CLAMP_NONE};
 use vulkano::shader::ShaderModule;
-use vulkano::swapchain::{Swapchain, SwapchainCreateInfo, Surface};
-use vulkano::sync::GpuFuture;
+use vulkano::swapchain::{Swapchain, SwapchainCreateInfo, Surface, ColorSpace, PresentMode};
+use vulkano::sync::{GpuFuture, FenceSignalFuture};
 use vulkano_win::VkSurfaceBuild;
 
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::collections::HashMap;
+use std::fmt::Debug;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
 use anyhow::{anyhow};
+use log::{info, warn, error};
+use nalgebra::{Vector3, Matrix3, Matrix4, Point3};
 use winit::dpi::LogicalSize;
 use winit::event_loop::{EventLoop};
 use winit::window::{Window, WindowBuilder};
@@ -44,9 +60,9 @@ use vulkano::device::{
     Queue, DeviceExtensions, Features
 };
 use vulkano::buffer::{CpuAccessibleBuffer, BufferUsage, TypedBufferAccess, CpuBufferPool};
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassContents, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract};
-use vulkano::image::{ImageUsage, SwapchainImage, ImmutableImage, ImageDimensions, MipmapsCount, ImageAccess, AttachmentImage};
-use vulkano::image::view::ImageView;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, RenderPassBeginInfo, SubpassContents, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract, CopyBufferToImageInfo, BufferImageCopy};
+use vulkano::image::{ImageUsage, SwapchainImage, ImmutableImage, StorageImage, ImageDimensions, MipmapsCount, ImageAccess, AttachmentImage, ImageCreateFlags, ImageLayout, ImageSubresourceLayers, SampleCount};
+use vulkano::image::view::{ImageView, ImageViewCreateInfo, ImageViewType, ImageViewAbstract};
 use vulkano::render_pass::{RenderPass, Framebuffer, FramebufferCreateInfo, Subpass};
 
 #[derive(Clone)]
@@ -58,7 +74,182 @@ pub struct Vulkan {
     buffer_memory_allocator: Arc<StandardMemoryAllocator>,
     pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
     // TODO: temporarily public
-    pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>
+    pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    // submitted texture uploads which may not have finished yet, shared between clones
+    pending_uploads: Rc<RefCell<Vec<(FenceSignalFuture<Box<dyn GpuFuture>>, UploadHandle)>>>,
+    // bound as the lightmap of renderables without one
+    white_texture: Arc<ImageView<ImmutableImage>>,
+    // shared by the pipelines rebuilt after a resize, so they skip shader compilation
+    // None if the driver couldn't create one, pipelines are then built without it
+    pipeline_cache: Option<Arc<PipelineCache>>,
+    // minimum fraction of samples shaded separately, None shades once per pixel
+    sample_shading: Option<f32>,
+    // clamp depth outside the depth range instead of clipping the geometry
+    depth_clamp: bool
+}
+
+/// Draws the loading screen onto the swapchain images while preloading, see HawkEngine::preload
+pub struct LoadingScreenPass {
+    pub render_pass: Arc<RenderPass>,
+    pub framebuffers: Vec<Arc<Framebuffer>>,
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub descriptor_set: Arc<PersistentDescriptorSet>,
+    // kept for recreating the pass with a new swapchain
+    pub background: Option<Arc<ImageView<ImmutableImage>>>
+}
+
+/// Mesh of an obj using a single material, see Vulkan::load_model_parts
+pub struct ModelPart {
+    pub vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    pub index_buffer: Arc<CpuAccessibleBuffer<[u32]>>,
+    // path of the diffuse texture from the mtl
+    pub texture: Option<String>
+}
+
+/// FXAA or upscaling pass drawing the scene onto the swapchain images, see Vulkan::create_post_process
+pub struct PostProcess {
+    pub render_pass: Arc<RenderPass>,
+    // the scene is rendered into these instead of the swapchain images, one per image
+    pub targets: Vec<Arc<AttachmentImage>>,
+    pub framebuffers: Vec<Arc<Framebuffer>>,
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub descriptor_sets: Vec<Arc<PersistentDescriptorSet>>
+}
+
+const UPLOAD_PENDING: u8 = 0;
+const UPLOAD_FINISHED: u8 = 1;
+const UPLOAD_FAILED: u8 = 2;
+
+/// Set once a tracked upload has finished on the gpu, see Vulkan::track_upload
+/// An upload which could not be submitted or whose fence could not be checked is marked failed,
+/// its image contents are undefined and it never finishes
+#[derive(Clone, Debug)]
+pub struct UploadHandle(Arc<AtomicU8>);
+
+impl Default for UploadHandle {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU8::new(UPLOAD_PENDING)))
+    }
+}
+
+impl UploadHandle {
+    pub fn is_finished(&self) -> bool {
+        self.0.load(Ordering::Acquire) == UPLOAD_FINISHED
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.0.load(Ordering::Acquire) == UPLOAD_FAILED
+    }
+
+    fn finish(&self) {
+        self.0.store(UPLOAD_FINISHED, Ordering::Release)
+    }
+
+    fn fail(&self) {
+        self.0.store(UPLOAD_FAILED, Ordering::Release)
+    }
+}
+
+/// Depth maps of the shadow cascades, one array layer each, see Vulkan::create_shadow_map
+#[derive(Clone)]
+pub struct ShadowMap {
+    pub render_pass: Arc<RenderPass>,
+    // one per layer
+    pub framebuffers: Vec<Arc<Framebuffer>>,
+    // every layer, sampled with a depth comparison by the default and pbr shaders
+    pub view: Arc<ImageView<StorageImage>>,
+    pub sampler: Arc<Sampler>,
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub resolution: u32
+}
+
+/// Texture a Minimap is rendered into every frame, see Vulkan::create_minimap_target
+#[derive(Clone)]
+pub struct MinimapTarget {
+    pub render_pass: Arc<RenderPass>,
+    pub framebuffer: Arc<Framebuffer>,
+    // sampled when the minimap is drawn over the window
+    pub color: Arc<ImageView<AttachmentImage>>,
+    // default and pbr shaders, the viewport is set to the size of the target
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub pipeline_pbr: Arc<GraphicsPipeline>,
+    pub size: [u32; 2]
+}
+
+/// Texture a camera renders into every frame, e.g. a security monitor or a mirror,
+/// see Vulkan::create_offscreen_target and RenderTarget
+#[derive(Clone)]
+pub struct OffscreenTarget {
+    // the pipelines of the target are named offscreen_<id> and offscreen_pbr_<id>
+    pub id: u32,
+    pub render_pass: Arc<RenderPass>,
+    pub framebuffer: Arc<Framebuffer>,
+    pub color: Arc<ImageView<AttachmentImage>>,
+    // default and pbr shaders, the viewport is set to the size of the target
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub pipeline_pbr: Arc<GraphicsPipeline>,
+    pub size: [u32; 2]
+}
+
+impl OffscreenTarget {
+    /*
+    For Vulkan::create_renderable_from_texture and Vulkan::replace_renderable_texture
+    */
+    pub fn texture(&self) -> Arc<dyn ImageViewAbstract> {
+        self.color.clone()
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.size[0] as f32 / self.size[1] as f32
+    }
+}
+
+// Keeps the pipelines of offscreen targets apart in Vulkan::pipelines
+static NEXT_OFFSCREEN_TARGET_ID: AtomicU32 = AtomicU32::new(0);
+
+// Loaded when Vulkan is created, the engine saves it again on exit
+pub const PIPELINE_CACHE_PATH: &str = "pipeline_cache.bin";
+
+// Tried in order when looking up a texture by name
+const TEXTURE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "bmp", "tga"];
+// Uploaded without decoding, so they win over the others where the texture isn't decoded anyway
+const COMPRESSED_TEXTURE_EXTENSIONS: [&str; 2] = ["ktx2", "dds"];
+
+/*
+Path of resources/<name> with the first texture extension that exists, compressed ones first
+Falls back to png so a missing texture is reported with a sensible path
+*/
+fn texture_path(name: &str) -> String {
+    COMPRESSED_TEXTURE_EXTENSIONS
+        .iter()
+        .map(|ext| format!("resources/{}.{}", name, ext))
+        .find(|path| Path::new(path).exists())
+        .unwrap_or_else(|| decodable_texture_path(name))
+}
+
+/*
+Same as texture_path, but skipping compressed textures, for a texture decoded with MipChain::load
+*/
+fn decodable_texture_path(name: &str) -> String {
+    TEXTURE_EXTENSIONS
+        .iter()
+        .map(|ext| format!("resources/{}.{}", name, ext))
+        .find(|path| Path::new(path).exists())
+        .unwrap_or_else(|| format!("resources/{}.png", name))
+}
+
+// Tried in order when looking up a glTF model by name
+const GLTF_EXTENSIONS: [&str; 2] = ["gltf", "glb"];
+
+/*
+Path of resources/<name> with the first glTF extension that exists
+*/
+fn gltf_path(name: &str) -> String {
+    GLTF_EXTENSIONS
+        .iter()
+        .map(|ext| format!("resources/{}.{}", name, ext))
+        .find(|path| Path::new(path).exists())
+        .unwrap_or_else(|| format!("resources/{}.gltf", name))
 }
 
 impl Vulkan {
@@ -86,20 +277,40 @@ impl Vulkan {
             SamplerCreateInfo {
                 mag_filter: Filter::Linear,
                 min_filter: Filter::Linear,
+                // every mip level of the textures, see load_image
+                mipmap_mode: SamplerMipmapMode::Linear,
+                lod: 0.0..=LOD_CLAMP_NONE,
                 address_mode: [SamplerAddressMode::Repeat; 3],
                 ..Default::default()
             }
         ).unwrap();
 
-        Self { 
+        let (white_texture, white_upload) = Vulkan::create_texture(
+            &buffer_memory_allocator,
+            &command_buffer_allocator,
+            queue,
+            vec![255; 4],
+            1,
+            1,
+            Format::R8G8B8A8_SRGB
+        );
+
+        let vulkan = Self { 
             device: device.clone(), 
             queue: queue.clone(), 
             sampler: sampler.clone(),
             pipelines: HashMap::new(),
             buffer_memory_allocator, 
             command_buffer_allocator, 
-            descriptor_set_allocator
-        }
+            descriptor_set_allocator,
+            pending_uploads: Rc::new(RefCell::new(Vec::new())),
+            white_texture,
+            pipeline_cache: Vulkan::load_pipeline_cache(device, PIPELINE_CACHE_PATH),
+            sample_shading: None,
+            depth_clamp: false
+        };
+        vulkan.track_upload(white_upload);
+        vulkan
     }
 
 
@@ -178,7 +389,33 @@ impl Vulkan {
             .expect("no device available")
     }
     
+    /*
+    Creates the logical device
+    fill_mode_non_solid is only enabled if supported, check device.enabled_features()
+    before creating pipelines with PolygonMode::Line
+    The same goes for wide_lines and line widths other than 1.0, sample_rate_shading and depth_clamp
+    */
     pub fn create_device(physical: &Arc<PhysicalDevice>, queue_family_index: u32, device_extensions: &DeviceExtensions) -> (Arc<Device>, Arc<Queue>) {
+        let fill_mode_non_solid = physical.supported_features().fill_mode_non_solid;
+        if !fill_mode_non_solid {
+            warn!("Device {} does not support fill_mode_non_solid, wireframe rendering is disabled", physical.properties().device_name);
+        }
+
+        let wide_lines = physical.supported_features().wide_lines;
+        if !wide_lines {
+            warn!("Device {} does not support wide_lines, debug lines are drawn 1px wide", physical.properties().device_name);
+        }
+
+        let sample_rate_shading = physical.supported_features().sample_rate_shading;
+        if !sample_rate_shading {
+            info!("Device {} does not support sample_rate_shading, sample shading is unavailable", physical.properties().device_name);
+        }
+
+        let depth_clamp = physical.supported_features().depth_clamp;
+        if !depth_clamp {
+            info!("Device {} does not support depth_clamp, shadow casters in front of a cascade are clipped", physical.properties().device_name);
+        }
+
         let (device, mut queues) = Device::new(
             physical.clone(),
             DeviceCreateInfo { 
@@ -187,7 +424,10 @@ impl Vulkan {
                     ..Default::default()
                 }],
                 enabled_features: Features {
-                    fill_mode_non_solid: true,
+                    fill_mode_non_solid,
+                    wide_lines,
+                    sample_rate_shading,
+                    depth_clamp,
                     ..Default::default()
                 },
                 enabled_extensions: *device_extensions,
@@ -203,125 +443,363 @@ impl Vulkan {
     // Member functions
     //--------------------------
 
-    pub fn create_swapchain(&self, physical: &Arc<PhysicalDevice>, surface: &Arc<Surface>) -> (Arc<Swapchain>, Vec<Arc<SwapchainImage>>) {
+    /*
+    Picks the first of the preferred formats the surface supports
+    If none of them are supported, any sRGB format is preferred over linear ones
+    and as a last resort the first format reported by the surface is used
+    */
+    pub fn select_surface_format(physical: &Arc<PhysicalDevice>, surface: &Arc<Surface>, preferred_formats: &[Format]) -> (Format, ColorSpace) {
+        let formats = physical
+            .surface_formats(surface, Default::default())
+            .expect("failed to get surface formats");
+
+        let preferred = preferred_formats
+            .iter()
+            .find_map(|p| formats.iter().find(|(f, c)| f == p && *c == ColorSpace::SrgbNonLinear));
+
+        let srgb = formats
+            .iter()
+            .find(|(f, c)| f.type_color() == Some(NumericType::SRGB) && *c == ColorSpace::SrgbNonLinear);
+
+        match preferred.or(srgb) {
+            Some(v) => *v,
+            None => formats[0]
+        }
+    }
+
+    pub fn supported_present_modes(physical: &Arc<PhysicalDevice>, surface: &Arc<Surface>) -> Vec<PresentMode> {
+        match physical.surface_present_modes(surface) {
+            Ok(v) => v.collect(),
+            Err(e) => {
+                warn!("Failed to get surface present modes: {:?}", e);
+                vec![]
+            }
+        }
+    }
+
+    /*
+    Picks the first of the preferred present modes the surface supports
+    Falls back to Fifo, which every surface has to support
+
+    Fifo waits for vblank and never tears, on VRR (FreeSync/G-Sync) displays
+    the refresh follows the frame rate while below the maximum refresh rate
+    FifoRelaxed presents late frames right away instead of waiting for the next vblank,
+    which tears on fixed refresh displays but not on VRR ones, and avoids the stutter
+    of Fifo when the frame rate drops below the refresh rate
+    Mailbox renders as fast as possible without tearing, Immediate tears
+    */
+    pub fn select_present_mode(physical: &Arc<PhysicalDevice>, surface: &Arc<Surface>, preferred_present_modes: &[PresentMode]) -> PresentMode {
+        let supported = Vulkan::supported_present_modes(physical, surface);
+
+        match preferred_present_modes.iter().find(|p| supported.contains(p)) {
+            Some(v) => *v,
+            None => PresentMode::Fifo
+        }
+    }
+
+    /*
+    image_usage is limited to what the surface supports, color_attachment is always required
+    Unsupported usages are dropped with a warning, check swapchain.image_usage() before relying on them
+    */
+    pub fn create_swapchain(
+        &self,
+        physical: &Arc<PhysicalDevice>,
+        surface: &Arc<Surface>,
+        preferred_formats: &[Format],
+        preferred_present_modes: &[PresentMode],
+        image_usage: ImageUsage
+    ) -> (Arc<Swapchain>, Vec<Arc<SwapchainImage>>) {
         let caps = physical
             .surface_capabilities(surface, Default::default())
             .expect("failed to get surface capabilities");
+
+        let image_usage = ImageUsage { color_attachment: true, ..image_usage };
+        let supported_usage = image_usage.intersection(&caps.supported_usage_flags);
+        if supported_usage != image_usage {
+            warn!("Surface does not support swapchain image usage {:?}, ignoring it", image_usage.difference(&caps.supported_usage_flags));
+        }
     
         let dimensions = surface.object().unwrap().downcast_ref::<Window>().unwrap().inner_size();
         let composite_alpha = caps.supported_composite_alpha.iter().next().unwrap();
-        let image_format = Some(
-            physical
-                .surface_formats(surface, Default::default())
-                .unwrap()[0]
-                .0,
-        );
+        let (image_format, image_color_space) = Vulkan::select_surface_format(physical, surface, preferred_formats);
+        info!("Using swapchain format {:?} with color space {:?}", image_format, image_color_space);
+        let present_mode = Vulkan::select_present_mode(physical, surface, preferred_present_modes);
+        info!("Using present mode {:?}", present_mode);
     
         Swapchain::new(
             self.device.clone(),
             surface.clone(),
             SwapchainCreateInfo {
                 min_image_count: caps.min_image_count + 1,
-                image_format,
+                image_format: Some(image_format),
+                image_color_space,
                 image_extent: dimensions.into(),
-                image_usage: ImageUsage {
-                    color_attachment: true,
-                    ..Default::default()
-                },
+                image_usage: supported_usage,
                 composite_alpha,
+                present_mode,
                 ..Default::default()
             }
         ).unwrap()
     }
 
-    pub fn create_render_pass(&self, swapchain: &Arc<Swapchain>) -> Arc<RenderPass> {
+    /*
+    Highest sample count up to requested which both color and depth attachments support
+    */
+    pub fn select_sample_count(physical: &Arc<PhysicalDevice>, requested: SampleCount) -> SampleCount {
+        let properties = physical.properties();
+        let supported = properties.framebuffer_color_sample_counts.intersection(&properties.framebuffer_depth_sample_counts);
+
+        let samples = [SampleCount::Sample64, SampleCount::Sample32, SampleCount::Sample16, SampleCount::Sample8, SampleCount::Sample4, SampleCount::Sample2]
+            .into_iter()
+            .filter(|s| *s as u32 <= requested as u32)
+            .find(|s| supported.contains_count(*s))
+            .unwrap_or(SampleCount::Sample1);
+
+        if samples != requested {
+            warn!("{:?} MSAA is not supported, using {:?}", requested, samples);
+        }
+        samples
+    }
+
+    /*
+    With more than one sample, color and depth are multisampled and
+    resolved into a third attachment, which is the swapchain image
+    Everything including Transparent entities is drawn in the one subpass, so blending is
+    depth tested against the depth of the same samples and nothing reads the depth afterwards
+    With multisampling only color needs to be resolved, a pass reading depth later would need
+    a depth resolve as well, which render passes of vulkano can't do yet
+    */
+    pub fn create_render_pass(&self, swapchain: &Arc<Swapchain>, msaa_samples: SampleCount) -> Arc<RenderPass> {
+        if msaa_samples == SampleCount::Sample1 {
+            return vulkano::single_pass_renderpass!(
+                self.device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: swapchain.image_format(),
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: Format::D16_UNORM,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            ).unwrap();
+        }
+
         vulkano::single_pass_renderpass!(
             self.device.clone(),
             attachments: {
                 color: {
                     load: Clear,
-                    store: Store,
+                    store: DontCare,
                     format: swapchain.image_format(),
-                    samples: 1,
+                    samples: msaa_samples as u32,
                 },
                 depth: {
                     load: Clear,
                     store: DontCare,
                     format: Format::D16_UNORM,
+                    samples: msaa_samples as u32,
+                },
+                resolve: {
+                    load: DontCare,
+                    store: Store,
+                    format: swapchain.image_format(),
                     samples: 1,
                 }
             },
             pass: {
                 color: [color],
-                depth_stencil: {depth}
+                depth_stencil: {depth},
+                resolve: [resolve]
             }
         ).unwrap()
     }
     
-    pub fn create_framebuffers(&self, render_pass: &Arc<RenderPass>, images: &Vec<Arc<SwapchainImage>>) -> Vec<Arc<Framebuffer>> {
+    /*
+    The sample count is taken from the render pass, see create_render_pass
+    */
+    pub fn create_framebuffers<I: ImageAccess + Debug + 'static>(&self, render_pass: &Arc<RenderPass>, images: &[Arc<I>]) -> Vec<Arc<Framebuffer>> {
+        let samples = render_pass.attachments()[0].samples;
+
         // Create depth buffer
         let dimensions = images[0].dimensions().width_height();
         let depth_buffer = ImageView::new_default(
-            AttachmentImage::transient(&self.buffer_memory_allocator, dimensions, Format::D16_UNORM).unwrap()
+            AttachmentImage::transient_multisampled(&self.buffer_memory_allocator, dimensions, samples, Format::D16_UNORM).unwrap()
         ).unwrap();
 
+        // Rendered to instead of the swapchain image, which it is then resolved into
+        let msaa_buffer = match samples {
+            SampleCount::Sample1 => None,
+            _ => Some(ImageView::new_default(
+                AttachmentImage::transient_multisampled(&self.buffer_memory_allocator, dimensions, samples, images[0].format()).unwrap()
+            ).unwrap())
+        };
+
         images
             .iter()
             .map(|image| {
                 let view = ImageView::new_default(image.clone()).unwrap();
+                let attachments: Vec<Arc<dyn ImageViewAbstract>> = match &msaa_buffer {
+                    Some(msaa_buffer) => vec![msaa_buffer.clone(), depth_buffer.clone(), view],
+                    None => vec![view, depth_buffer.clone()]
+                };
+
                 Framebuffer::new(
                     render_pass.clone(),
                     FramebufferCreateInfo { 
-                        attachments: vec![view, depth_buffer.clone()],
+                        attachments,
                         ..Default::default()
                     }
                 ).unwrap()
             })
             .collect::<Vec<_>>()
     }
+
+    /*
+    Runs build, which creates pipelines, against a copy of the pipelines created so far
+    The copy replaces them only if build succeeds, so a rebuild failing halfway,
+    e.g. on a broken reloaded shader, can't leave some pipelines replaced and others not
+    */
+    pub fn rebuild_pipelines<T>(&mut self, build: impl FnOnce(&mut Vulkan) -> Result<T, String>) -> Result<T, String> {
+        let mut previous = self.pipelines.clone();
+        std::mem::swap(&mut self.pipelines, &mut previous);
+        let result = build(self);
+        if result.is_err() {
+            self.pipelines = previous;
+        }
+        result
+    }
     
+    /*
+    The viewport and scissor are dynamic, they have to be set before drawing with the pipeline
+    Fails e.g. when a reloaded external shader doesn't match the vertex layout or the other stage
+    */
     pub fn create_pipeline(
         &mut self,
         pipeline_name: &str,
         render_pass: &Arc<RenderPass>, 
-        surface: &Arc<Surface>,
         vs: &Arc<ShaderModule>,
         fs: &Arc<ShaderModule>,
-        viewport: Option<&Viewport>,
-        rasterization_state: Option<&RasterizationState>
-    ) -> Arc<GraphicsPipeline> {
-        let viewport_value = match viewport {
-            Some(viewport) => viewport.clone(),
-            None => Viewport {
-                origin: [0.0, 0.0],
-                dimensions: surface.object().unwrap().downcast_ref::<Window>().unwrap().inner_size().into(),
-                depth_range: 0.0..1.0,
-            }
-        };
+        rasterization_state: Option<&RasterizationState>,
+        depth_stencil_state: Option<&DepthStencilState>
+    ) -> Result<Arc<GraphicsPipeline>, String> {
+        self.create_pipeline_with_topology(pipeline_name, render_pass, vs, fs, rasterization_state, depth_stencil_state, PrimitiveTopology::TriangleList)
+    }
 
-        let rasterization_state = match rasterization_state {
+    /*
+    Same as create_pipeline, e.g. with PrimitiveTopology::LineList to draw lines
+    without depending on fill_mode_non_solid
+    */
+    pub fn create_pipeline_with_topology(
+        &mut self,
+        pipeline_name: &str,
+        render_pass: &Arc<RenderPass>, 
+        vs: &Arc<ShaderModule>,
+        fs: &Arc<ShaderModule>,
+        rasterization_state: Option<&RasterizationState>,
+        depth_stencil_state: Option<&DepthStencilState>,
+        topology: PrimitiveTopology
+    ) -> Result<Arc<GraphicsPipeline>, String> {
+        let mut rasterization_state = match rasterization_state {
             Some(v) => v.clone(),
             None => RasterizationState::default()
         };
+        rasterization_state.depth_clamp_enable |= self.depth_clamp;
+        // e.g. a pipeline asking for it, clipping is the closest fallback
+        if rasterization_state.depth_clamp_enable && !self.device.enabled_features().depth_clamp {
+            warn!("Depth clamp is not supported by the device, pipeline {} clips depth instead", pipeline_name);
+            rasterization_state.depth_clamp_enable = false;
+        }
+
+        let depth_stencil_state = match depth_stencil_state {
+            Some(v) => v.clone(),
+            None => DepthStencilState::simple_depth_test()
+        };
     
+        let (vs_main, fs_main) = match (vs.entry_point("main"), fs.entry_point("main")) {
+            (Some(v), Some(f)) => (v, f),
+            _ => return Err(format!("Shaders of pipeline {} have no main entry point", pipeline_name))
+        };
+
         let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
         let pipeline = GraphicsPipeline::start()
             .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
-            .vertex_shader(vs.entry_point("main").unwrap(), ())
-            .input_assembly_state(InputAssemblyState::new())
-            .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([viewport_value]))
-            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .vertex_shader(vs_main, ())
+            .input_assembly_state(InputAssemblyState::new().topology(topology))
+            .viewport_state(ViewportState::viewport_dynamic_scissor_dynamic(1))
+            .fragment_shader(fs_main, ())
             .color_blend_state(ColorBlendState::new(subpass.num_color_attachments()).blend_alpha())
-            .depth_stencil_state(DepthStencilState::simple_depth_test())
+            .depth_stencil_state(depth_stencil_state)
             .rasterization_state(rasterization_state)
-            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-            .build(self.device.clone())
-            .unwrap();
+            .multisample_state(MultisampleState {
+                rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                sample_shading: self.sample_shading,
+                ..Default::default()
+            })
+            .render_pass(subpass);
+        let pipeline = match &self.pipeline_cache {
+            Some(cache) => pipeline.build_with_cache(cache.clone()),
+            None => pipeline
+        };
+        let pipeline = match pipeline.build(self.device.clone()) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create pipeline {}: {:?}", pipeline_name, e))
+        };
     
         // Insert to pipelines so we can use it later without needing a reference
         self.pipelines.insert(pipeline_name.into(), pipeline.clone());
 
-        return pipeline;
+        Ok(pipeline)
+    }
+
+    /*
+    Sample shading for pipelines created from now on, only has an effect with MSAA
+    With Some(1.0) the fragment shader runs for every sample instead of once per pixel,
+    which anti aliases high frequency detail inside the shader at a matching cost
+    Returns false and keeps the current value if the device doesn't support sample_rate_shading
+    */
+    pub fn set_sample_shading(&mut self, min_sample_shading: Option<f32>) -> bool {
+        if min_sample_shading.is_some() && !self.device.enabled_features().sample_rate_shading {
+            warn!("Sample shading is not supported by the device");
+            return false;
+        }
+
+        self.sample_shading = min_sample_shading.map(|v| if v.is_nan() { 1.0 } else { v.clamp(0.0, 1.0) });
+        true
+    }
+
+    pub fn sample_shading(&self) -> Option<f32> {
+        self.sample_shading
+    }
+
+    /*
+    Depth clamp for pipelines created from now on, fragments in front of the near plane
+    or behind the far plane get the depth of that plane instead of being clipped
+    The shadow pipeline always clamps if the device supports it, so casters in front of a cascade still cast
+    Pipelines can also enable it for themselves with RasterizationState::depth_clamp_enable
+    Returns false and keeps the current value if the device doesn't support depth_clamp
+    */
+    pub fn set_depth_clamp(&mut self, enabled: bool) -> bool {
+        if enabled && !self.device.enabled_features().depth_clamp {
+            warn!("Depth clamp is not supported by the device");
+            return false;
+        }
+
+        self.depth_clamp = enabled;
+        true
+    }
+
+    pub fn depth_clamp(&self) -> bool {
+        self.depth_clamp
     }
 
     pub fn create_view_ubo_pool(&self) -> Arc<CpuBufferPool<VPUniformBufferObject>> {
@@ -335,112 +813,1340 @@ impl Vulkan {
         ).into()
     }
 
-    pub fn create_command_buffer(
-        &self,
-        pipeline: &Arc<GraphicsPipeline>,
-        framebuffer: &Arc<Framebuffer>,
-        vertex_buffer: &Arc<CpuAccessibleBuffer<[Vertex]>>,
-        index_buffer: &Arc<CpuAccessibleBuffer<[u32]>>,
-        view_ubo: &Arc<CpuBufferPoolSubbuffer<VPUniformBufferObject>>,
-        descriptor_set_texture: &Arc<PersistentDescriptorSet>
-    ) -> Arc<PrimaryAutoCommandBuffer> {
-        // TODO: don't recreate the command buffer anew, but reset and write over the same one
-        // Not gonna optimize yet, since the library seems to have some type of optimizations already
-
-        // Setup MVP descriptor set
-        let layout_view = pipeline.layout().set_layouts().get(0).unwrap();
-        let descriptor_set_view = PersistentDescriptorSet::new(
-            &self.descriptor_set_allocator,
-            layout_view.clone(),
-            [WriteDescriptorSet::buffer(0, view_ubo.clone())]
-        ).unwrap();
+    pub fn create_shadow_ubo_pool(&self) -> Arc<CpuBufferPool<ShadowUniformBufferObject>> {
+        CpuBufferPool::<ShadowUniformBufferObject>::new(
+            self.buffer_memory_allocator.clone(),
+            BufferUsage {
+                uniform_buffer: true,
+                ..Default::default()
+            },
+            MemoryUsage::Upload
+        ).into()
+    }
 
-        let mut builder = AutoCommandBufferBuilder::primary(
-            &self.command_buffer_allocator,
-            self.queue.queue_family_index(),
-            CommandBufferUsage::MultipleSubmit
-        ).unwrap();
+    pub fn create_lights_ubo_pool(&self) -> Arc<CpuBufferPool<LightsUniformBufferObject>> {
+        CpuBufferPool::<LightsUniformBufferObject>::new(
+            self.buffer_memory_allocator.clone(),
+            BufferUsage {
+                uniform_buffer: true,
+                ..Default::default()
+            },
+            MemoryUsage::Upload
+        ).into()
+    }
 
-        builder
-            .begin_render_pass(
-                RenderPassBeginInfo {
-                    clear_values: vec![Some([0.0, 0.0, 0.0, 1.0].into()), Some(1f32.into())],
-                    ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
-                },
-                SubpassContents::Inline,
-            )
-            .unwrap()
-            .bind_pipeline_graphics(pipeline.clone())
-            .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, descriptor_set_view.clone())
-            .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 1, descriptor_set_texture.clone())
-            .bind_vertex_buffers(0, vertex_buffer.clone())
-            .bind_index_buffer(index_buffer.clone())
-            .draw_indexed(index_buffer.len() as u32, 1, 0, 0, 0)
-            .unwrap()
-            .end_render_pass()
-            .unwrap();
-    
-        Arc::new(builder.build().unwrap())
+    /*
+    Pool of vertex buffers rebuilt every frame, e.g. for the glyphs of WorldText labels
+    */
+    pub fn create_vertex_pool(&self) -> Arc<CpuBufferPool<Vertex>> {
+        CpuBufferPool::<Vertex>::new(
+            self.buffer_memory_allocator.clone(),
+            BufferUsage {
+                vertex_buffer: true,
+                ..Default::default()
+            },
+            MemoryUsage::Upload
+        ).into()
     }
 
+    /*
+    Creates the depth maps for layers cascades of resolution x resolution, both are clamped to at least 1
+    The default and pbr shaders always sample a shadow map, so one is needed even while shadows are off
+    */
+    pub fn create_shadow_map(&mut self, resolution: u32, layers: u32) -> Result<ShadowMap, String> {
+        let resolution = resolution.clamp(1, self.device.physical_device().properties().max_image_dimension2_d);
+        let layers = layers.max(1);
 
-    //--------------------------
-    // Utils
-    //--------------------------
-    
-    pub fn load_image(&self, path: &str) -> (Arc<ImageView<ImmutableImage>>, Box<dyn GpuFuture>) {
-        // TODO: add error handling
-        let image = File::open(path).unwrap();
+        let render_pass = match vulkano::single_pass_renderpass!(
+            self.device.clone(),
+            attachments: {
+                depth: {
+                    load: Clear,
+                    store: Store,
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [],
+                depth_stencil: {depth}
+            }
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the shadow render pass: {:?}", e))
+        };
 
-        let decoder = png::Decoder::new(image);
-        let mut reader = decoder.read_info().unwrap();
+        let usage = ImageUsage {
+            depth_stencil_attachment: true,
+            sampled: true,
+            ..ImageUsage::empty()
+        };
+        let image = match StorageImage::with_usage(
+            &self.buffer_memory_allocator,
+            ImageDimensions::Dim2d { width: resolution, height: resolution, array_layers: layers },
+            Format::D16_UNORM,
+            usage,
+            ImageCreateFlags::empty(),
+            [self.queue.queue_family_index()]
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the shadow map: {:?}", e))
+        };
+
+        let mut framebuffers = Vec::with_capacity(layers as usize);
+        for layer in 0..layers {
+            let mut create_info = ImageViewCreateInfo::from_image(&image);
+            create_info.view_type = ImageViewType::Dim2d;
+            create_info.subresource_range.array_layers = layer..layer + 1;
+            let framebuffer = ImageView::new(image.clone(), create_info)
+                .map_err(|e| format!("{:?}", e))
+                .and_then(|view| Framebuffer::new(
+                    render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![view],
+                        ..Default::default()
+                    }
+                ).map_err(|e| format!("{:?}", e)));
+            match framebuffer {
+                Ok(v) => framebuffers.push(v),
+                Err(e) => return Err(format!("Failed to create the framebuffer of shadow cascade {}: {}", layer, e))
+            }
+        }
+
+        // a single layer would default to a 2d view, the shaders sample an array
+        let view = match ImageView::new(image.clone(), ImageViewCreateInfo {
+            view_type: ImageViewType::Dim2dArray,
+            ..ImageViewCreateInfo::from_image(&image)
+        }) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the shadow map view: {:?}", e))
+        };
+
+        // Linear filtering of the comparison results smooths the edges further, if the format supports it
+        let filter = match self.device.physical_device().format_properties(Format::D16_UNORM) {
+            Ok(v) if v.optimal_tiling_features.sampled_image_filter_linear => Filter::Linear,
+            _ => Filter::Nearest
+        };
+        // Outside of the map everything is lit
+        let sampler = match Sampler::new(
+            self.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: filter,
+                min_filter: filter,
+                address_mode: [SamplerAddressMode::ClampToBorder; 3],
+                border_color: BorderColor::FloatOpaqueWhite,
+                compare: Some(CompareOp::LessOrEqual),
+                ..Default::default()
+            }
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the shadow map sampler: {:?}", e))
+        };
+
+        let pipeline = self.create_shadow_pipeline(&render_pass, resolution)?;
+
+        Ok(ShadowMap { render_pass, framebuffers, view, sampler, pipeline, resolution })
+    }
+
+    /*
+    Pipeline rendering the depth of shadow casters into render_pass, see create_shadow_map
+    Inserted to the pipelines as "shadow"
+    */
+    fn create_shadow_pipeline(&mut self, render_pass: &Arc<RenderPass>, resolution: u32) -> Result<Arc<GraphicsPipeline>, String> {
+        let vs = match shaders::load(&self.device, "shadow", "vs", shaders::shadow::vs::load) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to load shadow vs: {:?}", e))
+        };
+        let fs = match shaders::load(&self.device, "shadow", "fs", shaders::shadow::fs::load) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to load shadow fs: {:?}", e))
+        };
+
+        let viewport = Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [resolution as f32; 2],
+            depth_range: 0.0..1.0
+        };
+        // Both sides cast shadows, e.g. the terrain seen from below
+        // the bias keeps surfaces from shadowing themselves, depth clamp keeps casters
+        // in front of the cascade, those are clipped if the device doesn't support it
+        let rasterization_state = RasterizationState {
+            depth_clamp_enable: self.device.enabled_features().depth_clamp,
+            depth_bias: Some(DepthBiasState {
+                enable_dynamic: false,
+                bias: StateMode::Fixed(DepthBias { constant_factor: 4.0, clamp: 0.0, slope_factor: 1.5 })
+            }),
+            ..Default::default()
+        };
+
+        let subpass = match Subpass::from(render_pass.clone(), 0) {
+            Some(v) => v,
+            None => return Err("The shadow render pass has no subpass".into())
+        };
+        let pipeline = match GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([viewport]))
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .depth_stencil_state(DepthStencilState::simple_depth_test())
+            .rasterization_state(rasterization_state)
+            .render_pass(subpass)
+            .build(self.device.clone())
+        {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the shadow pipeline: {:?}", e))
+        };
+
+        self.pipelines.insert("shadow".into(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /*
+    Pipeline drawing the bounding boxes of OcclusionCulled entities for their occlusion queries,
+    depth tested against the solid pass without writing color or depth
+    Same shaders as the shadow pipeline, the push constant is the model view projection of the box
+    Inserted to the pipelines as "occlusion"
+    */
+    pub fn create_occlusion_pipeline(&mut self, render_pass: &Arc<RenderPass>) -> Result<Arc<GraphicsPipeline>, String> {
+        let vs = match shaders::load(&self.device, "shadow", "vs", shaders::shadow::vs::load) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to load occlusion vs: {:?}", e))
+        };
+        let fs = match shaders::load(&self.device, "shadow", "fs", shaders::shadow::fs::load) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to load occlusion fs: {:?}", e))
+        };
+
+        let depth_stencil_state = DepthStencilState {
+            depth: Some(DepthState {
+                enable_dynamic: false,
+                write_enable: StateMode::Fixed(false),
+                compare_op: StateMode::Fixed(CompareOp::LessOrEqual)
+            }),
+            ..Default::default()
+        };
+
+        let subpass = match Subpass::from(render_pass.clone(), 0) {
+            Some(v) => v,
+            None => return Err("The render pass has no subpass".into())
+        };
+        let pipeline = match GraphicsPipeline::start()
+            .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_dynamic(1))
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .color_blend_state(ColorBlendState::new(subpass.num_color_attachments()).color_write_mask(ColorComponents::empty()))
+            .depth_stencil_state(depth_stencil_state)
+            // the faces facing away count as well, e.g. when the front of the box is clipped
+            .rasterization_state(RasterizationState::default())
+            .multisample_state(MultisampleState {
+                rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                ..Default::default()
+            })
+            .render_pass(subpass)
+            .build(self.device.clone())
+        {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the occlusion pipeline: {:?}", e))
+        };
+
+        self.pipelines.insert("occlusion".into(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /*
+    Pipeline drawing the Skybox resource, a cube around the camera at the far plane
+    Inserted to the pipelines as "skybox", create_skybox needs it for the descriptor set
+    */
+    pub fn create_skybox_pipeline(&mut self, render_pass: &Arc<RenderPass>) -> Result<Arc<GraphicsPipeline>, String> {
+        let vs = shaders::load(&self.device, "skybox", "vs", shaders::skybox::vs::load)
+            .map_err(|e| format!("Failed to load skybox vs: {:?}", e))?;
+        let fs = shaders::load(&self.device, "skybox", "fs", shaders::skybox::fs::load)
+            .map_err(|e| format!("Failed to load skybox fs: {:?}", e))?;
+
+        // Everything drawn afterwards covers it, its depth is that of the cleared buffer
+        let depth_stencil_state = DepthStencilState {
+            depth: Some(DepthState {
+                enable_dynamic: false,
+                write_enable: StateMode::Fixed(false),
+                compare_op: StateMode::Fixed(CompareOp::LessOrEqual)
+            }),
+            ..Default::default()
+        };
+
+        let subpass = match Subpass::from(render_pass.clone(), 0) {
+            Some(v) => v,
+            None => return Err("The render pass has no subpass".into())
+        };
+        let pipeline = GraphicsPipeline::start()
+            // the cube is generated in the vertex shader
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new().topology(PrimitiveTopology::TriangleStrip))
+            .viewport_state(ViewportState::viewport_dynamic_scissor_dynamic(1))
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .depth_stencil_state(depth_stencil_state)
+            .multisample_state(MultisampleState {
+                rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                ..Default::default()
+            })
+            .render_pass(subpass)
+            .build(self.device.clone())
+            .map_err(|e| format!("Failed to create the skybox pipeline: {}", e))?;
+
+        self.pipelines.insert("skybox".into(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /*
+    Color and depth images of width x height for a Minimap, both are clamped to at least 1
+    */
+    pub fn create_minimap_target(&mut self, width: u32, height: u32) -> Result<MinimapTarget, String> {
+        let max = self.device.physical_device().properties().max_image_dimension2_d;
+        let size = [width.clamp(1, max), height.clamp(1, max)];
+
+        let render_pass = vulkano::single_pass_renderpass!(
+            self.device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: Format::R8G8B8A8_SRGB,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        ).map_err(|e| format!("Failed to create the minimap render pass: {}", e))?;
+
+        let color = AttachmentImage::sampled(&self.buffer_memory_allocator, size, Format::R8G8B8A8_SRGB)
+            .map_err(|e| format!("{}", e))
+            .and_then(|v| ImageView::new_default(v).map_err(|e| format!("{}", e)))
+            .map_err(|e| format!("Failed to create the minimap texture: {}", e))?;
+        let depth = AttachmentImage::transient(&self.buffer_memory_allocator, size, Format::D16_UNORM)
+            .map_err(|e| format!("{}", e))
+            .and_then(|v| ImageView::new_default(v).map_err(|e| format!("{}", e)))
+            .map_err(|e| format!("Failed to create the minimap depth buffer: {}", e))?;
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![color.clone(), depth],
+                ..Default::default()
+            }
+        ).map_err(|e| format!("Failed to create the minimap framebuffer: {}", e))?;
+
+        let vs = shaders::load(&self.device, "default", "vs", shaders::default::vs::load)
+            .map_err(|e| format!("Failed to load default vs: {}", e))?;
+        let fs = shaders::load(&self.device, "default", "fs", shaders::default::fs::load)
+            .map_err(|e| format!("Failed to load default fs: {}", e))?;
+        let fsp = shaders::load(&self.device, "pbr", "fs", shaders::pbr::fs::load)
+            .map_err(|e| format!("Failed to load pbr fs: {}", e))?;
+
+        let build = |fs: &Arc<ShaderModule>| {
+            GraphicsPipeline::start()
+                .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .input_assembly_state(InputAssemblyState::new())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_dynamic(1))
+                .fragment_shader(fs.entry_point("main").unwrap(), ())
+                .color_blend_state(ColorBlendState::new(1).blend_alpha())
+                .depth_stencil_state(DepthStencilState::simple_depth_test())
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(self.device.clone())
+                .map_err(|e| format!("Failed to create a minimap pipeline: {}", e))
+        };
+        let pipeline = build(&fs)?;
+        let pipeline_pbr = build(&fsp)?;
+
+        Ok(MinimapTarget { render_pass, framebuffer, color, pipeline, pipeline_pbr, size })
+    }
+
+    /*
+    Pipeline drawing the texture of a Minimap over the window, the fullscreen triangle
+    of the loading vs fills the viewport, which is set to the rect of the minimap
+    Inserted to the pipelines as "minimap"
+    */
+    pub fn create_minimap_pipeline(&mut self, render_pass: &Arc<RenderPass>) -> Result<Arc<GraphicsPipeline>, String> {
+        let vs = shaders::load(&self.device, "loading", "vs", shaders::loading::vs::load)
+            .map_err(|e| format!("Failed to load minimap vs: {}", e))?;
+        let fs = shaders::load(&self.device, "minimap", "fs", shaders::minimap::fs::load)
+            .map_err(|e| format!("Failed to load minimap fs: {}", e))?;
+
+        let subpass = match Subpass::from(render_pass.clone(), 0) {
+            Some(v) => v,
+            None => return Err("The render pass has no subpass".into())
+        };
+        let pipeline = GraphicsPipeline::start()
+            // the fullscreen triangle is generated in the vertex shader
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_dynamic(1))
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .color_blend_state(ColorBlendState::new(subpass.num_color_attachments()).blend_alpha())
+            // on top of everything
+            .depth_stencil_state(DepthStencilState::disabled())
+            .multisample_state(MultisampleState {
+                rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+                ..Default::default()
+            })
+            .render_pass(subpass)
+            .build(self.device.clone())
+            .map_err(|e| format!("Failed to create the minimap pipeline: {}", e))?;
+
+        self.pipelines.insert("minimap".into(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /*
+    Color and depth images of width x height for a RenderTarget, both are clamped to at least 1
+    The pipelines are created like the engine ones, so sample shading and depth clamp apply to them
+    */
+    pub fn create_offscreen_target(&mut self, width: u32, height: u32) -> Result<OffscreenTarget, String> {
+        let render_pass = vulkano::single_pass_renderpass!(
+            self.device.clone(),
+            attachments: {
+                color: {
+                    load: Clear,
+                    store: Store,
+                    format: Format::R8G8B8A8_SRGB,
+                    samples: 1,
+                },
+                depth: {
+                    load: Clear,
+                    store: DontCare,
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth}
+            }
+        ).map_err(|e| format!("Failed to create the offscreen render pass: {}", e))?;
+
+        let id = NEXT_OFFSCREEN_TARGET_ID.fetch_add(1, Ordering::Relaxed);
+        let size = self.offscreen_target_size(width, height);
+        let (framebuffer, color) = self.create_offscreen_framebuffer(&render_pass, size)?;
+        let (pipeline, pipeline_pbr) = self.create_offscreen_pipelines(id, &render_pass)?;
+
+        Ok(OffscreenTarget { id, render_pass, framebuffer, color, pipeline, pipeline_pbr, size })
+    }
+
+    /*
+    New images of width x height for target, which keeps its render pass, pipelines and id
+    Renderables sampling the old texture have to be given the new one with replace_renderable_texture
+    On error the target is left as it was
+    */
+    pub fn resize_offscreen_target(&self, target: &mut OffscreenTarget, width: u32, height: u32) -> Result<(), String> {
+        let size = self.offscreen_target_size(width, height);
+        if size == target.size {
+            return Ok(());
+        }
+
+        let (framebuffer, color) = self.create_offscreen_framebuffer(&target.render_pass, size)?;
+
+        *target = OffscreenTarget { framebuffer, color, size, ..target.clone() };
+        Ok(())
+    }
+
+    /*
+    Recreates the pipelines of target, e.g. after the shaders were reloaded
+    or the sample shading or depth clamp settings changed
+    On error the target keeps its previous pipelines
+    */
+    pub fn rebuild_offscreen_pipelines(&mut self, target: &mut OffscreenTarget) -> Result<(), String> {
+        let (pipeline, pipeline_pbr) = self.rebuild_pipelines(|vulkan| vulkan.create_offscreen_pipelines(target.id, &target.render_pass))?;
+        target.pipeline = pipeline;
+        target.pipeline_pbr = pipeline_pbr;
+        Ok(())
+    }
+
+    /*
+    Drops the pipelines of the target with id, once it isn't rendered into anymore
+    Its images are freed along with the last OffscreenTarget and renderable using them
+    The engine does this by itself when a RenderTarget is removed
+    */
+    pub fn destroy_offscreen_target(&mut self, id: u32) {
+        self.pipelines.remove(&format!("offscreen_{}", id));
+        self.pipelines.remove(&format!("offscreen_pbr_{}", id));
+    }
+
+    fn offscreen_target_size(&self, width: u32, height: u32) -> [u32; 2] {
+        let max = self.device.physical_device().properties().max_image_dimension2_d;
+        [width.clamp(1, max), height.clamp(1, max)]
+    }
+
+    fn create_offscreen_framebuffer(&self, render_pass: &Arc<RenderPass>, size: [u32; 2]) -> Result<(Arc<Framebuffer>, Arc<ImageView<AttachmentImage>>), String> {
+        let color = AttachmentImage::sampled(&self.buffer_memory_allocator, size, Format::R8G8B8A8_SRGB)
+            .map_err(|e| format!("{}", e))
+            .and_then(|v| ImageView::new_default(v).map_err(|e| format!("{}", e)))
+            .map_err(|e| format!("Failed to create the offscreen texture: {}", e))?;
+        let depth = AttachmentImage::transient(&self.buffer_memory_allocator, size, Format::D16_UNORM)
+            .map_err(|e| format!("{}", e))
+            .and_then(|v| ImageView::new_default(v).map_err(|e| format!("{}", e)))
+            .map_err(|e| format!("Failed to create the offscreen depth buffer: {}", e))?;
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![color.clone(), depth],
+                ..Default::default()
+            }
+        ).map_err(|e| format!("Failed to create the offscreen framebuffer: {}", e))?;
+
+        Ok((framebuffer, color))
+    }
+
+    fn create_offscreen_pipelines(&mut self, id: u32, render_pass: &Arc<RenderPass>) -> Result<(Arc<GraphicsPipeline>, Arc<GraphicsPipeline>), String> {
+        let vs = shaders::load(&self.device, "default", "vs", shaders::default::vs::load)
+            .map_err(|e| format!("Failed to load default vs: {}", e))?;
+        let fs = shaders::load(&self.device, "default", "fs", shaders::default::fs::load)
+            .map_err(|e| format!("Failed to load default fs: {}", e))?;
+        let fsp = shaders::load(&self.device, "pbr", "fs", shaders::pbr::fs::load)
+            .map_err(|e| format!("Failed to load pbr fs: {}", e))?;
+
+        let pipeline = self.create_pipeline(&format!("offscreen_{}", id), render_pass, &vs, &fs, None, None)?;
+        let pipeline_pbr = self.create_pipeline(&format!("offscreen_pbr_{}", id), render_pass, &vs, &fsp, None, None)?;
+        Ok((pipeline, pipeline_pbr))
+    }
+
+    /*
+    Atlas of the built in font for the "text" pipeline, see graphics::text
+    */
+    pub fn create_text_font(&self) -> Result<TextFont, String> {
+        let pipeline = match self.pipelines.get("text") {
+            Some(v) => v,
+            None => return Err("No pipeline called 'text' exists".into())
+        };
+
+        let (width, height) = text::atlas_size();
+        // the texels are either white or transparent, no need for srgb
+        let (atlas, upload) = self.upload_levels(&[text::font_atlas()], width, height, Format::R8G8B8A8_UNORM)?;
+
+        let layout_texture = pipeline.layout().set_layouts().get(1).unwrap();
+        let descriptor_set = match PersistentDescriptorSet::new(
+            &self.descriptor_set_allocator,
+            layout_texture.clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(0, atlas, self.sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(1, self.white_texture.clone(), self.sampler.clone())
+            ]
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the font descriptor set: {:?}", e))
+        };
+
+        Ok(TextFont { descriptor_set, upload: Some(self.track_upload(upload)) })
+    }
+
+    /*
+    Linear repeating sampler of the textures of renderables
+    */
+    pub fn sampler(&self) -> Arc<Sampler> {
+        self.sampler.clone()
+    }
+
+    /*
+    Allocator of the buffers created by the vulkan struct, e.g. for buffers kept across frames
+    */
+    pub fn memory_allocator(&self) -> Arc<StandardMemoryAllocator> {
+        self.buffer_memory_allocator.clone()
+    }
+
+    /*
+    Pass drawing background, or just a color without one, and a progress bar onto the images
+    */
+    pub fn create_loading_screen(
+        &self,
+        swapchain: &Arc<Swapchain>,
+        images: &[Arc<SwapchainImage>],
+        viewport: &Viewport,
+        background: Option<Arc<ImageView<ImmutableImage>>>
+    ) -> Result<LoadingScreenPass, String> {
+        let render_pass = vulkano::single_pass_renderpass!(
+            self.device.clone(),
+            attachments: {
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: swapchain.image_format(),
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        ).map_err(|e| format!("Failed to create the loading screen render pass: {}", e))?;
+
+        let framebuffers = images
+            .iter()
+            .map(|image| {
+                let view = ImageView::new_default(image.clone())
+                    .map_err(|e| format!("Failed to create a loading screen image view: {}", e))?;
+                Framebuffer::new(render_pass.clone(), FramebufferCreateInfo { attachments: vec![view], ..Default::default() })
+                    .map_err(|e| format!("Failed to create a loading screen framebuffer: {}", e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let vs = shaders::load(&self.device, "loading", "vs", shaders::loading::vs::load)
+            .map_err(|e| format!("Failed to load loading vs: {}", e))?;
+        let fs = shaders::load(&self.device, "loading", "fs", shaders::loading::fs::load)
+            .map_err(|e| format!("Failed to load loading fs: {}", e))?;
+
+        let pipeline = GraphicsPipeline::start()
+            // the fullscreen triangle is generated in the vertex shader
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([viewport.clone()]))
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(self.device.clone())
+            .map_err(|e| format!("Failed to create the loading screen pipeline: {}", e))?;
+
+        // a white texel leaves just the color without a background
+        let texture = match background.clone() {
+            Some(v) => v,
+            None => {
+                let (texture, upload) = self.upload_levels(&[vec![255; 4]], 1, 1, Format::R8G8B8A8_UNORM)?;
+                upload.then_signal_fence_and_flush()
+                    .map_err(|e| format!("Failed to upload the loading screen texture: {}", e))?
+                    .wait(None)
+                    .map_err(|e| format!("Failed waiting for the loading screen texture: {}", e))?;
+                texture
+            }
+        };
+        let descriptor_set = PersistentDescriptorSet::new(
+            &self.descriptor_set_allocator,
+            pipeline.layout().set_layouts().get(0).unwrap().clone(),
+            [WriteDescriptorSet::image_view_sampler(0, texture, self.sampler.clone())]
+        ).map_err(|e| format!("Failed to create the loading screen descriptor set: {}", e))?;
+
+        Ok(LoadingScreenPass { render_pass, framebuffers, pipeline, descriptor_set, background })
+    }
+
+    /*
+    Records drawing the loading screen onto the swapchain image image_i
+    */
+    pub fn draw_loading_screen(&self, pass: &LoadingScreenPass, image_i: usize, constants: LoadingPushConstants) -> Result<Arc<PrimaryAutoCommandBuffer>, String> {
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit
+        ).map_err(|e| format!("Failed to create the loading screen command buffer: {}", e))?;
+
+        builder.begin_render_pass(
+            RenderPassBeginInfo {
+                // every pixel is overwritten by the fullscreen triangle
+                clear_values: vec![None],
+                ..RenderPassBeginInfo::framebuffer(pass.framebuffers[image_i].clone())
+            },
+            SubpassContents::Inline
+        ).map_err(|e| format!("Failed beginning the loading screen render pass: {}", e))?;
+
+        builder
+            .bind_pipeline_graphics(pass.pipeline.clone())
+            .bind_descriptor_sets(PipelineBindPoint::Graphics, pass.pipeline.layout().clone(), 0, pass.descriptor_set.clone())
+            .push_constants(pass.pipeline.layout().clone(), 0, constants);
+
+        builder.draw(3, 1, 0, 0).map_err(|e| format!("Failed drawing the loading screen: {}", e))?;
+        builder.end_render_pass().map_err(|e| format!("Failed ending the loading screen render pass: {}", e))?;
+
+        builder.build()
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to build the loading screen command buffer: {}", e))
+    }
+
+    /*
+    Pass sampling the scene from its targets and drawing it onto the images, with FXAA if fxaa is set
+    The scene has to be rendered into the targets instead, with a single sampled or resolved render pass
+    The targets are render_scale times the size of the images and scaled up with its filter
+    */
+    pub fn create_post_process(
+        &self,
+        swapchain: &Arc<Swapchain>,
+        images: &[Arc<SwapchainImage>],
+        viewport: &Viewport,
+        render_scale: RenderScale,
+        fxaa: bool
+    ) -> Result<PostProcess, String> {
+        let render_pass = vulkano::single_pass_renderpass!(
+            self.device.clone(),
+            attachments: {
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: swapchain.image_format(),
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        ).map_err(|e| format!("Failed to create the post process render pass: {}", e))?;
+
+        let framebuffers = images
+            .iter()
+            .map(|image| {
+                let view = ImageView::new_default(image.clone())
+                    .map_err(|e| format!("Failed to create a post process image view: {}", e))?;
+                Framebuffer::new(render_pass.clone(), FramebufferCreateInfo { attachments: vec![view], ..Default::default() })
+                    .map_err(|e| format!("Failed to create a post process framebuffer: {}", e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let dimensions = render_scale.dimensions(images[0].dimensions().width_height());
+        let targets = images
+            .iter()
+            .map(|_| AttachmentImage::sampled(&self.buffer_memory_allocator, dimensions, swapchain.image_format())
+                .map_err(|e| format!("Failed to create a post process target: {}", e)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let vs = shaders::load(&self.device, "fxaa", "vs", shaders::fxaa::vs::load)
+            .map_err(|e| format!("Failed to load fxaa vs: {}", e))?;
+        let fs = match fxaa {
+            true => shaders::load(&self.device, "fxaa", "fs", shaders::fxaa::fs::load)
+                .map_err(|e| format!("Failed to load fxaa fs: {}", e))?,
+            false => shaders::load(&self.device, "blit", "fs", shaders::blit::fs::load)
+                .map_err(|e| format!("Failed to load blit fs: {}", e))?
+        };
+        let filter = match render_scale.filter {
+            UpscaleFilter::Nearest => Filter::Nearest,
+            UpscaleFilter::Linear => Filter::Linear
+        };
+
+        let pipeline = GraphicsPipeline::start()
+            // the fullscreen triangle is generated in the vertex shader
+            .vertex_input_state(BuffersDefinition::new())
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .input_assembly_state(InputAssemblyState::new())
+            .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([viewport.clone()]))
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap());
+        let pipeline = match &self.pipeline_cache {
+            Some(cache) => pipeline.build_with_cache(cache.clone()),
+            None => pipeline
+        };
+        let pipeline = pipeline
+            .build(self.device.clone())
+            .map_err(|e| format!("Failed to create the post process pipeline: {}", e))?;
+
+        // clamped, so the edges don't blend with the opposite side of the screen
+        let sampler = Sampler::new(
+            self.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: filter,
+                min_filter: filter,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            }
+        ).map_err(|e| format!("Failed to create the post process sampler: {}", e))?;
+
+        let layout = pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_sets = targets
+            .iter()
+            .map(|target| {
+                let view = ImageView::new_default(target.clone())
+                    .map_err(|e| format!("Failed to create a post process target view: {}", e))?;
+                PersistentDescriptorSet::new(
+                    &self.descriptor_set_allocator,
+                    layout.clone(),
+                    [WriteDescriptorSet::image_view_sampler(0, view, sampler.clone())]
+                ).map_err(|e| format!("Failed to create a post process descriptor set: {}", e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(PostProcess { render_pass, targets, framebuffers, pipeline, descriptor_sets })
+    }
+
+    pub fn create_command_buffer(
+        &self,
+        pipeline: &Arc<GraphicsPipeline>,
+        framebuffer: &Arc<Framebuffer>,
+        vertex_buffer: &Arc<CpuAccessibleBuffer<[Vertex]>>,
+        index_buffer: &Arc<CpuAccessibleBuffer<[u32]>>,
+        view_ubo: &Arc<CpuBufferPoolSubbuffer<VPUniformBufferObject>>,
+        descriptor_set_texture: &Arc<PersistentDescriptorSet>,
+        clear_color: [f32; 4],
+        clear_depth: f32
+    ) -> Arc<PrimaryAutoCommandBuffer> {
+        // TODO: don't recreate the command buffer anew, but reset and write over the same one
+        // Not gonna optimize yet, since the library seems to have some type of optimizations already
+
+        // Setup MVP descriptor set
+        let layout_view = pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set_view = PersistentDescriptorSet::new(
+            &self.descriptor_set_allocator,
+            layout_view.clone(),
+            [WriteDescriptorSet::buffer(0, view_ubo.clone())]
+        ).unwrap();
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::MultipleSubmit
+        ).unwrap();
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values: vec![Some(clear_color.into()), Some(clear_depth.into())],
+                    ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                },
+                SubpassContents::Inline,
+            )
+            .unwrap()
+            .bind_pipeline_graphics(pipeline.clone())
+            .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 0, descriptor_set_view.clone())
+            .bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline.layout().clone(), 1, descriptor_set_texture.clone())
+            .bind_vertex_buffers(0, vertex_buffer.clone())
+            .bind_index_buffer(index_buffer.clone())
+            .draw_indexed(index_buffer.len() as u32, 1, 0, 0, 0)
+            .unwrap()
+            .end_render_pass()
+            .unwrap();
+    
+        Arc::new(builder.build().unwrap())
+    }
+
+
+    //--------------------------
+    // Utils
+    //--------------------------
+    
+    /*
+    Loads a PNG, JPEG, BMP or TGA texture with a full chain of mip levels, see MipChain::load
+    Everything is converted to R8G8B8A8_SRGB, the returned future uploads it to the gpu
+    KTX2 and DDS files holding BCn data are uploaded in their compressed format instead,
+    see load_compressed_image
+    */
+    pub fn load_image(&self, path: &str) -> Result<(Arc<ImageView<ImmutableImage>>, Box<dyn GpuFuture>), String> {
+        let mut header = [0u8; 12];
+        let is_compressed = File::open(path)
+            .and_then(|mut f| f.read_exact(&mut header))
+            .map(|_| compressed::is_compressed_container(&header))
+            .unwrap_or(false);
+        if is_compressed {
+            return self.load_compressed_image(path);
+        }
+
+        let mips = MipChain::load(path)?;
+        self.upload_mip_chain(&mips, 0)
+    }
+
+    /*
+    Uploads a block compressed KTX2 or DDS texture without decoding it, including its mip levels
+    Fails if the device can't sample the format, BC formats are usually only missing on mobile gpus
+    */
+    pub fn load_compressed_image(&self, path: &str) -> Result<(Arc<ImageView<ImmutableImage>>, Box<dyn GpuFuture>), String> {
+        let bytes = match std::fs::read(path) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to open image {}: {}", path, e))
+        };
+
+        let image = match compressed::parse_compressed(&bytes) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to read compressed image {}: {}", path, e))
+        };
+
+        let supported = match self.device.physical_device().format_properties(image.format) {
+            Ok(v) => v.optimal_tiling_features.sampled_image,
+            Err(_) => false
+        };
+        if !supported {
+            return Err(format!("Image {} uses {:?}, which the device can't sample", path, image.format));
+        }
+
+        info!(
+            "Uploading {} as {:?}, {} bytes instead of {} as RGBA8",
+            path, image.format, image.byte_size(), image.width as usize * image.height as usize * 4
+        );
+
+        self.upload_levels(&image.levels, image.width, image.height, image.format)
+            .map_err(|e| format!("Failed to upload {}: {}", path, e))
+    }
+
+    /*
+    Loads the texture resources/<name> and waits until it is on the gpu, for textures needed right away
+    */
+    pub fn load_texture_now(&self, name: &str) -> Result<Arc<ImageView<ImmutableImage>>, String> {
+        let path = texture_path(name);
+        let (texture, upload) = self.load_image(&path)?;
+        match upload.then_signal_fence_and_flush() {
+            Ok(v) => v.wait(None).map_err(|e| format!("Failed waiting for the upload of {}: {}", path, e))?,
+            Err(e) => return Err(format!("Failed to upload {}: {}", path, e))
+        };
+        Ok(texture)
+    }
+
+    /*
+    Uploads the levels of mips from first_level on, the image is the size of that level
+    */
+    pub fn upload_mip_chain(&self, mips: &MipChain, first_level: u32) -> Result<(Arc<ImageView<ImmutableImage>>, Box<dyn GpuFuture>), String> {
+        let first_level = first_level.min(mips.level_count() - 1);
+        let [width, height] = mips.level_size(first_level);
+        self.upload_levels(&mips.levels[first_level as usize..], width, height, Format::R8G8B8A8_SRGB)
+    }
+
+    /*
+    Uploads the given mip levels of an image, each level half the size of the previous one
+    */
+    fn upload_levels(&self, levels: &[Vec<u8>], width: u32, height: u32, format: Format) -> Result<(Arc<ImageView<ImmutableImage>>, Box<dyn GpuFuture>), String> {
+        let mut uploads = match AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the upload command buffer: {:?}", e))
+        };
+
+        let source = match CpuAccessibleBuffer::from_iter(
+            &self.buffer_memory_allocator,
+            BufferUsage {
+                transfer_src: true,
+                ..Default::default()
+            },
+            false,
+            levels.concat()
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create staging buffer: {:?}", e))
+        };
+
+        let (gpu_image, initialization) = match ImmutableImage::uninitialized(
+            &self.buffer_memory_allocator,
+            ImageDimensions::Dim2d { width, height, array_layers: 1 },
+            format,
+            MipmapsCount::Specific(levels.len() as u32),
+            ImageUsage {
+                transfer_dst: true,
+                sampled: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags::empty(),
+            ImageLayout::ShaderReadOnlyOptimal,
+            [self.queue.queue_family_index()]
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create image: {:?}", e))
+        };
+
+        // levels are tightly packed in the buffer, one region each
+        let mut offset = 0;
+        let regions = levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let region = BufferImageCopy {
+                    buffer_offset: offset,
+                    image_subresource: ImageSubresourceLayers {
+                        mip_level: level as u32,
+                        ..ImageSubresourceLayers::from_parameters(format, 1)
+                    },
+                    image_extent: [(width >> level).max(1), (height >> level).max(1), 1],
+                    ..Default::default()
+                };
+                offset += data.len() as u64;
+                region
+            })
+            .collect();
+
+        if let Err(e) = uploads.copy_buffer_to_image(CopyBufferToImageInfo {
+            regions,
+            ..CopyBufferToImageInfo::buffer_image(source, initialization)
+        }) {
+            return Err(format!("Failed to record the upload: {:?}", e));
+        }
+
+        let image_upload = match uploads.build() {
+            Ok(v) => match v.execute(self.queue.clone()) {
+                Ok(v) => v.boxed(),
+                Err(e) => return Err(format!("Failed to submit the upload: {:?}", e))
+            },
+            Err(e) => return Err(format!("Failed to build the upload command buffer: {:?}", e))
+        };
+
+        let texture = match ImageView::new_default(gpu_image) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create image view: {:?}", e))
+        };
 
-        let mut pixels = vec![0; reader.info().raw_bytes()];
-        reader.next_frame(&mut pixels).unwrap();
+        Ok((texture, image_upload))
+    }
+
+    /*
+    Loads six square images of the same size into a cubemap,
+    faces in the order +X, -X, +Y, -Y, +Z, -Z
+    The returned future uploads it to the gpu
+    */
+    pub fn load_cubemap(&self, paths: [&str; 6]) -> Result<(Arc<ImageView<ImmutableImage>>, Box<dyn GpuFuture>), String> {
+        let mut pixels = Vec::new();
+        let mut size = 0;
+        for (i, path) in paths.iter().enumerate() {
+            let reader = match image::io::Reader::open(path) {
+                Ok(v) => v,
+                Err(e) => return Err(format!("Failed to open image {}: {}", path, e))
+            };
+
+            let reader = match reader.with_guessed_format() {
+                Ok(v) => v,
+                Err(e) => return Err(format!("Failed to read image {}: {}", path, e))
+            };
+
+            let image = match reader.decode() {
+                Ok(v) => v.into_rgba8(),
+                Err(e) => return Err(format!("Failed to decode image {}: {}", path, e))
+            };
+
+            let (width, height) = image.dimensions();
+            if width != height {
+                return Err(format!("Cubemap face {} is not square: {}x{}", path, width, height));
+            }
+            if i > 0 && width != size {
+                return Err(format!("Cubemap face {} is {}px, the previous faces are {}px", path, width, size));
+            }
+            size = width;
+            pixels.extend(image.into_raw());
+        }
+
+        let mut uploads = match AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the upload command buffer: {:?}", e))
+        };
+
+        let source = match CpuAccessibleBuffer::from_iter(
+            &self.buffer_memory_allocator,
+            BufferUsage {
+                transfer_src: true,
+                ..Default::default()
+            },
+            false,
+            pixels
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create cubemap staging buffer: {:?}", e))
+        };
+
+        // ImmutableImage::from_iter can't create cube compatible images
+        let (image, initialization) = match ImmutableImage::uninitialized(
+            &self.buffer_memory_allocator,
+            ImageDimensions::Dim2d { width: size, height: size, array_layers: 6 },
+            Format::R8G8B8A8_SRGB,
+            MipmapsCount::One,
+            ImageUsage {
+                transfer_dst: true,
+                sampled: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags {
+                cube_compatible: true,
+                ..ImageCreateFlags::empty()
+            },
+            ImageLayout::ShaderReadOnlyOptimal,
+            [self.queue.queue_family_index()]
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create cubemap image: {:?}", e))
+        };
+
+        // layers are tightly packed in the buffer, one after the other
+        if let Err(e) = uploads.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(source, initialization)) {
+            return Err(format!("Failed to record the cubemap upload: {:?}", e));
+        }
+
+        let image_upload = match uploads.build() {
+            Ok(v) => match v.execute(self.queue.clone()) {
+                Ok(v) => v.boxed(),
+                Err(e) => return Err(format!("Failed to submit the cubemap upload: {:?}", e))
+            },
+            Err(e) => return Err(format!("Failed to build the upload command buffer: {:?}", e))
+        };
+
+        let view = match ImageView::new(image.clone(), ImageViewCreateInfo {
+            view_type: ImageViewType::Cube,
+            ..ImageViewCreateInfo::from_image(&image)
+        }) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create cubemap view: {:?}", e))
+        };
+
+        Ok((view, image_upload))
+    }
+
+    /*
+    Creates the Skybox resource from six images, see load_cubemap
+    create_skybox_pipeline has to have been called, the engine does it when created
+    */
+    pub fn create_skybox(&self, paths: [&str; 6]) -> Result<Skybox, String> {
+        let pipeline = match self.pipelines.get("skybox") {
+            Some(v) => v,
+            None => return Err("No pipeline called 'skybox' exists".into())
+        };
+
+        let (cubemap, upload) = self.load_cubemap(paths)?;
+        let upload = self.track_upload(upload);
+
+        // the seams between the faces would bleed into each other with repeat
+        let sampler = match Sampler::new(
+            self.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            }
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create skybox sampler: {:?}", e))
+        };
+
+        let layout = pipeline.layout().set_layouts().get(0).unwrap();
+        let descriptor_set = match PersistentDescriptorSet::new(
+            &self.descriptor_set_allocator,
+            layout.clone(),
+            [WriteDescriptorSet::image_view_sampler(0, cubemap, sampler)]
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create skybox descriptor set: {:?}", e))
+        };
+
+        Ok(Skybox { descriptor_set, upload: Some(upload) })
+    }
+
+    /*
+    Creates a texture from 8 bit RGBA pixels, the returned future uploads it to the gpu
+    Colors are R8G8B8A8_SRGB, data like normal maps R8G8B8A8_UNORM so it isn't converted when sampled
+    */
+    fn create_texture(
+        buffer_memory_allocator: &StandardMemoryAllocator,
+        command_buffer_allocator: &StandardCommandBufferAllocator,
+        queue: &Arc<Queue>,
+        pixels: Vec<u8>,
+        width: u32,
+        height: u32,
+        format: Format
+    ) -> (Arc<ImageView<ImmutableImage>>, Box<dyn GpuFuture>) {
+        let dimensions = ImageDimensions::Dim2d { 
+            width, 
+            height, 
+            array_layers: 1 
+        };
+
+        let mut uploads = AutoCommandBufferBuilder::primary(
+            command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        let image = ImmutableImage::from_iter(
+            buffer_memory_allocator,
+            pixels,
+            dimensions,
+            MipmapsCount::One,
+            format,
+            &mut uploads
+        ).unwrap();
+
+        // Need to use the created command buffer to upload the texture to the gpu
+        let image_upload = uploads
+            .build()
+            .unwrap()
+            .execute(queue.clone())
+            .unwrap()
+            .boxed();
+
+        // TODO: move this to somewhere smart for cleanup
+        //image_upload.as_mut().cleanup_finished();
+
+        let texture = ImageView::new_default(image).unwrap();
+
+        return (texture, image_upload);
+    }
+
+    /*
+    Submits the upload and keeps track of it until it has finished,
+    see poll_uploads, has_pending_uploads and wait_for_uploads
+    The returned handle is marked failed if the upload could not be submitted
+    */
+    pub fn track_upload(&self, upload: Box<dyn GpuFuture>) -> UploadHandle {
+        // the list would otherwise grow with every upload while nothing polls it
+        self.poll_uploads();
+        let handle = UploadHandle::default();
+        match upload.then_signal_fence_and_flush() {
+            Ok(v) => self.pending_uploads.borrow_mut().push((v, handle.clone())),
+            Err(e) => {
+                error!("Failed to submit an upload: {:?}", e);
+                handle.fail();
+            }
+        }
+        handle
+    }
+
+    /*
+    Whether any tracked upload is still running
+    */
+    pub fn has_pending_uploads(&self) -> bool {
+        self.poll_uploads();
+        !self.pending_uploads.borrow().is_empty()
+    }
+
+    /*
+    Finishes the handles of uploads which are done and stops tracking them, doesn't block
+    Called once per frame by the engine
+    */
+    pub fn poll_uploads(&self) {
+        self.pending_uploads.borrow_mut().retain_mut(|(upload, handle)| {
+            match upload.is_signaled() {
+                Ok(false) => true,
+                Ok(true) => {
+                    upload.cleanup_finished();
+                    handle.finish();
+                    false
+                },
+                // the fence state can only fail to be read when the device is lost or out of memory,
+                // retrying wouldn't help, so the waiting renderables are told instead
+                Err(e) => {
+                    error!("Failed checking the state of an upload, marking it failed: {:?}", e);
+                    handle.fail();
+                    false
+                }
+            }
+        });
+    }
+
+    /*
+    Blocks until every tracked upload has finished
+    */
+    pub fn wait_for_uploads(&self) {
+        let uploads: Vec<_> = self.pending_uploads.borrow_mut().drain(..).collect();
+        let count = uploads.len();
+
+        for (upload, handle) in uploads {
+            match upload.wait(None) {
+                Ok(_) => handle.finish(),
+                Err(e) => {
+                    error!("Failed waiting for an upload to finish, marking it failed: {:?}", e);
+                    handle.fail();
+                }
+            }
+        }
+
+        if count > 0 {
+            info!("Waited for {count} pending uploads");
+        }
+    }
+
+    /*
+    Writes the pipeline cache to the given path, so the next run can load it
+    */
+    pub fn save_pipeline_cache(&self, path: &str) {
+        let cache = match &self.pipeline_cache {
+            Some(v) => v,
+            None => return
+        };
+
+        let data = match cache.get_data() {
+            Ok(v) => v,
+            Err(e) => return error!("Failed to get pipeline cache data: {:?}", e)
+        };
+
+        match std::fs::write(path, &data) {
+            Ok(_) => info!("Saved {} bytes of pipeline cache to {}", data.len(), path),
+            Err(e) => error!("Failed to write pipeline cache to {}: {:?}", path, e)
+        }
+    }
 
-        let (width, height) = reader.info().size();
+    /*
+    Loads the pipeline cache saved by save_pipeline_cache, or creates an empty one
+    if the file doesn't exist or was created by another device or driver
+    None if not even an empty cache could be created
+    */
+    fn load_pipeline_cache(device: &Arc<Device>, path: &str) -> Option<Arc<PipelineCache>> {
+        let empty = || match PipelineCache::empty(device.clone()) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("Failed to create a pipeline cache, building pipelines without one: {:?}", e);
+                None
+            }
+        };
 
-        let dimensions = ImageDimensions::Dim2d { 
-            width, 
-            height, 
-            array_layers: 1 
+        let data = match std::fs::read(path) {
+            Ok(v) => v,
+            Err(_) => {
+                info!("No pipeline cache at {}, starting with an empty one", path);
+                return empty()
+            }
         };
 
-        let mut uploads = AutoCommandBufferBuilder::primary(
-            &self.command_buffer_allocator,
-            self.queue.queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
+        if !Vulkan::pipeline_cache_matches(device.physical_device(), &data) {
+            warn!("Pipeline cache at {} is from another device or driver, ignoring it", path);
+            return empty();
+        }
 
-        let image = ImmutableImage::from_iter(
-            &self.buffer_memory_allocator,
-            pixels,
-            dimensions,
-            MipmapsCount::One,
-            Format::R8G8B8A8_SRGB,
-            &mut uploads
-        ).unwrap();
+        // Safe since the header was checked, the driver validates the rest
+        match unsafe { PipelineCache::with_data(device.clone(), &data) } {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("Failed to load pipeline cache from {}: {:?}", path, e);
+                empty()
+            }
+        }
+    }
 
-        // Need to use the created command buffer to upload the texture to the gpu
-        let image_upload = uploads
-            .build()
-            .unwrap()
-            .execute(self.queue.clone())
-            .unwrap()
-            .boxed();
+    /*
+    Checks the header of pipeline cache data against the physical device
+    The header is the header length, header version, vendor id, device id and the cache uuid
+    */
+    fn pipeline_cache_matches(physical: &Arc<PhysicalDevice>, data: &[u8]) -> bool {
+        const HEADER_LENGTH: usize = 32;
+        const HEADER_VERSION_ONE: u32 = 1;
 
-        // TODO: move this to somewhere smart for cleanup
-        //image_upload.as_mut().cleanup_finished();
+        if data.len() < HEADER_LENGTH {
+            return false;
+        }
 
-        let texture = ImageView::new_default(image).unwrap();
+        let read_u32 = |offset: usize| u32::from_ne_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let properties = physical.properties();
 
-        return (texture, image_upload);
+        read_u32(0) as usize >= HEADER_LENGTH
+            && read_u32(4) == HEADER_VERSION_ONE
+            && read_u32(8) == properties.vendor_id
+            && read_u32(12) == properties.device_id
+            && data[16..32] == properties.pipeline_cache_uuid
     }
 
     pub fn load_model(&self, path: &str) -> (
         Arc<CpuAccessibleBuffer<[Vertex]>>, 
         Arc<CpuAccessibleBuffer<[u32]>>
+    ) {
+        self.load_model_with_normals(path, false)
+    }
+
+    /*
+    With flat_normals, the normals of the obj are replaced by the normal of each face
+    Vertices are then not shared between faces, which gives a faceted look
+    */
+    pub fn load_model_with_normals(&self, path: &str, flat_normals: bool) -> (
+        Arc<CpuAccessibleBuffer<[Vertex]>>, 
+        Arc<CpuAccessibleBuffer<[u32]>>
     ) {
         // TODO: add error handling
         let mut reader = BufReader::new(File::open(path).unwrap());
@@ -451,10 +2157,73 @@ impl Vulkan {
             |_| Ok(Default::default())
         ).unwrap();
 
+        let (vertices, indices) = Vulkan::obj_vertices(models.iter(), flat_normals);
+
+        return self.create_vertex_buffers(vertices, indices);
+    }
+
+    /*
+    Loads an obj along with its mtl, with one part per diffuse texture
+    Parts without a material or whose material has no diffuse texture have None as the texture,
+    texture paths are relative to the directory of the obj like in the mtl
+    */
+    pub fn load_model_parts(&self, path: &str, flat_normals: bool) -> Result<Vec<ModelPart>, String> {
+        let (models, materials) = match tobj::load_obj(path, &tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() }) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to load model {}: {}", path, e))
+        };
+
+        // the mtl is optional, the model is still usable without it
+        let materials = match materials {
+            Ok(v) => v,
+            Err(e) => {
+                if !models.iter().all(|m| m.mesh.material_id.is_none()) {
+                    warn!("Failed to load materials of model {}: {}", path, e);
+                }
+                Vec::new()
+            }
+        };
+
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let texture_of = |material_id: Option<usize>| -> Option<String> {
+            let material = materials.get(material_id?)?;
+            if material.diffuse_texture.is_empty() {
+                return None;
+            }
+            Some(directory.join(&material.diffuse_texture).to_string_lossy().into_owned())
+        };
+
+        // Groups are in the order of their first model, so the parts are in file order
+        let mut groups: Vec<(Option<String>, Vec<&tobj::Model>)> = Vec::new();
+        for model in &models {
+            let texture = texture_of(model.mesh.material_id);
+            match groups.iter_mut().find(|(t, _)| *t == texture) {
+                Some((_, group)) => group.push(model),
+                None => groups.push((texture, vec![model]))
+            }
+        }
+
+        let parts = groups
+            .into_iter()
+            .map(|(texture, group)| {
+                let (vertices, indices) = Vulkan::obj_vertices(group.into_iter(), flat_normals);
+                let (vertex_buffer, index_buffer) = self.create_vertex_buffers(vertices, indices);
+                ModelPart { vertex_buffer, index_buffer, texture }
+            })
+            .collect();
+
+        Ok(parts)
+    }
+
+    /*
+    Vertices and indices of the given obj models merged together
+    With flat_normals every face gets its own vertices with the face normal
+    */
+    fn obj_vertices<'m>(models: impl Iterator<Item = &'m tobj::Model>, flat_normals: bool) -> (Vec<Vertex>, Vec<u32>) {
         let mut vertices: Vec<Vertex> = Vec::with_capacity(1000);
         let mut indices: Vec<u32> = Vec::with_capacity(1000);
         let mut unique_vertices = HashMap::new();
-        for model in &models {
+        for model in models {
             for index in &model.mesh.indices {
                 let pos_offset = (3 * index) as usize;
                 let normal_offset = (3 * index) as usize;
@@ -475,10 +2244,19 @@ impl Vulkan {
                     tex_coord: [
                         model.mesh.texcoords[tex_coord_offset], 
                         1.0 - model.mesh.texcoords[tex_coord_offset + 1]
+                    ],
+                    // obj only has a single uv set
+                    tex_coord2: [
+                        model.mesh.texcoords[tex_coord_offset], 
+                        1.0 - model.mesh.texcoords[tex_coord_offset + 1]
                     ]
                 };
 
-                if let Some(index) = unique_vertices.get(&vertex) {
+                if flat_normals {
+                    indices.push(vertices.len() as u32);
+                    vertices.push(vertex);
+                }
+                else if let Some(index) = unique_vertices.get(&vertex) {
                     indices.push(*index as u32);
                 }
                 else {
@@ -489,8 +2267,166 @@ impl Vulkan {
                 }
             }
         };
+
+        if flat_normals {
+            // triangulated and not deduplicated, so every 3 vertices are a face
+            Self::set_face_normals(&mut vertices);
+        }
     
-        return self.create_vertex_buffers(vertices, indices);
+        (vertices, indices)
+    }
+
+    /*
+    Gives every 3 vertices the normal of the face they make up, counter clockwise is the front
+    */
+    fn set_face_normals(vertices: &mut [Vertex]) {
+        for face in vertices.chunks_mut(3) {
+            if let [a, b, c] = face {
+                let (pa, pb, pc) = (Vector3::from(a.position), Vector3::from(b.position), Vector3::from(c.position));
+                let normal: [f32; 3] = (pb - pa).cross(&(pc - pa)).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::y).into();
+                a.normal = normal;
+                b.normal = normal;
+                c.normal = normal;
+            }
+        }
+    }
+
+    /*
+    Vertices and indices of every triangle primitive of node and its children along with their material
+    The transforms of the nodes are applied to the vertices, parent is the one of the parent node
+    */
+    fn gltf_primitives<'d>(
+        node: &gltf::Node<'d>,
+        parent: &Matrix4<f32>,
+        buffers: &[gltf::buffer::Data],
+        primitives: &mut Vec<(Vec<Vertex>, Vec<u32>, gltf::Material<'d>)>
+    ) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            let linear = transform.fixed_view::<3, 3>(0, 0).into_owned();
+            // keeps the normals perpendicular to the surface under non uniform scale
+            let normal_matrix = linear.try_inverse().map(|v| v.transpose()).unwrap_or_else(Matrix3::identity);
+            // a mirroring transform turns the front faces around
+            let mirrored = linear.determinant() < 0.0;
+
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    warn!("Skipping {:?} primitive of mesh {}, only triangles are supported", primitive.mode(), mesh.index());
+                    continue;
+                }
+
+                let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|v| &v.0[..]));
+                let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                    Some(v) => v.collect(),
+                    None => {
+                        warn!("Skipping primitive of mesh {} without positions", mesh.index());
+                        continue;
+                    }
+                };
+                let mut indices: Vec<u32> = match reader.read_indices() {
+                    Some(v) => v.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect()
+                };
+                if indices.iter().any(|&i| i as usize >= positions.len()) {
+                    warn!("Skipping primitive of mesh {} with out of range indices", mesh.index());
+                    continue;
+                }
+
+                let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|v| v.collect());
+                let colors: Option<Vec<[f32; 3]>> = reader.read_colors(0).map(|v| v.into_rgb_f32().collect());
+                let tex_coords: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|v| v.into_f32().collect());
+                let tex_coords2: Option<Vec<[f32; 2]>> = reader.read_tex_coords(1).map(|v| v.into_f32().collect());
+
+                let mut vertices: Vec<Vertex> = positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, position)| {
+                        let normal = match normals.as_ref().and_then(|v| v.get(i)) {
+                            Some(n) => (normal_matrix * Vector3::from(*n)).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::y).into(),
+                            None => [0.0, 1.0, 0.0]
+                        };
+                        // v already points down like in our textures, unlike in obj files
+                        let tex_coord = tex_coords.as_ref().and_then(|v| v.get(i)).copied().unwrap_or_default();
+                        Vertex {
+                            position: transform.transform_point(&Point3::from(*position)).coords.into(),
+                            normal,
+                            color: colors.as_ref().and_then(|v| v.get(i)).copied().unwrap_or([1.0; 3]),
+                            tex_coord,
+                            // lightmapped models carry a second set, otherwise the first one is reused like for obj
+                            tex_coord2: tex_coords2.as_ref().and_then(|v| v.get(i)).copied().unwrap_or(tex_coord)
+                        }
+                    })
+                    .collect();
+
+                if mirrored {
+                    for face in indices.chunks_exact_mut(3) {
+                        face.swap(1, 2);
+                    }
+                }
+
+                // glTF asks for flat normals when there are none
+                if normals.is_none() {
+                    vertices = indices.iter().map(|&i| vertices[i as usize]).collect();
+                    indices = (0..vertices.len() as u32).collect();
+                    Vulkan::set_face_normals(&mut vertices);
+                }
+
+                primitives.push((vertices, indices, primitive.material()));
+            }
+        }
+
+        for child in node.children() {
+            Vulkan::gltf_primitives(&child, &transform, buffers, primitives);
+        }
+    }
+
+    /*
+    Pixels of a glTF image as 8 bit RGBA, grayscale is spread to all color channels
+    Wider channels are cut down, float channels are clamped to [0, 1]
+    */
+    fn gltf_rgba8(image: &gltf::image::Data) -> Vec<u8> {
+        use gltf::image::Format as ImageFormat;
+
+        // channels per pixel and bytes per channel
+        let (channels, width) = match image.format {
+            ImageFormat::R8 => (1, 1),
+            ImageFormat::R8G8 => (2, 1),
+            ImageFormat::R8G8B8 => (3, 1),
+            ImageFormat::R8G8B8A8 => (4, 1),
+            ImageFormat::R16 => (1, 2),
+            ImageFormat::R16G16 => (2, 2),
+            ImageFormat::R16G16B16 => (3, 2),
+            ImageFormat::R16G16B16A16 => (4, 2),
+            ImageFormat::R32G32B32FLOAT => (3, 4),
+            ImageFormat::R32G32B32A32FLOAT => (4, 4)
+        };
+
+        // the bytes of wider channels are in native order
+        let channel = |bytes: &[u8]| -> u8 {
+            match bytes {
+                [v] => *v,
+                [a, b] => (u16::from_ne_bytes([*a, *b]) >> 8) as u8,
+                [a, b, c, d] => (f32::from_ne_bytes([*a, *b, *c, *d]).clamp(0.0, 1.0) * 255.0).round() as u8,
+                _ => 0
+            }
+        };
+
+        image.pixels
+            .chunks_exact(channels * width)
+            .flat_map(|pixel| {
+                let mut c = [0u8; 4];
+                for (i, bytes) in pixel.chunks_exact(width).enumerate() {
+                    c[i] = channel(bytes);
+                }
+                match channels {
+                    1 => [c[0], c[0], c[0], 255],
+                    2 => [c[0], c[0], c[0], c[1]],
+                    3 => [c[0], c[1], c[2], 255],
+                    _ => c
+                }
+            })
+            .collect()
     }
 
     pub fn create_vertex_buffers(&self, vertices: Vec<Vertex>, indices: Vec<u32>) -> (
@@ -520,13 +2456,103 @@ impl Vulkan {
         return (vertex_buffer, index_buffer);
     }
 
+    /*
+    The whole model with resources/<model_name> as the texture, the materials of the obj are ignored
+    See create_renderables for models with textures referenced by their mtl
+    */
     pub fn create_renderable(&self, model_name: &str, pipeline_name: Option<String>) -> Result<Renderable, String> {
+        self.create_renderable_with_normals(model_name, pipeline_name, false)
+    }
+
+    /*
+    Same as create_renderable, optionally with flat per face normals, see load_model_with_normals
+    */
+    pub fn create_renderable_with_normals(&self, model_name: &str, pipeline_name: Option<String>, flat_normals: bool) -> Result<Renderable, String> {
         let model_path = format!("resources/{}.obj", model_name);
-        let texture_path = format!("resources/{}.png", model_name);
-        let (vertices, indices) = self.load_model(&model_path);
-        let (texture, image_upload) = self.load_image(&texture_path);
+        let texture_file = texture_path(model_name);
+        let (vertices, indices) = self.load_model_with_normals(&model_path, flat_normals);
+        let (texture, image_upload) = self.load_image(&texture_file)?;
+        let upload = self.track_upload(image_upload);
         
-        self.internal_create_renderable(&vertices, &indices, &texture, pipeline_name)
+        self.internal_create_renderable(&vertices, &indices, texture, None, Some(upload), pipeline_name)
+    }
+
+    /*
+    One renderable per material of the model, textured with the diffuse texture from its mtl
+    Parts without one fall back to resources/<model_name> like create_renderable
+    Each texture is only loaded once, even if several materials use it
+    */
+    pub fn create_renderables(&self, model_name: &str, pipeline_name: Option<String>) -> Result<Vec<Renderable>, String> {
+        let model_path = format!("resources/{}.obj", model_name);
+        let parts = self.load_model_parts(&model_path, false)?;
+
+        let mut textures: HashMap<String, (Arc<ImageView<ImmutableImage>>, UploadHandle)> = HashMap::new();
+        let mut renderables = Vec::with_capacity(parts.len());
+        for part in parts {
+            let texture_file = part.texture.unwrap_or_else(|| texture_path(model_name));
+            let (texture, upload) = match textures.get(&texture_file) {
+                Some(v) => v.clone(),
+                None => {
+                    let (texture, image_upload) = self.load_image(&texture_file)?;
+                    let loaded = (texture, self.track_upload(image_upload));
+                    textures.insert(texture_file, loaded.clone());
+                    loaded
+                }
+            };
+
+            renderables.push(self.internal_create_renderable(&part.vertex_buffer, &part.index_buffer, texture, None, Some(upload), pipeline_name.clone())?);
+        }
+
+        Ok(renderables)
+    }
+
+    /*
+    Same as create_renderable, but only the smallest mip levels of the texture are uploaded,
+    the engine streams in the rest as the active camera gets closer, see TextureStreaming
+    Add the StreamedTexture to the entity along with the renderable
+    */
+    pub fn create_streamed_renderable(&self, model_name: &str, pipeline_name: Option<String>) -> Result<(Renderable, StreamedTexture), String> {
+        let model_path = format!("resources/{}.obj", model_name);
+        let texture_file = decodable_texture_path(model_name);
+        let (vertices, indices) = self.load_model(&model_path);
+        let mips = MipChain::load(&texture_file)?;
+        let level = mips.min_level();
+        let (texture, image_upload) = self.upload_mip_chain(&mips, level).map_err(|e| format!("Failed to upload {}: {}", texture_file, e))?;
+        let upload = self.track_upload(image_upload);
+
+        let renderable = self.internal_create_renderable(&vertices, &indices, texture, None, Some(upload), pipeline_name)?;
+        Ok((renderable, StreamedTexture::new(Arc::new(mips), level)))
+    }
+
+    /*
+    Same as create_renderable with a texture decoded beforehand, e.g. by MipChain::load while preloading
+    */
+    pub fn create_renderable_from_mips(&self, model_name: &str, mips: &MipChain, pipeline_name: Option<String>) -> Result<Renderable, String> {
+        let model_path = format!("resources/{}.obj", model_name);
+        let (vertices, indices) = self.load_model(&model_path);
+        let (texture, image_upload) = self.upload_mip_chain(mips, 0)?;
+        let upload = self.track_upload(image_upload);
+
+        self.internal_create_renderable(&vertices, &indices, texture, None, Some(upload), pipeline_name)
+    }
+
+    /*
+    Copy of renderable sampling the levels of mips from level on, sharing its vertex buffers
+    It may only be drawn once the returned future has finished
+    */
+    pub fn create_streamed_level(&self, renderable: &Renderable, mips: &MipChain, level: u32) -> Result<(Renderable, Box<dyn GpuFuture>), String> {
+        let (texture, image_upload) = self.upload_mip_chain(mips, level)?;
+
+        let descriptor_set_texture = match PersistentDescriptorSet::new(
+            &self.descriptor_set_allocator,
+            renderable.descriptor_set_texture.layout().clone(),
+            [WriteDescriptorSet::image_view_sampler(0, texture, self.sampler.clone())]
+        ) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to create the descriptor set of mip level {}: {:?}", level, e))
+        };
+
+        Ok((Renderable { descriptor_set_texture, ..renderable.clone() }, image_upload))
     }
 
     pub fn create_renderable_from_vertices(
@@ -536,20 +2562,232 @@ impl Vulkan {
         texture_name: &str,
         pipeline_name: Option<String>
     ) -> Result<Renderable, String> {
-        let texture_path = format!("resources/{}.png", texture_name);
+        let texture_file = texture_path(texture_name);
+        let (vertices, indices) = self.create_vertex_buffers(vertices, indices);
+        let (texture, image_upload) = self.load_image(&texture_file)?;
+        let upload = self.track_upload(image_upload);
+
+        self.internal_create_renderable(&vertices, &indices, texture, None, Some(upload), pipeline_name)
+    }
+
+    /*
+    Same as create_renderable_from_vertices, the lightmap is sampled with the second uv set (tex_coord2)
+    and multiplied with the texture
+    */
+    pub fn create_renderable_with_lightmap(
+        &self, 
+        vertices: Vec<Vertex>, 
+        indices: Vec<u32>, 
+        texture_name: &str,
+        lightmap_name: &str,
+        pipeline_name: Option<String>
+    ) -> Result<Renderable, String> {
+        let (vertices, indices) = self.create_vertex_buffers(vertices, indices);
+        let (texture, image_upload) = self.load_image(&texture_path(texture_name))?;
+        let (lightmap, lightmap_upload) = self.load_image(&texture_path(lightmap_name))?;
+        // tracked as one so the renderable waits for both
+        let upload = self.track_upload(image_upload.join(lightmap_upload).boxed());
+
+        self.internal_create_renderable(&vertices, &indices, texture, Some(&lightmap), Some(upload), pipeline_name)
+    }
+
+    /*
+    Same as create_renderable_from_vertices with a texture that is already on the gpu,
+    e.g. OffscreenTarget::texture for a screen showing what a RenderTarget sees
+    */
+    pub fn create_renderable_from_texture(
+        &self,
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        texture: Arc<dyn ImageViewAbstract>,
+        pipeline_name: Option<String>
+    ) -> Result<Renderable, String> {
         let (vertices, indices) = self.create_vertex_buffers(vertices, indices);
-        let (texture, image_upload) = self.load_image(&texture_path);
-        // TODO: save image_upload to an array and periodically check if they are finished
-        // Should also probably check that the upload has finished before using it
+        self.internal_create_renderable(&vertices, &indices, texture, None, None, pipeline_name)
+    }
+
+    /*
+    Copy of renderable sampling texture instead, sharing its vertex buffers
+    e.g. after the OffscreenTarget it showed was resized, a lightmap of the renderable is dropped
+    */
+    pub fn replace_renderable_texture(&self, renderable: &Renderable, texture: Arc<dyn ImageViewAbstract>) -> Result<Renderable, String> {
+        let layout = renderable.descriptor_set_texture.layout().clone();
+        let mut writes = vec![WriteDescriptorSet::image_view_sampler(0, texture, self.sampler.clone())];
+        if layout.bindings().contains_key(&1) {
+            writes.push(WriteDescriptorSet::image_view_sampler(1, self.white_texture.clone(), self.sampler.clone()));
+        }
+
+        let descriptor_set_texture = PersistentDescriptorSet::new(&self.descriptor_set_allocator, layout, writes)
+            .map_err(|e| format!("Failed to create the descriptor set of the new texture: {:?}", e))?;
+        Ok(Renderable { descriptor_set_texture, ..renderable.clone() })
+    }
+
+    /*
+    One renderable per triangle primitive in the default scene of resources/<model_name>.gltf or .glb,
+    each with the PbrMaterial to add to its entity, the transforms of the nodes are applied to the vertices
+    Needs the "pbr" pipeline, the renderables themselves are created for the default one
+    Every texture is loaded once, even if several materials use it
+    */
+    pub fn create_gltf_renderables(&self, model_name: &str) -> Result<Vec<(Renderable, PbrMaterial)>, String> {
+        let path = gltf_path(model_name);
+        let (document, buffers, images) = match gltf::import(&path) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Failed to load model {}: {}", path, e))
+        };
+
+        let layout_material = match self.pipelines.get("pbr").and_then(|v| v.layout().set_layouts().get(1)) {
+            Some(v) => v.clone(),
+            None => return Err("No pipeline called 'pbr' exists".into())
+        };
+
+        let scene = match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(v) => v,
+            None => return Err(format!("Model {} has no scene", path))
+        };
+        let mut primitives = Vec::new();
+        for node in scene.nodes() {
+            Vulkan::gltf_primitives(&node, &Matrix4::identity(), &buffers, &mut primitives);
+        }
+
+        // bound in place of the textures a material doesn't have
+        let (white, white_upload) = Vulkan::create_texture(
+            &self.buffer_memory_allocator,
+            &self.command_buffer_allocator,
+            &self.queue,
+            vec![255; 4],
+            1,
+            1,
+            Format::R8G8B8A8_SRGB
+        );
+        let mut textures = HashMap::new();
+        let mut uploads = vec![white_upload];
+        // material index -> its base color texture and descriptor set, None is the default material
+        let mut materials: HashMap<Option<usize>, (Arc<ImageView<ImmutableImage>>, Arc<PersistentDescriptorSet>)> = HashMap::new();
+        let mut renderables = Vec::with_capacity(primitives.len());
+        for (vertices, indices, material) in primitives {
+            let (base_color, descriptor_set) = match materials.get(&material.index()) {
+                Some(v) => v.clone(),
+                None => {
+                    let pbr = material.pbr_metallic_roughness();
+                    let base_color = self.gltf_texture(pbr.base_color_texture(), true, &images, &white, &mut textures, &mut uploads)?;
+                    let metallic_roughness = self.gltf_texture(pbr.metallic_roughness_texture(), false, &images, &white, &mut textures, &mut uploads)?;
+                    let normal = material.normal_texture();
+                    let normal_scale = normal.as_ref().map_or(0.0, |v| v.scale());
+                    let normal = self.gltf_texture(normal, false, &images, &white, &mut textures, &mut uploads)?;
+
+                    let alpha_cutoff = match material.alpha_mode() {
+                        gltf::material::AlphaMode::Opaque => 0.0,
+                        gltf::material::AlphaMode::Mask => material.alpha_cutoff().unwrap_or(0.5),
+                        gltf::material::AlphaMode::Blend => {
+                            warn!("Material {:?} of model {} is blended, drawing it opaque", material.index(), path);
+                            0.0
+                        }
+                    };
+                    let factors = MaterialUniformBufferObject {
+                        base_color_factor: pbr.base_color_factor(),
+                        factors: [pbr.metallic_factor(), pbr.roughness_factor(), normal_scale, alpha_cutoff]
+                    };
+                    let factors = match CpuAccessibleBuffer::from_data(
+                        &self.buffer_memory_allocator,
+                        BufferUsage {
+                            uniform_buffer: true,
+                            ..Default::default()
+                        },
+                        false,
+                        factors
+                    ) {
+                        Ok(v) => v,
+                        Err(e) => return Err(format!("Failed to allocate material {:?} of model {}: {:?}", material.index(), path, e))
+                    };
+
+                    let descriptor_set = match PersistentDescriptorSet::new(
+                        &self.descriptor_set_allocator,
+                        layout_material.clone(),
+                        [
+                            WriteDescriptorSet::image_view_sampler(0, base_color.clone(), self.sampler.clone()),
+                            WriteDescriptorSet::image_view_sampler(1, metallic_roughness, self.sampler.clone()),
+                            WriteDescriptorSet::image_view_sampler(2, normal, self.sampler.clone()),
+                            WriteDescriptorSet::buffer(3, factors)
+                        ]
+                    ) {
+                        Ok(v) => v,
+                        Err(e) => return Err(format!("Failed to create the descriptor set of material {:?} of model {}: {:?}", material.index(), path, e))
+                    };
+
+                    materials.insert(material.index(), (base_color.clone(), descriptor_set.clone()));
+                    (base_color, descriptor_set)
+                }
+            };
+
+            let (vertex_buffer, index_buffer) = self.create_vertex_buffers(vertices, indices);
+            let renderable = self.internal_create_renderable(&vertex_buffer, &index_buffer, base_color.clone(), None, None, None)?;
+            renderables.push((renderable, PbrMaterial { descriptor_set }));
+        }
+
+        // textures are shared between the primitives, so every renderable waits for all of them
+        if let Some(upload) = uploads.into_iter().reduce(|a, b| a.join(b).boxed()) {
+            let upload = self.track_upload(upload);
+            for (renderable, _) in renderables.iter_mut() {
+                renderable.upload = Some(upload.clone());
+            }
+        }
+        Ok(renderables)
+    }
+
+    /*
+    Texture of a glTF material, white if it has none
+    Base colors are srgb and everything else linear, so an image used as both is created twice
+    loaded is keyed by image index and srgb, the uploads of new textures are added to uploads
+    */
+    fn gltf_texture<'d, T: AsRef<gltf::texture::Texture<'d>>>(
+        &self,
+        info: Option<T>,
+        srgb: bool,
+        images: &[gltf::image::Data],
+        white: &Arc<ImageView<ImmutableImage>>,
+        loaded: &mut HashMap<(usize, bool), Arc<ImageView<ImmutableImage>>>,
+        uploads: &mut Vec<Box<dyn GpuFuture>>
+    ) -> Result<Arc<ImageView<ImmutableImage>>, String> {
+        let texture = match &info {
+            Some(v) => v.as_ref(),
+            None => return Ok(white.clone())
+        };
+
+        let index = texture.source().index();
+        if let Some(v) = loaded.get(&(index, srgb)) {
+            return Ok(v.clone());
+        }
+
+        let image = match images.get(index) {
+            Some(v) => v,
+            None => return Err(format!("Texture {} refers to missing image {}", texture.index(), index))
+        };
+        let format = match srgb {
+            true => Format::R8G8B8A8_SRGB,
+            false => Format::R8G8B8A8_UNORM
+        };
+        let (view, upload) = Vulkan::create_texture(
+            &self.buffer_memory_allocator,
+            &self.command_buffer_allocator,
+            &self.queue,
+            Vulkan::gltf_rgba8(image),
+            image.width,
+            image.height,
+            format
+        );
 
-        self.internal_create_renderable(&vertices, &indices, &texture, pipeline_name)
+        uploads.push(upload);
+        loaded.insert((index, srgb), view.clone());
+        Ok(view)
     }
 
     fn internal_create_renderable(
         &self, 
         vertices: &Arc<CpuAccessibleBuffer<[Vertex]>>, 
         indices: &Arc<CpuAccessibleBuffer<[u32]>>, 
-        texture: &Arc<ImageView<ImmutableImage>>,
+        texture: Arc<dyn ImageViewAbstract>,
+        lightmap: Option<&Arc<ImageView<ImmutableImage>>>,
+        upload: Option<UploadHandle>,
         pipeline_name: Option<String>
     ) -> Result<Renderable, String> {
         let pipeline_name = match pipeline_name {
@@ -565,14 +2803,101 @@ impl Vulkan {
         };
 
         let layout_texture = pipeline.layout().set_layouts().get(1).unwrap();
+        let mut writes = vec![WriteDescriptorSet::image_view_sampler(0, texture, self.sampler.clone())];
+        // Custom pipelines don't have to sample a lightmap
+        if layout_texture.bindings().contains_key(&1) {
+            let lightmap = lightmap.unwrap_or(&self.white_texture);
+            writes.push(WriteDescriptorSet::image_view_sampler(1, lightmap.clone(), self.sampler.clone()));
+        }
+        else if lightmap.is_some() {
+            warn!("Pipeline '{}' doesn't sample a lightmap, ignoring it", pipeline_name);
+        }
+
         let descriptor_set_texture = PersistentDescriptorSet::new(
             &self.descriptor_set_allocator,
             layout_texture.clone(),
-            [WriteDescriptorSet::image_view_sampler(0, texture.clone(), self.sampler.clone())]
+            writes
         ).unwrap();
 
-        Ok(Renderable { vertex_buffer: vertices.clone(), index_buffer: indices.clone(), descriptor_set_texture })
+        Ok(Renderable { vertex_buffer: vertices.clone(), index_buffer: indices.clone(), descriptor_set_texture, upload })
     } 
     
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Binary glTF with a single buffer holding bin
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize((json.len() + 3) / 4 * 4, b' ');
+        let mut bin = bin.to_vec();
+        bin.resize((bin.len() + 3) / 4 * 4, 0);
+
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        bytes.extend((json.len() as u32).to_le_bytes());
+        bytes.extend(b"JSON");
+        bytes.extend(json);
+        bytes.extend((bin.len() as u32).to_le_bytes());
+        bytes.extend(b"BIN\0");
+        bytes.extend(bin);
+        bytes
+    }
+
+    // Counter clockwise triangle facing +z without normals or indices, under a node mirroring x
+    fn mirrored_triangle() -> Vec<u8> {
+        let positions: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let bin: Vec<u8> = positions.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "scale": [-1.0, 1.0, 1.0], "children": [1] }, { "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "buffers": [{ "byteLength": 36 }]
+        }"#;
+        glb(json, &bin)
+    }
+
+    #[test]
+    fn gltf_nodes_are_applied() {
+        let (document, buffers, _) = gltf::import_slice(mirrored_triangle()).unwrap();
+        let mut primitives = Vec::new();
+        for node in document.default_scene().unwrap().nodes() {
+            Vulkan::gltf_primitives(&node, &Matrix4::identity(), &buffers, &mut primitives);
+        }
+
+        assert_eq!(primitives.len(), 1);
+        let (vertices, indices, material) = &primitives[0];
+        assert_eq!(material.index(), None);
+        assert_eq!(indices, &vec![0, 1, 2]);
+
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.position).collect();
+        // mirrored and turned around, so it still faces +z
+        assert_eq!(positions, vec![[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]]);
+        for vertex in vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+            assert_eq!(vertex.color, [1.0; 3]);
+        }
+    }
+
+    #[test]
+    fn gltf_images_become_rgba8() {
+        let image = |format, pixels| gltf::image::Data { pixels, format, width: 1, height: 1 };
+
+        let luma_alpha = image(gltf::image::Format::R8G8, vec![10, 20]);
+        assert_eq!(Vulkan::gltf_rgba8(&luma_alpha), vec![10, 10, 10, 20]);
+
+        let rgb16 = image(gltf::image::Format::R16G16B16, [0x1234u16, 0xff00, 0x00ff].iter().flat_map(|v| v.to_ne_bytes()).collect());
+        assert_eq!(Vulkan::gltf_rgba8(&rgb16), vec![0x12, 0xff, 0x00, 255]);
+
+        let rgba32 = image(gltf::image::Format::R32G32B32A32FLOAT, [2.0f32, -1.0, 0.5, 1.0].iter().flat_map(|v| v.to_ne_bytes()).collect());
+        assert_eq!(Vulkan::gltf_rgba8(&rgba32), vec![255, 0, 128, 255]);
+    }
+}
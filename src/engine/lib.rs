@@ -15,60 +15,164 @@ pub mod ecs;
 mod graphics;
 mod physics;
 mod network;
+mod preload;
 mod shaders;
 
 use ecs::ECS;
-use ecs::resources::{ProjectionMatrix, RenderData, CommandBuffer, RenderDataFrameBuffer, CursorGrab, DeltaTime};
-use ecs::systems::general::PlayerInput;
-use ecs::systems::physics::Physics;
-use ecs::systems::render::Render;
-use graphics::vulkan::Vulkan;
-use log::{info, trace};
-use nalgebra::Perspective3;
-use shaders::default::vs::ty::VPUniformBufferObject;
-use specs::{WorldExt, DispatcherBuilder, Dispatcher};
+use ecs::components::general::{Transform, Renderable, StreamedTexture, RenderTarget};
+use ecs::resources::audio::{SoundQueue, SoundPlayer};
+use ecs::resources::input::Gamepads;
+use ecs::resources::network::{ConnectionState, ConnectionCallbacks, StateHistory};
+use ecs::resources::physics::{CollisionEvents, PhysicsDebugRender};
+use ecs::resources::{ProjectionMatrix, RenderData, CommandBuffer, RenderDataFrameBuffer, RenderDataShadowMap, Shadows, TextureStreaming, LoadingScreen, ActiveCamera, CursorGrab, ActiveProfile, DeltaTime, MaxDeltaTime, UnscaledDeltaTime, InputEvents, InputEventQueue, RandomSource, TimeScale, AmbientLight, FramePacing, FrameStats, FramePacer, DrawCallBudget, ClearColor, ClearDepth, SwapchainPresentMode, AntiAliasing, RenderScale, PipelineOverride, RenderDataPostProcess, PostProcessFrame, ProjectionKind, Cameras, events::Events};
+use ecs::systems::audio::{CollisionSounds, PlaySounds};
+use ecs::systems::general::{PlayerInput, FollowCamera, FaceMovementDirection, SpriteAnimator, Lifetimes, UpdateScheduler, HealthRegeneration};
+use ecs::systems::network::checksum::StateHashSender;
+use ecs::systems::network::connection::ConnectionMonitor;
+use ecs::systems::network::health::HealthSender;
+use ecs::systems::network::history::StateHistoryRecorder;
+use ecs::systems::network::lockstep::LockstepSync;
+use ecs::systems::network::receiver::ReplicationReceiver;
+use ecs::systems::network::snapshot::{SnapshotSender, SnapshotRequests};
+use ecs::resources::lockstep::Lockstep;
+use ecs::systems::physics::{Physics, Respawn, Attractors};
+use ecs::systems::render::{Render, UpdateProjection};
+use ecs::systems::role::{Role, RoleDispatcherBuilder};
+use graphics::occlusion::proxy_box;
+use graphics::streaming::{desired_level, fit_to_budget};
+use graphics::utils::{get_window_from_surface, supported_resolutions, set_window_resolution, screen_to_world_ray};
+use graphics::vulkan::{Vulkan, ShadowMap, LoadingScreenPass, PostProcess, OffscreenTarget, PIPELINE_CACHE_PATH};
+use gilrs::Gilrs;
+use log::{info, trace, warn, error};
+use preload::PreloadQueue;
+use shaders::default::{vs::ty::VPUniformBufferObject, fs::ty::{ShadowUniformBufferObject, LightsUniformBufferObject}};
+use shaders::loading::fs::ty::LoadingPushConstants;
+use specs::{World, WorldExt, Dispatcher, Entity, Join, ReaderId, storage::ComponentEvent, world::Index};
 use vulkano::buffer::CpuBufferPool;
-use vulkano::pipeline::graphics::rasterization::{RasterizationState, PolygonMode};
+use vulkano::pipeline::graphics::rasterization::{RasterizationState, PolygonMode, DepthBias, DepthBiasState, CullMode, FrontFace};
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::pipeline::StateMode;
 use vulkano::pipeline::{GraphicsPipeline};
 use vulkano::pipeline::graphics::viewport::{Viewport};
+use vulkano::pipeline::graphics::depth_stencil::{DepthStencilState, DepthState, CompareOp};
 use vulkano::shader;
-use vulkano::swapchain::{Swapchain, SwapchainCreateInfo, Surface, SwapchainCreationError, acquire_next_image, AcquireError, SwapchainPresentInfo};
+use vulkano::format::Format;
+use vulkano::swapchain::{Swapchain, SwapchainCreateInfo, Surface, SwapchainCreationError, ColorSpace, acquire_next_image, AcquireError, SwapchainPresentInfo, PresentMode};
 use vulkano::sync::{self, GpuFuture, FenceSignalFuture};
 use vulkano::sync::FlushError;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
 use winit_input_helper::WinitInputHelper;
 
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Instant;
+use winit::event::Event;
 use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::Window;
+use winit::dpi::PhysicalSize;
+use nalgebra::{Point3, Vector3, Unit};
 use vulkano::device::{
     Device, 
     Queue, DeviceExtensions,
 };
-use vulkano::image::{SwapchainImage};
+use vulkano::image::{SwapchainImage, ImageUsage, SampleCount};
 use vulkano::render_pass::{RenderPass, Framebuffer};
 
+// Decoded on other threads while preloading, see HawkEngine::preload
+pub use graphics::streaming::MipChain;
+
+// Min and max corner of the progress bar of the loading screen in uv, from the top left
+const LOADING_BAR_RECT: [f32; 4] = [0.2, 0.85, 0.8, 0.87];
+
 #[cfg(all(debug_assertions))]
 const ENABLE_VALIDATION_LAYERS: bool = true;
 #[cfg(not(debug_assertions))]
 const ENABLE_VALIDATION_LAYERS: bool = false;
 
+// Formats tried in order when creating the swapchain
+// sRGB so that the output is gamma corrected without any extra work in the shaders
+const PREFERRED_SWAPCHAIN_FORMATS: &[Format] = &[Format::B8G8R8A8_SRGB, Format::R8G8B8A8_SRGB];
+
+// Fifo is always supported, see Vulkan::select_present_mode for picking
+// another mode, the SwapchainPresentMode resource switches at runtime
+const PREFERRED_PRESENT_MODES: &[PresentMode] = &[PresentMode::Fifo];
+
+// Name of the dispatcher running the physics systems, profiles have to include it for physics to run
+pub const PHYSICS_DISPATCHER: &str = "physics";
+
+// transfer_src so swapchain images can be copied out, e.g. for screenshots
+// storage would allow compute post-processing, but is rarely supported with sRGB formats
+const SWAPCHAIN_IMAGE_USAGE: ImageUsage = ImageUsage {
+    color_attachment: true,
+    transfer_src: true,
+    ..ImageUsage::empty()
+};
+
+// Lowered to the highest count the device supports, HawkEngine::set_msaa_samples changes it
+const MSAA_SAMPLES: SampleCount = SampleCount::Sample1;
+
+// Wireframe and debug lines are hard to make out against textured surfaces at 1px
+const DEBUG_LINE_WIDTH: f32 = 2.0;
+
 pub struct HawkEngine<'a> {
     device: Arc<Device>,
     queue: Arc<Queue>,
     render_pass: Arc<RenderPass>,
     framebuffers: Vec<Arc<Framebuffer>>,
+    msaa_samples: SampleCount,
+    fxaa: bool,
+    render_scale: RenderScale,
+    // only while FXAA is on or the render scale is below 1, the scene is then rendered into its targets
+    post_process: Option<PostProcess>,
     pipeline: Arc<GraphicsPipeline>,
-    pipeline_wireframe: Arc<GraphicsPipeline>,
+    pipeline_wireframe: Option<Arc<GraphicsPipeline>>,
+    pipeline_pbr: Arc<GraphicsPipeline>,
+    pipeline_double_sided: Arc<GraphicsPipeline>,
+    pipeline_pbr_double_sided: Arc<GraphicsPipeline>,
+    pipeline_no_depth: Arc<GraphicsPipeline>,
+    pipeline_depth_bias: Arc<GraphicsPipeline>,
+    pipeline_wireframe_overlay: Option<Arc<GraphicsPipeline>>,
+    pipeline_transparent: Arc<GraphicsPipeline>,
+    pipeline_occlusion: Arc<GraphicsPipeline>,
+    pipeline_minimap: Arc<GraphicsPipeline>,
+    pipeline_text: Arc<GraphicsPipeline>,
+    pipeline_unlit: Arc<GraphicsPipeline>,
+    pipeline_skybox: Arc<GraphicsPipeline>,
+    pipeline_debug_lines: Arc<GraphicsPipeline>,
+    pipeline_override: Option<String>,
     surface: Arc<Surface>,
     swapchain: Arc<Swapchain>,
     images: Vec<Arc<SwapchainImage>>,
     ubo_pool: Arc<CpuBufferPool<VPUniformBufferObject>>,
+    shadow_ubo_pool: Arc<CpuBufferPool<ShadowUniformBufferObject>>,
+    lights_ubo_pool: Arc<CpuBufferPool<LightsUniformBufferObject>>,
+    shadows: Shadows,
+    // a single 1x1 layer while shadows are off
+    shadow_map: ShadowMap,
+    // mip levels of streamed textures being uploaded, swapped in once their fence is signaled
+    streaming_uploads: Vec<(Entity, Renderable, FenceSignalFuture<Box<dyn GpuFuture>>)>,
+    // insertions and removals of RenderTarget, with the target id of each entity,
+    // since a removed component can't be looked at anymore
+    render_target_events: ReaderId<ComponentEvent>,
+    render_target_ids: HashMap<Index, u32>,
 
     pub vulkan: Vulkan,
 
     pub ecs: ECS,
-    dispatchers: Vec<Dispatcher<'a,'a>>,
-    event_loop: EventLoop<()>
+    role: Role,
+    // named dispatchers can be switched on and off with profiles
+    dispatchers: Vec<(Option<String>, Dispatcher<'a,'a>)>,
+    // profile name -> names of the dispatchers it runs
+    profiles: HashMap<String, HashSet<String>>,
+    // started with preload, finished on the main thread at the start of a frame
+    preload_jobs: PreloadQueue<'a, HawkEngine<'a>>,
+    // drawn instead of the game until the preload jobs started before the engine are done
+    loading_screen: Option<LoadingScreenPass>,
+    // Events::update_in of every type registered with add_event
+    event_updates: Vec<(TypeId, fn(&World))>,
+    // taken when the engine starts
+    event_loop: Option<EventLoop<()>>
 }
 
 impl<'a> HawkEngine<'a> {
@@ -76,20 +180,56 @@ impl<'a> HawkEngine<'a> {
     If use_physics is true, PhysicsData is expected to be provided as a resource
     */
     pub fn new(use_physics: bool) -> Self {
+        HawkEngine::new_with_role(use_physics, Role::Both)
+    }
+
+    /*
+    Same as new, but only registers the internal systems meant for the given role
+    e.g. a server does not run input or rendering
+    */
+    pub fn new_with_role(use_physics: bool, role: Role) -> Self {
         match pretty_env_logger::try_init() {
             Ok(_) => {},
             Err(e) => trace!("Failed to init pretty_env_logger, probably already initialized: {:?}", e)
         }
 
         // Create ECS classes
-        let ecs = ECS::new();
+        let mut ecs = ECS::new();
+        ecs.world.insert(ActiveProfile(None));
+        let render_target_events = ecs.world.write_storage::<RenderTarget>().register_reader();
 
-        let mut dbuilder = DispatcherBuilder::new();
+        let mut dispatchers = Vec::new();
 
+        // Named so that profiles can switch physics off, e.g. in an editor
+        // Runs after the dispatchers of the game and before the internal one
         if use_physics {
-            dbuilder.add(Physics::default(), "physics", &[]);
+            let physics_dispatcher = RoleDispatcherBuilder::new(role)
+                .with(Physics::default(), "physics", &[], Role::Both)
+                .with(Respawn, "respawn", &["physics"], Role::Server)
+                .with(Attractors, "attractors", &["physics"], Role::Both)
+                .with(CollisionSounds, "collision_sounds", &["physics"], Role::Client)
+                .build();
+            dispatchers.push((Some(PHYSICS_DISPATCHER.to_string()), physics_dispatcher));
         }
 
+        let mut dbuilder = RoleDispatcherBuilder::new(role);
+
+        // the systems of the other dispatchers have queued their sounds already
+        dbuilder.add(PlaySounds, "play_sounds", &[], Role::Client);
+        dbuilder.add(SpriteAnimator, "sprite_animator", &[], Role::Client);
+        dbuilder.add(Lifetimes, "lifetimes", &[], Role::Both);
+        dbuilder.add(UpdateScheduler, "update_scheduler", &[], Role::Both);
+        dbuilder.add(HealthRegeneration, "health_regeneration", &[], Role::Server);
+        dbuilder.add(HealthSender::default(), "health_sender", &["health_regeneration"], Role::Server);
+        dbuilder.add(ConnectionMonitor, "connection_monitor", &[], Role::Both);
+        // lockstep peers exchange inputs both ways, so it runs on servers too
+        dbuilder.add(ReplicationReceiver::default(), "replication_receiver", &[], Role::Both);
+        // physics ran already in its own dispatcher, so the state hash covers the whole tick
+        dbuilder.add(LockstepSync, "lockstep_sync", &["replication_receiver"], Role::Both);
+        dbuilder.add(StateHistoryRecorder, "state_history_recorder", &[], Role::Server);
+        dbuilder.add(StateHashSender::default(), "state_hash_sender", &[], Role::Server);
+        dbuilder.add(SnapshotSender::default(), "snapshot_sender", &[], Role::Server);
+
         let dispatcher = dbuilder
             // Using thread_local for player input for a couple of reasons
             // 1. it's probably a good idea to have the camera view be updated 
@@ -100,10 +240,14 @@ impl<'a> HawkEngine<'a> {
             //    (this works on macos probably because macos is really particular about
             //     threading for UI operations and the winit team has taken this into
             //     account probably for macos only)
-            .with_thread_local(PlayerInput)
-            .with_thread_local(Render)
+            .with_thread_local(PlayerInput, Role::Client)
+            .with_thread_local(FollowCamera, Role::Client)
+            .with_thread_local(FaceMovementDirection, Role::Client)
+            .with_thread_local(UpdateProjection::default(), Role::Client)
+            .with_thread_local(Render::default(), Role::Client)
             .build();
-        let dispatchers = vec![dispatcher];
+        // iterated in reverse, so the internal dispatcher runs last
+        dispatchers.insert(0, (None, dispatcher));
 
         let device_extensions = DeviceExtensions {
             khr_swapchain: true,
@@ -118,31 +262,1014 @@ impl<'a> HawkEngine<'a> {
 
         let mut vulkan = Vulkan::new(&device, &queue);
 
-        // Default
-        let vs = shaders::default::vs::load(device.clone()).expect("Failed to load default vs");
-        let fs = shaders::default::fs::load(device.clone()).expect("Failed to load default fs");
-        // Wireframe
-        let vsw = shaders::wireframe::vs::load(device.clone()).expect("Failed to load wireframe vs");
-        let fsw = shaders::wireframe::fs::load(device.clone()).expect("Failed to load wireframe fs");
-
-        let (swapchain, images) = vulkan.create_swapchain(&physical, &surface);
-        let render_pass = vulkan.create_render_pass(&swapchain);
+        let (swapchain, images) = vulkan.create_swapchain(&physical, &surface, PREFERRED_SWAPCHAIN_FORMATS, PREFERRED_PRESENT_MODES, SWAPCHAIN_IMAGE_USAGE);
+        let msaa_samples = Vulkan::select_sample_count(&physical, MSAA_SAMPLES);
+        let render_pass = vulkan.create_render_pass(&swapchain, msaa_samples);
         let framebuffers= vulkan.create_framebuffers(&render_pass, &images);
-        let pipeline = vulkan.create_pipeline("default", &render_pass, &surface, &vs, &fs, None, None);
-        let rasterization_state = RasterizationState { polygon_mode: PolygonMode::Line, ..Default::default() };
-        let pipeline_wireframe = vulkan.create_pipeline("wireframe", &render_pass, &surface, &vsw, &fsw, None, Some(&rasterization_state));
+        let pipelines = create_pipelines(&mut vulkan, &device, &render_pass).expect("Failed to create the pipelines");
+        let shadow_map = vulkan.create_shadow_map(1, 1).expect("Failed to create the shadow map");
         let ubo_pool = vulkan.create_view_ubo_pool();
-        return Self { device, queue, render_pass, framebuffers, pipeline, pipeline_wireframe, surface, swapchain, images, ubo_pool, vulkan, ecs, dispatchers, event_loop };
+        let shadow_ubo_pool = vulkan.create_shadow_ubo_pool();
+        let lights_ubo_pool = vulkan.create_lights_ubo_pool();
+        let shadows = Shadows::default();
+        return Self {
+            device,
+            queue,
+            render_pass,
+            framebuffers,
+            msaa_samples,
+            fxaa: false,
+            render_scale: RenderScale::default(),
+            post_process: None,
+            pipeline: pipelines.default,
+            pipeline_wireframe: pipelines.wireframe,
+            pipeline_pbr: pipelines.pbr,
+            pipeline_double_sided: pipelines.double_sided,
+            pipeline_pbr_double_sided: pipelines.pbr_double_sided,
+            pipeline_no_depth: pipelines.no_depth,
+            pipeline_depth_bias: pipelines.depth_bias,
+            pipeline_wireframe_overlay: pipelines.wireframe_overlay,
+            pipeline_transparent: pipelines.transparent,
+            pipeline_occlusion: pipelines.occlusion,
+            pipeline_minimap: pipelines.minimap,
+            pipeline_text: pipelines.text,
+            pipeline_unlit: pipelines.unlit,
+            pipeline_skybox: pipelines.skybox,
+            pipeline_debug_lines: pipelines.debug_lines,
+            pipeline_override: None,
+            surface,
+            swapchain,
+            images,
+            ubo_pool,
+            shadow_ubo_pool,
+            lights_ubo_pool,
+            shadows,
+            shadow_map,
+            streaming_uploads: Vec::new(),
+            render_target_events,
+            render_target_ids: HashMap::new(),
+            vulkan,
+            ecs,
+            role,
+            dispatchers,
+            profiles: HashMap::new(),
+            preload_jobs: PreloadQueue::default(),
+            loading_screen: None,
+            event_updates: Vec::new(),
+            event_loop: Some(event_loop)
+        };
     }
 
     pub fn add_dispatcher(&mut self, dispatcher: Dispatcher<'a, 'a>) {
-        self.dispatchers.push(dispatcher);
+        self.dispatchers.push((None, dispatcher));
+    }
+
+    /*
+    Adds a dispatcher which only runs while the active profile includes it,
+    or while no profile is active
+    */
+    pub fn add_named_dispatcher(&mut self, name: &str, dispatcher: Dispatcher<'a, 'a>) {
+        self.dispatchers.push((Some(name.to_string()), dispatcher));
+    }
+
+    /*
+    Defines a profile running the given named dispatchers, replacing any profile with the same name
+    Dispatchers added with add_dispatcher, as well as the internal one, always run
+    Physics only runs if the profile includes PHYSICS_DISPATCHER
+    */
+    pub fn define_profile(&mut self, name: &str, dispatchers: &[&str]) {
+        self.profiles.insert(name.to_string(), dispatchers.iter().map(|d| d.to_string()).collect());
+    }
+
+    /*
+    Switches to the profile from the next frame on, None runs every dispatcher
+    Systems can switch by writing the ActiveProfile resource instead
+    */
+    pub fn activate_profile(&mut self, name: Option<&str>) -> bool {
+        if let Some(name) = name {
+            if !self.profiles.contains_key(name) {
+                warn!("Tried to activate unknown dispatcher profile {name}");
+                return false;
+            }
+        }
+
+        self.ecs.world.insert(ActiveProfile(name.map(|n| n.to_string())));
+        true
+    }
+
+    /*
+    Creates a dispatcher builder which only registers systems meant for the role of this engine
+    */
+    pub fn dispatcher_builder(&self) -> RoleDispatcherBuilder<'a, 'a> {
+        RoleDispatcherBuilder::new(self.role)
+    }
+
+    /*
+    Inserts an empty Events<T> resource unless one exists and updates it at the end of every frame,
+    after all dispatchers ran
+    */
+    pub fn add_event<T: Send + Sync + 'static>(&mut self) {
+        if !self.ecs.world.has_value::<Events<T>>() {
+            self.ecs.world.insert(Events::<T>::default());
+        }
+
+        let type_id = TypeId::of::<T>();
+        if !self.event_updates.iter().any(|(t, _)| *t == type_id) {
+            self.event_updates.push((type_id, Events::<T>::update_in));
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /*
+    Pixel format of the swapchain images, needed for interpreting
+    the raw bytes of a swapchain image correctly (e.g. BGRA vs RGBA)
+    */
+    pub fn swapchain_format(&self) -> Format {
+        self.swapchain.image_format()
+    }
+
+    pub fn swapchain_color_space(&self) -> ColorSpace {
+        self.swapchain.image_color_space()
+    }
+
+    /*
+    What the swapchain images can be used for, may be less than requested
+    if the surface doesn't support everything
+    */
+    pub fn swapchain_image_usage(&self) -> ImageUsage {
+        self.swapchain.image_usage()
+    }
+
+    pub fn msaa_samples(&self) -> SampleCount {
+        self.msaa_samples
+    }
+
+    /*
+    Rebuilds the render pass, framebuffers and pipelines with the given MSAA sample count
+    Falls back to the highest count supported below it, returns the count actually used
+    */
+    pub fn set_msaa_samples(&mut self, samples: SampleCount) -> SampleCount {
+        let samples = Vulkan::select_sample_count(self.device.physical_device(), samples);
+        if samples == self.msaa_samples {
+            return samples;
+        }
+
+        let render_pass = self.vulkan.create_render_pass(&self.swapchain, samples);
+        let pipelines = match create_pipelines(&mut self.vulkan, &self.device, &render_pass) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to create the pipelines for {:?} MSAA, keeping {:?}: {}", samples, self.msaa_samples, e);
+                return self.msaa_samples;
+            }
+        };
+
+        self.framebuffers = self.scene_framebuffers(&render_pass);
+        self.render_pass = render_pass;
+        self.set_pipelines(pipelines);
+        self.msaa_samples = samples;
+        samples
+    }
+
+    /*
+    The method currently in use, see set_anti_aliasing
+    */
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        match (self.fxaa, self.msaa_samples) {
+            (true, _) => AntiAliasing::Fxaa,
+            (false, SampleCount::Sample1) => AntiAliasing::Off,
+            (false, samples) => AntiAliasing::Msaa(samples)
+        }
+    }
+
+    /*
+    Switches between MSAA, FXAA and neither, the sample count is applied with set_msaa_samples
+    Returns the method actually used, e.g. Off if the device doesn't support multisampling
+    Games can also write the AntiAliasing resource while running
+    */
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) -> AntiAliasing {
+        let (samples, fxaa) = match anti_aliasing {
+            AntiAliasing::Off => (SampleCount::Sample1, false),
+            AntiAliasing::Msaa(samples) => (samples, false),
+            AntiAliasing::Fxaa => (SampleCount::Sample1, true)
+        };
+
+        self.set_msaa_samples(samples);
+        if fxaa != self.fxaa {
+            self.fxaa = fxaa;
+            self.rebuild_post_process();
+        }
+        self.anti_aliasing()
+    }
+
+    pub fn render_scale(&self) -> RenderScale {
+        self.render_scale
+    }
+
+    /*
+    Renders the scene at a fraction of the window resolution and upscales it, see RenderScale
+    Returns the scale actually used, clamped and back at 1 if the upscaling pass couldn't be created
+    Games can also write the RenderScale resource while running
+    */
+    pub fn set_render_scale(&mut self, render_scale: RenderScale) -> RenderScale {
+        let render_scale = render_scale.clamped();
+        if render_scale != self.render_scale {
+            self.render_scale = render_scale;
+            self.rebuild_post_process();
+        }
+        self.render_scale
+    }
+
+    /*
+    Recreates the engine pipelines, which loads their shaders again
+    With the external-shaders feature, edited SPIR-V files are picked up without restarting,
+    the engine does this by itself when a file in the shader directory changes
+    If a shader fails to load or doesn't fit its pipeline, the current pipelines are kept
+    */
+    pub fn reload_shaders(&mut self) {
+        match create_pipelines(&mut self.vulkan, &self.device, &self.render_pass) {
+            Ok(v) => {
+                self.set_pipelines(v);
+                info!("Reloaded shaders");
+            },
+            Err(e) => error!("Failed to reload shaders, keeping the previous pipelines: {}", e)
+        }
+    }
+
+    /*
+    Enables per sample shading under MSAA for the engine pipelines, see Vulkan::set_sample_shading
+    Returns false and keeps the current setting if the device doesn't support it
+    or the pipelines couldn't be recreated with it
+    */
+    pub fn set_sample_shading(&mut self, min_sample_shading: Option<f32>) -> bool {
+        let previous = self.vulkan.sample_shading();
+        if !self.vulkan.set_sample_shading(min_sample_shading) {
+            return false;
+        }
+
+        match create_pipelines(&mut self.vulkan, &self.device, &self.render_pass) {
+            Ok(v) => {
+                self.set_pipelines(v);
+                true
+            },
+            Err(e) => {
+                error!("Failed to recreate the pipelines with sample shading {:?}, keeping {:?}: {}", min_sample_shading, previous, e);
+                self.vulkan.set_sample_shading(previous);
+                false
+            }
+        }
+    }
+
+    /*
+    Clamps instead of clipping depth for the engine pipelines, see Vulkan::set_depth_clamp
+    Returns false and keeps the current setting if the device doesn't support it
+    or the pipelines couldn't be recreated with it
+    */
+    pub fn set_depth_clamp(&mut self, enabled: bool) -> bool {
+        let previous = self.vulkan.depth_clamp();
+        if !self.vulkan.set_depth_clamp(enabled) {
+            return false;
+        }
+
+        match create_pipelines(&mut self.vulkan, &self.device, &self.render_pass) {
+            Ok(v) => {
+                self.set_pipelines(v);
+                true
+            },
+            Err(e) => {
+                error!("Failed to recreate the pipelines with depth clamp {}, keeping {}: {}", enabled, previous, e);
+                self.vulkan.set_depth_clamp(previous);
+                false
+            }
+        }
+    }
+
+    /*
+    Framebuffers of render_pass for the swapchain images, with a post process pass they render into its targets instead
+    */
+    fn scene_framebuffers(&self, render_pass: &Arc<RenderPass>) -> Vec<Arc<Framebuffer>> {
+        match &self.post_process {
+            Some(v) => self.vulkan.create_framebuffers(render_pass, &v.targets),
+            None => self.vulkan.create_framebuffers(render_pass, &self.images)
+        }
+    }
+
+    /*
+    Recreates the framebuffers and the post process pass, which depend on the size of the swapchain images, for new images
+    */
+    fn set_images(&mut self, images: Vec<Arc<SwapchainImage>>) {
+        self.images = images;
+        self.rebuild_post_process();
+    }
+
+    /*
+    Creates the post process pass for the current images, FXAA and render scale, or drops it if neither needs it
+    If it can't be created both are turned off, so the scene is rendered straight onto the images again
+    */
+    fn rebuild_post_process(&mut self) {
+        self.post_process = match self.fxaa || self.render_scale.is_scaled() {
+            true => match self.vulkan.create_post_process(&self.swapchain, &self.images, &self.swapchain_viewport(), self.render_scale, self.fxaa) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    error!("Failed to create the post process pass, turning off FXAA and the render scale: {}", e);
+                    self.fxaa = false;
+                    self.render_scale = RenderScale { scale: 1.0, ..self.render_scale };
+                    None
+                }
+            },
+            false => None
+        };
+        self.framebuffers = self.scene_framebuffers(&self.render_pass);
+    }
+
+    /*
+    The post process pass for the swapchain image image_i, None unless FXAA is on or the render scale is below 1
+    */
+    fn post_process_frame(&self, image_i: usize) -> Option<PostProcessFrame> {
+        self.post_process.as_ref().map(|p| PostProcessFrame {
+            framebuffer: p.framebuffers[image_i].clone(),
+            pipeline: p.pipeline.clone(),
+            descriptor_set: p.descriptor_sets[image_i].clone()
+        })
+    }
+
+    /*
+    Also hands them to the render system, which draws with the pipelines from RenderData
+    */
+    fn set_pipelines(&mut self, pipelines: Pipelines) {
+        self.pipeline = pipelines.default;
+        self.pipeline_wireframe = pipelines.wireframe;
+        self.pipeline_pbr = pipelines.pbr;
+        self.pipeline_double_sided = pipelines.double_sided;
+        self.pipeline_pbr_double_sided = pipelines.pbr_double_sided;
+        self.pipeline_no_depth = pipelines.no_depth;
+        self.pipeline_depth_bias = pipelines.depth_bias;
+        self.pipeline_wireframe_overlay = pipelines.wireframe_overlay;
+        self.pipeline_transparent = pipelines.transparent;
+        self.pipeline_occlusion = pipelines.occlusion;
+        self.pipeline_minimap = pipelines.minimap;
+        self.pipeline_text = pipelines.text;
+        self.pipeline_unlit = pipelines.unlit;
+        self.pipeline_skybox = pipelines.skybox;
+        self.pipeline_debug_lines = pipelines.debug_lines;
+        let pipeline_override = self.pipeline_override_data();
+
+        if let Some(mut render_data) = self.ecs.world.try_fetch_mut::<RenderData>() {
+            render_data.pipeline = self.pipeline.clone();
+            render_data.pipeline_wireframe = self.pipeline_wireframe.clone();
+            render_data.pipeline_pbr = self.pipeline_pbr.clone();
+            render_data.pipeline_double_sided = self.pipeline_double_sided.clone();
+            render_data.pipeline_pbr_double_sided = self.pipeline_pbr_double_sided.clone();
+            render_data.pipeline_no_depth = self.pipeline_no_depth.clone();
+            render_data.pipeline_depth_bias = self.pipeline_depth_bias.clone();
+            render_data.pipeline_wireframe_overlay = self.pipeline_wireframe_overlay.clone();
+            render_data.pipeline_transparent = self.pipeline_transparent.clone();
+            render_data.pipeline_occlusion = self.pipeline_occlusion.clone();
+            render_data.pipeline_minimap = self.pipeline_minimap.clone();
+            render_data.pipeline_text = self.pipeline_text.clone();
+            render_data.pipeline_override = pipeline_override;
+            render_data.pipeline_skybox = self.pipeline_skybox.clone();
+            render_data.pipeline_debug_lines = self.pipeline_debug_lines.clone();
+        }
+
+        // Render targets draw with the same shaders and settings
+        let mut render_targets = self.ecs.world.write_storage::<RenderTarget>();
+        for render_target in (&mut render_targets).join() {
+            if let Err(e) = self.vulkan.rebuild_offscreen_pipelines(&mut render_target.target) {
+                error!("Failed to recreate the pipelines of a render target, keeping the previous ones: {}", e);
+            }
+        }
+    }
+
+    /*
+    Texture of width x height for a RenderTarget, see Vulkan::create_offscreen_target
+    Its pipelines are recreated along with the engine ones, e.g. when the shaders are reloaded
+    */
+    pub fn create_offscreen_target(&mut self, width: u32, height: u32) -> Result<OffscreenTarget, String> {
+        self.vulkan.create_offscreen_target(width, height)
+    }
+
+    /*
+    Resizes the RenderTarget of entity and gives its displays the new texture
+    Systems can change RenderTarget::size instead, the engine applies it before the next frame
+    */
+    pub fn resize_render_target(&mut self, entity: Entity, width: u32, height: u32) -> Result<(), String> {
+        match self.ecs.world.write_storage::<RenderTarget>().get_mut(entity) {
+            Some(v) => v.size = [width, height],
+            None => return Err("The entity has no RenderTarget".into())
+        }
+        self.apply_render_target_size(entity)
+    }
+
+    /*
+    Drops the pipelines of render targets removed during the last frame, or replaced by another target
+    */
+    fn release_render_targets(&mut self) {
+        let entities = self.ecs.world.entities();
+        let render_targets = self.ecs.world.read_storage::<RenderTarget>();
+        for event in render_targets.channel().read(&mut self.render_target_events) {
+            let (index, id) = match event {
+                ComponentEvent::Inserted(index) | ComponentEvent::Modified(index) => {
+                    match render_targets.get(entities.entity(*index)) {
+                        Some(v) => (*index, Some(v.target.id)),
+                        // removed again before it was ever rendered, the Removed event follows
+                        None => (*index, None)
+                    }
+                },
+                ComponentEvent::Removed(index) => (*index, None)
+            };
+
+            let previous = match id {
+                Some(id) => self.render_target_ids.insert(index, id),
+                None => self.render_target_ids.remove(&index)
+            };
+            if let Some(previous) = previous.filter(|p| Some(*p) != id) {
+                self.vulkan.destroy_offscreen_target(previous);
+            }
+        }
+    }
+
+    /*
+    Applies the sizes systems changed during the last frame
+    */
+    fn resize_render_targets(&mut self) {
+        let resized = {
+            let entities = self.ecs.world.entities();
+            let render_targets = self.ecs.world.read_storage::<RenderTarget>();
+            (&entities, &render_targets).join()
+                .filter(|(_, v)| v.size != v.target.size)
+                .map(|(e, _)| e)
+                .collect::<Vec<_>>()
+        };
+
+        for entity in resized {
+            if let Err(e) = self.apply_render_target_size(entity) {
+                error!("Failed to resize a render target: {}", e);
+            }
+        }
+    }
+
+    /*
+    The size is set to the one actually used, which is clamped to what the device supports
+    or left as it was if the target couldn't be resized
+    */
+    fn apply_render_target_size(&mut self, entity: Entity) -> Result<(), String> {
+        let mut render_targets = self.ecs.world.write_storage::<RenderTarget>();
+        let render_target = match render_targets.get_mut(entity) {
+            Some(v) => v,
+            None => return Err("The entity has no RenderTarget".into())
+        };
+
+        let [width, height] = render_target.size;
+        let resized = self.vulkan.resize_offscreen_target(&mut render_target.target, width, height);
+        render_target.size = render_target.target.size;
+        resized?;
+
+        let texture = render_target.target.texture();
+        let mut renderables = self.ecs.world.write_storage::<Renderable>();
+        for display in &render_target.displays {
+            if let Some(renderable) = renderables.get_mut(*display) {
+                *renderable = self.vulkan.replace_renderable_texture(renderable, texture.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    Draws every renderable with the named pipeline instead of the one it would normally use,
+    e.g. "unlit" to look at the scene without lighting, None goes back to the normal pipelines
+    Names are default, double_sided, unlit, no_depth and wireframe, the latter only if the device can draw lines
+    Returns false and keeps the current override if there is no pipeline with the name
+    Games can also write the PipelineOverride resource while running
+    */
+    pub fn set_pipeline_override(&mut self, name: Option<&str>) -> bool {
+        if let Some(name) = name {
+            if self.pipeline_by_name(name).is_none() {
+                warn!("No pipeline named {} to override with", name);
+                return false;
+            }
+        }
+
+        self.pipeline_override = name.map(String::from);
+        let pipeline_override = self.pipeline_override_data();
+        if let Some(mut render_data) = self.ecs.world.try_fetch_mut::<RenderData>() {
+            render_data.pipeline_override = pipeline_override;
+        }
+        true
+    }
+
+    pub fn pipeline_override(&self) -> Option<&str> {
+        self.pipeline_override.as_deref()
+    }
+
+    fn pipeline_by_name(&self, name: &str) -> Option<Arc<GraphicsPipeline>> {
+        match name {
+            "default" => Some(self.pipeline.clone()),
+            "double_sided" => Some(self.pipeline_double_sided.clone()),
+            "unlit" => Some(self.pipeline_unlit.clone()),
+            "no_depth" => Some(self.pipeline_no_depth.clone()),
+            "wireframe" => self.pipeline_wireframe.clone(),
+            _ => None
+        }
+    }
+
+    /*
+    The override is kept by name, so it picks up the rebuilt pipeline after a reload
+    */
+    fn pipeline_override_data(&self) -> Option<Arc<GraphicsPipeline>> {
+        self.pipeline_override.as_deref().and_then(|v| self.pipeline_by_name(v))
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.swapchain.present_mode()
+    }
+
+    pub fn supported_present_modes(&self) -> Vec<PresentMode> {
+        Vulkan::supported_present_modes(self.device.physical_device(), &self.surface)
+    }
+
+    /*
+    Recreates the swapchain with the given present mode, e.g. FifoRelaxed for VRR displays
+    Returns false and keeps the current mode if the surface doesn't support it
+    Games can also write the SwapchainPresentMode resource while running
+    */
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) -> bool {
+        if !self.supported_present_modes().contains(&present_mode) {
+            warn!("Present mode {:?} is not supported by the surface", present_mode);
+            return false;
+        }
+
+        let (swapchain, images) = match self.swapchain.recreate(SwapchainCreateInfo {
+            present_mode,
+            ..self.swapchain.create_info()
+        }) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Failed to recreate the swapchain with present mode {:?}: {:?}", present_mode, e);
+                return false;
+            }
+        };
+
+        self.swapchain = swapchain;
+        self.set_images(images);
+
+        // its framebuffers point at the old swapchain images
+        if let Some(loading_screen) = self.loading_screen.take() {
+            let viewport = Viewport {
+                origin: [0.0, 0.0],
+                dimensions: self.swapchain.image_extent().map(|v| v as f32),
+                depth_range: 0.0..1.0,
+            };
+            match self.vulkan.create_loading_screen(&self.swapchain, &self.images, &viewport, loading_screen.background) {
+                Ok(v) => self.loading_screen = Some(v),
+                Err(e) => error!("Failed to recreate the loading screen, starting the game while loading: {}", e)
+            }
+        }
+        true
     }
+
+    /*
+    The winit window the engine renders to, owned by the surface
+    Systems can get it from the Arc<Surface> resource with get_window_from_surface
+    */
+    pub fn window(&self) -> Option<&Window> {
+        get_window_from_surface(&self.surface)
+    }
+
+    pub fn supported_resolutions(&self) -> Vec<PhysicalSize<u32>> {
+        match self.window() {
+            Some(v) => supported_resolutions(v),
+            None => vec![]
+        }
+    }
+
+    pub fn set_resolution(&self, size: PhysicalSize<u32>) {
+        if let Some(window) = self.window() {
+            set_window_resolution(window, size);
+        }
+    }
+
+    /*
+    World space ray through the given pixel as seen from the active camera,
+    e.g. for PhysicsData::cast_ray with the cursor position
+    Systems can do the same with graphics::utils::screen_to_world_ray
+    Returns None before the engine is started or without an active camera
+    */
+    pub fn screen_to_world_ray(&self, x: f32, y: f32) -> Option<(Point3<f32>, Unit<Vector3<f32>>)> {
+        let active_camera = self.ecs.world.try_fetch::<ActiveCamera>()?;
+        let projection = self.ecs.world.try_fetch::<ProjectionMatrix>()?;
+        let transforms = self.ecs.world.read_storage::<Transform>();
+        let view = transforms.get(active_camera.0)?.transformation_matrix().try_inverse()?;
+
+        let size = self.window()?.inner_size();
+        screen_to_world_ray(x, y, [size.width, size.height], &view, &projection.0)
+    }
+
+    pub fn shadows(&self) -> Shadows {
+        self.shadows
+    }
+
+    /*
+    Recreates the shadow map for the given cascades, see Shadows
+    Returns the clamped settings actually used, the current ones are kept if the map can't be created
+    Games can also write the Shadows resource while running
+    */
+    pub fn set_shadows(&mut self, shadows: Shadows) -> Shadows {
+        let shadows = shadows.clamped();
+        let (resolution, layers) = match shadows.enabled() {
+            true => (shadows.resolution, shadows.cascade_count),
+            false => (1, 1)
+        };
+
+        if resolution != self.shadow_map.resolution || layers as usize != self.shadow_map.framebuffers.len() {
+            match self.vulkan.create_shadow_map(resolution, layers) {
+                Ok(v) => self.shadow_map = v,
+                Err(e) => {
+                    error!("Failed to create the shadow map, keeping the previous one: {}", e);
+                    return self.shadows;
+                }
+            }
+
+            if self.ecs.world.has_value::<RenderDataShadowMap>() {
+                self.ecs.world.insert(RenderDataShadowMap(self.shadow_map.clone()));
+            }
+        }
+        // clamped to what the device supports
+        self.shadows = match shadows.enabled() {
+            true => Shadows { resolution: self.shadow_map.resolution, ..shadows },
+            false => shadows
+        };
+        self.shadows
+    }
+
+    /*
+    Runs load on another thread, finish then gets its result on the main thread at the start of a frame,
+    e.g. decoding files in load and creating renderables from them in finish, see MipChain::load
+    Jobs started before start_engine are loaded behind the loading screen, see LoadingScreen
+    */
+    pub fn preload<T, L, F>(&mut self, load: L, finish: F)
+    where
+        T: Send + 'static,
+        L: FnOnce() -> T + Send + 'static,
+        F: FnOnce(&mut HawkEngine<'a>, T) + 'a
+    {
+        self.preload_jobs.push(load, finish);
+    }
+
+    fn finish_preload_jobs(&mut self) {
+        for finish in self.preload_jobs.take_finished() {
+            finish(self);
+        }
+    }
+
+    fn swapchain_viewport(&self) -> Viewport {
+        let [width, height] = self.swapchain.image_extent();
+        Viewport { origin: [0.0, 0.0], dimensions: [width as f32, height as f32], depth_range: 0.0..1.0 }
+    }
+
+    /*
+    Shows the loading screen from the first frame on if anything is still loading or uploading
+    Without one, the preload jobs and uploads are waited for instead
+    */
+    fn show_loading_screen(&mut self) {
+        if self.preload_jobs.is_empty() && !self.vulkan.has_pending_uploads() {
+            return;
+        }
+
+        let background = self.ecs.world.read_resource::<LoadingScreen>().background.clone();
+        let background = match background.map(|name| self.vulkan.load_texture_now(&name)) {
+            Some(Ok(v)) => Some(v),
+            Some(Err(e)) => {
+                warn!("Failed to load the loading screen background, using only its color: {}", e);
+                None
+            },
+            None => None
+        };
+
+        match self.vulkan.create_loading_screen(&self.swapchain, &self.images, &self.swapchain_viewport(), background) {
+            Ok(v) => self.loading_screen = Some(v),
+            Err(e) => {
+                error!("Failed to create the loading screen, loading without it: {}", e);
+                while !self.preload_jobs.is_empty() {
+                    self.finish_preload_jobs();
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                self.vulkan.wait_for_uploads();
+            }
+        }
+    }
+
+    /*
+    Records the loading screen for image_i, once nothing is loading anymore it is dropped,
+    so the game runs from the next frame on
+    */
+    fn loading_screen_frame(&mut self, image_i: usize) -> Option<Arc<PrimaryAutoCommandBuffer>> {
+        let pass = self.loading_screen.as_ref()?;
+        let constants = {
+            let settings = self.ecs.world.read_resource::<LoadingScreen>();
+            LoadingPushConstants {
+                background_color: settings.background_color,
+                bar_color: settings.bar_color,
+                bar_rect: LOADING_BAR_RECT,
+                progress: self.preload_jobs.progress()
+            }
+        };
+
+        let command_buffer = match self.vulkan.draw_loading_screen(pass, image_i, constants) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        };
+
+        if self.preload_jobs.is_empty() && !self.vulkan.has_pending_uploads() {
+            info!("Finished loading");
+            self.loading_screen = None;
+        }
+        command_buffer
+    }
+
+    /*
+    Swaps in the streamed texture levels which finished uploading and uploads
+    the levels TextureStreaming asks for at the position of the active camera
+    */
+    fn stream_textures(&mut self) {
+        use specs::Join;
+
+        let settings = *self.ecs.world.read_resource::<TextureStreaming>();
+        let camera = match self.ecs.world.try_fetch::<ActiveCamera>() {
+            Some(v) => v.0,
+            None => return
+        };
+        let projection = self.ecs.world.read_resource::<ProjectionMatrix>().0;
+        let entities = self.ecs.world.entities();
+        let transforms = self.ecs.world.read_storage::<Transform>();
+        let mut renderables = self.ecs.world.write_storage::<Renderable>();
+        let mut streamed = self.ecs.world.write_storage::<StreamedTexture>();
+
+        let mut still_uploading = Vec::with_capacity(self.streaming_uploads.len());
+        for (e, renderable, fence) in self.streaming_uploads.drain(..) {
+            let uploaded = match fence.is_signaled() {
+                Ok(false) => {
+                    still_uploading.push((e, renderable, fence));
+                    continue;
+                },
+                Ok(true) => true,
+                Err(err) => {
+                    warn!("Failed to check the upload of a streamed texture of {:?}: {:?}", e, err);
+                    false
+                }
+            };
+
+            // the entity may have been deleted or lost its texture in the meantime
+            if let Some(texture) = streamed.get_mut(e) {
+                texture.finish(uploaded);
+                if uploaded {
+                    if let Err(err) = renderables.insert(e, renderable) {
+                        error!("Failed to swap in the streamed texture of {:?}: {:?}", e, err);
+                    }
+                }
+            }
+        }
+        self.streaming_uploads = still_uploading;
+
+        let (view_projection, camera_pos) = match transforms.get(camera) {
+            Some(t) => match t.transformation_matrix().try_inverse() {
+                Some(view) => (projection * view, t.pos()),
+                None => return
+            },
+            None => return
+        };
+
+        // Levels for the distance and visibility of each texture, the farthest coarsened to fit the budget
+        let mut levels: Vec<(Entity, u32, f32)> = {
+            let (targets, mut requests): (Vec<Entity>, Vec<(u32, f32, &MipChain)>) = (&entities, &transforms, &streamed).join()
+                .map(|(e, t, s)| {
+                    let distance = (t.pos() - camera_pos).norm();
+                    let clip = view_projection * t.pos().push(1.0);
+                    // only the origin of the mesh is known, so with a generous margin
+                    let on_screen = clip.w > 0.0 && clip.x.abs() <= clip.w * 1.5 && clip.y.abs() <= clip.w * 1.5;
+                    let visible = on_screen || distance <= settings.full_detail_distance;
+                    (e, (desired_level(distance, settings.full_detail_distance, visible, s.mips().min_level()), distance, s.mips()))
+                })
+                .unzip();
+            fit_to_budget(&mut requests, settings.budget_bytes);
+            targets.into_iter().zip(requests).map(|(e, (level, distance, _))| (e, level, distance)).collect()
+        };
+
+        // Closest first, they are the most noticeable
+        levels.sort_by(|a, b| a.2.total_cmp(&b.2));
+        let mut uploads = 0;
+        for (e, level, _) in levels {
+            if uploads >= settings.max_uploads_per_frame {
+                break;
+            }
+
+            let (renderable, texture) = match (renderables.get(e), streamed.get_mut(e)) {
+                (Some(r), Some(s)) => (r, s),
+                _ => continue
+            };
+            if texture.is_pending() || texture.resident_level() == level {
+                continue;
+            }
+
+            let upload = self.vulkan.create_streamed_level(renderable, texture.mips(), level)
+                .and_then(|(renderable, upload)| match upload.then_signal_fence_and_flush() {
+                    Ok(fence) => Ok((renderable, fence)),
+                    Err(err) => Err(format!("Failed to submit the upload: {:?}", err))
+                });
+            match upload {
+                Ok((renderable, fence)) => {
+                    texture.request(level);
+                    self.streaming_uploads.push((e, renderable, fence));
+                },
+                Err(err) => error!("Failed to stream mip level {} of {:?}: {}", level, e, err)
+            }
+            uploads += 1;
+        }
+    }
+}
+
+
+/// The pipelines drawing into the render pass of the window, they share its sample count,
+/// so they are always rebuilt together, their viewport is dynamic to render each camera of Cameras
+struct Pipelines {
+    // default and pbr cull back faces, the double sided ones are for the DoubleSided component
+    default: Arc<GraphicsPipeline>,
+    // None if the device doesn't support non solid fill modes
+    wireframe: Option<Arc<GraphicsPipeline>>,
+    pbr: Arc<GraphicsPipeline>,
+    double_sided: Arc<GraphicsPipeline>,
+    pbr_double_sided: Arc<GraphicsPipeline>,
+    no_depth: Arc<GraphicsPipeline>,
+    depth_bias: Arc<GraphicsPipeline>,
+    // None if the device doesn't support non solid fill modes
+    wireframe_overlay: Option<Arc<GraphicsPipeline>>,
+    transparent: Arc<GraphicsPipeline>,
+    occlusion: Arc<GraphicsPipeline>,
+    minimap: Arc<GraphicsPipeline>,
+    // WorldText labels, both sides are drawn so the winding of the quads doesn't matter
+    text: Arc<GraphicsPipeline>,
+    // default without lighting, only used as an override
+    unlit: Arc<GraphicsPipeline>,
+    skybox: Arc<GraphicsPipeline>,
+    // PhysicsDebugRender lines, doesn't need fill_mode_non_solid
+    debug_lines: Arc<GraphicsPipeline>
+}
+
+/*
+All or nothing, on error the pipelines of vulkan are left as they were, see Vulkan::rebuild_pipelines
+*/
+fn create_pipelines(vulkan: &mut Vulkan, device: &Arc<Device>, render_pass: &Arc<RenderPass>) -> Result<Pipelines, String> {
+    vulkan.rebuild_pipelines(|vulkan| build_pipelines(vulkan, device, render_pass))
+}
+
+fn build_pipelines(vulkan: &mut Vulkan, device: &Arc<Device>, render_pass: &Arc<RenderPass>) -> Result<Pipelines, String> {
+    // TODO: do not load these again every time
+    // Default
+    let vs = shaders::load(device, "default", "vs", shaders::default::vs::load)
+        .map_err(|e| format!("Failed to load default vs: {:?}", e))?;
+    let fs = shaders::load(device, "default", "fs", shaders::default::fs::load)
+        .map_err(|e| format!("Failed to load default fs: {:?}", e))?;
+    // Wireframe
+    let vsw = shaders::load(device, "wireframe", "vs", shaders::wireframe::vs::load)
+        .map_err(|e| format!("Failed to load wireframe vs: {:?}", e))?;
+    let fsw = shaders::load(device, "wireframe", "fs", shaders::wireframe::fs::load)
+        .map_err(|e| format!("Failed to load wireframe fs: {:?}", e))?;
+    // Pbr
+    let fsp = shaders::load(device, "pbr", "fs", shaders::pbr::fs::load)
+        .map_err(|e| format!("Failed to load pbr fs: {:?}", e))?;
+    // Text
+    let fst = shaders::load(device, "text", "fs", shaders::text::fs::load)
+        .map_err(|e| format!("Failed to load text fs: {:?}", e))?;
+    // Unlit
+    let fsu = shaders::load(device, "unlit", "fs", shaders::unlit::fs::load)
+        .map_err(|e| format!("Failed to load unlit fs: {:?}", e))?;
+
+    let default = vulkan.create_pipeline("default", render_pass, &vs, &fs, Some(&cull_rasterization_state(CullMode::Back)), None)?;
+    let wireframe = match device.enabled_features().fill_mode_non_solid {
+        true => Some(vulkan.create_pipeline("wireframe", render_pass, &vsw, &fsw, Some(&wireframe_rasterization_state(device)), None)?),
+        false => None
+    };
+    let pbr = vulkan.create_pipeline("pbr", render_pass, &vs, &fsp, Some(&cull_rasterization_state(CullMode::Back)), None)?;
+    let double_sided = vulkan.create_pipeline("double_sided", render_pass, &vs, &fs, Some(&cull_rasterization_state(CullMode::None)), None)?;
+    let pbr_double_sided = vulkan.create_pipeline("pbr_double_sided", render_pass, &vs, &fsp, Some(&cull_rasterization_state(CullMode::None)), None)?;
+    let no_depth = vulkan.create_pipeline("no_depth", render_pass, &vs, &fs, None, Some(&DepthStencilState::disabled()))?;
+    let depth_bias = vulkan.create_pipeline("depth_bias", render_pass, &vs, &fs, Some(&depth_bias_rasterization_state()), None)?;
+    let wireframe_overlay = match device.enabled_features().fill_mode_non_solid {
+        true => Some(vulkan.create_pipeline("wireframe_overlay", render_pass, &vsw, &fsw, Some(&overlay_rasterization_state(device)), None)?),
+        false => None
+    };
+    let transparent = vulkan.create_pipeline("transparent", render_pass, &vs, &fs, None, Some(&transparent_depth_stencil_state()))?;
+    let occlusion = vulkan.create_occlusion_pipeline(render_pass)?;
+    let minimap = vulkan.create_minimap_pipeline(render_pass)?;
+    let text = vulkan.create_pipeline("text", render_pass, &vs, &fst, None, None)?;
+    let unlit = vulkan.create_pipeline("unlit", render_pass, &vs, &fsu, None, None)?;
+    let skybox = vulkan.create_skybox_pipeline(render_pass)?;
+    let debug_lines = vulkan.create_pipeline_with_topology("debug_lines", render_pass, &vsw, &fsw, Some(&debug_lines_rasterization_state(device)), None, PrimitiveTopology::LineList)?;
+
+    Ok(Pipelines { default, wireframe, pbr, double_sided, pbr_double_sided, no_depth, depth_bias, wireframe_overlay, transparent, occlusion, minimap, text, unlit, skybox, debug_lines })
+}
+
+/*
+Tested against the solid geometry without hiding what is drawn behind it afterwards
+*/
+fn transparent_depth_stencil_state() -> DepthStencilState {
+    DepthStencilState {
+        depth: Some(DepthState {
+            enable_dynamic: false,
+            write_enable: StateMode::Fixed(false),
+            compare_op: StateMode::Fixed(CompareOp::Less)
+        }),
+        ..Default::default()
+    }
+}
+
+/*
+Width of wireframe and debug lines, clamped to what the device supports
+Falls back to 1px lines when wide_lines is not enabled
+*/
+fn debug_line_width(device: &Arc<Device>) -> f32 {
+    if !device.enabled_features().wide_lines {
+        return 1.0;
+    }
+
+    let [min, max] = device.physical_device().properties().line_width_range;
+    DEBUG_LINE_WIDTH.clamp(min, max)
 }
 
+fn wireframe_rasterization_state(device: &Arc<Device>) -> RasterizationState {
+    RasterizationState {
+        polygon_mode: PolygonMode::Line,
+        line_width: StateMode::Fixed(debug_line_width(device)),
+        ..Default::default()
+    }
+}
+
+/*
+Faces wound counter clockwise on screen are the front faces, the projection flips y
+so this matches the counter clockwise winding of obj files and the generated meshes
+*/
+fn cull_rasterization_state(cull_mode: CullMode) -> RasterizationState {
+    RasterizationState {
+        cull_mode: StateMode::Fixed(cull_mode),
+        front_face: StateMode::Fixed(FrontFace::CounterClockwise),
+        ..Default::default()
+    }
+}
+
+fn debug_lines_rasterization_state(device: &Arc<Device>) -> RasterizationState {
+    RasterizationState {
+        line_width: StateMode::Fixed(debug_line_width(device)),
+        ..Default::default()
+    }
+}
+
+/*
+Depth bias is dynamic so every entity can have its own
+*/
+fn depth_bias_rasterization_state() -> RasterizationState {
+    RasterizationState {
+        depth_bias: Some(DepthBiasState {
+            enable_dynamic: false,
+            bias: StateMode::Dynamic
+        }),
+        ..Default::default()
+    }
+}
+
+/*
+Wireframe pulled slightly towards the camera, so its lines win
+the depth test against the solid surface they are drawn on
+*/
+fn overlay_rasterization_state(device: &Arc<Device>) -> RasterizationState {
+    RasterizationState {
+        polygon_mode: PolygonMode::Line,
+        line_width: StateMode::Fixed(debug_line_width(device)),
+        depth_bias: Some(DepthBiasState {
+            enable_dynamic: false,
+            bias: StateMode::Fixed(DepthBias { constant_factor: -1.0, clamp: 0.0, slope_factor: -1.0 })
+        }),
+        ..Default::default()
+    }
+}
 
 pub fn start_engine(mut engine: HawkEngine<'static>) {
     let mut input = WinitInputHelper::new();
+    let mut input_events = InputEventQueue::default();
+    let mut gilrs = match Gilrs::new() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("Gamepads are not available: {}", e);
+            None
+        }
+    };
+    let mut frame_pacer = FramePacer::default();
+    #[cfg(feature = "external-shaders")]
+    let mut shader_watcher = shaders::external::ShaderWatcher::new();
 
     let frames_in_flight = engine.images.len();
     let mut fences: Vec<Option<Arc<FenceSignalFuture<_>>>> = vec![None; frames_in_flight];
@@ -151,42 +1278,181 @@ pub fn start_engine(mut engine: HawkEngine<'static>) {
     let mut destroying = false;
     let mut recreate_swapchain = false;
 
-    let mut proj = Perspective3::new(
-        engine.swapchain.image_extent()[0] as f32 / engine.swapchain.image_extent()[1] as f32,
-        (45.0 as f32).to_radians(),
-        0.1,
-        1000.0,
-    ).to_homogeneous();
-    // convert from OpenGL to Vulkan coordinates
-    proj[(1, 1)] *= -1.0;
+    if !engine.ecs.world.has_value::<ProjectionKind>() {
+        engine.ecs.world.insert(ProjectionKind::default());
+    }
+    // Kept up to date by the UpdateProjection system afterwards
+    let proj = engine.ecs.world.read_resource::<ProjectionKind>().matrix(
+        engine.swapchain.image_extent()[0] as f32 / engine.swapchain.image_extent()[1] as f32
+    );
     
     // Add initial input
     engine.ecs.world.insert(Arc::new(input.clone()));
     // Add initial surface
     engine.ecs.world.insert(engine.surface.clone());
+    engine.ecs.world.insert(InputEvents::default());
+    engine.ecs.world.insert(match &gilrs {
+        Some(v) => Gamepads::from_connected(v),
+        None => Gamepads::default()
+    });
     // Add initial cursor grab
     engine.ecs.world.insert(CursorGrab { 0: false });
     // Add projection matrix
     engine.ecs.world.insert(ProjectionMatrix(proj));
     // Add initial render data
+    let text_font = match engine.vulkan.create_text_font() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            error!("{}, WorldText labels won't be drawn", e);
+            None
+        }
+    };
     engine.ecs.world.insert(RenderData {
         pipeline: engine.pipeline.clone(),
         pipeline_wireframe: engine.pipeline_wireframe.clone(),
+        pipeline_pbr: engine.pipeline_pbr.clone(),
+        pipeline_double_sided: engine.pipeline_double_sided.clone(),
+        pipeline_pbr_double_sided: engine.pipeline_pbr_double_sided.clone(),
+        pipeline_no_depth: engine.pipeline_no_depth.clone(),
+        pipeline_depth_bias: engine.pipeline_depth_bias.clone(),
+        pipeline_wireframe_overlay: engine.pipeline_wireframe_overlay.clone(),
+        pipeline_transparent: engine.pipeline_transparent.clone(),
+        pipeline_override: engine.pipeline_override_data(),
+        pipeline_occlusion: engine.pipeline_occlusion.clone(),
+        occlusion_proxy: {
+            let (vertices, indices) = proxy_box();
+            engine.vulkan.create_vertex_buffers(vertices, indices)
+        },
+        pipeline_minimap: engine.pipeline_minimap.clone(),
+        pipeline_text: engine.pipeline_text.clone(),
+        pipeline_skybox: engine.pipeline_skybox.clone(),
+        text_font,
+        pipeline_debug_lines: engine.pipeline_debug_lines.clone(),
+        sampler: engine.vulkan.sampler(),
+        vertex_pool: engine.vulkan.create_vertex_pool(),
         ubo_pool: engine.ubo_pool.clone(),
+        shadow_ubo_pool: engine.shadow_ubo_pool.clone(),
+        lights_ubo_pool: engine.lights_ubo_pool.clone(),
         command_buffer_allocator: engine.vulkan.command_buffer_allocator.clone(),
         descriptor_set_allocator: engine.vulkan.descriptor_set_allocator.clone(),
+        memory_allocator: engine.vulkan.memory_allocator(),
         queue_family_index: engine.vulkan.queue.queue_family_index()
     });
     engine.ecs.world.insert(RenderDataFrameBuffer(engine.framebuffers[0].clone()));
+    engine.ecs.world.insert(RenderDataShadowMap(engine.shadow_map.clone()));
+    if !engine.ecs.world.has_value::<TextureStreaming>() {
+        engine.ecs.world.insert(TextureStreaming::default());
+    }
+    // Game might have configured shadows already
+    let shadows = match engine.ecs.world.try_fetch::<Shadows>() {
+        Some(v) => *v,
+        None => Shadows::default()
+    };
+    let shadows = engine.set_shadows(shadows);
+    engine.ecs.world.insert(shadows);
     // Add empty command buffer
     engine.ecs.world.insert(CommandBuffer { command_buffer: None });
+    // Add empty collision events
+    engine.ecs.world.insert(CollisionEvents::default());
+    // Add empty sound queue
+    engine.ecs.world.insert(SoundQueue::default());
+    if !engine.ecs.world.has_value::<SoundPlayer>() {
+        engine.ecs.world.insert(SoundPlayer::default());
+    }
     // Add 0 delta time
     engine.ecs.world.insert(DeltaTime(0.0));
+    engine.ecs.world.insert(UnscaledDeltaTime(0.0));
+    // Game might have already configured this
+    if !engine.ecs.world.has_value::<MaxDeltaTime>() {
+        engine.ecs.world.insert(MaxDeltaTime::default());
+    }
+    if !engine.ecs.world.has_value::<ClearColor>() {
+        engine.ecs.world.insert(ClearColor::default());
+    }
+    if !engine.ecs.world.has_value::<AmbientLight>() {
+        engine.ecs.world.insert(AmbientLight::default());
+    }
+    if !engine.ecs.world.has_value::<ClearDepth>() {
+        engine.ecs.world.insert(ClearDepth::default());
+    }
+    if !engine.ecs.world.has_value::<TimeScale>() {
+        engine.ecs.world.insert(TimeScale::default());
+    }
+    if !engine.ecs.world.has_value::<FramePacing>() {
+        engine.ecs.world.insert(FramePacing::default());
+    }
+    if !engine.ecs.world.has_value::<PhysicsDebugRender>() {
+        engine.ecs.world.insert(PhysicsDebugRender::default());
+    }
+    if !engine.ecs.world.has_value::<DrawCallBudget>() {
+        engine.ecs.world.insert(DrawCallBudget::default());
+    }
+    if !engine.ecs.world.has_value::<Cameras>() {
+        engine.ecs.world.insert(Cameras::default());
+    }
+    engine.ecs.world.insert(FrameStats::default());
+    if let Some(present_mode) = engine.ecs.world.remove::<SwapchainPresentMode>() {
+        if present_mode.0 != engine.present_mode() {
+            engine.set_present_mode(present_mode.0);
+        }
+    }
+    engine.ecs.world.insert(SwapchainPresentMode(engine.present_mode()));
+    // Game might have picked a method already, otherwise the one set on the engine is kept
+    if let Some(anti_aliasing) = engine.ecs.world.remove::<AntiAliasing>() {
+        engine.set_anti_aliasing(anti_aliasing);
+    }
+    engine.ecs.world.insert(engine.anti_aliasing());
+    if let Some(render_scale) = engine.ecs.world.remove::<RenderScale>() {
+        engine.set_render_scale(render_scale);
+    }
+    engine.ecs.world.insert(engine.render_scale());
+    if let Some(pipeline_override) = engine.ecs.world.remove::<PipelineOverride>() {
+        engine.set_pipeline_override(pipeline_override.0.as_deref());
+    }
+    let pipeline_override = PipelineOverride(engine.pipeline_override().map(String::from));
+    engine.ecs.world.insert(pipeline_override);
+    engine.ecs.world.insert(RenderDataPostProcess::default());
+    // Game might have registered callbacks already
+    if !engine.ecs.world.has_value::<ConnectionCallbacks>() {
+        engine.ecs.world.insert(ConnectionCallbacks::default());
+    }
+    engine.ecs.world.insert(ConnectionState::default());
+    // Game might want a longer history
+    if !engine.ecs.world.has_value::<StateHistory>() {
+        engine.ecs.world.insert(StateHistory::default());
+    }
+    // Game might have queued clients already
+    if !engine.ecs.world.has_value::<SnapshotRequests>() {
+        engine.ecs.world.insert(SnapshotRequests::default());
+    }
+    if !engine.ecs.world.has_value::<RandomSource>() {
+        engine.ecs.world.insert(RandomSource::from_time());
+    }
+    // Logging the seed so a run can be reproduced
+    info!("Random seed: {}", engine.ecs.world.read_resource::<RandomSource>().seed());
+    if !engine.ecs.world.has_value::<LoadingScreen>() {
+        engine.ecs.world.insert(LoadingScreen::default());
+    }
+    // Everything preloaded has to be finished before the game starts
+    engine.show_loading_screen();
 
     let mut last_time = Instant::now();
+    // unknown ActiveProfile which was warned about already, so it isn't repeated every frame
+    let mut unknown_profile: Option<String> = None;
 
     // look into this when rendering https://www.reddit.com/r/vulkan/comments/e7n5b6/drawing_multiple_objects/
-    engine.event_loop.run(move |event, _, control_flow| {
+    let event_loop = engine.event_loop.take().expect("Engine was already started");
+    event_loop.run(move |event, _, control_flow| {
+        if let Event::WindowEvent { event: window_event, .. } = &event {
+            input_events.record(window_event);
+        }
+
+        // Sent once however the loop exits, pipelines created this run are loaded from the cache on the next start
+        if let Event::LoopDestroyed = &event {
+            engine.vulkan.save_pipeline_cache(PIPELINE_CACHE_PATH);
+            return;
+        }
+
         // Render a frame if app not being destroyed
         if input.update(&event) && !destroying {
             if input.quit() {
@@ -195,12 +1461,16 @@ pub fn start_engine(mut engine: HawkEngine<'static>) {
             }
 
             if input.window_resized().is_some() || recreate_swapchain {
-                recreate_swapchain = false;
-
-                let new_dimensions = input.window_resized().expect("Window resized was none");
+                // Recreation can also be requested by an out of date swapchain
+                // without any resize event, so the size comes from the window
+                let new_dimensions = match get_window_from_surface(&engine.surface) {
+                    Some(v) => v.inner_size(),
+                    None => return
+                };
 
-                // ignore rendering if one of the dimensions is 0
+                // skip rendering while minimized, the flag stays set until the window is restored
                 if new_dimensions.height == 0 || new_dimensions.width == 0 {
+                    recreate_swapchain = true;
                     return
                 }
 
@@ -211,72 +1481,90 @@ pub fn start_engine(mut engine: HawkEngine<'static>) {
                     Ok(r) => r,
                     // Apparently the creation can fail if the user keeps resizing
                     // In that case we can just try to recreate again on the next frame
-                    Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                    Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => {
+                        recreate_swapchain = true;
+                        return
+                    },
                     // Happens when minimized
-                    Err(SwapchainCreationError::ImageExtentZeroLengthDimensions { .. }) => return,
+                    Err(SwapchainCreationError::ImageExtentZeroLengthDimensions { .. }) => {
+                        recreate_swapchain = true;
+                        return
+                    },
                     Err(e) => panic!("Failed to recreate swapcahin: {:?}", e),
                 };
+                recreate_swapchain = false;
                 engine.swapchain = new_swapchain;
-                let new_framebuffers = engine.vulkan.create_framebuffers(
-                    &engine.render_pass,
-                    &new_images
-                );
+                engine.set_images(new_images);
 
-                if input.window_resized().is_some() {
+                // for the loading screen, the engine pipelines set their viewport while drawing
+                let viewport = Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: new_dimensions.into(),
+                    depth_range: 0.0..1.0,
+                };
 
-                    let viewport = Viewport {
-                        origin: [0.0, 0.0],
-                        dimensions: new_dimensions.into(),
-                        depth_range: 0.0..1.0,
-                    };
+                if let Some(loading_screen) = engine.loading_screen.take() {
+                    match engine.vulkan.create_loading_screen(&engine.swapchain, &engine.images, &viewport, loading_screen.background) {
+                        Ok(v) => engine.loading_screen = Some(v),
+                        Err(e) => error!("Failed to recreate the loading screen, starting the game while loading: {}", e)
+                    }
+                }
+
+                // ProjectionMatrix is updated by the UpdateProjection system
+            }
+
+            // Present mode changed by a system during the last frame
+            let present_mode = engine.ecs.world.read_resource::<SwapchainPresentMode>().0;
+            if present_mode != engine.present_mode() && !engine.set_present_mode(present_mode) {
+                *engine.ecs.world.write_resource::<SwapchainPresentMode>() = SwapchainPresentMode(engine.present_mode());
+            }
+
+            // Anti aliasing changed by a system during the last frame
+            let anti_aliasing = *engine.ecs.world.read_resource::<AntiAliasing>();
+            if anti_aliasing != engine.anti_aliasing() {
+                let applied = engine.set_anti_aliasing(anti_aliasing);
+                if applied != anti_aliasing {
+                    warn!("Anti aliasing {:?} is not supported, using {:?} instead", anti_aliasing, applied);
+                    *engine.ecs.world.write_resource::<AntiAliasing>() = applied;
+                }
+            }
+
+            // Render scale changed by a system during the last frame
+            let render_scale = *engine.ecs.world.read_resource::<RenderScale>();
+            if render_scale != engine.render_scale() {
+                let applied = engine.set_render_scale(render_scale);
+                if applied != render_scale {
+                    *engine.ecs.world.write_resource::<RenderScale>() = applied;
+                }
+            }
+
+            // Render targets removed or resized by a system during the last frame
+            engine.release_render_targets();
+            engine.resize_render_targets();
 
-                    // TODO: do not load these again every time
-                    let vs = shaders::default::vs::load(engine.device.clone()).expect("Failed to create vs");
-                    let fs = shaders::default::fs::load(engine.device.clone()).expect("Failed to load fs");
-                    // Wireframe
-                    let vsw = shaders::wireframe::vs::load(engine.device.clone()).expect("Failed to load wireframe vs");
-                    let fsw = shaders::wireframe::fs::load(engine.device.clone()).expect("Failed to load wireframe fs");
-                    let new_pipeline = engine.vulkan.create_pipeline(
-                        "default", 
-                        &engine.render_pass, 
-                        &engine.surface, 
-                        &vs,
-                        &fs,
-                        Some(&viewport),
-                        None
-                    );
-                    let rasterization_state = RasterizationState { polygon_mode: PolygonMode::Line, ..Default::default() };
-                    let new_pipeline_wireframe = engine.vulkan.create_pipeline(
-                        "wireframe", 
-                        &engine.render_pass, 
-                        &engine.surface, 
-                        &vsw,
-                        &fsw,
-                        Some(&viewport),
-                        Some(&rasterization_state)
-                    );
-
-                    // TODO: shouldn't we update renderdata in ecs here???
-                    engine.images = new_images;
-                    engine.pipeline = new_pipeline;
-                    engine.pipeline_wireframe = new_pipeline_wireframe;
-                    engine.framebuffers = new_framebuffers;
-
-                    // Recreate projection matrix
-                    let mut proj = Perspective3::new(
-                        engine.swapchain.image_extent()[0] as f32 / engine.swapchain.image_extent()[1] as f32,
-                        (45.0 as f32).to_radians(),
-                        0.1,
-                        1000.0,
-                    ).to_homogeneous();
-                    // convert from OpenGL to Vulkan coordinates
-                    proj[(1, 1)] *= -1.0;
-
-                    let mut projection_mat = engine.ecs.world.write_resource::<ProjectionMatrix>();
-                    *projection_mat = ProjectionMatrix(proj);
+            // Pipeline override changed by a system during the last frame
+            let requested = {
+                let pipeline_override = engine.ecs.world.read_resource::<PipelineOverride>();
+                match pipeline_override.0.as_deref() != engine.pipeline_override() {
+                    true => Some(pipeline_override.0.clone()),
+                    false => None
                 }
+            };
+            if let Some(requested) = requested {
+                if !engine.set_pipeline_override(requested.as_deref()) {
+                    let applied = PipelineOverride(engine.pipeline_override().map(String::from));
+                    *engine.ecs.world.write_resource::<PipelineOverride>() = applied;
+                }
+            }
+
+            #[cfg(feature = "external-shaders")]
+            if shader_watcher.changed() {
+                engine.reload_shaders();
             }
 
+            // Renderables created while running are drawn once their textures are on the gpu
+            engine.vulkan.poll_uploads();
+
             let (image_i, suboptimal, acquire_future) =
                 match acquire_next_image(engine.swapchain.clone(), None) {
                     Ok(r) => (usize::try_from(r.0).unwrap(), r.1, r.2),
@@ -291,38 +1579,121 @@ pub fn start_engine(mut engine: HawkEngine<'static>) {
                 recreate_swapchain = true;
             }
 
-            // Own scope for immutable reference
-            {
-                // Update render data
-                let mut framebuffer = engine.ecs.world.write_resource::<RenderDataFrameBuffer>();
-                *framebuffer = RenderDataFrameBuffer(engine.framebuffers[image_i].clone());
-                
-                let mut input_res = engine.ecs.world.write_resource::<Arc<WinitInputHelper>>();
-                *input_res = Arc::new(input.clone());
+            // Before the systems run, so the Render system can write the buffers the last frame drawn to this image used
+            if let Some(image_fence) = &fences[image_i] {
+                image_fence.wait(None).unwrap();
+            }
+
+            // Shadows changed by a system during the last frame
+            let shadows = *engine.ecs.world.read_resource::<Shadows>();
+            if shadows != engine.shadows() {
+                let applied = engine.set_shadows(shadows);
+                *engine.ecs.world.write_resource::<Shadows>() = applied;
+            }
+
+            engine.finish_preload_jobs();
 
-                // Update delta time
-                let delta = Instant::now() - last_time;
-                let mut deltatime_resource = engine.ecs.world.write_resource::<DeltaTime>();
-                *deltatime_resource = DeltaTime(delta.as_secs_f32());
+            // Also while loading, so presses from then don't reach the game
+            if let Some(gilrs) = &mut gilrs {
+                engine.ecs.world.write_resource::<Gamepads>().record(gilrs);
+            }
+
+            // Until everything preloaded is finished only the loading screen is drawn, the systems don't run
+            if engine.loading_screen.is_some() {
+                let command_buffer = engine.loading_screen_frame(image_i);
+                engine.ecs.world.write_resource::<CommandBuffer>().command_buffer = command_buffer;
+                // input while loading isn't meant for the game
+                input_events.drain();
+                // the first frame of the game shouldn't include the time spent loading
                 last_time = Instant::now();
             }
+            else {
+                // Streamed textures follow the camera position of the last frame
+                engine.stream_textures();
+
+                // Own scope for immutable reference
+                {
+                    // Update render data
+                    let mut framebuffer = engine.ecs.world.write_resource::<RenderDataFrameBuffer>();
+                    *framebuffer = RenderDataFrameBuffer(engine.framebuffers[image_i].clone());
+
+                    let mut post_process = engine.ecs.world.write_resource::<RenderDataPostProcess>();
+                    *post_process = RenderDataPostProcess(engine.post_process_frame(image_i));
+                
+                    let mut input_res = engine.ecs.world.write_resource::<Arc<WinitInputHelper>>();
+                    *input_res = Arc::new(input.clone());
+
+                    let mut input_events_res = engine.ecs.world.write_resource::<InputEvents>();
+                    *input_events_res = InputEvents(input_events.drain());
+
+                    let frame_pacing = *engine.ecs.world.read_resource::<FramePacing>();
+                    if frame_pacing.enabled {
+                        frame_pacer.pace(last_time);
+                    }
+
+                    // Update delta time
+                    let delta = Instant::now() - last_time;
+                    engine.ecs.world.write_resource::<FrameStats>().record(delta.as_secs_f32());
+
+                    let max_delta = engine.ecs.world.read_resource::<MaxDeltaTime>();
+                    let time_scale = engine.ecs.world.read_resource::<TimeScale>();
+                    let mut deltatime_resource = engine.ecs.world.write_resource::<DeltaTime>();
+                    // clamp before scaling so fast-forward can still exceed the limit
+                    let mut delta_time = delta.as_secs_f32().min(max_delta.0);
+                    if frame_pacing.enabled {
+                        delta_time = frame_pacer.smooth(delta_time, frame_pacing.smoothing_frames);
+                    }
+                    *deltatime_resource = DeltaTime(delta_time * time_scale.0.max(0.0));
+                    *engine.ecs.world.write_resource::<UnscaledDeltaTime>() = UnscaledDeltaTime(delta_time);
+
+                    // Lockstep simulates whole ticks of a fixed length, once the inputs of every player arrived
+                    if let Some(mut lockstep) = engine.ecs.world.try_fetch_mut::<Lockstep>() {
+                        *deltatime_resource = DeltaTime(lockstep.advance().unwrap_or(0.0));
+                    }
+                    last_time = Instant::now();
+                }
 
-            // Iterate through all dispatchers, with the internal being last
-            for dispatcher in engine.dispatchers.iter_mut().rev() {
-                dispatcher.dispatch(&engine.ecs.world);
+                // Resolving the profile once so switching mid-frame doesn't matter
+                // An unknown name, e.g. written to ActiveProfile by a system, runs every dispatcher like None
+                let active_profile = match &engine.ecs.world.read_resource::<ActiveProfile>().0 {
+                    Some(name) => match engine.profiles.get(name) {
+                        Some(profile) => Some(profile.clone()),
+                        None => {
+                            if unknown_profile.as_ref() != Some(name) {
+                                warn!("Dispatcher profile {name} is not defined, running all dispatchers");
+                                unknown_profile = Some(name.clone());
+                            }
+                            None
+                        }
+                    },
+                    None => None
+                };
+
+                // Iterate through all dispatchers, with the internal being last
+                for (name, dispatcher) in engine.dispatchers.iter_mut().rev() {
+                    let active = match (name, &active_profile) {
+                        (Some(name), Some(profile)) => profile.contains(name),
+                        _ => true
+                    };
+
+                    if active {
+                        dispatcher.dispatch(&engine.ecs.world);
+                    }
+                }
+                engine.ecs.world.maintain();
             }
-            engine.ecs.world.maintain();
 
-            let command_buffer = engine.ecs.world.read_resource::<CommandBuffer>();
-            let command_buffer = match &command_buffer.command_buffer {
+            for (_, update) in engine.event_updates.iter() {
+                update(&engine.ecs.world);
+            }
+
+            // Taken since the buffer is recorded for a single submit
+            let command_buffer = engine.ecs.world.write_resource::<CommandBuffer>().command_buffer.take();
+            let command_buffer = match &command_buffer {
                 Some(v) => v,
                 None => return eprintln!("Command buffer received from ECS was none, skipping rendering for this frame")
             };
 
-            if let Some(image_fence) = &fences[image_i] {
-                image_fence.wait(None).unwrap();
-            }
-
             let previous_future = match fences[previous_fence_i].clone() {
                 None => {
                     let mut now = sync::now(engine.device.clone());
@@ -0,0 +1,154 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use uuid::Uuid;
+
+use crate::ecs::resources::network::{KeepAlive, KeepAliveStats, MessageType, NetworkPacket};
+
+// Weight of a new answer in the smoothed round trip time, the same TCP uses
+const ROUND_TRIP_WEIGHT: f64 = 0.125;
+
+/// Client side, keep-alives sent over UDP which weren't answered yet
+/// Any of them can get lost, so the connection only counts as lost after KeepAlive::max_missed in a row
+pub struct KeepAliveTracker {
+    options: KeepAlive,
+    next_sequence: u32,
+    // oldest first
+    pending: VecDeque<(u32, Instant)>,
+    missed: u32,
+    round_trip: Option<Duration>
+}
+
+impl KeepAliveTracker {
+    pub fn new(options: KeepAlive) -> Self {
+        Self { options, next_sequence: 0, pending: VecDeque::new(), missed: 0, round_trip: None }
+    }
+
+    /*
+    Sequence of the next keep-alive, sent at now
+    Earlier ones which weren't answered within the timeout count as missed
+    */
+    pub fn send(&mut self, now: Instant) -> u32 {
+        while let Some((_, sent)) = self.pending.front() {
+            if now.duration_since(*sent) < self.options.timeout {
+                break;
+            }
+            self.pending.pop_front();
+            self.missed += 1;
+        }
+
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.pending.push_back((sequence, now));
+        sequence
+    }
+
+    /*
+    Answer to the keep-alive sequence arrived at now, returns its round trip
+    Unknown sequences are ignored, e.g. answers arriving after their timeout
+    */
+    pub fn ack(&mut self, sequence: u32, now: Instant) -> Option<Duration> {
+        let i = self.pending.iter().position(|(s, _)| *s == sequence)?;
+        let sent = self.pending[i].1;
+        // earlier ones are lost or late, the connection works either way
+        self.pending.drain(..=i);
+        self.missed = 0;
+
+        let sample = now.duration_since(sent);
+        self.round_trip = Some(match self.round_trip {
+            Some(v) => v.mul_f64(1.0 - ROUND_TRIP_WEIGHT) + sample.mul_f64(ROUND_TRIP_WEIGHT),
+            None => sample
+        });
+        Some(sample)
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.missed >= self.options.max_missed
+    }
+
+    pub fn stats(&self) -> KeepAliveStats {
+        KeepAliveStats { round_trip: self.round_trip, missed: self.missed }
+    }
+}
+
+/*
+A keep-alive, or the answer to one with ack, not about any entity so without a net_id
+*/
+pub fn keep_alive_packet(sequence: u32, ack: bool) -> Result<Vec<u8>, String> {
+    rmp_serde::to_vec(&NetworkPacket::new(Uuid::nil(), MessageType::KeepAlive { sequence, ack }, Vec::new()))
+        .map_err(|e| format!("Could not serialize keep-alive {sequence}: {e}"))
+}
+
+/*
+Server side, the answer to send back if packet is a keep-alive of a client
+*/
+pub fn keep_alive_answer(packet: &NetworkPacket) -> Option<Result<Vec<u8>, String>> {
+    match packet.message_type {
+        MessageType::KeepAlive { sequence, ack: false } => Some(keep_alive_packet(sequence, true)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> KeepAlive {
+        KeepAlive { interval: Some(Duration::from_secs(1)), timeout: Duration::from_secs(2), max_missed: 3 }
+    }
+
+    #[test]
+    fn dropped_keep_alives_are_tolerated() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut tracker = KeepAliveTracker::new(options());
+
+        let first = tracker.send(at(0));
+        tracker.send(at(1));
+        let third = tracker.send(at(2));
+        // the first one was lost
+        assert_eq!(tracker.stats().missed, 1);
+        assert!(!tracker.is_lost());
+
+        // answering the third one covers the second as well
+        assert_eq!(tracker.ack(third, at(2) + Duration::from_millis(40)), Some(Duration::from_millis(40)));
+        assert_eq!(tracker.stats().missed, 0);
+        assert_eq!(tracker.ack(first, at(3)), None);
+
+        // nothing comes back anymore
+        for secs in 3..7 {
+            tracker.send(at(secs));
+        }
+        assert!(!tracker.is_lost());
+        tracker.send(at(7));
+        assert!(tracker.is_lost());
+    }
+
+    #[test]
+    fn round_trip_is_smoothed() {
+        let start = Instant::now();
+        let mut tracker = KeepAliveTracker::new(options());
+        assert_eq!(tracker.stats().round_trip, None);
+
+        let sequence = tracker.send(start);
+        tracker.ack(sequence, start + Duration::from_millis(80));
+        assert_eq!(tracker.stats().round_trip, Some(Duration::from_millis(80)));
+
+        // a single spike barely moves it
+        let sequence = tracker.send(start);
+        tracker.ack(sequence, start + Duration::from_millis(400));
+        assert_eq!(tracker.stats().round_trip, Some(Duration::from_millis(120)));
+    }
+
+    #[test]
+    fn server_answers_keep_alives() {
+        let ping = rmp_serde::from_slice::<NetworkPacket>(&keep_alive_packet(7, false).unwrap()).unwrap();
+        let answer = keep_alive_answer(&ping).unwrap().unwrap();
+        match rmp_serde::from_slice::<NetworkPacket>(&answer).unwrap().message_type {
+            MessageType::KeepAlive { sequence: 7, ack: true } => {},
+            other => panic!("unexpected answer {:?}", other)
+        }
+
+        let pong = rmp_serde::from_slice::<NetworkPacket>(&answer).unwrap();
+        assert!(keep_alive_answer(&pong).is_none());
+    }
+}
@@ -1 +1,3 @@
+pub mod keep_alive;
+pub mod queue;
 pub mod tokio;
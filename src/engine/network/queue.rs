@@ -0,0 +1,120 @@
+use std::{collections::BinaryHeap, cmp::Ordering};
+
+use log::trace;
+
+use crate::ecs::resources::network::{NetworkMessageData, MessagePriority};
+
+// Bulk messages arriving while this many messages wait are dropped,
+// newer state is going to be sent soon anyway
+const MAX_QUEUED_BEFORE_DROPPING_BULK: usize = 1024;
+
+/// Outgoing messages of the network thread, highest priority first
+/// Messages of the same priority keep the order they were queued in
+#[derive(Default)]
+pub struct SendQueue {
+    heap: BinaryHeap<QueuedMessage>,
+    next_seq: u64
+}
+
+impl SendQueue {
+    pub fn push(&mut self, message: NetworkMessageData) {
+        if message.priority == MessagePriority::Bulk && self.heap.len() >= MAX_QUEUED_BEFORE_DROPPING_BULK {
+            return trace!("Send queue is congested, dropping a {:?} message", message.packet.message_type);
+        }
+
+        self.heap.push(QueuedMessage { priority: message.priority, seq: self.next_seq, message });
+        self.next_seq += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<NetworkMessageData> {
+        self.heap.pop().map(|v| v.message)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+struct QueuedMessage {
+    priority: MessagePriority,
+    seq: u64,
+    message: NetworkMessageData
+}
+
+impl Ord for QueuedMessage {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap pops the largest, so older messages have to compare greater
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for QueuedMessage {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedMessage {}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::ecs::resources::network::{NetworkPacket, MessageType};
+
+    // the data tells the messages apart
+    fn message(priority: MessagePriority, id: u8) -> NetworkMessageData {
+        let packet = NetworkPacket::new(Uuid::nil(), MessageType::ComponentCustom("test".into()), vec![id]);
+        NetworkMessageData::new(([127, 0, 0, 1], 7777).into(), packet).with_priority(priority)
+    }
+
+    fn drain(queue: &mut SendQueue) -> Vec<u8> {
+        std::iter::from_fn(|| queue.pop()).map(|v| v.packet.data[0]).collect()
+    }
+
+    #[test]
+    fn highest_priority_first_oldest_first() {
+        let mut queue = SendQueue::default();
+        queue.push(message(MessagePriority::Bulk, 0));
+        queue.push(message(MessagePriority::Normal, 1));
+        queue.push(message(MessagePriority::Bulk, 2));
+        queue.push(message(MessagePriority::Critical, 3));
+        queue.push(message(MessagePriority::Normal, 4));
+        queue.push(message(MessagePriority::Critical, 5));
+
+        assert_eq!(drain(&mut queue), vec![3, 5, 1, 4, 0, 2]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn bulk_is_dropped_when_congested() {
+        let mut queue = SendQueue::default();
+        for _ in 0..MAX_QUEUED_BEFORE_DROPPING_BULK - 1 {
+            queue.push(message(MessagePriority::Normal, 0));
+        }
+        queue.push(message(MessagePriority::Bulk, 1));
+        assert_eq!(queue.len(), MAX_QUEUED_BEFORE_DROPPING_BULK);
+
+        // the queue is full now, only bulk messages are turned away
+        queue.push(message(MessagePriority::Bulk, 2));
+        assert_eq!(queue.len(), MAX_QUEUED_BEFORE_DROPPING_BULK);
+        queue.push(message(MessagePriority::Critical, 3));
+        assert_eq!(queue.len(), MAX_QUEUED_BEFORE_DROPPING_BULK + 1);
+
+        let sent = drain(&mut queue);
+        assert_eq!(sent.first(), Some(&3));
+        assert_eq!(sent.last(), Some(&1));
+        assert!(!sent.contains(&2));
+    }
+}
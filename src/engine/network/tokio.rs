@@ -1,20 +1,111 @@
-use std::{collections::HashMap, default, net::IpAddr, sync::Arc, thread, time::Duration};
+use std::{collections::HashMap, default, net::{IpAddr, SocketAddr}, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
 
-use log::error;
-use tokio::{sync::mpsc::{self, Sender, Receiver}, net::{UdpSocket}, runtime::Runtime, time::timeout};
+use log::{error, warn, info};
+use socket2::{Socket, Domain, Type, Protocol};
+use tokio::{sync::{mpsc::{self, Sender, Receiver}, watch}, net::{UdpSocket}, runtime::Runtime};
 
-use crate::ecs::resources::network::{NetworkMessageData, NetworkData, NetworkPacket};
+use super::keep_alive::{KeepAliveTracker, keep_alive_packet, keep_alive_answer};
+use super::queue::SendQueue;
+use crate::ecs::resources::network::{NetworkMessageData, NetworkData, NetworkPacket, MessageType, ConnectionState, KeepAlive, KeepAliveStats, DEFAULT_MAX_REPLICATED};
 
 const UDP_BUF_SIZE: usize = 1432;
 
-async fn server_loop(socket: UdpSocket, sender: Sender<NetworkMessageData>, mut receiver: Receiver<NetworkMessageData>) {
+/// Options applied to the socket before it is bound
+/// 
+/// The buffer sizes are only hints for the os, which is free to round
+/// them up (linux doubles the value) or clamp them to a system maximum.
+/// None keeps the os default.
+#[derive(Clone, Copy, Debug)]
+pub struct SocketOptions {
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    // size of a single datagram we are able to receive,
+    // anything larger gets truncated
+    pub datagram_size: usize
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self { send_buffer_size: None, recv_buffer_size: None, datagram_size: UDP_BUF_SIZE }
+    }
+}
+
+fn create_udp_socket(addr: SocketAddr, options: &SocketOptions) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if let Some(size) = options.send_buffer_size {
+        if let Err(e) = socket.set_send_buffer_size(size) {
+            warn!("Failed to set socket send buffer size to {size}: {e}");
+        }
+    }
+
+    if let Some(size) = options.recv_buffer_size {
+        if let Err(e) = socket.set_recv_buffer_size(size) {
+            warn!("Failed to set socket receive buffer size to {size}: {e}");
+        }
+    }
+
+    socket.bind(&addr.into())?;
+    // tokio requires the socket to be in non-blocking mode
+    socket.set_nonblocking(true)?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+/*
+Waits for the next outgoing message, then moves it and everything else already waiting
+into the queue, so a whole tick of messages is sent in priority order
+Returns false if the channel was closed
+*/
+async fn receive_queued(receiver: &mut Receiver<NetworkMessageData>, queue: &mut SendQueue) -> bool {
+    match receiver.recv().await {
+        Some(v) => queue.push(v),
+        None => return false
+    }
+
+    while let Ok(v) = receiver.try_recv() {
+        queue.push(v);
+    }
+    true
+}
+
+async fn server_loop(socket: UdpSocket, options: SocketOptions, state: watch::Sender<ConnectionState>, sender: Sender<NetworkMessageData>, mut receiver: Receiver<NetworkMessageData>) {
+    // nothing to connect to, we are ready as soon as the socket is bound
+    let _ = state.send(ConnectionState::Connected);
     let r = Arc::new(socket);
     let s = r.clone();
     let recv_task = tokio::spawn(async move {
-        let mut buf = [0u8; UDP_BUF_SIZE];
+        let mut buf = vec![0u8; options.datagram_size];
         loop {
             // TODO: handle errors
             let (len, addr) = r.recv_from(&mut buf).await.unwrap();
+
+            let packet = match rmp_serde::from_slice::<NetworkPacket>(&buf[..len]) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Could not deserialize a packet from {:?}: {e}", addr);
+                    continue;
+                }
+            };
+
+            // answered right away, so the round trip doesn't include waiting for a frame
+            match keep_alive_answer(&packet) {
+                Some(Ok(data)) => {
+                    if let Err(e) = r.send_to(&data, addr).await {
+                        warn!("Failed to answer a keep-alive of {:?}: {e}", addr);
+                    }
+                    continue;
+                },
+                Some(Err(e)) => {
+                    error!("{e}");
+                    continue;
+                },
+                None => {}
+            }
+
+            // only logged until the server handles packets
+            #[cfg(feature = "net-debug")]
+            packet.log("received", &addr);
             
             /*match sender.send(NetworkMessageData {addr, data: buf[..len].to_vec()}).await {
                 Ok(_) => {},
@@ -24,21 +115,24 @@ async fn server_loop(socket: UdpSocket, sender: Sender<NetworkMessageData>, mut
     });
 
     let send_task = tokio::spawn(async move {
+        let mut queue = SendQueue::default();
         loop {
-            let message = match receiver.recv().await {
-                Some(v) => v,
-                None => {
-                    error!("Failed to receive message data on async from sync");
-                    continue;
-                }
-            };
-            
-            /*match s.send_to(&message.data.into_boxed_slice(), message.addr).await {
-                Ok(_) => {},
-                Err(e) => {
-                    error!("Failed to send data to address {:?}: {}", message.addr, e);
-                }
-            }*/
+            if !receive_queued(&mut receiver, &mut queue).await {
+                error!("Failed to receive message data on async from sync");
+                continue;
+            }
+
+            while let Some(message) = queue.pop() {
+                #[cfg(feature = "net-debug")]
+                message.packet.log("sending", &message.addr);
+                
+                /*match s.send_to(&message.data.into_boxed_slice(), message.addr).await {
+                    Ok(_) => {},
+                    Err(e) => {
+                        error!("Failed to send data to address {:?}: {}", message.addr, e);
+                    }
+                }*/
+            }
         }
     });
 
@@ -46,20 +140,104 @@ async fn server_loop(socket: UdpSocket, sender: Sender<NetworkMessageData>, mut
     recv_task.await.unwrap_or_else(|e| error!("Failed to join recv_task: {e}"));
 }
 
-async fn client_loop(socket: UdpSocket, addr: IpAddr, port: u16, sender: Sender<NetworkMessageData>, mut receiver: Receiver<NetworkMessageData>) {
-    socket.connect((addr, port)).await.unwrap();
+/*
+Client side, sends a keep-alive every interval over the connected socket
+A connection with too many unanswered in a row is reported as Disconnected, until one is answered again
+*/
+async fn keep_alive_loop(socket: Arc<UdpSocket>, interval: Duration, tracker: Arc<Mutex<KeepAliveTracker>>, state: Arc<watch::Sender<ConnectionState>>, stats: Arc<watch::Sender<KeepAliveStats>>) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        let (sequence, lost, current) = match tracker.lock() {
+            Ok(mut v) => (v.send(Instant::now()), v.is_lost(), v.stats()),
+            Err(_) => return error!("Keep-alive tracker was poisoned, no longer sending keep-alives")
+        };
+        let _ = stats.send(current);
+
+        if lost && *state.borrow() == ConnectionState::Connected {
+            warn!("{} keep-alives in a row were not answered, connection lost", current.missed);
+            let _ = state.send(ConnectionState::Disconnected);
+        }
+
+        let data = match keep_alive_packet(sequence, false) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{e}");
+                continue;
+            }
+        };
+        if let Err(e) = socket.send(&data).await {
+            warn!("Failed to send keep-alive {sequence}: {e}");
+        }
+    }
+}
+
+async fn client_loop(socket: UdpSocket, options: SocketOptions, keep_alive: KeepAlive, addr: IpAddr, port: u16, state: watch::Sender<ConnectionState>, stats: watch::Sender<KeepAliveStats>, sender: Sender<NetworkMessageData>, mut receiver: Receiver<NetworkMessageData>) {
+    if let Err(e) = socket.connect((addr, port)).await {
+        error!("Failed to connect to {:?}:{:?}: {e}", addr, port);
+        let _ = state.send(ConnectionState::Failed);
+        return;
+    }
+    let _ = state.send(ConnectionState::Connected);
     let r = Arc::new(socket);
     let s = r.clone();
 
+    // shared with the receiving task, which gets the answers
+    let state = Arc::new(state);
+    let stats = Arc::new(stats);
+    let tracker = Arc::new(Mutex::new(KeepAliveTracker::new(keep_alive)));
+    let keep_alive_task = keep_alive.interval.map(|interval| {
+        tokio::spawn(keep_alive_loop(s.clone(), interval, tracker.clone(), state.clone(), stats.clone()))
+    });
     
     let recv_task = tokio::spawn(async move {
-        let mut buf = [0u8; UDP_BUF_SIZE];
+        let mut buf = vec![0u8; options.datagram_size];
         loop {
             // TODO: handle errors
-            let len = r.recv(&mut buf).await.unwrap();
-            let network_message = rmp_serde::from_slice::<NetworkPacket>(&buf[..len]).unwrap();
+            let len = match r.recv(&mut buf).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Lost connection to {:?}:{:?}: {e}", addr, port);
+                    let _ = state.send(ConnectionState::Disconnected);
+                    break;
+                }
+            };
+            // a datagram larger than datagram_size arrives truncated
+            let network_message = match rmp_serde::from_slice::<NetworkPacket>(&buf[..len]) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Could not deserialize a packet from {:?}:{:?}: {e}", addr, port);
+                    continue;
+                }
+            };
+
+            if let MessageType::KeepAlive { sequence, ack } = network_message.message_type {
+                if !ack {
+                    warn!("Received a keep-alive from the server, only clients send them");
+                    continue;
+                }
+
+                let current = match tracker.lock() {
+                    Ok(mut v) => {
+                        v.ack(sequence, Instant::now());
+                        v.stats()
+                    },
+                    Err(_) => continue
+                };
+                let _ = stats.send(current);
+
+                if *state.borrow() == ConnectionState::Disconnected {
+                    info!("Keep-alives are answered again by {:?}:{:?}", addr, port);
+                    let _ = state.send(ConnectionState::Connected);
+                }
+                continue;
+            }
+
+            #[cfg(feature = "net-debug")]
+            network_message.log("received", &(addr, port).into());
             
-            match sender.send(NetworkMessageData {addr: (addr, port).into(), packet: network_message}).await {
+            match sender.send(NetworkMessageData::new((addr, port).into(), network_message)).await {
                 Ok(_) => {},
                 Err(e) => error!("Failed to send a network message from async to sync: {e}")
             } 
@@ -67,62 +245,63 @@ async fn client_loop(socket: UdpSocket, addr: IpAddr, port: u16, sender: Sender<
     });
 
     let send_task = tokio::spawn(async move {
+        let mut queue = SendQueue::default();
         loop {
-            let message = match receiver.recv().await {
-                Some(v) => v,
-                None => {
-                    error!("Failed to receive message data on async from sync");
-                    continue;
-                }
-            };
-            
-            /*match s.send(&message.data.into_boxed_slice()).await {
-                Ok(_) => {},
-                Err(e) => {
-                    error!("Failed to send data to address {:?}:{:?}: {}", addr, port, e);
-                }
-            }*/
+            if !receive_queued(&mut receiver, &mut queue).await {
+                error!("Failed to receive message data on async from sync");
+                continue;
+            }
+
+            while let Some(message) = queue.pop() {
+                #[cfg(feature = "net-debug")]
+                message.packet.log("sending", &message.addr);
+                
+                /*match s.send(&message.data.into_boxed_slice()).await {
+                    Ok(_) => {},
+                    Err(e) => {
+                        error!("Failed to send data to address {:?}:{:?}: {}", addr, port, e);
+                    }
+                }*/
+            }
         }
     });
 
     send_task.await.unwrap_or_else(|e| error!("Failed to join send_task: {e}"));
     recv_task.await.unwrap_or_else(|e| error!("Failed to join recv_task: {e}"));
+    if let Some(task) = keep_alive_task {
+        task.await.unwrap_or_else(|e| error!("Failed to join keep_alive_task: {e}"));
+    }
 }
 
 /// If server is true, will use many-to-one style connection
 /// otherwise connects to the specific address
-async fn tokio_network_loop(addr: IpAddr, port: u16, server: bool, sender: Sender<NetworkMessageData>, receiver: Receiver<NetworkMessageData>) {
-    // udp might never connect which would block this thread forever
-    // thus using a 10s timeout
-    let socket_res = timeout(Duration::from_secs(10), UdpSocket::bind(("0.0.0.0", port))).await;
-
-    let socket = match socket_res {
-        Ok(s) => match s {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Failed to open socket to address {:?}:{:?}", addr, port);
-                error!("{e}");
-                return;
-            }
-        }
+/// Only clients send keep-alives, servers answer them
+async fn tokio_network_loop(addr: IpAddr, port: u16, server: bool, options: SocketOptions, keep_alive: KeepAlive, state: watch::Sender<ConnectionState>, stats: watch::Sender<KeepAliveStats>, sender: Sender<NetworkMessageData>, receiver: Receiver<NetworkMessageData>) {
+    // binding happens synchronously through socket2 so there is
+    // nothing that could block here, unlike with connect
+    let socket = match create_udp_socket(([0, 0, 0, 0], port).into(), &options) {
+        Ok(v) => v,
         Err(e) => {
-            error!("Failed to connect socket to address {:?}:{:?}: timeout elapsed", addr, port);
+            error!("Failed to open socket to address {:?}:{:?}", addr, port);
             error!("{e}");
+            let _ = state.send(ConnectionState::Failed);
             return;
         }
     };
 
     if server {
-        server_loop(socket, sender, receiver).await;
+        server_loop(socket, options, state, sender, receiver).await;
     }
     else {
-        client_loop(socket, addr, port, sender, receiver).await;
+        client_loop(socket, options, keep_alive, addr, port, state, stats, sender, receiver).await;
     }
 }
 
-pub fn start_network_thread(address: &str, port: u16, server: bool) -> Option<NetworkData> {
+pub fn start_network_thread(address: &str, port: u16, server: bool, options: SocketOptions, keep_alive: KeepAlive) -> Option<NetworkData> {
     let (a2s_sender, a2s_receiver) = mpsc::channel::<NetworkMessageData>(16384);
     let (s2a_sender, s2a_receiver) = mpsc::channel::<NetworkMessageData>(16384);
+    let (state_sender, state_receiver) = watch::channel(ConnectionState::Connecting);
+    let (stats_sender, stats_receiver) = watch::channel(KeepAliveStats::default());
 
     let addr_parsed= address.parse::<IpAddr>();
     
@@ -142,14 +321,15 @@ pub fn start_network_thread(address: &str, port: u16, server: bool) -> Option<Ne
             Ok(v) => v,
             Err(e) => {
                 error!("Failed creating tokio runtime.");
+                let _ = state_sender.send(ConnectionState::Failed);
                 return;
             }
         }; 
 
         rt.block_on(async move {
-            tokio_network_loop(addr_ok, port, server, a2s_sender, s2a_receiver).await;
+            tokio_network_loop(addr_ok, port, server, options, keep_alive, state_sender, stats_sender, a2s_sender, s2a_receiver).await;
         });
     });
 
-    return Some(NetworkData {sender: s2a_sender, receiver: a2s_receiver, target_addr: (addr_ok, port).into(), net_id_ent: HashMap::new()});
+    return Some(NetworkData {sender: s2a_sender, receiver: a2s_receiver, target_addr: (addr_ok, port).into(), connection_state: state_receiver, keep_alive: stats_receiver, net_id_ent: HashMap::new(), max_replicated: DEFAULT_MAX_REPLICATED});
 }
@@ -0,0 +1,131 @@
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use log::error;
+
+/// Jobs started with HawkEngine::preload, each loading on its own thread and finishing on the main one
+/// C is what the finishing closures get to work with, the engine outside of tests
+pub(crate) struct PreloadQueue<'a, C> {
+    jobs: Vec<(JoinHandle<()>, Box<dyn FnOnce(&mut C) + 'a>)>,
+    total: usize
+}
+
+impl<'a, C> Default for PreloadQueue<'a, C> {
+    fn default() -> Self {
+        PreloadQueue { jobs: Vec::new(), total: 0 }
+    }
+}
+
+impl<'a, C> PreloadQueue<'a, C> {
+    /*
+    Starts load on a new thread, finish gets its result once take_finished returns it
+    A panicking load is logged and its finish is skipped
+    */
+    pub fn push<T, L, F>(&mut self, load: L, finish: F)
+    where
+        T: Send + 'static,
+        L: FnOnce() -> T + Send + 'static,
+        F: FnOnce(&mut C, T) + 'a
+    {
+        let result = Arc::new(Mutex::new(None));
+        let slot = result.clone();
+        let handle = thread::spawn(move || {
+            let v = load();
+            if let Ok(mut slot) = slot.lock() {
+                *slot = Some(v);
+            }
+        });
+
+        let finish = move |context: &mut C| {
+            match result.lock().ok().and_then(|mut v| v.take()) {
+                Some(v) => finish(context, v),
+                None => error!("A preload job panicked, skipping it")
+            }
+        };
+        self.jobs.push((handle, Box::new(finish)));
+        self.total += 1;
+    }
+
+    /*
+    Removes the jobs whose thread is done, in the order they were pushed
+    */
+    pub fn take_finished(&mut self) -> Vec<Box<dyn FnOnce(&mut C) + 'a>> {
+        let (finished, pending) = self.jobs.drain(..).partition(|(handle, _)| handle.is_finished());
+        self.jobs = pending;
+        finished.into_iter().map(|(_, finish)| finish).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /*
+    Share of the jobs pushed so far which finished, 1.0 without any
+    */
+    pub fn progress(&self) -> f32 {
+        match self.total {
+            0 => 1.0,
+            total => (total - self.jobs.len()) as f32 / total as f32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn wait_for<'a>(queue: &mut PreloadQueue<'a, Vec<i32>>, count: usize) -> Vec<Box<dyn FnOnce(&mut Vec<i32>) + 'a>> {
+        let mut finished = Vec::new();
+        for _ in 0..500 {
+            finished.extend(queue.take_finished());
+            if finished.len() >= count {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        finished
+    }
+
+    #[test]
+    fn finishes_with_the_loaded_value() {
+        let mut queue = PreloadQueue::default();
+        assert_eq!(queue.progress(), 1.0);
+
+        let (release, blocked) = mpsc::channel::<()>();
+        queue.push(move || { blocked.recv().ok(); 2 }, |loaded: &mut Vec<i32>, v| loaded.push(v));
+        queue.push(|| 1, |loaded: &mut Vec<i32>, v| loaded.push(v));
+        assert_eq!(queue.progress(), 0.0);
+
+        let mut loaded = Vec::new();
+        for finish in wait_for(&mut queue, 1) {
+            finish(&mut loaded);
+        }
+        // the first job is still waiting
+        assert_eq!(loaded, vec![1]);
+        assert_eq!(queue.progress(), 0.5);
+        assert!(!queue.is_empty());
+
+        release.send(()).unwrap();
+        for finish in wait_for(&mut queue, 1) {
+            finish(&mut loaded);
+        }
+        assert_eq!(loaded, vec![1, 2]);
+        assert_eq!(queue.progress(), 1.0);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn panicking_jobs_are_skipped() {
+        let mut queue = PreloadQueue::default();
+        queue.push(|| -> i32 { panic!("failed loading") }, |loaded: &mut Vec<i32>, v| loaded.push(v));
+
+        let mut loaded = Vec::new();
+        for finish in wait_for(&mut queue, 1) {
+            finish(&mut loaded);
+        }
+        assert!(loaded.is_empty());
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,19 @@
+use vulkano_shaders;
+
+vulkano_shaders::shader! {
+    ty: "fragment",
+    src: "
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+
+layout(location = 0) in vec2 frag_uv;
+
+layout(location = 0) out vec4 f_color;
+
+// Copies the scene as is, scaled by the sampler when it has another resolution
+void main() {
+    f_color = texture(scene, frag_uv);
+}
+"
+}
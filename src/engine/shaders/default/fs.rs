@@ -2,19 +2,109 @@ use vulkano_shaders;
 
 vulkano_shaders::shader! {
     ty: "fragment",
+    types_meta: {
+        use bytemuck::{Pod, Zeroable};
+
+        #[derive(Clone, Copy, Zeroable, Pod)]
+    },
     src: "
 #version 450
 
+// everything in view space, filled in by the Render system
+struct LightData {
+    // xyz position of a point light, w range
+    vec4 position_range;
+    // xyz direction a directional light shines in, w 0 for directional and 1 for point lights
+    vec4 direction_kind;
+    // rgb color, a intensity
+    vec4 color_intensity;
+};
+
 layout(set = 1, binding = 0) uniform sampler2D tex_sampler;
+// white unless the renderable was created with a lightmap
+layout(set = 1, binding = 1) uniform sampler2D lightmap_sampler;
+
+layout(set = 2, binding = 0) uniform ShadowUniformBufferObject {
+    // view space to the clip space of each shadow cascade,
+    // array size has to match MAX_SHADOW_CASCADES
+    mat4 shadow_matrices[4];
+    // 0 without shadows
+    uint shadow_cascades;
+} ubo_shadows;
+
+// one layer per cascade, see Shadows
+layout(set = 2, binding = 1) uniform sampler2DArrayShadow shadow_map;
+
+// array size has to match MAX_LIGHTS
+layout(set = 2, binding = 2) uniform LightsUniformBufferObject {
+    LightData lights[16];
+    vec4 ambient;
+    // 0 draws the scene unlit
+    uint count;
+} ubo_lights;
 
 layout(location = 0) in vec3 frag_color;
 layout(location = 1) in vec2 frag_tex_coord;
 layout(location = 2) in vec3 v_normal;
+layout(location = 3) in vec3 v_position;
+layout(location = 4) in vec2 frag_tex_coord2;
 
 layout(location = 0) out vec4 f_color;
 
+// Without lights shadowed surfaces are just darkened
+const float SHADOW_BRIGHTNESS = 0.5;
+
+// 1 where the sun reaches the fragment and 0 in its shadow, filtered over 3x3 texels
+// Explicit gradients since the loop isn't uniform control flow, the map has no mipmaps anyway
+float shadow(vec3 position) {
+    for (uint i = 0u; i < ubo_shadows.shadow_cascades; i++) {
+        vec4 clip = ubo_shadows.shadow_matrices[i] * vec4(position, 1.0);
+        vec3 coords = clip.xyz / clip.w;
+        // the cascades get larger with their index, so the first one containing the fragment is the sharpest
+        if (any(greaterThan(abs(coords.xy), vec2(1.0))) || coords.z > 1.0) {
+            continue;
+        }
+
+        vec2 uv = coords.xy * 0.5 + 0.5;
+        vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+        float lit = 0.0;
+        for (int x = -1; x <= 1; x++) {
+            for (int y = -1; y <= 1; y++) {
+                lit += textureGrad(shadow_map, vec4(uv + vec2(x, y) * texel, float(i), coords.z), vec2(0.0), vec2(0.0));
+            }
+        }
+        return lit / 9.0;
+    }
+    return 1.0;
+}
+
+// Lambert shading of every light plus the ambient light, the sun casting the shadows is directional
+vec3 lighting(float lit) {
+    vec3 n = normalize(v_normal);
+    vec3 total = ubo_lights.ambient.rgb;
+    for (uint i = 0u; i < ubo_lights.count; i++) {
+        LightData l = ubo_lights.lights[i];
+        vec3 to_light = -l.direction_kind.xyz;
+        float attenuation = lit;
+        if (l.direction_kind.w > 0.5) {
+            vec3 d = l.position_range.xyz - v_position;
+            float dist = length(d);
+            to_light = d / max(dist, 0.0001);
+            // smooth falloff reaching 0 at the range
+            attenuation = clamp(1.0 - dist / l.position_range.w, 0.0, 1.0);
+            attenuation *= attenuation;
+        }
+        total += l.color_intensity.rgb * l.color_intensity.a * attenuation * max(dot(n, to_light), 0.0);
+    }
+    return total;
+}
+
 void main() {
-    f_color = vec4(texture(tex_sampler, frag_tex_coord).rgb, 1.0);
+    float lit = shadow(v_position);
+    vec3 shade = ubo_lights.count > 0u ? lighting(lit) : vec3(mix(SHADOW_BRIGHTNESS, 1.0, lit));
+    vec3 base = texture(tex_sampler, frag_tex_coord).rgb;
+    vec3 light = texture(lightmap_sampler, frag_tex_coord2).rgb;
+    f_color = vec4(base * light * shade, 1.0);
 }
 "
 }
@@ -17,23 +17,30 @@ layout(binding = 0) uniform VPUniformBufferObject {
 
 layout(push_constant) uniform ModelPushConstants {
     mat4 model;
+    // xy offset, zw scale
+    vec4 uv_transform;
 } pcs_m;
 
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec3 color;
 layout(location = 3) in vec2 tex_coord;
+layout(location = 4) in vec2 tex_coord2;
 
 layout(location = 0) out vec3 frag_color;
 layout(location = 1) out vec2 frag_tex_coord;
 layout(location = 2) out vec3 v_normal;
+layout(location = 3) out vec3 v_position;
+layout(location = 4) out vec2 frag_tex_coord2;
 
 void main() {
     mat4 worldview = ubo_vp.view * pcs_m.model;
     gl_Position = ubo_vp.proj * worldview * vec4(position, 1.0);
     frag_color = color;
-    frag_tex_coord = tex_coord;
+    frag_tex_coord = tex_coord * pcs_m.uv_transform.zw + pcs_m.uv_transform.xy;
     v_normal = transpose(inverse(mat3(worldview))) * normal;
+    v_position = (worldview * vec4(position, 1.0)).xyz;
+    frag_tex_coord2 = tex_coord2;
 }
 "
 }
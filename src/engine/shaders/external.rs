@@ -0,0 +1,106 @@
+use std::{sync::Arc, fs, time::{SystemTime, Instant, Duration}, collections::HashMap, path::PathBuf};
+
+use log::{info, warn};
+use vulkano::{device::Device, shader::ShaderModule};
+
+pub const SHADER_DIR: &str = "resources/shaders";
+
+/*
+Loads resources/shaders/<name>/<stage>.spv if it exists
+
+The shader interface (descriptor sets, push constants, vertex inputs)
+has to match the compiled in shader, since the Rust side types come from it
+*/
+pub fn load(device: &Arc<Device>, name: &str, stage: &str) -> Option<Arc<ShaderModule>> {
+    let path = format!("{SHADER_DIR}/{name}/{stage}.spv");
+
+    let bytes = match fs::read(&path) {
+        Ok(v) => v,
+        // no override for this shader
+        Err(_) => return None
+    };
+
+    if bytes.len() % 4 != 0 {
+        warn!("{path} is not valid SPIR-V, its size is not a multiple of 4, using the compiled in shader");
+        return None;
+    }
+
+    // SAFETY: nothing can be validated here, a broken shader is the developer's problem
+    match unsafe { ShaderModule::from_bytes(device.clone(), &bytes) } {
+        Ok(v) => {
+            info!("Loaded external shader {path}");
+            Some(v)
+        },
+        Err(e) => {
+            warn!("Failed to load external shader {path}, using the compiled in shader: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Polls the modification times of the SPIR-V files in SHADER_DIR
+pub struct ShaderWatcher {
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant
+}
+
+impl Default for ShaderWatcher {
+    fn default() -> Self {
+        ShaderWatcher::new()
+    }
+}
+
+impl ShaderWatcher {
+    // walking the directory every frame would be wasteful
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn new() -> Self {
+        ShaderWatcher { modified: ShaderWatcher::scan(), last_poll: Instant::now() }
+    }
+
+    /*
+    True if a shader was added, removed or modified since the last call
+    */
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < ShaderWatcher::POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = ShaderWatcher::scan();
+        if modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+
+    // resources/shaders/<name>/<stage>.spv
+    fn scan() -> HashMap<PathBuf, SystemTime> {
+        let mut modified = HashMap::new();
+        let dirs = match fs::read_dir(SHADER_DIR) {
+            Ok(v) => v,
+            Err(_) => return modified
+        };
+
+        for dir in dirs.flatten() {
+            let files = match fs::read_dir(dir.path()) {
+                Ok(v) => v,
+                Err(_) => continue
+            };
+
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().map_or(true, |e| e != "spv") {
+                    continue;
+                }
+
+                if let Ok(time) = file.metadata().and_then(|m| m.modified()) {
+                    modified.insert(path, time);
+                }
+            }
+        }
+        modified
+    }
+}
@@ -0,0 +1,64 @@
+use vulkano_shaders;
+
+vulkano_shaders::shader! {
+    ty: "fragment",
+    src: "
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+
+layout(location = 0) in vec2 frag_uv;
+
+layout(location = 0) out vec4 f_color;
+
+const float SPAN_MAX = 8.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+// FXAA, blurs along the direction of edges found from the luma of the corners
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(scene, 0));
+
+    vec4 color_m = texture(scene, frag_uv);
+    float luma_nw = luma(texture(scene, frag_uv + vec2(-1.0, -1.0) * texel).rgb);
+    float luma_ne = luma(texture(scene, frag_uv + vec2(1.0, -1.0) * texel).rgb);
+    float luma_sw = luma(texture(scene, frag_uv + vec2(-1.0, 1.0) * texel).rgb);
+    float luma_se = luma(texture(scene, frag_uv + vec2(1.0, 1.0) * texel).rgb);
+    float luma_m = luma(color_m.rgb);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel;
+
+    vec3 color_a = 0.5 * (
+        texture(scene, frag_uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        texture(scene, frag_uv + dir * (2.0 / 3.0 - 0.5)).rgb
+    );
+    vec3 color_b = color_a * 0.5 + 0.25 * (
+        texture(scene, frag_uv + dir * -0.5).rgb +
+        texture(scene, frag_uv + dir * 0.5).rgb
+    );
+
+    // the wider sample went past the edge, fall back to the narrow one
+    float luma_b = luma(color_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        f_color = vec4(color_a, color_m.a);
+    }
+    else {
+        f_color = vec4(color_b, color_m.a);
+    }
+}
+"
+}
@@ -0,0 +1,16 @@
+use vulkano_shaders;
+
+vulkano_shaders::shader! {
+    ty: "vertex",
+    src: "
+#version 450
+
+layout(location = 0) out vec2 frag_uv;
+
+// Fullscreen triangle without any vertex buffer, draw with 3 vertices
+void main() {
+    frag_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(frag_uv * 2.0 - 1.0, 0.0, 1.0);
+}
+"
+}
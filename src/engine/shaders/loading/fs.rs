@@ -0,0 +1,39 @@
+use vulkano_shaders;
+
+// Loading screen drawn over the fullscreen triangle of the vs while preloading, see Vulkan::create_loading_screen
+vulkano_shaders::shader! {
+    ty: "fragment",
+    types_meta: {
+        use bytemuck::{Pod, Zeroable};
+
+        #[derive(Clone, Copy, Zeroable, Pod)]
+    },
+    src: "
+#version 450
+
+// white unless the loading screen has a background texture
+layout(set = 0, binding = 0) uniform sampler2D background;
+
+layout(push_constant) uniform LoadingPushConstants {
+    vec4 background_color;
+    vec4 bar_color;
+    // min and max corner of the progress bar in uv
+    vec4 bar_rect;
+    float progress;
+} pcs_l;
+
+layout(location = 0) in vec2 frag_uv;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = texture(background, frag_uv) * pcs_l.background_color;
+
+    if (all(greaterThanEqual(frag_uv, pcs_l.bar_rect.xy)) && all(lessThanEqual(frag_uv, pcs_l.bar_rect.zw))) {
+        float filled = mix(pcs_l.bar_rect.x, pcs_l.bar_rect.z, clamp(pcs_l.progress, 0.0, 1.0));
+        // the part still to load is a darker shade of the bar
+        f_color = frag_uv.x <= filled ? pcs_l.bar_color : vec4(pcs_l.bar_color.rgb * 0.25, pcs_l.bar_color.a);
+    }
+}
+"
+}
@@ -0,0 +1,36 @@
+use vulkano_shaders;
+
+// Texture of a Minimap drawn with the loading vs into the viewport of its rect, see Vulkan::create_minimap_pipeline
+vulkano_shaders::shader! {
+    ty: "fragment",
+    types_meta: {
+        use bytemuck::{Pod, Zeroable};
+
+        #[derive(Clone, Copy, Zeroable, Pod)]
+    },
+    src: "
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D map;
+
+layout(push_constant) uniform MinimapPushConstants {
+    vec4 marker_color;
+    // center in uv, radius in uv per axis, so the dot stays round in any rect
+    vec2 marker;
+    vec2 marker_radius;
+} pcs_m;
+
+layout(location = 0) in vec2 frag_uv;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = texture(map, frag_uv);
+
+    // a radius of 0 hides the marker
+    if (all(greaterThan(pcs_m.marker_radius, vec2(0.0))) && length((frag_uv - pcs_m.marker) / pcs_m.marker_radius) <= 1.0) {
+        f_color = pcs_m.marker_color;
+    }
+}
+"
+}
@@ -1,2 +1,41 @@
+use std::sync::Arc;
+
+use vulkano::{device::Device, shader::{ShaderModule, ShaderCreationError}};
+
+pub mod blit;
 pub mod default;
+pub mod fxaa;
+pub mod loading;
+pub mod minimap;
+pub mod pbr;
+pub mod shadow;
+pub mod skybox;
+pub mod text;
+pub mod unlit;
 pub mod wireframe;
+#[cfg(feature = "external-shaders")]
+pub mod external;
+
+/*
+Loads a shader using the compiled in loader
+
+With the external-shaders feature, a SPIR-V file at resources/shaders/<name>/<stage>.spv
+takes priority, so shaders can be edited without rebuilding the engine.
+Shaders are loaded again whenever the pipelines are recreated, e.g. on window resize.
+*/
+pub fn load(
+    device: &Arc<Device>,
+    name: &str,
+    stage: &str,
+    compiled: fn(Arc<Device>) -> Result<Arc<ShaderModule>, ShaderCreationError>
+) -> Result<Arc<ShaderModule>, ShaderCreationError> {
+    #[cfg(feature = "external-shaders")]
+    if let Some(v) = external::load(device, name, stage) {
+        return Ok(v);
+    }
+
+    #[cfg(not(feature = "external-shaders"))]
+    let _ = (name, stage);
+
+    compiled(device.clone())
+}
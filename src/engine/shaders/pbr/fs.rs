@@ -0,0 +1,180 @@
+use vulkano_shaders;
+
+// Fragment shader for the default vertex shader shading glTF metallic roughness materials,
+// see Vulkan::create_gltf_renderables
+vulkano_shaders::shader! {
+    ty: "fragment",
+    types_meta: {
+        use bytemuck::{Pod, Zeroable};
+
+        #[derive(Clone, Copy, Zeroable, Pod)]
+    },
+    src: "
+#version 450
+
+const float PI = 3.14159265359;
+
+// same as in the default shader
+struct LightData {
+    vec4 position_range;
+    vec4 direction_kind;
+    vec4 color_intensity;
+};
+
+layout(set = 1, binding = 0) uniform sampler2D base_color_sampler;
+// roughness in g, metallic in b like in glTF
+layout(set = 1, binding = 1) uniform sampler2D metallic_roughness_sampler;
+layout(set = 1, binding = 2) uniform sampler2D normal_sampler;
+
+layout(set = 1, binding = 3) uniform MaterialUniformBufferObject {
+    vec4 base_color_factor;
+    // metallic, roughness, normal scale and alpha cutoff
+    // a normal scale of 0 skips the normal map, an alpha cutoff of 0 keeps every fragment
+    vec4 factors;
+} ubo_material;
+
+// same as in the default shader
+layout(set = 2, binding = 0) uniform ShadowUniformBufferObject {
+    mat4 shadow_matrices[4];
+    uint shadow_cascades;
+} ubo_shadows;
+
+layout(set = 2, binding = 1) uniform sampler2DArrayShadow shadow_map;
+
+layout(set = 2, binding = 2) uniform LightsUniformBufferObject {
+    LightData lights[16];
+    vec4 ambient;
+    uint count;
+} ubo_lights;
+
+layout(location = 0) in vec3 frag_color;
+layout(location = 1) in vec2 frag_tex_coord;
+layout(location = 2) in vec3 v_normal;
+layout(location = 3) in vec3 v_position;
+
+layout(location = 0) out vec4 f_color;
+
+const float SHADOW_BRIGHTNESS = 0.5;
+
+// same as in the default shader
+float shadow(vec3 position) {
+    for (uint i = 0u; i < ubo_shadows.shadow_cascades; i++) {
+        vec4 clip = ubo_shadows.shadow_matrices[i] * vec4(position, 1.0);
+        vec3 coords = clip.xyz / clip.w;
+        if (any(greaterThan(abs(coords.xy), vec2(1.0))) || coords.z > 1.0) {
+            continue;
+        }
+
+        vec2 uv = coords.xy * 0.5 + 0.5;
+        vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+        float lit = 0.0;
+        for (int x = -1; x <= 1; x++) {
+            for (int y = -1; y <= 1; y++) {
+                lit += textureGrad(shadow_map, vec4(uv + vec2(x, y) * texel, float(i), coords.z), vec2(0.0), vec2(0.0));
+            }
+        }
+        return lit / 9.0;
+    }
+    return 1.0;
+}
+
+// Tangent frame from screen space derivatives, the vertices don't have tangents
+mat3 cotangent_frame(vec3 n, vec3 p, vec2 uv) {
+    vec3 dp1 = dFdx(p);
+    vec3 dp2 = dFdy(p);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+
+    vec3 dp2perp = cross(dp2, n);
+    vec3 dp1perp = cross(n, dp1);
+    vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    float scale = inversesqrt(max(max(dot(t, t), dot(b, b)), 1e-12));
+    return mat3(t * scale, b * scale, n);
+}
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 1e-6);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float gv = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float gl = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return gv * gl;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Reflected light arriving from to_light, times pi so a white diffuse surface facing a light
+// of intensity 1 is as bright as with the default shader
+vec3 brdf(vec3 n, vec3 v, vec3 to_light, vec3 base, float metallic, float roughness) {
+    float n_dot_v = max(dot(n, v), 1e-4);
+    vec3 f0 = mix(vec3(0.04), base, metallic);
+    vec3 h = normalize(to_light + v);
+    float n_dot_l = max(dot(n, to_light), 0.0);
+    float n_dot_h = max(dot(n, h), 0.0);
+
+    vec3 f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    vec3 specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * f / (4.0 * n_dot_v * max(n_dot_l, 1e-4));
+    vec3 diffuse = (1.0 - f) * (1.0 - metallic) * base / PI;
+    return (diffuse + specular) * n_dot_l * PI;
+}
+
+void main() {
+    // everything is sampled before discarding, derivatives are undefined afterwards
+    vec4 base = texture(base_color_sampler, frag_tex_coord) * ubo_material.base_color_factor * vec4(frag_color, 1.0);
+    vec4 metallic_roughness = texture(metallic_roughness_sampler, frag_tex_coord);
+    float metallic = clamp(metallic_roughness.b * ubo_material.factors.x, 0.0, 1.0);
+    // fully smooth surfaces make the highlights vanish
+    float roughness = clamp(metallic_roughness.g * ubo_material.factors.y, 0.04, 1.0);
+
+    vec3 n = normalize(v_normal);
+    if (ubo_material.factors.z != 0.0) {
+        vec3 mapped = texture(normal_sampler, frag_tex_coord).xyz * 2.0 - 1.0;
+        // glTF normal maps point green towards smaller v, the frame towards larger v
+        mapped.xy *= vec2(ubo_material.factors.z, -ubo_material.factors.z);
+        n = normalize(cotangent_frame(n, v_position, frag_tex_coord) * mapped);
+    }
+
+    if (base.a < ubo_material.factors.w) {
+        discard;
+    }
+
+    // the camera is at the origin in view space
+    vec3 v = normalize(-v_position);
+    float lit = shadow(v_position);
+
+    vec3 color;
+    if (ubo_lights.count == 0u) {
+        // Without lights surfaces are lit by a white light at the camera
+        // the headlight isn't the sun casting the shadows, so they darken it like in the default shader
+        color = brdf(n, v, v, base.rgb, metallic, roughness) * mix(SHADOW_BRIGHTNESS, 1.0, lit);
+    }
+    else {
+        // same falloff as in the default shader, the sun casting the shadows is directional
+        color = ubo_lights.ambient.rgb * base.rgb * (1.0 - metallic);
+        for (uint i = 0u; i < ubo_lights.count; i++) {
+            LightData l = ubo_lights.lights[i];
+            vec3 to_light = -l.direction_kind.xyz;
+            float attenuation = lit;
+            if (l.direction_kind.w > 0.5) {
+                vec3 d = l.position_range.xyz - v_position;
+                float dist = length(d);
+                to_light = d / max(dist, 0.0001);
+                attenuation = clamp(1.0 - dist / l.position_range.w, 0.0, 1.0);
+                attenuation *= attenuation;
+            }
+            color += l.color_intensity.rgb * l.color_intensity.a * attenuation * brdf(n, v, normalize(to_light), base.rgb, metallic, roughness);
+        }
+    }
+    f_color = vec4(color, 1.0);
+}
+"
+}
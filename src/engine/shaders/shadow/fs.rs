@@ -0,0 +1,12 @@
+use vulkano_shaders;
+
+vulkano_shaders::shader! {
+    ty: "fragment",
+    src: "
+#version 450
+
+// only the depth is written
+void main() {
+}
+"
+}
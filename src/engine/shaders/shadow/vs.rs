@@ -0,0 +1,25 @@
+use vulkano_shaders;
+
+// Depth of the shadow casters as seen from a light, see Vulkan::create_shadow_map
+vulkano_shaders::shader! {
+    ty: "vertex",
+    types_meta: {
+        use bytemuck::{Pod, Zeroable};
+
+        #[derive(Clone, Copy, Zeroable, Pod)]
+    },
+    src: "
+#version 450
+
+layout(push_constant) uniform ShadowPushConstants {
+    // projection of the cascade times the model matrix
+    mat4 light_mvp;
+} pcs_s;
+
+layout(location = 0) in vec3 position;
+
+void main() {
+    gl_Position = pcs_s.light_mvp * vec4(position, 1.0);
+}
+"
+}
@@ -0,0 +1,19 @@
+use vulkano_shaders;
+
+// Samples the cubemap of the Skybox resource in the direction of the fragment
+vulkano_shaders::shader! {
+    ty: "fragment",
+    src: "
+#version 450
+
+layout(set = 0, binding = 0) uniform samplerCube skybox_sampler;
+
+layout(location = 0) in vec3 frag_dir;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = vec4(texture(skybox_sampler, frag_dir).rgb, 1.0);
+}
+"
+}
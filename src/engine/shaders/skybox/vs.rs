@@ -0,0 +1,30 @@
+use vulkano_shaders;
+
+vulkano_shaders::shader! {
+    ty: "vertex",
+    types_meta: {
+        use bytemuck::{Pod, Zeroable};
+
+        #[derive(Clone, Copy, Zeroable, Pod)]
+    },
+    src: "
+#version 450
+
+layout(push_constant) uniform SkyboxPushConstants {
+    // projection times the rotation of the view, the cube moves with the camera
+    mat4 view_proj;
+} pcs_s;
+
+layout(location = 0) out vec3 frag_dir;
+
+// Unit cube without any vertex buffer as a triangle strip, draw with 14 vertices
+// each bit of the masks is one coordinate of the corresponding vertex
+void main() {
+    int b = 1 << gl_VertexIndex;
+    vec3 position = vec3((0x287a & b) != 0, (0x02af & b) != 0, (0x31e3 & b) != 0) * 2.0 - 1.0;
+    frag_dir = position;
+    // z = w ends up at the far plane after the perspective divide
+    gl_Position = (pcs_s.view_proj * vec4(position, 1.0)).xyww;
+}
+"
+}
@@ -0,0 +1,31 @@
+use vulkano_shaders;
+
+// Fragment shader for WorldText, the alpha of the font atlas cuts out the glyphs
+vulkano_shaders::shader! {
+    ty: "fragment",
+    src: "
+#version 450
+
+layout(set = 1, binding = 0) uniform sampler2D tex_sampler;
+// declared like in default, so the set matches the layout of the default pipeline
+layout(set = 1, binding = 1) uniform sampler2D lightmap_sampler;
+
+layout(location = 0) in vec3 frag_color;
+layout(location = 1) in vec2 frag_tex_coord;
+layout(location = 2) in vec3 v_normal;
+layout(location = 3) in vec3 v_position;
+layout(location = 4) in vec2 frag_tex_coord2;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    float coverage = texture(tex_sampler, frag_tex_coord).a;
+    vec3 light = texture(lightmap_sampler, frag_tex_coord2).rgb;
+    // discarded instead of blended, so labels write depth and need no sorting
+    if (coverage < 0.5) {
+        discard;
+    }
+    f_color = vec4(frag_color * light, 1.0);
+}
+"
+}
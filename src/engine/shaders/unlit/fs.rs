@@ -0,0 +1,27 @@
+use vulkano_shaders;
+
+// Fragment shader for the default vertex shader which ignores lights and shadows
+vulkano_shaders::shader! {
+    ty: "fragment",
+    src: "
+#version 450
+
+layout(set = 1, binding = 0) uniform sampler2D tex_sampler;
+// white unless the renderable was created with a lightmap
+layout(set = 1, binding = 1) uniform sampler2D lightmap_sampler;
+
+layout(location = 0) in vec3 frag_color;
+layout(location = 1) in vec2 frag_tex_coord;
+layout(location = 2) in vec3 v_normal;
+layout(location = 3) in vec3 v_position;
+layout(location = 4) in vec2 frag_tex_coord2;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    vec3 base = texture(tex_sampler, frag_tex_coord).rgb;
+    vec3 light = texture(lightmap_sampler, frag_tex_coord2).rgb;
+    f_color = vec4(base * light, 1.0);
+}
+"
+}
@@ -17,6 +17,8 @@ layout(binding = 0) uniform VPUniformBufferObject {
 
 layout(push_constant) uniform ModelPushConstants {
     mat4 model;
+    // xy offset, zw scale
+    vec4 uv_transform;
 } pcs_m;
 
 layout(location = 0) in vec3 position;
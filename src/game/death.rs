@@ -0,0 +1,163 @@
+use log::info;
+use specs::{System, Read, Write, ReadStorage, WriteStorage, Entity};
+
+use engine::ecs::{resources::{SpawnPoints, events::{Events, EventReader}, physics::{CollisionEvents, PhysicsData}}, components::{general::{Health, Transform}, physics::RigidBodyComponent}, utils::objects::respawn_at};
+
+/// Sent when an entity's Health reaches zero
+pub struct PlayerDied {
+    pub player: Entity
+}
+
+/// Deals damage to everything entering the zone's sensor, sending PlayerDied for those it kills
+pub struct DamageZone {
+    pub zone: Entity,
+    pub damage: f32
+}
+
+impl<'a> System<'a> for DamageZone {
+    type SystemData = (
+        Read<'a, CollisionEvents>,
+        Write<'a, Events<PlayerDied>>,
+        WriteStorage<'a, Health>
+    );
+
+    fn run(&mut self, (collision_events, mut deaths, mut health): Self::SystemData) {
+        for event in collision_events.0.iter().filter(|e| e.sensor() && e.started()) {
+            let player = match event.other(self.zone) {
+                Some(v) => v,
+                None => continue
+            };
+
+            if let Some(h) = health.get_mut(player) {
+                if h.is_dead() {
+                    continue;
+                }
+
+                h.damage(self.damage);
+                if h.is_dead() {
+                    deaths.send(PlayerDied { player });
+                }
+            }
+        }
+    }
+}
+
+/// Moves dead players to the next spawn point with full health
+#[derive(Default)]
+pub struct RespawnOnDeath {
+    reader: EventReader<PlayerDied>
+}
+
+impl<'a> System<'a> for RespawnOnDeath {
+    type SystemData = (
+        Read<'a, Events<PlayerDied>>,
+        Write<'a, PhysicsData>,
+        Write<'a, SpawnPoints>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Health>,
+        ReadStorage<'a, RigidBodyComponent>
+    );
+
+    fn run(&mut self, (deaths, mut physics_data, mut spawn_points, mut transform, mut health, rigid_body): Self::SystemData) {
+        for death in self.reader.read(&deaths) {
+            let spawn = match spawn_points.next() {
+                Some(v) => v,
+                None => continue
+            };
+
+            if let (Some(t), Some(r)) = (transform.get_mut(death.player), rigid_body.get(death.player)) {
+                respawn_at(&spawn, t, r, &mut physics_data);
+            }
+            if let Some(h) = health.get_mut(death.player) {
+                h.current = h.max;
+            }
+        }
+    }
+}
+
+/// Stand-in for a UI, tells the player they died
+#[derive(Default)]
+pub struct DeathMessage {
+    reader: EventReader<PlayerDied>
+}
+
+impl<'a> System<'a> for DeathMessage {
+    type SystemData = Read<'a, Events<PlayerDied>>;
+
+    fn run(&mut self, deaths: Self::SystemData) {
+        for death in self.reader.read(&deaths) {
+            info!("{:?} died", death.player);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use engine::ecs::{ECS, resources::DeltaTime, systems::physics::Physics, utils::objects::{create_character, create_trigger_zone, CapsuleSize}};
+    use nalgebra::Vector3;
+    use rapier3d::control::KinematicCharacterController;
+    use specs::{Builder, DispatcherBuilder, WorldExt};
+
+    use super::*;
+
+    // another reader next to RespawnOnDeath, like DeathMessage but countable
+    #[derive(Default)]
+    struct CountDeaths {
+        reader: EventReader<PlayerDied>,
+        count: std::sync::Arc<std::sync::atomic::AtomicUsize>
+    }
+
+    impl<'a> System<'a> for CountDeaths {
+        type SystemData = Read<'a, Events<PlayerDied>>;
+
+        fn run(&mut self, deaths: Self::SystemData) {
+            let read = self.reader.read(&deaths).count();
+            self.count.fetch_add(read, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn walking_into_the_damage_zone_kills_and_respawns_the_player() {
+        let mut world = ECS::new().world;
+        let mut physics_data = PhysicsData::default();
+        physics_data.set_gravity(Vector3::zeros());
+
+        let (transform, rigid_body, collider) = create_character(&mut physics_data, CapsuleSize::default(), Vector3::zeros(), KinematicCharacterController::default());
+        let player = world.create_entity().with(transform).with(rigid_body).with(collider).with(Health::new(100.0)).build();
+        let (zone_transform, zone_collider) = create_trigger_zone(&mut physics_data, Vector3::new(1.0, 2.0, 1.0), Vector3::new(3.0, 1.0, 0.0));
+        let zone = world.create_entity().with(zone_transform).with(zone_collider).build();
+
+        world.insert(physics_data);
+        world.insert(SpawnPoints::new(vec![Transform::from_position(Vector3::new(-10.0, 0.0, 0.0))]));
+        world.insert(DeltaTime(0.1));
+        world.insert(Events::<PlayerDied>::default());
+
+        let counter = CountDeaths::default();
+        let count = counter.count.clone();
+        // same order as in the game, the damage zone sees the collision events of the step before
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(DamageZone { zone, damage: 100.0 }, "damage_zone", &[])
+            .with(RespawnOnDeath::default(), "respawn_on_death", &["damage_zone"])
+            .with(counter, "count_deaths", &["damage_zone"])
+            .with(Physics::default(), "physics", &["respawn_on_death", "count_deaths"])
+            .build();
+        dispatcher.setup(&mut world);
+
+        for _ in 0..20 {
+            if count.load(std::sync::atomic::Ordering::Relaxed) > 0 {
+                break;
+            }
+            world.write_storage::<Transform>().get_mut(player).unwrap().mov = Vector3::new(0.5, 0.0, 0.0);
+            dispatcher.dispatch(&world);
+            world.maintain();
+            Events::<PlayerDied>::update_in(&world);
+        }
+
+        // both readers saw the death, the respawn in the same frame as the counter
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), 1);
+        let transforms = world.read_storage::<Transform>();
+        assert_eq!(transforms.get(player).unwrap().pos(), Vector3::new(-10.0, 0.0, 0.0));
+        let health = world.read_storage::<Health>();
+        assert_eq!(health.get(player).unwrap().current, 100.0);
+    }
+}
@@ -1,10 +1,15 @@
+mod death;
+mod trigger_zone;
+
 use std::f32::consts::PI;
 
 use log::error;
-use engine::{HawkEngine, start_engine, ecs::{components::{general::{Transform, Camera, Movement, Wireframe}, physics::{RigidBodyComponent, ColliderComponent, ColliderRenderable}}, resources::{ActiveCamera, physics::PhysicsData}, utils::objects::create_terrain}};
+use engine::{HawkEngine, MipChain, start_engine, ecs::{components::{general::{Transform, Camera, Movement, Wireframe, OcclusionCulled, Minimap, WorldText, Light, Health}, physics::{RigidBodyComponent, ColliderComponent, ColliderRenderable}}, resources::{ActiveCamera, SpawnPoints, Shadows, physics::{PhysicsData, KillPlane}}, utils::objects::{create_terrain, create_character, create_trigger_zone, CapsuleSize}, systems::role::Role}};
 use nalgebra::{Vector3, UnitQuaternion, UnitVector3};
-use rapier3d::{control::{KinematicCharacterController, CharacterLength}, prelude::{RigidBodyBuilder, RigidBodyType, ColliderBuilder, SharedShape, UnitVector, ActiveCollisionTypes}};
+use rapier3d::{control::{KinematicCharacterController, CharacterLength}, prelude::{UnitVector, ActiveCollisionTypes}};
 use specs::{WorldExt, Builder};
+use trigger_zone::TriggerZoneAnnouncer;
+use death::{PlayerDied, DamageZone, RespawnOnDeath, DeathMessage};
 
 fn main() {
     let mut engine = HawkEngine::new(true);
@@ -13,48 +18,78 @@ fn main() {
 
     // Add physics stuff
     let mut physics_data = PhysicsData::default();
+    // keeps the terrain from letting objects visibly sink into steep slopes
+    physics_data.set_contact_skin(0.02);
 
     let character_controller = KinematicCharacterController {
         offset: CharacterLength::Relative(0.1),
         snap_to_ground: Some(CharacterLength::Relative(0.025)),
         ..Default::default()
     };
-    let rigid_body = RigidBodyBuilder::new(RigidBodyType::KinematicPositionBased)
-        //.ccd_enabled(true)
-        .can_sleep(false)
-        .enabled(true)
-        .user_data(1)
-        .translation(Vector3::new(0.0, 15.0, 0.0))
-        .lock_rotations()
-        .build();
-    let collider = ColliderBuilder::new(SharedShape::capsule_y(0.9, 1.0))
-        //.active_collision_types(ActiveCollisionTypes::default() | ActiveCollisionTypes::KINEMATIC_FIXED)
-        //.friction(0.7)
-        //.mass(80.0)
-        .enabled(true)
-        .build();
-
-    let rigid_body_component = RigidBodyComponent::new(rigid_body, &mut physics_data, Some(character_controller));
-
-    let collider = ColliderComponent::new(collider, Some(&rigid_body_component.handle), &mut physics_data);
-    let (v, i) = collider.get_vertices(&physics_data);
-    let vert = ColliderRenderable::convert_to_vertex(v);
+    let (transform, rigid_body_component, collider) = create_character(
+        &mut physics_data,
+        CapsuleSize::default(),
+        Vector3::new(0.0, 13.1, 0.0),
+        character_controller
+    );
+    let (v, i) = collider.get_vertices_subdivided(&physics_data, 12);
+    let vert = ColliderRenderable::convert_to_vertex_colored(v, ColliderRenderable::debug_color(&collider, &physics_data));
     let (vb, ib) = engine.vulkan.create_vertex_buffers(vert, i);
 
     // Add a camera
     let camera_entity = world
         .create_entity()
         .with(Camera)
-        .with(Transform {
-            pos: Vector3::new(0.0, 15.0, 0.0),
-            ..Default::default()
-        })
+        .with(transform)
         .with(Movement {speed: 10.0, boost: 20.0, slow: 5.0, jump: 3000.0, sensitivity: 0.1, max_jumps: 2, ..Default::default()})
         .with(collider)
         .with(ColliderRenderable { vertex_buffer: vb, index_buffer: ib })
         .with(rigid_body_component)
+        .with(Health::new(100.0))
         .build();
     world.insert(ActiveCamera(camera_entity));
+
+    // Minimap in the top right corner, the same aspect ratio as its rect on a 16:9 window
+    match engine.vulkan.create_minimap_target(320, 180) {
+        Ok(v) => {
+            world.create_entity().with(Minimap::new(v, camera_entity)).build();
+        },
+        Err(e) => error!("Failed to create the minimap: {}", e)
+    }
+    world.insert(SpawnPoints::new(vec![Transform::from_position(Vector3::new(0.0, 15.0, 0.0))]));
+    world.insert(KillPlane(-50.0));
+    // sharp near the player and still covering the far side of the terrain
+    world.insert(Shadows { cascade_count: 3, ..Default::default() });
+
+    // Trigger zone next to the spawn point
+    let (zone_transform, zone_collider) = create_trigger_zone(&mut physics_data, Vector3::new(2.0, 2.0, 2.0), Vector3::new(5.0, 12.0, 0.0));
+    let (ve, i) = zone_collider.get_vertices(&physics_data);
+    let vert = ColliderRenderable::convert_to_vertex_colored(ve, ColliderRenderable::debug_color(&zone_collider, &physics_data));
+    let (vb, ib) = engine.vulkan.create_vertex_buffers(vert, i);
+    let trigger_zone = world
+        .create_entity()
+        .with(zone_transform)
+        .with(zone_collider)
+        .with(ColliderRenderable { vertex_buffer: vb, index_buffer: ib })
+        .build();
+
+    // Damage zone on the other side, deadly enough to send PlayerDied
+    let (zone_transform, zone_collider) = create_trigger_zone(&mut physics_data, Vector3::new(2.0, 2.0, 2.0), Vector3::new(-5.0, 12.0, 0.0));
+    let (ve, i) = zone_collider.get_vertices(&physics_data);
+    let vert = ColliderRenderable::convert_to_vertex_colored(ve, ColliderRenderable::debug_color(&zone_collider, &physics_data));
+    let (vb, ib) = engine.vulkan.create_vertex_buffers(vert, i);
+    let damage_zone = world
+        .create_entity()
+        .with(zone_transform)
+        .with(zone_collider)
+        .with(ColliderRenderable { vertex_buffer: vb, index_buffer: ib })
+        .build();
+
+    // Sun, shining the way the shadows are cast
+    world
+        .create_entity()
+        .with(Light::Directional { direction: Vector3::new(-0.4, -1.0, -0.3), color: Vector3::new(1.0, 0.95, 0.85), intensity: 1.0 })
+        .build();
     
     // Add a terrain
     let (
@@ -69,7 +104,7 @@ fn main() {
 
             let collider = ColliderComponent::new(terrain_collider, Some(&terrain_rb_comp.handle), &mut physics_data);
             let (ve, i) = collider.get_vertices(&physics_data);
-            let vert = ColliderRenderable::convert_to_vertex(ve);
+            let vert = ColliderRenderable::convert_to_vertex_colored(ve, ColliderRenderable::debug_color(&collider, &physics_data));
             let (vb, ib) = engine.vulkan.create_vertex_buffers(vert, i);
 
             let terrain = world
@@ -88,23 +123,47 @@ fn main() {
     // Inserting this last so the components can borrow it
     world.insert(physics_data);
 
-    for i in 0..2 {
-        let renderable = engine.vulkan.create_renderable("viking_room", Some("default".into()));
+    // the texture is decoded while the loading screen is shown
+    engine.preload(
+        || MipChain::load("resources/viking_room.png"),
+        |engine, mips| {
+            let renderable = match mips.and_then(|mips| engine.vulkan.create_renderable_from_mips("viking_room", &mips, Some("default".into()))) {
+                Ok(v) => v,
+                Err(e) => return println!("Failed creating viking_room renderable: {:?}", e)
+            };
 
-        match renderable {
-            Ok(v) => {
-                world
+            // not drawn while the terrain or the other room hides it
+            let bounds = OcclusionCulled::from_renderable(&renderable);
+            for i in 0..2 {
+                let mut builder = engine.ecs.world
                     .create_entity()
-                    .with(v)
-                    .with(Transform {
-                        pos: Vector3::new(0.0, i as f32 * 1.0, -1.0),
-                        ..Transform::default()
-                    })
-                    .build();
+                    .with(renderable.clone())
+                    .with(Transform::from_position(Vector3::new(0.0, i as f32 * 1.0, -1.0)));
+                if let Some(bounds) = bounds {
+                    builder = builder.with(bounds);
+                }
+                builder.build();
             }
-            Err(e) => println!("Failed creating viking_room renderable: {:?}", e)
+
+            // floats over the upper room
+            engine.ecs.world
+                .create_entity()
+                .with(Transform::from_position(Vector3::new(0.0, 1.0, -1.0)))
+                .with(WorldText::new("Viking room", Vector3::new(0.0, 1.5, 0.0)).with_height(0.3))
+                .build();
         }
-    }
+    );
+
+    engine.add_event::<PlayerDied>();
+    // both readers run after the damage zone, so they see its events in the same frame
+    let dispatcher = engine
+        .dispatcher_builder()
+        .with(TriggerZoneAnnouncer { zone: trigger_zone, player: camera_entity }, "trigger_zone", &[], Role::Both)
+        .with(DamageZone { zone: damage_zone, damage: 100.0 }, "damage_zone", &[], Role::Both)
+        .with(RespawnOnDeath::default(), "respawn_on_death", &["damage_zone"], Role::Both)
+        .with(DeathMessage::default(), "death_message", &["damage_zone"], Role::Both)
+        .build();
+    engine.add_dispatcher(dispatcher);
 
     start_engine(engine);
 }
\ No newline at end of file
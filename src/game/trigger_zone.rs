@@ -0,0 +1,29 @@
+use log::info;
+use specs::{System, Read, Entity};
+
+use engine::ecs::resources::physics::CollisionEvents;
+
+/// Example of a trigger zone, logs whenever the player enters or leaves the zone's sensor
+pub struct TriggerZoneAnnouncer {
+    pub zone: Entity,
+    pub player: Entity
+}
+
+impl<'a> System<'a> for TriggerZoneAnnouncer {
+    type SystemData = Read<'a, CollisionEvents>;
+
+    fn run(&mut self, collision_events: Self::SystemData) {
+        for event in collision_events.0.iter().filter(|e| e.sensor()) {
+            if event.other(self.zone) != Some(self.player) {
+                continue;
+            }
+
+            if event.started() {
+                info!("Player entered the trigger zone");
+            }
+            else {
+                info!("Player left the trigger zone");
+            }
+        }
+    }
+}